@@ -0,0 +1,30 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use elgato_keylight::{parse_escaped_ascii, DeviceStatus, MdnsPacket};
+
+/// Resolved service line of `avahi-browse --parsable --resolve`
+const RESOLVED: &str = r#"=;enp6s0;IPv4;Elgato\032Key\032Light\0328D7C;_elg._tcp;local;elgato-key-light-8d7c.local;192.168.0.92;9123;"pv=1.0" "md=Elgato Key Light 20GAK9901" "id=3C:6A:9D:21:B1:6E" "dt=53" "mf=Elgato""#;
+
+const STATUS: &str =
+    r#"{"numberOfLights":1,"lights":[{"on":1,"brightness":40,"temperature":200}]}"#;
+
+fn mdns(c: &mut Criterion) {
+    c.bench_function("mdns_packet", |b| {
+        b.iter(|| MdnsPacket::try_from(black_box(RESOLVED).to_string()))
+    });
+    c.bench_function("escaped_ascii", |b| {
+        b.iter(|| parse_escaped_ascii(black_box(r"Elgato\032Key\032Light\032M\195\188nchen")))
+    });
+}
+
+fn device_status(c: &mut Criterion) {
+    c.bench_function("device_status_de", |b| {
+        b.iter(|| serde_json::from_str::<DeviceStatus>(black_box(STATUS)))
+    });
+    let status: DeviceStatus = serde_json::from_str(STATUS).unwrap();
+    c.bench_function("device_status_ser", |b| {
+        b.iter(|| serde_json::to_string(black_box(&status)))
+    });
+}
+
+criterion_group!(benches, mdns, device_status);
+criterion_main!(benches);
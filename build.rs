@@ -0,0 +1,81 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc_service();
+    #[cfg(feature = "ffi")]
+    c_header();
+    // Leaves the symbols of Node.js to be resolved when the addon is loaded
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
+
+/// Generate `include/elgato_keylight.h` from the functions of `src/ffi.rs`
+#[cfg(feature = "ffi")]
+fn c_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        include_guard: Some("ELGATO_KEYLIGHT_H".to_string()),
+        autogen_warning: Some(
+            "/* Generated by build.rs with the `ffi` feature, do not edit */".to_string(),
+        ),
+        documentation_style: cbindgen::DocumentationStyle::C99,
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/ffi.rs")
+        .generate()
+        .expect("failed to generate the C header")
+        .write_to_file("include/elgato_keylight.h");
+}
+
+/// Generate the gRPC service of `proto/keylight.proto`. The messages are written by hand in
+/// `src/daemon/grpc.rs`, so building does not require `protoc`.
+#[cfg(feature = "grpc")]
+fn grpc_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    println!("cargo:rerun-if-changed=build.rs");
+    let method = |name: &str, route: &str, input: &str, output: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("super::{input}"))
+            .output_type(format!("super::{output}"))
+            .codec_path("tonic::codec::ProstCodec")
+    };
+    let service = Service::builder()
+        .name("KeyLight")
+        .package("keylight.v1")
+        .method(
+            method(
+                "list_devices",
+                "ListDevices",
+                "ListDevicesRequest",
+                "ListDevicesResponse",
+            )
+            .build(),
+        )
+        .method(method("get_status", "GetStatus", "DeviceRequest", "LightState").build())
+        .method(method("set_status", "SetStatus", "SetStatusRequest", "LightState").build())
+        .method(method("toggle", "Toggle", "DeviceRequest", "LightState").build())
+        .method(
+            method(
+                "apply_preset",
+                "ApplyPreset",
+                "ApplyPresetRequest",
+                "LightState",
+            )
+            .build(),
+        )
+        .method(
+            method("watch", "Watch", "WatchRequest", "StateChange")
+                .server_streaming()
+                .build(),
+        )
+        .build();
+    Builder::new()
+        .build_client(true)
+        .build_transport(true)
+        .compile(&[service]);
+}
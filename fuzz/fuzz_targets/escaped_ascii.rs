@@ -0,0 +1,8 @@
+#![no_main]
+
+use elgato_keylight::parse_escaped_ascii;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|s: &str| {
+    let _ = parse_escaped_ascii(s);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use elgato_keylight::parse_answer;
+use libfuzzer_sys::fuzz_target;
+
+// Answers to the mDNS queries of the builtin discovery backend
+fuzz_target!(|message: &[u8]| {
+    let _ = parse_answer("_elg._tcp.local", message);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use elgato_keylight::MdnsPacket;
+use libfuzzer_sys::fuzz_target;
+
+// Lines of `avahi-browse --parsable`, e.g. `=;eth0;IPv4;Elgato\032Key\032Light;_elg._tcp;local;...`
+fuzz_target!(|line: String| {
+    let _ = MdnsPacket::try_from(line);
+});
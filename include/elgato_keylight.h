@@ -0,0 +1,65 @@
+#ifndef ELGATO_KEYLIGHT_H
+#define ELGATO_KEYLIGHT_H
+
+/* Generated by build.rs with the `ffi` feature, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define KEYLIGHT_OK 0
+
+// A null pointer, an invalid URL or an out of range value
+#define KEYLIGHT_INVALID_ARGUMENT -1
+
+// The request to the light or the discovery failed
+#define KEYLIGHT_REQUEST_FAILED -2
+
+// State of the first light of a device
+typedef struct KeylightStatus {
+  // 0 or 1
+  uint8_t on;
+  // Percent, from 0 to 100
+  uint8_t brightness;
+  // Device units, from 143 (7000 K) to 344 (2900 K), 0 for a light in color mode
+  uint16_t temperature;
+} KeylightStatus;
+
+// Message of the last error of the calling thread, null if none. Valid until the next call
+// failing on this thread.
+const char *keylight_last_error(void);
+
+// Discover the lights on the network through Avahi. On success `*devices` is set to a JSON
+// array of `{"name": ..., "url": ...}`, to release with [`keylight_string_free`].
+//
+// # Safety
+// `devices` must be a valid pointer
+int32_t keylight_discover(char **devices);
+
+// Release a string returned by this library
+//
+// # Safety
+// `s` must be null or a string returned by this library, not released yet
+void keylight_string_free(char *s);
+
+// Read the state of the light at `url`, e.g. `http://192.168.1.100:9123`
+//
+// # Safety
+// `url` must be a valid nul-terminated string and `status` a valid pointer
+int32_t keylight_get_status(const char *url, struct KeylightStatus *status);
+
+// Set the state of the light at `url`
+//
+// # Safety
+// `url` must be a valid nul-terminated string and `status` a valid pointer
+int32_t keylight_set_status(const char *url, const struct KeylightStatus *status);
+
+// Turn the light at `url` off if it is on, on otherwise. On success `*status`, if not null,
+// is set to the new state.
+//
+// # Safety
+// `url` must be a valid nul-terminated string and `status` null or a valid pointer
+int32_t keylight_toggle(const char *url, struct KeylightStatus *status);
+
+#endif  /* ELGATO_KEYLIGHT_H */
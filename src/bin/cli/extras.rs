@@ -0,0 +1,775 @@
+//! Commands beyond the control of a light given by its address: lights by name, schedules,
+//! scenes, recordings, stats, settings backups and calibration
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Subcommand;
+use reqwest::Url;
+
+use elgato_keylight::{
+    exit_code::{NotFound, PartialFailure, ValidationError},
+    *,
+};
+
+use crate::parse_brightness;
+
+/// Timeout of the writes of settings, slower than the ones of the state
+const SETTINGS_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Manage the schedules run by the daemon
+    #[command(subcommand)]
+    Schedule(ScheduleCommand),
+    /// Check and play the scenes of the scenes directory
+    #[command(subcommand)]
+    Scene(SceneCommand),
+    /// Apply the presets of the config
+    #[command(subcommand)]
+    Preset(PresetCommand),
+    /// Record the changes of the lights, e.g. from their buttons or another app, until Ctrl-C
+    Record {
+        /// Scene file the timeline is written to
+        file: PathBuf,
+        /// Light to record, all of them if not set
+        #[arg(long = "device")]
+        devices: Vec<String>,
+        /// Milliseconds between two reads of the lights
+        #[arg(long, default_value_t = 250)]
+        interval: u64,
+        /// Stop after this many seconds
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Replay a recorded timeline, or any scene file, with its original timing
+    Play { file: PathBuf },
+    /// Usage history and estimated power usage of the lights
+    Stats {
+        /// Days to summarize
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+    },
+    /// Back up the settings of a light and apply them to others
+    #[command(subcommand)]
+    Settings(SettingsCommand),
+    /// Step a light through brightness and color temperature values, to check the exposure and
+    /// white balance of a camera at each of them
+    Calibrate {
+        /// Name of the light, host:port or base URL
+        device: String,
+        /// Brightness values in percent, from 3 to 100
+        #[arg(long, value_delimiter = ',', default_value = "25,50,75,100", value_parser = parse_brightness)]
+        brightness: Vec<Brightness>,
+        /// Color temperatures in kelvin, unused by the lights in color mode
+        #[arg(long, value_delimiter = ',', default_value = "3000,4000,5000,6000")]
+        kelvin: Vec<Kelvin>,
+        /// Seconds each step is held
+        #[arg(long, default_value_t = 3)]
+        pause: u64,
+        /// Pick a step at the end, save it as this preset and leave the light on it
+        #[arg(long)]
+        save_as: Option<String>,
+    },
+    /// Who changed the lights through the daemon, from its history
+    #[cfg(unix)]
+    Audit {
+        /// Only the changes of this device
+        #[arg(long)]
+        device: Option<String>,
+        /// Only the changes made through this source, e.g. ipc, rest or scheduler
+        #[arg(long)]
+        source: Option<String>,
+        /// Number of changes
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Meeting mode of the daemon: the meeting preset, with the automations paused
+    #[cfg(unix)]
+    #[command(subcommand)]
+    Meeting(MeetingCommand),
+}
+
+#[cfg(unix)]
+#[derive(Debug, Subcommand)]
+pub enum MeetingCommand {
+    /// Start a meeting, or change the end of the current one
+    Start {
+        /// End the meeting after this long, e.g. 45m or 1h30m
+        #[arg(long = "for", value_parser = parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// End the meeting, restoring the lights and the automations
+    Stop,
+    /// Whether a meeting is in progress, and until when
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SceneCommand {
+    /// List the scenes
+    List,
+    /// Validate a scene and print the change of each light
+    Check { name: String },
+    /// Play a scene on the lights found on the network
+    Play { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PresetCommand {
+    /// Set the lights to a preset, then fade them to another one
+    Crossfade {
+        from: String,
+        to: String,
+        /// Duration of the fade, e.g. 10s or 1m30s
+        #[arg(long, value_parser = parse_duration)]
+        duration: Duration,
+        /// Light to fade, all of them if not set
+        #[arg(long = "device")]
+        devices: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SettingsCommand {
+    /// Save the display name, power-on behavior and transitions of a light to a JSON file
+    Backup {
+        /// Name of the light, host:port or base URL
+        device: String,
+        file: PathBuf,
+    },
+    /// Apply a backup to lights, e.g. to configure a replacement like the old one
+    Apply {
+        file: PathBuf,
+        /// Names of the lights, host:port or base URLs, the display name is only applied to a single light
+        #[arg(long, required = true, num_args = 1..)]
+        to: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScheduleCommand {
+    /// List the schedules
+    List,
+    /// Add a schedule
+    Add(ScheduleAddArgs),
+    /// Remove a schedule
+    Remove { name: String },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ScheduleAddArgs {
+    name: String,
+    /// Cron expression, e.g. "0 8 * * 1-5" for 8:00 on weekdays
+    #[arg(long, required_unless_present = "at", conflicts_with = "at")]
+    cron: Option<String>,
+    /// One-shot time, e.g. 2024-09-01T08:00:00+02:00
+    #[arg(long)]
+    at: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// Light to control, all of them if not set
+    #[arg(long = "device")]
+    devices: Vec<String>,
+    /// Turn the lights on or off
+    #[arg(long, group = "action")]
+    power: Option<PowerStatus>,
+    /// Apply a preset
+    #[arg(long, group = "action")]
+    preset: Option<String>,
+    /// Play a scene, which picks its own lights
+    #[arg(long, group = "action", conflicts_with = "devices")]
+    scene: Option<String>,
+    /// Fade to this brightness, from 3 to 100
+    #[arg(long, group = "action", requires = "duration", value_parser = parse_brightness)]
+    fade_brightness: Option<Brightness>,
+    /// Fade to this temperature
+    #[arg(long, group = "action", requires = "duration")]
+    fade_temperature: Option<Temperature>,
+    /// Duration of the fade in seconds
+    #[arg(long)]
+    duration: Option<u64>,
+}
+
+pub async fn run(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Schedule(command) => schedule(command),
+        Command::Scene(command) => scene(command).await,
+        Command::Preset(PresetCommand::Crossfade {
+            from,
+            to,
+            duration,
+            devices,
+        }) => play_scene(&scene::Scene::crossfade(&from, &to, devices, duration)).await,
+        Command::Record {
+            file,
+            devices,
+            interval,
+            duration,
+        } => {
+            let duration = duration.map(Duration::from_secs);
+            record(&file, &devices, Duration::from_millis(interval), duration).await
+        }
+        Command::Play { file } => play_scene(&scene::Scene::load_from(&file)?).await,
+        Command::Stats { days } => stats(days).await,
+        Command::Settings(command) => settings(command).await,
+        Command::Calibrate {
+            device,
+            brightness,
+            kelvin,
+            pause,
+            save_as,
+        } => {
+            let limits = Config::load()?.limits;
+            let mut brightness: Vec<Brightness> = brightness
+                .into_iter()
+                .map(|brightness| limits.cap_brightness(&device, brightness))
+                .collect();
+            brightness.dedup();
+            let steps = calibration_steps(&brightness, &kelvin);
+            calibrate(&device, &steps, Duration::from_secs(pause), save_as).await
+        }
+        #[cfg(unix)]
+        Command::Audit {
+            device,
+            source,
+            limit,
+        } => {
+            let params = client::AuditParams {
+                device,
+                source,
+                limit: Some(limit),
+            };
+            audit(params).await
+        }
+        #[cfg(unix)]
+        Command::Meeting(command) => meeting(command).await,
+    }
+}
+
+/// Parse a duration made of hours, minutes and seconds, e.g. `1h30m`
+#[cfg(unix)]
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("Unknown unit {c:?}, expected h, m or s")),
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("Missing number before {c:?}"))?;
+        seconds += value * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("Missing unit after {number}, e.g. {number}m"));
+    }
+    if seconds == 0 {
+        return Err("The duration must not be empty".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+#[cfg(unix)]
+async fn meeting(command: MeetingCommand) -> anyhow::Result<()> {
+    let mut client = client::Client::connect_default().await?;
+    let status = match command {
+        MeetingCommand::Start { duration } => client.start_meeting(duration).await?,
+        MeetingCommand::Stop => client.stop_meeting().await?,
+        MeetingCommand::Status => client.meeting_status().await?,
+    };
+    match (status.active, status.ends_at) {
+        (false, _) => println!("No meeting"),
+        (true, Some(ends_at)) => println!(
+            "In a meeting until {}",
+            ends_at.with_timezone(&chrono::Local).format("%H:%M")
+        ),
+        (true, None) => println!("In a meeting until `meeting stop`"),
+    }
+    Ok(())
+}
+
+/// Edit the schedules of the config file, the daemon reloads them on change
+fn schedule(command: ScheduleCommand) -> anyhow::Result<()> {
+    let mut config = Config::load()?;
+    match command {
+        ScheduleCommand::List => {
+            for schedule in &config.schedules {
+                let when = match (&schedule.cron, &schedule.at) {
+                    (Some(cron), _) => cron.clone(),
+                    (None, Some(at)) => at.to_rfc3339(),
+                    (None, None) => "never".to_string(),
+                };
+                let devices = if schedule.devices.is_empty() {
+                    "all".to_string()
+                } else {
+                    schedule.devices.join(", ")
+                };
+                println!(
+                    "{}\t{when}\t{devices}\t{}",
+                    schedule.name,
+                    serde_json::to_string(&schedule.action)?
+                );
+            }
+            return Ok(());
+        }
+        ScheduleCommand::Add(args) => {
+            if config.schedules.iter().any(|s| s.name == args.name) {
+                let message = format!("Schedule {} already exists", args.name);
+                return Err(ValidationError(message).into());
+            }
+            if let Some(cron) = &args.cron {
+                croner::Cron::new(cron)
+                    .parse()
+                    .map_err(|err| ValidationError(format!("Invalid cron expression: {err}")))?;
+            }
+            let action = match (args.power, args.preset, args.scene, args.duration) {
+                (Some(power), _, _, _) => ScheduleAction::Power(power),
+                (_, Some(preset), _, _) => {
+                    if !config.presets.contains_key(&preset) {
+                        return Err(NotFound {
+                            kind: "Preset",
+                            name: preset,
+                        }
+                        .into());
+                    }
+                    ScheduleAction::Preset(preset)
+                }
+                (_, _, Some(scene), _) => {
+                    scene::Scene::load(&scene)?.validate(&config)?;
+                    ScheduleAction::Scene(scene)
+                }
+                (_, _, _, Some(duration)) => ScheduleAction::Fade(Fade {
+                    target: LightUpdate {
+                        brightness: args.fade_brightness,
+                        temperature: args.fade_temperature,
+                        ..Default::default()
+                    },
+                    duration,
+                }),
+                _ => {
+                    let message = "An action is required: --power, --preset, --scene or --fade-*";
+                    return Err(ValidationError(message.to_string()).into());
+                }
+            };
+            config.schedules.push(Schedule {
+                name: args.name,
+                cron: args.cron,
+                at: args.at,
+                devices: args.devices,
+                action,
+            });
+        }
+        ScheduleCommand::Remove { name } => {
+            let len = config.schedules.len();
+            config.schedules.retain(|schedule| schedule.name != name);
+            if config.schedules.len() == len {
+                return Err(NotFound {
+                    kind: "Schedule",
+                    name,
+                }
+                .into());
+            }
+        }
+    }
+    config.save()?;
+    Ok(())
+}
+
+async fn scene(command: SceneCommand) -> anyhow::Result<()> {
+    match command {
+        SceneCommand::List => {
+            for name in scene::Scene::list()? {
+                println!("{name}");
+            }
+        }
+        SceneCommand::Check { name } => {
+            let config = Config::load()?;
+            let scene = scene::Scene::load(&name)?;
+            let all = "<all lights>".to_string();
+            for step in scene.steps(&config, std::slice::from_ref(&all))? {
+                println!(
+                    "{}\t{}\tdelay {}s\tfade {}s",
+                    step.device,
+                    serde_json::to_string(&step.update)?,
+                    step.delay.as_secs_f64(),
+                    step.fade.as_secs_f64()
+                );
+            }
+        }
+        SceneCommand::Play { name } => play_scene(&scene::Scene::load(&name)?).await?,
+    }
+    Ok(())
+}
+
+/// Play a scene on the lights found on the network
+async fn play_scene(scene: &scene::Scene) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let devices = avahi::find_elgato_devices().await?;
+    let names: Vec<String> = devices.iter().map(|device| device.name.clone()).collect();
+    let steps = scene.steps(&config, &names)?;
+    scene::play(&steps, devices.as_slice()).await?;
+    Ok(())
+}
+
+/// Poll the lights every `interval` and write their changes to `file`, replayed by `play`
+async fn record(
+    file: &Path,
+    names: &[String],
+    interval: Duration,
+    duration: Option<Duration>,
+) -> anyhow::Result<()> {
+    let found = avahi::find_elgato_devices().await?;
+    let devices = if names.is_empty() {
+        found
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                found
+                    .iter()
+                    .find(|device| device.name.eq_ignore_ascii_case(name))
+                    .cloned()
+                    .ok_or_else(|| NotFound {
+                        kind: "Light",
+                        name: name.clone(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    if devices.is_empty() {
+        anyhow::bail!("No light found on the network");
+    }
+
+    let stop = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => {
+                if let Err(err) = tokio::signal::ctrl_c().await {
+                    log::error!("Failed to wait for Ctrl-C: {err}");
+                }
+            }
+        }
+    };
+    tokio::pin!(stop);
+    eprintln!("Recording {} lights, Ctrl-C to stop", devices.len());
+
+    let mut recording = scene::Recording::default();
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let start = tokio::time::Instant::now();
+    loop {
+        tokio::select! {
+            () = &mut stop => break,
+            _ = ticks.tick() => {}
+        }
+        let elapsed = start.elapsed();
+        for (device, status) in get_statuses(devices.clone()).await {
+            let light = match status {
+                Ok(status) => status.lights.into_iter().next(),
+                Err(err) => {
+                    log::warn!("{}: {err:#}", device.name);
+                    continue;
+                }
+            };
+            if let Some(light) = light {
+                if recording.observe(&device.name, &light, elapsed) {
+                    println!(
+                        "{:.1}s\t{}\t{}",
+                        elapsed.as_secs_f64(),
+                        device.name,
+                        serde_json::to_string(&light)?
+                    );
+                }
+            }
+        }
+    }
+
+    let changes = recording.len();
+    recording.into_scene(None).save_to(file)?;
+    println!("Saved {changes} changes to {}", file.display());
+    Ok(())
+}
+
+async fn settings(command: SettingsCommand) -> anyhow::Result<()> {
+    match command {
+        SettingsCommand::Backup { device, file } => {
+            let url = resolve_device(&device).await?;
+            let info = get_accessory_info(url.clone()).await?;
+            let backup = SettingsBackup {
+                product_name: info.product_name,
+                display_name: info.display_name,
+                settings: get_settings(url).await?,
+            };
+            std::fs::write(&file, serde_json::to_string_pretty(&backup)?)?;
+            println!("Saved the settings of {device} to {}", file.display());
+        }
+        SettingsCommand::Apply { file, to } => {
+            let backup: SettingsBackup = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            if let [device] = to.as_slice() {
+                return apply_settings(&backup, device, true).await;
+            }
+            let mut failed = 0;
+            for device in &to {
+                // Several lights with the same name could not be told apart
+                if let Err(err) = apply_settings(&backup, device, false).await {
+                    eprintln!("Error: {device}: {err:?}");
+                    failed += 1;
+                }
+            }
+            if failed > 0 {
+                return Err(PartialFailure {
+                    failed,
+                    total: to.len(),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn apply_settings(
+    backup: &SettingsBackup,
+    device: &str,
+    display_name: bool,
+) -> anyhow::Result<()> {
+    let url = resolve_device(device).await?;
+    let info = get_accessory_info(url.clone()).await?;
+    if info.product_name != backup.product_name {
+        log::warn!(
+            "{device} is a {}, the backup comes from a {}",
+            info.product_name,
+            backup.product_name
+        );
+    }
+    // The light stores the settings before answering
+    let options = RequestOptions::with_timeout(SETTINGS_WRITE_TIMEOUT);
+    set_settings_with(url.clone(), &backup.settings, &options).await?;
+    if display_name && !backup.display_name.is_empty() {
+        set_display_name_with(url, &backup.display_name, &options).await?;
+    }
+    println!("Applied the settings to {device}");
+    Ok(())
+}
+
+/// Every brightness at each color temperature, the camera white balance being the slowest to
+/// settle
+fn calibration_steps(brightness: &[Brightness], kelvin: &[Kelvin]) -> Vec<(Brightness, Kelvin)> {
+    kelvin
+        .iter()
+        .flat_map(|kelvin| brightness.iter().map(|brightness| (*brightness, *kelvin)))
+        .collect()
+}
+
+/// Hold each step for `pause`, then restore the light, or leave it on the step picked by the
+/// user and save it as the preset `save_as`
+async fn calibrate(
+    device: &str,
+    steps: &[(Brightness, Kelvin)],
+    pause: Duration,
+    save_as: Option<String>,
+) -> anyhow::Result<()> {
+    let url = resolve_device(device).await?;
+    let original = get_status(url.clone()).await?;
+    let step_status = |(brightness, kelvin): (Brightness, Kelvin)| {
+        let mut status = original.clone();
+        status.set(LightIndex::FIRST, |light| {
+            light.power = PowerStatus::On;
+            light.brightness = brightness;
+            if light.temperature.is_some() {
+                light.temperature = Some(Temperature::from(kelvin));
+            }
+        })?;
+        anyhow::Ok(status)
+    };
+
+    for (i, &(brightness, kelvin)) in steps.iter().enumerate() {
+        set_status(url.clone(), step_status((brightness, kelvin))?).await?;
+        println!("{}/{}	{brightness}	{kelvin}", i + 1, steps.len());
+        tokio::time::sleep(pause).await;
+    }
+
+    let picked = match &save_as {
+        Some(name) => pick_step(name, steps.len())?.map(|i| steps[i]),
+        None => None,
+    };
+    let (Some(name), Some((brightness, kelvin))) = (save_as, picked) else {
+        set_status(url, original).await?;
+        println!("Restored {device}");
+        return Ok(());
+    };
+    set_status(url, step_status((brightness, kelvin))?).await?;
+    let mut config = Config::load()?;
+    let preset = LightUpdate {
+        brightness: Some(brightness),
+        kelvin: Some(kelvin),
+        ..Default::default()
+    };
+    config.presets.insert(name.clone(), preset);
+    config.save()?;
+    println!("Saved {brightness} at {kelvin} as the preset {name}");
+    Ok(())
+}
+
+/// Index of the step the user picks to save as the preset `name`, `None` to skip
+fn pick_step(name: &str, count: usize) -> anyhow::Result<Option<usize>> {
+    eprint!("Step to save as the preset {name}, from 1 to {count}, empty to skip: ");
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    match line.parse::<usize>() {
+        Ok(step @ 1..) if step <= count => Ok(Some(step - 1)),
+        _ => Err(ValidationError(format!("Invalid step {line}, expected 1 to {count}")).into()),
+    }
+}
+
+/// URL of a light given as `host:port`, as a base URL or by its name on the network
+async fn resolve_device(device: &str) -> anyhow::Result<Url> {
+    if let Ok(addr) = device.parse::<std::net::SocketAddr>() {
+        return Ok(Url::parse(&format!("http://{addr}"))?);
+    }
+    if device.starts_with("http://") || device.starts_with("https://") {
+        return Ok(Url::parse(device)?);
+    }
+    avahi::find_elgato_devices()
+        .await?
+        .into_iter()
+        .find(|found| found.name.eq_ignore_ascii_case(device))
+        .map(|found| found.url)
+        .ok_or_else(|| {
+            NotFound {
+                kind: "Light",
+                name: device.to_string(),
+            }
+            .into()
+        })
+}
+
+async fn stats(days: u32) -> anyhow::Result<()> {
+    let now = chrono::Utc::now();
+    let since = now - chrono::Duration::days(i64::from(days));
+    let records = load_history(&history_path()?)?;
+    let summary = summarize(&records, since, now, &chrono::Local);
+    println!("Last {days} day(s)");
+    if summary.devices.is_empty() {
+        println!("No history, is the daemon running with history enabled?");
+    }
+    for (device, usage) in &summary.devices {
+        let on_time: std::time::Duration = usage.on_time.values().sum();
+        let brightness = usage
+            .average_brightness
+            .map_or("-".to_string(), |brightness| format!("{brightness:.0}%"));
+        println!(
+            "{device}\ton {}\taverage brightness {brightness}",
+            format_duration(on_time)
+        );
+        for (day, on_time) in &usage.on_time {
+            println!("  {day}\t{}", format_duration(*on_time));
+        }
+    }
+    if !summary.presets.is_empty() {
+        println!("Most used presets");
+        for (preset, count) in summary.presets.iter().take(5) {
+            println!("  {preset}\t{count}");
+        }
+    }
+
+    #[cfg(unix)]
+    power_stats().await;
+    Ok(())
+}
+
+fn format_duration(duration: std::time::Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+/// Print the last changes made through the daemon
+#[cfg(unix)]
+async fn audit(params: client::AuditParams) -> anyhow::Result<()> {
+    let records = client::Client::connect_default()
+        .await?
+        .audit(&params)
+        .await?;
+    if records.is_empty() {
+        println!("No change recorded");
+    }
+    for record in records {
+        let time = record
+            .time
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S");
+        let (change, source, actor) = match &record.event {
+            HistoryEvent::State {
+                status,
+                source,
+                actor,
+            } => (
+                match status.temperature {
+                    Some(temperature) => format!(
+                        "{} brightness {} temperature {}",
+                        status.power, status.brightness.0, temperature.0
+                    ),
+                    None => format!("{} brightness {}", status.power, status.brightness.0),
+                },
+                source,
+                actor,
+            ),
+            HistoryEvent::Preset {
+                preset,
+                source,
+                actor,
+            } => (format!("preset {preset}"), source, actor),
+        };
+        let by = actor
+            .as_ref()
+            .map_or(source.clone(), |actor| format!("{source} ({actor})"));
+        println!("{time}\t{}\t{change}\t{by}", record.device);
+    }
+    Ok(())
+}
+
+/// Print the estimated power usage if the daemon is running
+#[cfg(unix)]
+async fn power_stats() {
+    let stats = match client::Client::connect_default().await {
+        Ok(mut client) => client.stats().await,
+        Err(err) => Err(err),
+    };
+    let stats = match stats {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("No power usage, failed to reach the daemon: {err}");
+            return;
+        }
+    };
+    println!("Power usage");
+    let mut total = 0.0;
+    for stat in stats {
+        let product = stat.product.as_deref().unwrap_or("unknown product");
+        match (stat.watts, stat.watt_hours, stat.since) {
+            (Some(watts), Some(watt_hours), Some(since)) => {
+                total += watt_hours;
+                println!(
+                    "{}\t{product}\t{watts:.1} W\t{watt_hours:.2} Wh since {}",
+                    stat.device,
+                    since.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+                );
+            }
+            _ => println!("{}\t{product}\tno estimate", stat.device),
+        }
+    }
+    println!("Total\t{total:.2} Wh");
+}
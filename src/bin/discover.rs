@@ -1,6 +1,35 @@
+use clap::{Parser, Subcommand};
+use elgato_keylight::{diagnostics::UserError, generate::Generate, logging::LogArgs};
+
+/// List the Elgato lights found on the network
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Write the man pages or print the completion scripts
+    #[command(subcommand)]
+    Generate(Generate),
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let devices = elgato_keylight::avahi::find_elgato_devices().await?;
+async fn main() -> miette::Result<()> {
+    let args = Args::parse();
+    if let Some(Commands::Generate(generate)) = &args.command {
+        return generate
+            .run::<Args>(env!("CARGO_BIN_NAME"))
+            .map_err(|err| UserError::new(&err).into());
+    }
+    args.log.init();
+    let devices = elgato_keylight::avahi::find_elgato_devices()
+        .await
+        .map_err(|err| UserError::new(&err))?;
     for device in devices {
         println!("{device}")
     }
@@ -1,12 +1,34 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use eframe::egui::{self, Color32, Id, PopupCloseBehavior, Ui};
+use clap::{Parser, Subcommand};
+use eframe::egui::{self, Color32, Id, Key, PopupCloseBehavior, Ui};
 use elgato_keylight::{
-    avahi::{find_elgato_devices, spawn_avahi_daemon, AvahiState, Device},
-    get_status, set_status, Brightness, DeviceStatus, KeyLightStatus, PowerStatus, Temperature,
+    avahi::{
+        find_elgato_devices_with, spawn_avahi_daemon, AvahiState, Backend, Device,
+        ELGATO_SERVICE_ID,
+    },
+    crash,
+    diagnostics::UserError,
+    generate::Generate,
+    get_statuses,
+    logging::LogArgs,
+    ping, redact_url,
+    scene::{self, Scene},
+    AccessoryInfo, AccessoryInfoCache, Brightness, CachedStatus, Config, Delivery,
+    DeviceCapabilities, DeviceStatus, KeyLightStatus, PowerStatus, StatusCache, Temperature,
 };
 use log::{error, info};
+use strum::IntoEnumIterator as _;
 use tokio::runtime::Runtime;
+use url::Url;
+
+#[cfg(unix)]
+use elgato_keylight::instance;
 
 #[cfg(feature = "tray-icon")]
 use {
@@ -18,24 +40,131 @@ use {
 /// Identifier for the popup error
 const ERROR_POPUP_ID: &str = "error-popup";
 
+/// Time without keyboard input after which a keyboard adjustment is sent to the device
+const KEYBOARD_COALESCE_DELAY: Duration = Duration::from_millis(300);
+
 #[cfg(feature = "tray-icon")]
 const OPEN_MENU_ITEM_ID: &str = "open-menu-item";
 
 #[cfg(feature = "tray-icon")]
 const EXIT_MENU_ITEM_ID: &str = "exit-menu-item";
 
+#[cfg(feature = "tray-icon")]
+const TOGGLE_MENU_ITEM_ID: &str = "toggle-menu-item";
+
+/// Interval between the attempts to reach a device that went offline
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Port of the API of the lights, for the addresses entered without one
+const DEFAULT_PORT: u16 = 9123;
+
+/// Range of the UI scale factor
+const UI_SCALE_RANGE: RangeInclusive<f32> = 0.5..=3.0;
+
+/// Elgato Keylight controller GUI
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// UI scale factor, overrides the scale from the settings
+    #[arg(long, value_parser = parse_scale)]
+    scale: Option<f32>,
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Write the man pages or print the completion scripts
+    #[command(subcommand)]
+    Generate(Generate),
+}
+
+fn parse_scale(s: &str) -> Result<f32, String> {
+    let scale: f32 = s.parse().map_err(|e| format!("{e}"))?;
+    if !UI_SCALE_RANGE.contains(&scale) {
+        return Err(format!(
+            "Outside range [{}, {}]",
+            UI_SCALE_RANGE.start(),
+            UI_SCALE_RANGE.end()
+        ));
+    }
+    Ok(scale)
+}
+
 fn main() -> eframe::Result {
     #[cfg(not(target_os = "linux"))]
     panic!("Only Linux is supported");
 
-    // RUST_LOG=debug cargo run
-    env_logger::init();
-
     #[cfg(feature = "tray-icon")]
     let is_window_opened = Arc::new(AtomicBool::new(true));
     #[cfg(feature = "tray-icon")]
     let stop_signal = Arc::new(AtomicBool::new(false));
 
+    let args = Args::parse();
+    if let Some(Commands::Generate(generate)) = &args.command {
+        if let Err(err) = generate.run::<Args>(env!("CARGO_BIN_NAME")) {
+            eprintln!("Failed to generate: {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    args.log.init();
+
+    // Context of the open window, shown again when the GUI is launched a second time
+    let window: Arc<RwLock<Option<egui::Context>>> = Arc::default();
+    #[cfg(unix)]
+    {
+        let window = Arc::clone(&window);
+        #[cfg(feature = "tray-icon")]
+        let is_window_opened = Arc::clone(&is_window_opened);
+        let show = move || {
+            info!("Launched again, showing the window");
+            #[cfg(feature = "tray-icon")]
+            is_window_opened.store(true, Ordering::Release);
+            if let Some(ctx) = window.read().ok().as_deref().and_then(Option::as_ref) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
+        };
+        if show_running_instance(show) {
+            return Ok(());
+        }
+    }
+
+    let mut config = Config::load().unwrap_or_else(|err| {
+        error!("Failed to load config: {err}");
+        Config::default()
+    });
+    // A hand-edited scale out of the range of the slider would make the window unusable
+    if let Some(scale) = &mut config.gui.scale {
+        *scale = if scale.is_finite() {
+            scale.clamp(*UI_SCALE_RANGE.start(), *UI_SCALE_RANGE.end())
+        } else {
+            1.0
+        };
+    }
+    if let Err(err) = elgato_keylight::set_network_config(&config.network) {
+        error!("Invalid network config: {err}");
+    }
+
+    let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+    {
+        let avahi = Arc::clone(&avahi);
+        crash::install_panic_hook(move || {
+            avahi
+                .try_read()
+                .map(|avahi| avahi.devices.clone())
+                .unwrap_or_default()
+        });
+    }
+
+    let runtime = Arc::new(Runtime::new().expect("Unable to create runtime"));
+
+    #[cfg(feature = "tray-icon")]
+    let last_device = Arc::new(RwLock::new(None));
+
     // Since egui uses winit under the hood and doesn't use gtk on Linux, and we need gtk for
     // the tray icon to show up, we need to spawn a thread
     // where we initialize gtk and create the tray_icon
@@ -43,9 +172,17 @@ fn main() -> eframe::Result {
     {
         let is_window_opened = Arc::clone(&is_window_opened);
         let stop_signal = Arc::clone(&stop_signal);
+        let runtime = Arc::clone(&runtime);
+        let avahi = Arc::clone(&avahi);
+        let last_device = Arc::clone(&last_device);
+        let window = Arc::clone(&window);
+        let config = config.clone();
 
         std::thread::spawn(move || {
-            gtk::init().expect("Couldn't start gtk context");
+            if let Err(err) = gtk::init() {
+                error!("Couldn't start gtk context, no tray icon: {err}");
+                return;
+            }
 
             let open_menu_item = MenuItem::with_id(
                 OPEN_MENU_ITEM_ID,
@@ -57,86 +194,137 @@ fn main() -> eframe::Result {
             let tray_menu = tray_icon::menu::Menu::with_id_and_items(
                 MenuId::new("main"),
                 &[
+                    // libappindicator doesn't report clicks on the icon itself,
+                    // so toggling is done from the menu.
+                    &MenuItem::with_id(TOGGLE_MENU_ITEM_ID, "toggle", true, None),
                     &open_menu_item,
                     &MenuItem::with_id(EXIT_MENU_ITEM_ID, "exit", true, None),
                 ],
-            )
-            .unwrap();
+            );
+            let tray_menu = match tray_menu {
+                Ok(tray_menu) => tray_menu,
+                Err(err) => {
+                    error!("Couldn't create the tray menu: {err}");
+                    return;
+                }
+            };
 
-            let tray_icon_icon = load_icon();
+            let tray_icon_icon = match load_icon() {
+                Ok(icon) => icon,
+                Err(err) => {
+                    error!("Couldn't load the tray icon: {err}");
+                    return;
+                }
+            };
 
-            let _tray_icon = tray_icon::TrayIconBuilder::new()
+            let tray_icon = tray_icon::TrayIconBuilder::new()
                 .with_menu(Box::new(tray_menu))
                 .with_icon(tray_icon_icon)
                 .with_tooltip("Elgato Keylight Controller")
                 .with_title("Elgato Keylight Controller")
-                .build()
-                .expect("Couldn't start tray icon");
+                .build();
+            let _tray_icon = match tray_icon {
+                Ok(tray_icon) => tray_icon,
+                Err(err) => {
+                    error!("Couldn't start tray icon: {err}");
+                    return;
+                }
+            };
+
+            let toggle = || {
+                let device = tray_device(&config, &avahi, &last_device);
+                toggle_tray_device(&runtime, device, &config.limits)
+            };
 
             while gtk::main_iteration() {
                 let main_window_opened = is_window_opened.load(Ordering::Acquire);
                 open_menu_item.set_enabled(!main_window_opened);
-                if !main_window_opened {
-                    if let Ok(event) = MenuEvent::receiver().try_recv() {
-                        debug!("Menu event: {:?}", event);
-                        if event.id() == OPEN_MENU_ITEM_ID {
-                            is_window_opened.store(true, Ordering::Relaxed);
-                        }
-                        if event.id() == EXIT_MENU_ITEM_ID {
-                            stop_signal.store(true, Ordering::Relaxed);
+                if main_window_opened {
+                    // Handled by the window, which only updates when repainted
+                    if !MenuEvent::receiver().is_empty() {
+                        if let Some(ctx) = window.read().ok().as_deref().and_then(Option::as_ref) {
+                            ctx.request_repaint();
                         }
                     }
+                } else if let Ok(event) = MenuEvent::receiver().try_recv() {
+                    debug!("Menu event: {:?}", event);
+                    if event.id() == TOGGLE_MENU_ITEM_ID {
+                        toggle();
+                    }
+                    if event.id() == OPEN_MENU_ITEM_ID {
+                        is_window_opened.store(true, Ordering::Relaxed);
+                    }
+                    if event.id() == EXIT_MENU_ITEM_ID {
+                        stop_signal.store(true, Ordering::Relaxed);
+                    }
                 }
             }
         });
     }
 
-    let runtime = Arc::new(Runtime::new().expect("Unable to create runtime"));
-
-    let devices = get_available_devices(&runtime).unwrap_or_else(|err| {
-        error!("Failed to get available devices: {err}");
-        vec![]
-    });
-    let opt_device = devices.first().cloned();
-
-    let avahi = Arc::new(RwLock::new(AvahiState {
-        devices: devices.clone(),
+    // Started after the tray icon, which shows up without waiting for the lights
+    let discovery = Arc::new(RwLock::new(Discovery {
+        running: true,
+        ..Discovery::default()
     }));
+    spawn_discovery(&runtime, Arc::clone(&avahi), Arc::clone(&discovery));
 
     let _ = spawn_avahi_daemon(Arc::clone(&avahi));
 
+    let scale = args.scale.or(config.gui.scale).unwrap_or(1.0);
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([320.0, 240.0])
+            .with_inner_size([320.0 * scale, 240.0 * scale])
             .with_close_button(true)
             .with_resizable(true),
         run_and_return: true,
         ..Default::default()
     };
 
+    let scenes = Scene::list().unwrap_or_else(|err| {
+        error!("Failed to list scenes: {err}");
+        vec![]
+    });
+
     #[cfg(feature = "tray-icon")]
-    let mut app = MyApp {
+    let app = MyApp {
         is_window_open: Arc::clone(&is_window_opened),
         stop_signal: Arc::clone(&stop_signal),
+        last_device,
         runtime,
+        cache: Arc::new(StatusCache::new()),
+        infos: Arc::default(),
         avahi,
-        devices,
+        discovery,
+        diagnostics: Arc::default(),
+        manual_address: String::new(),
+        devices: vec![],
         error: None,
         state: AppState::default(),
+        pending_update: None,
+        config: config.clone(),
+        scale,
+        scenes: scenes.clone(),
     };
     #[cfg(not(feature = "tray-icon"))]
-    let mut app = MyApp {
+    let app = MyApp {
         runtime,
+        cache: Arc::new(StatusCache::new()),
+        infos: Arc::default(),
         avahi,
-        devices,
+        discovery,
+        diagnostics: Arc::default(),
+        manual_address: String::new(),
+        devices: vec![],
         error: None,
         state: AppState::default(),
+        pending_update: None,
+        config: config.clone(),
+        scale,
+        scenes: scenes.clone(),
     };
 
-    if let Some(device) = opt_device {
-        app.select_device(None, device.clone());
-    }
-
     #[cfg(feature = "tray-icon")]
     {
         while !stop_signal.load(Ordering::Acquire) {
@@ -144,10 +332,15 @@ fn main() -> eframe::Result {
 
             if is_window_opened.load(Ordering::Acquire) {
                 let app = app.clone();
+                let window = Arc::clone(&window);
                 eframe::run_native(
                     "Elgato Key Light Controller",
                     options.clone(),
-                    Box::new(|_cc| Ok(Box::new(app))),
+                    Box::new(move |cc| {
+                        cc.egui_ctx.set_zoom_factor(app.scale);
+                        set_window(&window, &cc.egui_ctx);
+                        Ok(Box::new(app))
+                    }),
                 )
                 .unwrap()
             }
@@ -165,7 +358,11 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "Elgato Key Light Controller",
         options.clone(),
-        Box::new(|_cc| Ok(Box::new(app))),
+        Box::new(move |cc| {
+            cc.egui_ctx.set_zoom_factor(app.scale);
+            set_window(&window, &cc.egui_ctx);
+            Ok(Box::new(app))
+        }),
     )
 }
 
@@ -177,16 +374,65 @@ struct MyApp {
     /// Stop app
     #[cfg(feature = "tray-icon")]
     stop_signal: Arc<AtomicBool>,
+    /// Last selected device, toggled from the tray icon
+    #[cfg(feature = "tray-icon")]
+    last_device: Arc<RwLock<Option<Device>>>,
     /// `tokio` runtime to execute asynchronous task
     runtime: Arc<Runtime>,
+    /// Last known state of the devices, shown through Wi-Fi dropouts
+    cache: Arc<StatusCache>,
+    /// Accessory info of the devices, not fetched again on every selection
+    infos: Arc<AccessoryInfoCache>,
     /// Asynchronous avahi state of devices
     avahi: Arc<RwLock<AvahiState>>,
+    /// Startup discovery, filling the avahi state as the devices answer
+    discovery: Arc<RwLock<Discovery>>,
+    /// Diagnostics of the panel shown when no device is found
+    diagnostics: Arc<RwLock<Diagnostics>>,
+    /// Address typed in the manual entry of the panel shown when no device is found
+    manual_address: String,
     /// Current list of available devices
     devices: Vec<Device>,
     /// Error messageCLI & device discover
     error: Option<String>,
     /// Application state
     state: AppState,
+    /// Keyboard adjustment waiting to be sent, and when it was last modified
+    pending_update: Option<(PendingUpdate, Instant)>,
+    /// User configuration, updated from the settings panel
+    config: Config,
+    /// UI scale factor applied on top of the native pixels-per-point
+    scale: f32,
+    /// Names of the scenes of the scenes directory
+    scenes: Vec<String>,
+}
+
+/// Progress of the discovery run at startup
+#[derive(Debug, Default)]
+struct Discovery {
+    /// Still browsing or pinging devices
+    running: bool,
+    /// First device that answered, selected once the window shows it
+    first: Option<Device>,
+    /// Names of the devices that didn't answer the ping
+    unreachable: HashSet<String>,
+    /// Power of each device, all read at once when the discovery is done
+    powers: HashMap<String, PowerStatus>,
+}
+
+/// Check of each discovery backend, run from the panel shown when no device is found
+#[derive(Debug, Default)]
+struct Diagnostics {
+    running: bool,
+    /// Outcome of each backend, once they all ran
+    results: Option<Vec<String>>,
+}
+
+/// A slider value changed from the keyboard that has not been sent yet
+#[derive(Debug, Clone, Copy)]
+enum PendingUpdate {
+    Brightness(u8),
+    Temperature(u16),
 }
 
 #[derive(Debug, Default, Clone)]
@@ -198,7 +444,14 @@ enum AppState {
         device: Device,
         power_status: PowerStatus,
         brightness: Brightness,
-        temperature: Temperature,
+        /// Unset for a light in color mode
+        temperature: Option<Temperature>,
+        /// Static device information, if the device reported it
+        info: Option<Box<AccessoryInfo>>,
+        /// Why the device is unreachable, the last known state is shown meanwhile
+        offline: Option<String>,
+        /// Next attempt to reach the device while it is offline
+        retry_at: Instant,
     },
 }
 
@@ -212,9 +465,32 @@ impl eframe::App for MyApp {
             }
         });
 
+        // The tray thread only handles the menu while the window is closed
         #[cfg(feature = "tray-icon")]
         if let Ok(event) = MenuEvent::receiver().try_recv() {
             debug!("Menu event: {:?}", event);
+            if event.id() == TOGGLE_MENU_ITEM_ID {
+                let device = tray_device(&self.config, &self.avahi, &self.last_device);
+                let toggled =
+                    toggle_tray_device(&self.runtime, device.clone(), &self.config.limits);
+                if let (
+                    Some(toggled),
+                    AppState::Selected {
+                        device: selected,
+                        power_status,
+                        ..
+                    },
+                ) = (toggled, &mut self.state)
+                {
+                    if device.as_ref() == Some(&*selected) {
+                        *power_status = toggled;
+                    }
+                }
+            }
+            if event.id() == OPEN_MENU_ITEM_ID {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+            }
             if event.id() == EXIT_MENU_ITEM_ID {
                 self.stop_signal.store(true, Ordering::Release);
                 ctx.send_viewport_cmd(egui::ViewportCommand::Close);
@@ -236,8 +512,29 @@ impl eframe::App for MyApp {
         if let Ok(rlock) = self.avahi.try_read() {
             self.devices = rlock.devices.clone();
         }
+        let mut unreachable = HashSet::new();
+        let mut powers = HashMap::new();
+        let mut discovering = false;
+        let mut first = None;
+        if let Ok(mut discovery) = self.discovery.try_write() {
+            discovering = discovery.running;
+            first = discovery.first.take();
+            unreachable.clone_from(&discovery.unreachable);
+            powers.clone_from(&discovery.powers);
+        }
+        if discovering {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        if let Some(device) = first {
+            if matches!(self.state, AppState::NotSelected) {
+                self.select_device(None, device);
+            }
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
+            self.flush_pending_update(ui);
+            self.reconnect(ui);
+
             let response = ui.horizontal(|ui| {
                 ui.heading("Elgato Key Light Controller");
                 ui.add(egui::Image::new(elgato_icon))
@@ -253,15 +550,26 @@ impl eframe::App for MyApp {
                     ui.set_min_width(300.0);
                     ui.heading("Error");
                     ui.separator();
-                    ui.label(self.error.clone().unwrap_or_else(|| "No error".to_string()));
+                    let error = self.error.clone().unwrap_or_else(|| "No error".to_string());
+                    ui.label(egui::RichText::new(error).monospace());
                 },
             );
 
             ui.separator();
             ui.add_space(10.0);
 
+            if let AppState::Selected {
+                device,
+                power_status,
+                ..
+            } = &self.state
+            {
+                powers.insert(device.name.clone(), *power_status);
+            }
             let mut device_selected = if let AppState::Selected { device, .. } = &self.state {
                 device.name.clone()
+            } else if discovering {
+                "Searching for devices…".to_string()
             } else {
                 "No device found".to_string()
             };
@@ -271,11 +579,14 @@ impl eframe::App for MyApp {
                     self.devices
                         .iter()
                         .map(|device| {
-                            ui.selectable_value(
-                                &mut device_selected,
-                                device.name.clone(),
-                                device.name.clone(),
-                            )
+                            let label = if unreachable.contains(&device.name) {
+                                format!("{} (unreachable)", device.name)
+                            } else if let Some(power) = powers.get(&device.name) {
+                                format!("{} ({power})", device.name)
+                            } else {
+                                device.name.clone()
+                            };
+                            ui.selectable_value(&mut device_selected, device.name.clone(), label)
                         })
                         .reduce(|acc, e| acc.union(e))
                 });
@@ -290,16 +601,36 @@ impl eframe::App for MyApp {
             ui.add_space(20.0);
 
             match &self.state {
+                AppState::NotSelected if !discovering && self.devices.is_empty() => {
+                    self.onboarding_panel(ui);
+                }
                 AppState::NotSelected => {}
                 AppState::Selected {
+                    device,
                     power_status,
                     brightness,
                     temperature,
+                    info,
+                    offline,
                     ..
                 } => {
+                    let info = info.clone();
+                    let max_brightness = self.config.limits.device_max_brightness(&device.name);
+                    if let Some(err) = offline {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("Offline, showing the last known state: {err}"),
+                        );
+                    }
                     let power_status = (*power_status).into();
-                    let mut brightness = brightness.0;
-                    let mut temperature = temperature.0;
+                    let mut brightness = match self.pending_update {
+                        Some((PendingUpdate::Brightness(value), _)) => value,
+                        _ => brightness.0,
+                    };
+                    let temperature = match self.pending_update {
+                        Some((PendingUpdate::Temperature(value), _)) => Some(value),
+                        _ => temperature.map(|temperature| temperature.0),
+                    };
 
                     if power_status {
                         let r = ui.add(egui::Button::image(bulb_icon).fill(Color32::YELLOW));
@@ -313,44 +644,259 @@ impl eframe::App for MyApp {
                         }
                     }
 
-                    ui.horizontal(|ui| {
-                        ui.label("Temperature:");
-                        let response = ui.add(
-                            egui::Slider::new(&mut temperature, 143..=344)
-                                .suffix("K")
-                                .clamp_to_range(true)
-                                .trailing_fill(true),
-                        );
-                        if response.drag_stopped() {
-                            self.set_temperature(ui, temperature)
-                        }
-                    });
+                    let capabilities = info
+                        .as_deref()
+                        .map_or_else(
+                            DeviceCapabilities::default,
+                            DeviceCapabilities::from_accessory_info,
+                        )
+                        .with_max_brightness(max_brightness);
+                    if let Some(range) = capabilities.temperature.clone() {
+                        let mut temperature = temperature.unwrap_or(*range.start());
+                        ui.horizontal(|ui| {
+                            ui.label("Temperature:");
+                            let response = ui.add(
+                                egui::Slider::new(&mut temperature, range.clone())
+                                    .custom_formatter(|value, _| {
+                                        Temperature::new_clamped(value as u16).to_string()
+                                    })
+                                    .step_by(1.0)
+                                    .clamp_to_range(true)
+                                    .trailing_fill(true),
+                            );
+                            if response.drag_stopped() {
+                                self.set_temperature(ui, temperature)
+                            } else if response.has_focus() {
+                                let step = page_presses(ui) * self.config.gui.temperature_step().0;
+                                let stepped = Temperature::new_clamped(temperature)
+                                    .offset(step)
+                                    .0
+                                    .clamp(*range.start(), *range.end());
+                                if response.changed() || stepped != temperature {
+                                    self.set_pending_update(
+                                        ui,
+                                        PendingUpdate::Temperature(stepped),
+                                    );
+                                }
+                            }
+                        });
+                    }
 
                     ui.horizontal(|ui| {
                         ui.label("Brightness:");
                         ui.add_space(15.0);
                         let response = ui.add(
-                            egui::Slider::new(&mut brightness, 3..=100)
-                                .suffix("%")
+                            egui::Slider::new(&mut brightness, capabilities.brightness.clone())
+                                .custom_formatter(|value, _| {
+                                    Brightness::new_clamped(value as u8).to_string()
+                                })
+                                .step_by(1.0)
                                 .clamp_to_range(true)
                                 .trailing_fill(true),
                         );
                         if response.drag_stopped() {
                             self.set_brightness(ui, brightness)
+                        } else if response.has_focus() {
+                            let step = page_presses(ui) * self.config.gui.brightness_step().0;
+                            let stepped = Brightness::new_clamped(brightness).offset(step).0.clamp(
+                                *capabilities.brightness.start(),
+                                *capabilities.brightness.end(),
+                            );
+                            if response.changed() || stepped != brightness {
+                                self.set_pending_update(ui, PendingUpdate::Brightness(stepped));
+                            }
                         }
                     });
+
+                    if let Some(info) = info {
+                        ui.add_space(10.0);
+                        device_info_panel(ui, &info);
+                    }
                 }
             }
+
+            if !self.scenes.is_empty() {
+                ui.add_space(10.0);
+                egui::CollapsingHeader::new("Scenes").show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        for name in self.scenes.clone() {
+                            if ui.button(&name).clicked() {
+                                self.play_scene(ui, &name);
+                            }
+                        }
+                    });
+                });
+            }
+
+            ui.add_space(10.0);
+            egui::CollapsingHeader::new("Settings").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("UI scale:");
+                    let response = ui.add(
+                        egui::Slider::new(&mut self.scale, UI_SCALE_RANGE)
+                            .step_by(0.05)
+                            .clamp_to_range(true),
+                    );
+                    if response.drag_stopped() || (response.changed() && !response.dragged()) {
+                        self.set_scale(ui);
+                    }
+                });
+            });
         });
     }
 }
 
 impl MyApp {
-    fn error_popup<E: std::fmt::Display>(&mut self, ui: &Ui, err: E) {
-        self.error = Some(format!("{err}"));
+    fn error_popup<E: Into<anyhow::Error>>(&mut self, ui: &Ui, err: E) {
+        self.error = Some(UserError::from(err.into()).render());
         ui.memory_mut(|mem| mem.toggle_popup(Id::new(ERROR_POPUP_ID)));
     }
 
+    /// Guide shown instead of the empty dropdown when the discovery found no device: what the
+    /// discovery needs, a new scan, a manual address and the diagnostics of the discovery
+    fn onboarding_panel(&mut self, ui: &mut Ui) {
+        ui.heading("No lights found");
+        ui.label(
+            "The lights are found through mDNS. Check that they are plugged in and on the same \
+             network (VLAN) as this computer, that the router or the firewall lets multicast \
+             through (UDP port 5353) and, if installed, that the Avahi daemon is running.",
+        );
+        ui.add_space(10.0);
+
+        if ui.button("Rescan").clicked() {
+            info!("Rescanning");
+            if let Ok(mut discovery) = self.discovery.write() {
+                discovery.running = true;
+                discovery.unreachable.clear();
+                discovery.powers.clear();
+            }
+            spawn_discovery(
+                &self.runtime,
+                Arc::clone(&self.avahi),
+                Arc::clone(&self.discovery),
+            );
+        }
+        ui.add_space(10.0);
+
+        ui.horizontal(|ui| {
+            ui.label("Address:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.manual_address)
+                    .hint_text("192.168.1.100:9123"),
+            );
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+            if ui.button("Connect").clicked() || entered {
+                self.connect_manual_address(ui);
+            }
+        });
+        ui.add_space(10.0);
+
+        let (running, results) = match self.diagnostics.try_read() {
+            Ok(diagnostics) => (diagnostics.running, diagnostics.results.clone()),
+            Err(_) => (true, None),
+        };
+        if running {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Running the diagnostics…");
+            });
+            ui.ctx().request_repaint_after(Duration::from_millis(100));
+        } else if ui.button("Run diagnostics").clicked() {
+            spawn_diagnostics(&self.runtime, Arc::clone(&self.diagnostics));
+        }
+        for result in results.into_iter().flatten() {
+            ui.label(egui::RichText::new(result).monospace());
+        }
+    }
+
+    /// Add the light at the address of the manual entry and select it, once it answers
+    fn connect_manual_address(&mut self, ui: &Ui) {
+        let address = self.manual_address.trim().to_string();
+        let url = match parse_address(&address) {
+            Ok(url) => url,
+            Err(err) => return self.error_popup(ui, err),
+        };
+        if let Err(err) = self.runtime.block_on(ping(url.clone())) {
+            return self.error_popup(ui, err.context(format!("{address} doesn't answer")));
+        }
+        let redacted = redact_url(&url);
+        info!("Light added at {redacted}");
+        let name = match url.password() {
+            Some(_) => redacted.to_string(),
+            None => address,
+        };
+        let device = Device { name, url };
+        if let Ok(mut avahi) = self.avahi.write() {
+            if !avahi.devices.contains(&device) {
+                avahi.devices.push(device.clone());
+            }
+        }
+        self.devices.push(device.clone());
+        self.select_device(Some(ui), device);
+    }
+
+    /// Apply the current UI scale and persist it in the config
+    fn set_scale(&mut self, ui: &Ui) {
+        info!("Setting UI scale to {}", self.scale);
+        ui.ctx().set_zoom_factor(self.scale);
+        self.config.gui.scale = Some(self.scale);
+        if let Err(err) = Config::save_gui_scale(self.scale) {
+            error!("Failed to save config: {err}");
+            self.error_popup(ui, err);
+        }
+    }
+
+    /// Play a scene in the background, the fades can take a while
+    fn play_scene(&mut self, ui: &Ui, name: &str) {
+        let names: Vec<String> = self.devices.iter().map(|d| d.name.clone()).collect();
+        let steps = match Scene::load(name).and_then(|scene| scene.steps(&self.config, &names)) {
+            Ok(steps) => steps,
+            Err(err) => return self.error_popup(ui, err),
+        };
+        info!("Playing scene {name}");
+        let devices = self.devices.clone();
+        self.runtime.spawn(async move {
+            if let Err(err) = scene::play(&steps, devices.as_slice()).await {
+                error!("Scene failed: {err}");
+            }
+        });
+    }
+
+    /// Send the pending keyboard adjustment once the user stopped pressing keys
+    fn flush_pending_update(&mut self, ui: &Ui) {
+        let Some((update, modified_at)) = self.pending_update else {
+            return;
+        };
+
+        let elapsed = modified_at.elapsed();
+        if elapsed < KEYBOARD_COALESCE_DELAY {
+            ui.ctx()
+                .request_repaint_after(KEYBOARD_COALESCE_DELAY - elapsed);
+            return;
+        }
+
+        self.pending_update = None;
+        self.send_update(ui, update);
+    }
+
+    /// Delay sending a keyboard adjustment, a pending one of the other slider is sent right away
+    /// rather than replaced
+    fn set_pending_update(&mut self, ui: &Ui, update: PendingUpdate) {
+        if let Some((pending, _)) = self.pending_update.take() {
+            if std::mem::discriminant(&pending) != std::mem::discriminant(&update) {
+                self.send_update(ui, pending);
+            }
+        }
+        self.pending_update = Some((update, Instant::now()));
+    }
+
+    fn send_update(&mut self, ui: &Ui, update: PendingUpdate) {
+        match update {
+            PendingUpdate::Brightness(brightness) => self.set_brightness(ui, brightness),
+            PendingUpdate::Temperature(temperature) => self.set_temperature(ui, temperature),
+        }
+    }
+
     pub fn select_device(&mut self, ui: Option<&Ui>, new_device: Device) {
         if let AppState::Selected { ref device, .. } = self.state {
             if *device == new_device {
@@ -359,38 +905,108 @@ impl MyApp {
             }
         }
 
-        match self.runtime.block_on(get_status(new_device.url.clone())) {
+        match self
+            .runtime
+            .block_on(self.cache.get_status(new_device.url.clone()))
+        {
             Err(err) => {
                 error!("Get status failed: {err}");
                 if let Some(ui) = ui {
                     self.error_popup(ui, err);
                 }
             }
-            Ok(status) => {
+            Ok(CachedStatus { status, stale }) => {
                 let Some(light) = status.lights.first() else {
                     error!("No light found");
                     return;
                 };
 
+                #[cfg(feature = "tray-icon")]
+                if let Ok(mut last_device) = self.last_device.write() {
+                    *last_device = Some(new_device.clone());
+                }
+
+                let info = match self
+                    .runtime
+                    .block_on(self.infos.get(new_device.url.clone()))
+                {
+                    Ok(info) => Some(Box::new(info)),
+                    Err(err) => {
+                        error!("Get accessory info failed: {err}");
+                        None
+                    }
+                };
+
                 self.state = AppState::Selected {
                     device: new_device,
                     power_status: light.power,
                     brightness: light.brightness,
                     temperature: light.temperature,
+                    info,
+                    offline: stale.map(|stale| stale.error),
+                    retry_at: Instant::now() + RECONNECT_INTERVAL,
                 };
             }
         }
     }
 
-    fn set_status(&mut self, ui: &Ui, new_status: KeyLightStatus) {
+    /// Try to reach the selected device while it is offline, picking up its state once it is back
+    fn reconnect(&mut self, ui: &Ui) {
+        let AppState::Selected {
+            device,
+            power_status,
+            brightness,
+            temperature,
+            offline: offline @ Some(_),
+            retry_at,
+            ..
+        } = &mut self.state
+        else {
+            return;
+        };
+        let now = Instant::now();
+        if now < *retry_at {
+            ui.ctx().request_repaint_after(*retry_at - now);
+            return;
+        }
+        *retry_at = now + RECONNECT_INTERVAL;
+        ui.ctx().request_repaint_after(RECONNECT_INTERVAL);
+
+        // Cheaper than reading the state while the light is still away
+        if self.runtime.block_on(ping(device.url.clone())).is_err() {
+            return;
+        }
+        let Ok(CachedStatus { status, stale }) = self
+            .runtime
+            .block_on(self.cache.get_status(device.url.clone()))
+        else {
+            return;
+        };
+        if let Some(light) = status.lights.first() {
+            if stale.is_none() {
+                info!("{} is back", device.name);
+            }
+            *power_status = light.power;
+            *brightness = light.brightness;
+            *temperature = light.temperature;
+            *offline = stale.map(|stale| stale.error);
+        }
+    }
+
+    fn set_status(&mut self, ui: &Ui, mut new_status: KeyLightStatus) {
         if let AppState::Selected {
             device,
             power_status,
             brightness,
             temperature,
+            offline,
             ..
         } = &mut self.state
         {
+            new_status.brightness = self
+                .config
+                .limits
+                .cap_brightness(&device.name, new_status.brightness);
             let payload = DeviceStatus {
                 number_of_lights: 1,
                 lights: vec![new_status.clone()],
@@ -398,12 +1014,22 @@ impl MyApp {
 
             match self
                 .runtime
-                .block_on(set_status(device.url.clone(), payload))
+                .block_on(self.cache.set_status(device.url.clone(), payload))
             {
-                Ok(_) => {
+                Ok(delivery) => {
+                    *offline = match delivery {
+                        Delivery::Sent => None,
+                        Delivery::Queued => Some(
+                            offline
+                                .take()
+                                .unwrap_or_else(|| "the change is sent once it is back".into()),
+                        ),
+                    };
                     info!(
-                        "Setting new status: power={}, brightness={}, temperature={}",
-                        power_status, brightness.0, temperature.0
+                        "Setting new status: power={}, brightness={}, temperature={:?}",
+                        power_status,
+                        brightness.0,
+                        temperature.map(|temperature| temperature.0)
                     );
                     // Set new state
                     *power_status = new_status.power;
@@ -426,6 +1052,8 @@ impl MyApp {
                 power,
                 brightness: *brightness,
                 temperature: *temperature,
+                hue: None,
+                saturation: None,
             };
             self.set_status(ui, new_status);
         }
@@ -438,11 +1066,11 @@ impl MyApp {
             ..
         } = &self.state
         {
-            let new_status = KeyLightStatus {
-                power: *power_status,
-                brightness: *brightness,
-                temperature: Temperature::new(temperature).expect("Temperature range [143,344]"),
-            };
+            let new_status = KeyLightStatus::white(
+                *power_status,
+                *brightness,
+                Temperature::new_clamped(temperature),
+            );
             self.set_status(ui, new_status);
         }
     }
@@ -457,19 +1085,218 @@ impl MyApp {
             let new_status = KeyLightStatus {
                 power: *power_status,
                 temperature: *temperature,
-                brightness: Brightness::new(brightness).expect("Brightness range [0, 100]"),
+                brightness: Brightness::new_clamped(brightness),
+                hue: None,
+                saturation: None,
             };
             self.set_status(ui, new_status);
         }
     }
 }
 
-fn get_available_devices(rt: &Runtime) -> anyhow::Result<Vec<Device>> {
-    Ok(rt.block_on(find_elgato_devices())?)
+/// Collapsible panel with the model, serial number and firmware of the device
+fn device_info_panel(ui: &mut Ui, info: &AccessoryInfo) {
+    egui::CollapsingHeader::new("Device info").show(ui, |ui| {
+        egui::Grid::new("device-info").show(ui, |ui| {
+            ui.label("Model:");
+            ui.label(&info.product_name);
+            ui.end_row();
+
+            ui.label("Serial number:");
+            ui.label(&info.serial_number);
+            ui.end_row();
+
+            ui.label("Firmware:");
+            ui.horizontal(|ui| {
+                ui.label(info.firmware_version.to_string());
+                if let Some(latest) = info.firmware_update() {
+                    ui.label(
+                        egui::RichText::new("update available")
+                            .small()
+                            .color(Color32::LIGHT_BLUE),
+                    )
+                    .on_hover_text(format!(
+                        "Firmware {latest} is available, install it from the Elgato Control Center"
+                    ));
+                }
+            });
+            ui.end_row();
+        });
+    });
+}
+
+/// PageUp presses minus PageDown presses, the number of steps to apply to a slider
+fn page_presses(ui: &Ui) -> i32 {
+    let (up, down) = ui.input(|i| (i.num_presses(Key::PageUp), i.num_presses(Key::PageDown)));
+    up as i32 - down as i32
+}
+
+/// Discover the devices in the background, adding them to the dropdown as they answer their ping,
+/// then read the state of all of them at once
+fn spawn_discovery(
+    runtime: &Runtime,
+    avahi: Arc<RwLock<AvahiState>>,
+    discovery: Arc<RwLock<Discovery>>,
+) {
+    runtime.spawn(async move {
+        let result = find_elgato_devices_with(|found| {
+            if let Ok(mut discovery) = discovery.write() {
+                if !found.is_reachable() {
+                    discovery.unreachable.insert(found.device.name.clone());
+                } else if discovery.first.is_none() {
+                    discovery.first = Some(found.device.clone());
+                }
+            }
+            if let Ok(mut avahi) = avahi.write() {
+                if !avahi.devices.contains(&found.device) {
+                    avahi.devices.push(found.device.clone());
+                }
+            }
+        })
+        .await;
+        if let Err(err) = result {
+            error!("Failed to get available devices: {err}");
+        }
+
+        let devices = match avahi.read() {
+            Ok(avahi) => avahi.devices.clone(),
+            Err(_) => vec![],
+        };
+        let statuses = get_statuses(devices).await;
+        if let Ok(mut discovery) = discovery.write() {
+            for (device, status) in statuses {
+                match status {
+                    Ok(status) => {
+                        if let Some(light) = status.lights.first() {
+                            discovery.powers.insert(device.name, light.power);
+                        }
+                    }
+                    Err(err) => {
+                        error!("Get status of `{}` failed: {err}", device.name);
+                        discovery.unreachable.insert(device.name);
+                    }
+                }
+            }
+            discovery.running = false;
+        }
+    });
+}
+
+/// Browse through each discovery backend in the background, reporting what each of them found
+fn spawn_diagnostics(runtime: &Runtime, diagnostics: Arc<RwLock<Diagnostics>>) {
+    if let Ok(mut diagnostics) = diagnostics.write() {
+        diagnostics.running = true;
+    }
+    runtime.spawn(async move {
+        let mut results = Vec::new();
+        for backend in Backend::iter() {
+            let result = match backend.browse(ELGATO_SERVICE_ID).await {
+                Ok(devices) => format!("{backend}: {} lights found", devices.len()),
+                Err(err) => format!("{backend}: {err}"),
+            };
+            info!("Diagnostics: {result}");
+            results.push(result);
+        }
+        if let Ok(mut diagnostics) = diagnostics.write() {
+            diagnostics.running = false;
+            diagnostics.results = Some(results);
+        }
+    });
+}
+
+/// Ask the running instance, if any, to show its window, returns whether there is one. Otherwise
+/// this instance becomes the running one and calls `show` on the next launches.
+#[cfg(unix)]
+fn show_running_instance(show: impl Fn() + Send + 'static) -> bool {
+    match instance::acquire(&instance::socket_path()) {
+        Ok(Some(listener)) => {
+            instance::listen(listener, show);
+            false
+        }
+        Ok(None) => {
+            info!("Already running, showing its window");
+            true
+        }
+        Err(err) => {
+            error!("Failed to check for a running instance: {err}");
+            false
+        }
+    }
+}
+
+/// Keep the context of the window just opened, shown again by [`show_running_instance`]
+fn set_window(window: &RwLock<Option<egui::Context>>, ctx: &egui::Context) {
+    if let Ok(mut window) = window.write() {
+        *window = Some(ctx.clone());
+    }
+}
+
+/// URL of the API of a light given its address, e.g. `192.168.1.100`, `192.168.1.100:9123` or
+/// a base URL like `https://lights.example.com/studio/left`, kept as is
+fn parse_address(address: &str) -> Result<Url, url::ParseError> {
+    if address.contains("://") {
+        return Url::parse(address);
+    }
+    let mut url = Url::parse(&format!("http://{address}"))?;
+    if url.port().is_none() {
+        let _ = url.set_port(Some(DEFAULT_PORT));
+    }
+    Ok(url)
+}
+
+/// Device toggled from the tray icon: the configured toggle device if available,
+/// otherwise the last used one
+#[cfg(feature = "tray-icon")]
+fn tray_device(
+    config: &Config,
+    avahi: &RwLock<AvahiState>,
+    last_device: &RwLock<Option<Device>>,
+) -> Option<Device> {
+    let toggle_device = config.tray.toggle_device.as_ref().and_then(|name| {
+        let avahi = avahi.read().ok()?;
+        avahi.devices.iter().find(|d| &d.name == name).cloned()
+    });
+    toggle_device.or_else(|| last_device.read().ok()?.clone())
+}
+
+/// Toggle the device from the tray, returns its new power status
+#[cfg(feature = "tray-icon")]
+fn toggle_tray_device(
+    runtime: &Runtime,
+    device: Option<Device>,
+    limits: &elgato_keylight::LimitsConfig,
+) -> Option<PowerStatus> {
+    let Some(device) = device else {
+        error!("No device to toggle");
+        return None;
+    };
+
+    let result = runtime.block_on(async {
+        let mut power = PowerStatus::Off;
+        let mut status = elgato_keylight::get_status(device.url.clone()).await?;
+        status.set(elgato_keylight::LightIndex::FIRST, |status| {
+            status.power.toggle();
+            status.brightness = limits.cap_brightness(&device.name, status.brightness);
+            power = status.power;
+        })?;
+        elgato_keylight::set_status(device.url.clone(), status)
+            .await
+            .map(|()| power)
+    });
+    match result {
+        Ok(power) => {
+            info!("Device `{}` toggled", device.name);
+            Some(power)
+        }
+        Err(err) => {
+            error!("Toggle `{}` failed: {err}", device.name);
+            None
+        }
+    }
 }
 
 #[cfg(feature = "tray-icon")]
-fn load_icon() -> tray_icon::Icon {
+fn load_icon() -> anyhow::Result<tray_icon::Icon> {
     use std::io::Cursor;
 
     use image::{ImageFormat, ImageReader};
@@ -483,14 +1310,11 @@ fn load_icon() -> tray_icon::Icon {
             ))),
             ImageFormat::Png,
         );
-        let image = reader
-            .decode()
-            .expect("decode tray icon failed")
-            .into_rgba8();
+        let image = reader.decode()?.into_rgba8();
         let (width, height) = image.dimensions();
         let rgba = image.into_raw();
         (rgba, width, height)
     };
 
-    Icon::from_rgba(icon_rgba, icon_width, icon_height).expect("Failed to open icon")
+    Ok(Icon::from_rgba(icon_rgba, icon_width, icon_height)?)
 }
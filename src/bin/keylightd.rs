@@ -0,0 +1,504 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+#[cfg(target_os = "linux")]
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+
+#[cfg(windows)]
+use elgato_keylight::daemon::windows;
+#[cfg(target_os = "linux")]
+use elgato_keylight::daemon::{
+    ambilight, apps, audio, camera, do_not_disturb, hotkeys, lock, microphone, presence, resume,
+    systemd,
+};
+use elgato_keylight::{
+    daemon::{
+        advertise, ambient, chat, circadian, control, day_curve, dbus, history, obs, rest, rules,
+        scheduler, triggers, webhooks, with_source, Daemon,
+    },
+    generate::Generate,
+    logging::LogArgs,
+    Config,
+};
+
+/// Elgato Keylight daemon: keeps track of the devices and exposes them on the session bus
+#[derive(Debug, Parser)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Serve a REST API proxying to the lights
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        /// Also export the lights on the session bus
+        #[arg(long)]
+        dbus: bool,
+    },
+    /// Write a systemd user unit starting the daemon at login
+    #[cfg(target_os = "linux")]
+    InstallService {
+        /// Serve the REST API on this address, started on the first connection
+        #[arg(long)]
+        listen: Option<String>,
+        /// Also export the lights on the session bus when serving the REST API
+        #[arg(long, requires = "listen")]
+        dbus: bool,
+    },
+    /// Register a Windows service starting the daemon at boot, from an elevated prompt
+    #[cfg(windows)]
+    InstallService {
+        /// Also serve the REST API on this address
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+    },
+    /// Stop and remove the Windows service
+    #[cfg(windows)]
+    UninstallService,
+    /// Run as the Windows service, started by the service control manager
+    #[cfg(windows)]
+    #[command(hide = true)]
+    RunService {
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+        /// Config of the user who installed the service
+        #[arg(long)]
+        config: PathBuf,
+    },
+    /// Write the man pages or print the completion scripts
+    #[command(subcommand)]
+    Generate(Generate),
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    // The service logs to the event log instead
+    #[cfg(windows)]
+    if let Some(Commands::RunService { listen, config }) = args.command {
+        return run_service(args.log.log_level(), listen, config);
+    }
+    args.log.init();
+
+    if let Some(Commands::Generate(generate)) = &args.command {
+        return Ok(generate.run::<Args>(env!("CARGO_BIN_NAME"))?);
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(Commands::InstallService { listen, dbus }) = &args.command {
+        return install_service(listen.as_deref(), *dbus);
+    }
+    #[cfg(windows)]
+    match &args.command {
+        Some(Commands::InstallService { listen }) => {
+            let args = windows::launch_arguments(*listen, &Config::path()?);
+            windows::install(std::env::current_exe()?, args)?;
+            println!(
+                "Installed the {} service, started at boot",
+                windows::SERVICE_NAME
+            );
+            println!("Start it now with: sc start {}", windows::SERVICE_NAME);
+            return Ok(());
+        }
+        Some(Commands::UninstallService) => {
+            windows::uninstall()?;
+            println!("Removed the {} service", windows::SERVICE_NAME);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let config_path = Config::path()?;
+    let daemon = start(Config::load_from(&config_path)?, config_path).await?;
+
+    match args.command {
+        None => {
+            let _connection = dbus::serve(daemon).await?;
+            log::info!("Serving {} on the session bus", dbus::DBUS_NAME);
+            notify_ready();
+            tokio::signal::ctrl_c().await?;
+        }
+        Some(Commands::Serve { listen, dbus }) => {
+            let _connection = if dbus {
+                Some(dbus::serve(daemon.clone()).await?)
+            } else {
+                None
+            };
+            let listener = match activated_listener()? {
+                Some(listener) => listener,
+                None => tokio::net::TcpListener::bind(listen).await?,
+            };
+            let _advertisement = advertise_rest(&daemon.config().advertise, &listener).await;
+            notify_ready();
+            tokio::select! {
+                res = rest::serve_on(daemon, listener) => res?,
+                res = tokio::signal::ctrl_c() => res?,
+            }
+        }
+        #[cfg(any(target_os = "linux", windows))]
+        Some(Commands::InstallService { .. }) => unreachable!("handled before starting"),
+        #[cfg(windows)]
+        Some(Commands::UninstallService | Commands::RunService { .. }) => {
+            unreachable!("handled before starting")
+        }
+        Some(Commands::Generate(_)) => unreachable!("handled before starting"),
+    }
+
+    Ok(())
+}
+
+/// Start the daemon with its automations and protocols, the config being read from `config_path`
+async fn start(config: Config, config_path: PathBuf) -> anyhow::Result<Daemon> {
+    elgato_keylight::set_network_config(&config.network)?;
+    let daemon = Daemon::start(config).await?;
+    spawn_automations(&daemon, config_path);
+
+    let triggers = &daemon.config().triggers;
+    if triggers.enabled {
+        let (daemon, triggers) = (daemon.clone(), triggers.clone());
+        tokio::spawn(async move {
+            if let Err(err) = triggers::serve(daemon, triggers).await {
+                log::error!("Triggers failed: {err}");
+            }
+        });
+    }
+
+    let control = &daemon.config().control;
+    if control.enabled {
+        let (daemon, listen) = (daemon.clone(), control.listen);
+        tokio::spawn(async move {
+            if let Err(err) = control::serve(daemon, listen).await {
+                log::error!("Control protocol failed: {err}");
+            }
+        });
+    }
+
+    #[cfg(feature = "grpc")]
+    if daemon.config().grpc.enabled {
+        let (daemon, listen) = (daemon.clone(), daemon.config().grpc.listen);
+        tokio::spawn(async move {
+            if let Err(err) = elgato_keylight::daemon::grpc::serve(daemon, listen).await {
+                log::error!("gRPC failed: {err}");
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    if daemon.config().ipc.enabled {
+        let ipc = &daemon.config().ipc;
+        match ipc
+            .socket
+            .clone()
+            .map_or_else(elgato_keylight::client::socket_path, Ok)
+        {
+            Ok(path) => {
+                let daemon = daemon.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = elgato_keylight::daemon::ipc::serve(daemon, &path).await {
+                        log::error!("IPC failed: {err}");
+                    }
+                });
+            }
+            Err(err) => log::error!("IPC failed: {err}"),
+        }
+    }
+
+    Ok(daemon)
+}
+
+/// Run as the Windows service until the service control manager stops it, serving the REST API
+/// on `listen` if given
+#[cfg(windows)]
+fn run_service(
+    level: log::Level,
+    listen: Option<SocketAddr>,
+    config: PathBuf,
+) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Handle::current();
+    let body = move |stop: tokio::sync::oneshot::Receiver<()>| {
+        runtime.block_on(async move {
+            let daemon = start(Config::load_from(&config)?, config).await?;
+            match listen {
+                Some(listen) => {
+                    let listener = tokio::net::TcpListener::bind(listen).await?;
+                    let _advertisement =
+                        advertise_rest(&daemon.config().advertise, &listener).await;
+                    tokio::select! {
+                        res = rest::serve_on(daemon, listener) => res?,
+                        _ = stop => {}
+                    }
+                }
+                None => {
+                    let _ = stop.await;
+                }
+            }
+            Ok(())
+        })
+    };
+    tokio::task::block_in_place(|| windows::run(level, Box::new(body)))
+}
+
+/// Publish the REST API via mDNS, unless it is only reachable from this machine
+async fn advertise_rest(
+    config: &elgato_keylight::AdvertiseConfig,
+    listener: &tokio::net::TcpListener,
+) -> Option<advertise::Advertisement> {
+    let address = listener.local_addr().ok()?;
+    if !config.enabled || address.ip().is_loopback() {
+        return None;
+    }
+    match advertise::advertise(config, address.port()).await {
+        Ok(advertisement) => Some(advertisement),
+        Err(err) => {
+            log::warn!("Failed to advertise the REST API via mDNS: {err}");
+            None
+        }
+    }
+}
+
+/// Listener passed by systemd socket activation, if any
+fn activated_listener() -> std::io::Result<Option<tokio::net::TcpListener>> {
+    #[cfg(target_os = "linux")]
+    if let Some(listener) = systemd::activated_listener() {
+        listener.set_nonblocking(true)?;
+        return tokio::net::TcpListener::from_std(listener).map(Some);
+    }
+    Ok(None)
+}
+
+/// Tell systemd the daemon is ready (`Type=notify`)
+fn notify_ready() {
+    #[cfg(target_os = "linux")]
+    if let Err(err) = systemd::notify("READY=1") {
+        log::warn!("Failed to notify systemd: {err}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn install_service(listen: Option<&str>, dbus: bool) -> anyhow::Result<()> {
+    let dir = systemd::user_unit_dir().context("Config directory not found")?;
+    std::fs::create_dir_all(&dir)?;
+
+    let exe = std::env::current_exe()?;
+    let args: &[&str] = match (listen, dbus) {
+        (None, _) => &[],
+        (Some(_), false) => &["serve"],
+        (Some(_), true) => &["serve", "--dbus"],
+    };
+    let service = dir.join(format!("{}.service", systemd::UNIT_NAME));
+    std::fs::write(&service, systemd::service_unit(&exe, args))?;
+    println!("Wrote {}", service.display());
+
+    let unit = match listen {
+        Some(listen) => {
+            let socket = dir.join(format!("{}.socket", systemd::UNIT_NAME));
+            std::fs::write(&socket, systemd::socket_unit(listen))?;
+            println!("Wrote {}", socket.display());
+            format!("{}.socket", systemd::UNIT_NAME)
+        }
+        None => format!("{}.service", systemd::UNIT_NAME),
+    };
+    println!(
+        "Enable it with: systemctl --user daemon-reload && systemctl --user enable --now {unit}"
+    );
+    Ok(())
+}
+
+/// Start the automations enabled in the config
+fn spawn_automations(daemon: &Daemon, config_path: PathBuf) {
+    let config = daemon.config();
+
+    if !config.rules.is_empty() {
+        let (daemon, rules) = (daemon.clone(), config.rules.clone());
+        tokio::spawn(with_source("rules", async move {
+            if let Err(err) = rules::run(daemon, rules).await {
+                log::error!("Rules failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.camera.enabled {
+        let (daemon, camera) = (daemon.clone(), config.camera.clone());
+        tokio::spawn(with_source("camera", async move {
+            if let Err(err) = camera::run(daemon, camera).await {
+                log::error!("Camera automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.microphone.enabled {
+        let (daemon, microphone) = (daemon.clone(), config.microphone.clone());
+        tokio::spawn(with_source("microphone", async move {
+            if let Err(err) = microphone::run(daemon, microphone).await {
+                log::error!("Microphone automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.hotkeys.enabled {
+        let (daemon, hotkeys) = (daemon.clone(), config.hotkeys.clone());
+        tokio::spawn(with_source("hotkeys", async move {
+            if let Err(err) = hotkeys::run(daemon, hotkeys).await {
+                log::error!("Hotkeys failed: {err}");
+            }
+        }));
+    }
+
+    if config.ambient.enabled {
+        let (daemon, ambient) = (daemon.clone(), config.ambient.clone());
+        tokio::spawn(with_source("ambient", async move {
+            if let Err(err) = ambient::run(daemon, ambient).await {
+                log::error!("Ambient light automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.ambilight.enabled {
+        let (daemon, ambilight) = (daemon.clone(), config.ambilight.clone());
+        tokio::spawn(with_source("ambilight", async move {
+            if let Err(err) = ambilight::run(daemon, ambilight).await {
+                log::error!("Ambilight failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.audio.enabled {
+        let (daemon, audio) = (daemon.clone(), config.audio.clone());
+        tokio::spawn(with_source("audio", async move {
+            if let Err(err) = audio::run(daemon, audio).await {
+                log::error!("Audio automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.do_not_disturb.enabled {
+        let (daemon, do_not_disturb) = (daemon.clone(), config.do_not_disturb.clone());
+        tokio::spawn(with_source("do_not_disturb", async move {
+            if let Err(err) = do_not_disturb::run(daemon, do_not_disturb).await {
+                log::error!("Do Not Disturb automation failed: {err}");
+            }
+        }));
+    }
+
+    if config.circadian.enabled {
+        tokio::spawn(with_source(
+            "circadian",
+            circadian::run(daemon.clone(), config.circadian.clone()),
+        ));
+    }
+
+    if config.day_curve.enabled {
+        let (daemon, day_curve) = (daemon.clone(), config.day_curve.clone());
+        tokio::spawn(with_source("day_curve", async move {
+            if let Err(err) = day_curve::run(daemon, day_curve).await {
+                log::error!("Day curve failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.lock.enabled {
+        let (daemon, lock) = (daemon.clone(), config.lock.clone());
+        tokio::spawn(with_source("lock", async move {
+            if let Err(err) = lock::run(daemon, lock).await {
+                log::error!("Lock automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.resume.enabled {
+        let (daemon, resume) = (daemon.clone(), config.resume.clone());
+        tokio::spawn(with_source("resume", async move {
+            if let Err(err) = resume::run(daemon, resume).await {
+                log::error!("Resume automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.presence.enabled {
+        let (daemon, presence) = (daemon.clone(), config.presence.clone());
+        tokio::spawn(with_source("presence", async move {
+            if let Err(err) = presence::run(daemon, presence).await {
+                log::error!("Presence automation failed: {err}");
+            }
+        }));
+    }
+
+    chat::spawn(daemon, &config.chat);
+
+    if config.history.enabled {
+        match elgato_keylight::history_path() {
+            Ok(path) => {
+                let (daemon, history) = (daemon.clone(), config.history.clone());
+                tokio::spawn(async move {
+                    if let Err(err) = history::run(daemon, history, &path).await {
+                        log::error!("History failed: {err}");
+                    }
+                });
+            }
+            Err(err) => log::error!("History failed: {err}"),
+        }
+    }
+
+    if !config.webhooks.urls.is_empty() {
+        tokio::spawn(webhooks::run(daemon.clone(), config.webhooks.clone()));
+    }
+
+    #[cfg(feature = "scripting")]
+    if !config.scripts.files.is_empty() {
+        let (daemon, scripts) = (daemon.clone(), config.scripts.clone());
+        tokio::spawn(async move {
+            if let Err(err) = elgato_keylight::daemon::scripting::run(daemon, scripts).await {
+                log::error!("Scripts failed: {err}");
+            }
+        });
+    }
+    #[cfg(not(feature = "scripting"))]
+    if !config.scripts.files.is_empty() {
+        log::warn!(
+            "Scripts are configured but the daemon was built without the `scripting` feature"
+        );
+    }
+
+    #[cfg(unix)]
+    if config.wm.enabled {
+        let (daemon, wm) = (daemon.clone(), config.wm.clone());
+        tokio::spawn(with_source("wm", async move {
+            if let Err(err) = elgato_keylight::daemon::wm::run(daemon, wm).await {
+                log::error!("Window manager automation failed: {err}");
+            }
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.apps.enabled {
+        tokio::spawn(with_source(
+            "apps",
+            apps::run(daemon.clone(), config.apps.clone()),
+        ));
+    }
+
+    // Always running, schedules may be added later on
+    tokio::spawn(scheduler::run(daemon.clone(), config_path));
+
+    if config.obs.enabled {
+        tokio::spawn(with_source(
+            "obs",
+            obs::run(daemon.clone(), config.obs.clone()),
+        ));
+    }
+}
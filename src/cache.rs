@@ -0,0 +1,326 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use reqwest::Url;
+use tokio::time::Instant;
+
+use crate::{get_accessory_info, get_status, set_status, AccessoryInfo, DeviceStatus};
+
+/// Age after which the accessory info is fetched again, it only changes with a firmware update
+/// or a rename
+const ACCESSORY_INFO_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Age after which a state kept by [`OptimisticState`] is read again from the device, short
+/// enough to pick up the changes made from other apps
+const OPTIMISTIC_STATE_TTL: Duration = Duration::from_secs(2);
+
+/// Last known state of the devices, served when they fail to answer.
+///
+/// Changes made while a device is unreachable are queued and sent by the next
+/// [`StatusCache::get_status`] that reaches it, so the device ends up in the state last asked for.
+#[derive(Debug, Default)]
+pub struct StatusCache {
+    entries: Mutex<HashMap<Url, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Last state read from or set on the device
+    status: DeviceStatus,
+    fetched_at: Instant,
+    /// Change that failed to reach the device, sent once it answers again
+    pending: Option<DeviceStatus>,
+}
+
+/// State read through a [`StatusCache`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedStatus {
+    pub status: DeviceStatus,
+    /// Set when the device did not answer and the last known state is served instead
+    pub stale: Option<Stale>,
+}
+
+/// Why and since when a [`CachedStatus`] is stale
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stale {
+    /// Error of the failed read
+    pub error: String,
+    /// Time since the state was last read from or set on the device
+    pub age: Duration,
+}
+
+/// Outcome of [`StatusCache::set_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    /// The device is unreachable, the change is sent once it answers again
+    Queued,
+}
+
+impl StatusCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the state of the device, or the last known one marked stale if it fails to answer.
+    /// Fails if the state of the device was never read.
+    pub async fn get_status(&self, url: Url) -> anyhow::Result<CachedStatus> {
+        let pending = self.entry(&url, |entry| entry.pending.clone()).flatten();
+        if let Some(pending) = pending {
+            if set_status(url.clone(), pending.clone()).await.is_ok() {
+                log::info!("{url} is back, sent the queued change");
+                self.store(&url, pending);
+            }
+        }
+
+        match get_status(url.clone()).await {
+            Ok(status) => {
+                // A change queued in the meantime is sent by the next read
+                if self.entry(&url, |entry| entry.pending.is_none()) != Some(false) {
+                    self.store(&url, status.clone());
+                }
+                Ok(CachedStatus {
+                    status,
+                    stale: None,
+                })
+            }
+            Err(err) => {
+                let cached = self.entry(&url, |entry| CachedStatus {
+                    status: entry
+                        .pending
+                        .clone()
+                        .unwrap_or_else(|| entry.status.clone()),
+                    stale: Some(Stale {
+                        error: err.to_string(),
+                        age: entry.fetched_at.elapsed(),
+                    }),
+                });
+                cached.ok_or(err)
+            }
+        }
+    }
+
+    /// Set the state of the device, queueing it if the device fails to answer and its state
+    /// was read before
+    pub async fn set_status(&self, url: Url, status: DeviceStatus) -> anyhow::Result<Delivery> {
+        match set_status(url.clone(), status.clone()).await {
+            Ok(()) => {
+                self.store(&url, status);
+                Ok(Delivery::Sent)
+            }
+            Err(err) => {
+                let queued = self.entry(&url, |entry| entry.pending = Some(status));
+                match queued {
+                    Some(()) => {
+                        log::info!("{url} is unreachable ({err}), queueing the change");
+                        Ok(Delivery::Queued)
+                    }
+                    None => Err(err),
+                }
+            }
+        }
+    }
+
+    fn entry<T>(&self, url: &Url, f: impl FnOnce(&mut Entry) -> T) -> Option<T> {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .get_mut(url)
+            .map(f)
+    }
+
+    /// Record the state of the device, dropping the queued change it supersedes
+    fn store(&self, url: &Url, status: DeviceStatus) {
+        self.entries.lock().expect("lock poisoned").insert(
+            url.clone(),
+            Entry {
+                status,
+                fetched_at: Instant::now(),
+                pending: None,
+            },
+        );
+    }
+}
+
+/// Accessory info of the devices, fetched once per TTL
+#[derive(Debug)]
+pub struct AccessoryInfoCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<Url, (AccessoryInfo, Instant)>>,
+}
+
+impl Default for AccessoryInfoCache {
+    fn default() -> Self {
+        Self::new(ACCESSORY_INFO_TTL)
+    }
+}
+
+impl AccessoryInfoCache {
+    pub fn new(ttl: Duration) -> Self {
+        AccessoryInfoCache {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Accessory info of the device, fetched if unknown or older than the TTL
+    pub async fn get(&self, url: Url) -> anyhow::Result<AccessoryInfo> {
+        let cached = self
+            .entries
+            .lock()
+            .expect("lock poisoned")
+            .get(&url)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < self.ttl)
+            .map(|(info, _)| info.clone());
+        if let Some(info) = cached {
+            return Ok(info);
+        }
+        let info = get_accessory_info(url.clone()).await?;
+        self.insert(url, info.clone());
+        Ok(info)
+    }
+
+    /// Forget the accessory info of the device, e.g. after renaming it
+    pub fn invalidate(&self, url: &Url) {
+        self.entries.lock().expect("lock poisoned").remove(url);
+    }
+
+    fn insert(&self, url: Url, info: AccessoryInfo) {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .insert(url, (info, Instant::now()));
+    }
+}
+
+/// State of the devices as last read or set, so that bursts of changes (hotkeys held down,
+/// repeated toggles) don't read the state before each of them
+#[derive(Debug)]
+pub struct OptimisticState {
+    ttl: Duration,
+    entries: Mutex<HashMap<Url, (DeviceStatus, Instant)>>,
+}
+
+impl Default for OptimisticState {
+    fn default() -> Self {
+        Self::new(OPTIMISTIC_STATE_TTL)
+    }
+}
+
+impl OptimisticState {
+    pub fn new(ttl: Duration) -> Self {
+        OptimisticState {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// State of the device, read from it if unknown or older than the TTL
+    pub async fn get(&self, url: Url) -> anyhow::Result<DeviceStatus> {
+        let known = self
+            .entries
+            .lock()
+            .expect("lock poisoned")
+            .get(&url)
+            .filter(|(_, updated_at)| updated_at.elapsed() < self.ttl)
+            .map(|(status, _)| status.clone());
+        if let Some(status) = known {
+            return Ok(status);
+        }
+        let status = get_status(url.clone()).await?;
+        self.store(&url, status.clone());
+        Ok(status)
+    }
+
+    /// Record the state read from or reported back by the device
+    pub fn store(&self, url: &Url, status: DeviceStatus) {
+        self.entries
+            .lock()
+            .expect("lock poisoned")
+            .insert(url.clone(), (status, Instant::now()));
+    }
+
+    /// Forget the state of the device, e.g. when a change failed to reach it
+    pub fn forget(&self, url: &Url) {
+        self.entries.lock().expect("lock poisoned").remove(url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, FirmwareVersion, KeyLightStatus, PowerStatus, Temperature};
+
+    use super::*;
+
+    fn status(power: PowerStatus) -> DeviceStatus {
+        DeviceStatus {
+            number_of_lights: 1,
+            lights: vec![KeyLightStatus::white(
+                power,
+                Brightness::new(40).unwrap(),
+                Temperature::new(200).unwrap(),
+            )],
+        }
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        // Nothing listens on the discard port
+        let url: Url = "http://127.0.0.1:9".parse().unwrap();
+        let cache = StatusCache::new();
+        assert!(cache.get_status(url.clone()).await.is_err());
+        assert!(cache
+            .set_status(url.clone(), status(PowerStatus::On))
+            .await
+            .is_err());
+
+        cache.store(&url, status(PowerStatus::Off));
+        let cached = cache.get_status(url.clone()).await.unwrap();
+        assert_eq!(cached.status, status(PowerStatus::Off));
+        assert!(cached.stale.is_some());
+
+        // The queued change is served until the device answers
+        let delivery = cache.set_status(url.clone(), status(PowerStatus::On));
+        assert_eq!(delivery.await.unwrap(), Delivery::Queued);
+        let cached = cache.get_status(url).await.unwrap();
+        assert_eq!(cached.status, status(PowerStatus::On));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accessory_info_ttl() {
+        let url: Url = "http://127.0.0.1:9".parse().unwrap();
+        let cache = AccessoryInfoCache::new(Duration::from_secs(60));
+        let info = AccessoryInfo {
+            product_name: "Elgato Key Light".to_string(),
+            hardware_board_type: 53,
+            firmware_build_number: 218,
+            firmware_version: FirmwareVersion::new(1, 0, 3),
+            serial_number: "BW33J1A02110".to_string(),
+            display_name: String::new(),
+            features: vec!["lights".to_string()],
+        };
+        cache.insert(url.clone(), info.clone());
+        assert_eq!(cache.get(url.clone()).await.unwrap(), info);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(cache.get(url).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn optimistic_state() {
+        let url: Url = "http://127.0.0.1:9".parse().unwrap();
+        let state = OptimisticState::default();
+        assert!(state.get(url.clone()).await.is_err());
+
+        state.store(&url, status(PowerStatus::On));
+        assert_eq!(
+            state.get(url.clone()).await.unwrap(),
+            status(PowerStatus::On)
+        );
+        tokio::time::advance(OPTIMISTIC_STATE_TTL).await;
+        assert!(state.get(url.clone()).await.is_err());
+
+        state.store(&url, status(PowerStatus::On));
+        state.forget(&url);
+        assert!(state.get(url).await.is_err());
+    }
+}
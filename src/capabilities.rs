@@ -0,0 +1,168 @@
+use std::ops::RangeInclusive;
+
+use crate::{AccessoryInfo, KeyLightStatus, Percent};
+
+/// Product names as reported by `productName` in the accessory info and prefixing the `md=`
+/// TXT record, longest first so that "Elgato Key Light Air" isn't taken for a Key Light
+const PRODUCTS: &[&str] = &[
+    "Elgato Key Light Mini",
+    "Elgato Key Light Air",
+    "Elgato Key Light",
+    "Elgato Light Strip",
+    "Elgato Ring Light",
+];
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum CapabilityError {
+    #[error("Brightness {value}% is outside the range {}-{}% of the device", range.start(), range.end())]
+    Brightness {
+        value: u8,
+        range: RangeInclusive<u8>,
+    },
+    #[error("Temperature {value} is outside the range {}-{} of the device", range.start(), range.end())]
+    Temperature {
+        value: u16,
+        range: RangeInclusive<u16>,
+    },
+}
+
+/// What a device supports and the values it accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// Product name, `None` if unknown
+    pub product: Option<&'static str>,
+    /// Color temperature, in device units
+    pub temperature: Option<RangeInclusive<u16>>,
+    /// Hue and saturation
+    pub color: bool,
+    pub battery: bool,
+    /// Brightness in percent
+    pub brightness: RangeInclusive<u8>,
+}
+
+/// The capabilities of a Key Light, assumed for unknown devices
+impl Default for DeviceCapabilities {
+    fn default() -> Self {
+        DeviceCapabilities {
+            product: None,
+            temperature: Some(143..=344),
+            color: false,
+            battery: false,
+            brightness: 3..=100,
+        }
+    }
+}
+
+impl DeviceCapabilities {
+    /// Capabilities of a product by its name, the ones of a Key Light if unknown
+    pub fn from_product(product_name: &str) -> Self {
+        let Some(product) = PRODUCTS
+            .iter()
+            .copied()
+            .find(|product| product_name.starts_with(product))
+        else {
+            return Self::default();
+        };
+        let defaults = DeviceCapabilities {
+            product: Some(product),
+            ..Self::default()
+        };
+        match product {
+            "Elgato Key Light Mini" => DeviceCapabilities {
+                battery: true,
+                ..defaults
+            },
+            "Elgato Light Strip" => DeviceCapabilities {
+                color: true,
+                brightness: 0..=100,
+                ..defaults
+            },
+            _ => defaults,
+        }
+    }
+
+    pub fn from_accessory_info(info: &AccessoryInfo) -> Self {
+        let mut capabilities = Self::from_product(&info.product_name);
+        capabilities.battery |= info.features.iter().any(|feature| feature == "battery");
+        capabilities
+    }
+
+    /// Capabilities from the TXT records of the mDNS service, by the model in `md=`
+    pub fn from_txt(records: &[String]) -> Self {
+        records
+            .iter()
+            .find_map(|record| record.strip_prefix("md="))
+            .map_or_else(Self::default, Self::from_product)
+    }
+
+    /// Brightness range ending at `max_brightness`, the cap of the light in the config
+    pub fn with_max_brightness(mut self, max_brightness: Option<Percent>) -> Self {
+        if let Some(max) = max_brightness {
+            let start = *self.brightness.start();
+            self.brightness = start..=max.0.clamp(start, *self.brightness.end());
+        }
+        self
+    }
+
+    /// Check that the device accepts the values of `status`
+    pub fn validate(&self, status: &KeyLightStatus) -> Result<(), CapabilityError> {
+        if !self.brightness.contains(&status.brightness.0) {
+            return Err(CapabilityError::Brightness {
+                value: status.brightness.0,
+                range: self.brightness.clone(),
+            });
+        }
+        if let (Some(range), Some(temperature)) = (&self.temperature, status.temperature) {
+            if !range.contains(&temperature.0) {
+                return Err(CapabilityError::Temperature {
+                    value: temperature.0,
+                    range: range.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, PowerStatus, Temperature};
+
+    use super::*;
+
+    #[test]
+    fn capabilities() {
+        let txt = [
+            "pv=1.0",
+            "md=Elgato Key Light Air 20LAB9901",
+            "dt=200",
+            "mf=Elgato",
+        ]
+        .map(String::from);
+        let capabilities = DeviceCapabilities::from_txt(&txt);
+        assert_eq!(capabilities.product, Some("Elgato Key Light Air"));
+
+        let strip = DeviceCapabilities::from_product("Elgato Light Strip");
+        assert!(strip.color);
+        assert_eq!(
+            DeviceCapabilities::from_txt(&[]),
+            DeviceCapabilities::default()
+        );
+
+        let mut status = KeyLightStatus::white(
+            PowerStatus::On,
+            Brightness::new(1).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        assert!(strip.validate(&status).is_ok());
+        assert_eq!(
+            capabilities.validate(&status),
+            Err(CapabilityError::Brightness {
+                value: 1,
+                range: 3..=100
+            })
+        );
+        status.brightness = Brightness::new(3).unwrap();
+        assert!(capabilities.validate(&status).is_ok());
+    }
+}
@@ -0,0 +1,492 @@
+//! Client of the daemon IPC protocol.
+//!
+//! The daemon listens on a Unix socket (see [`socket_path`]) speaking JSON-RPC 2.0, one JSON
+//! object per line. Methods:
+//!
+//! - `devices`: `[{"name": ..., "status": {...} | null}]`, with the last known state
+//! - `status {"device"}`: current state of a device
+//! - `toggle {"device"}`: toggle a device, returns its new state
+//! - `set {"device", "on"?, "brightness"?, "temperature"?}`: partial update, returns the new state
+//! - `preset {"device", "preset"}`: apply a preset, returns the new state
+//! - `rooms`: `{"<room>": {"devices", "on", "brightness", "temperature"}}`, aggregated state of
+//!   the rooms
+//! - `room_status {"room"}`, `room_toggle {"room"}`, `room_set {"room", "on"?, ...}` and
+//!   `room_preset {"room", "preset"}`: same as above for all the lights of a room, returning its
+//!   aggregated state. `room_toggle` turns the room off if any light is on.
+//! - `scenes`: names of the scenes
+//! - `scene {"scene"}`: play a scene, returns `null` once it is over
+//! - `stats`: `[{"device", "product", "watts", "watt_hours", "since"}]`, estimated power usage
+//! - `audit {"device"?, "source"?, "limit"?}`: last changes made through the daemon, oldest
+//!   first, `[{"time", "device", "event": "state" | "preset", "source", "actor"?, ...}]` as
+//!   recorded in the history. `actor` tells who made the change through the source, e.g. the
+//!   process of an IPC client or the address of a REST client.
+//! - `meeting_start {"duration"?}`: apply the meeting preset and pause the automations of the
+//!   `meeting` config, for `duration` seconds or until `meeting_stop`, which restores the lights.
+//!   Both return `{"active", "devices", "ends_at"}`, also returned by `meeting_status`.
+//! - `subscribe`: returns `null`, then `state` notifications
+//!   `{"device", "status", "source"}` are sent on every change
+//!
+//! Reading the state is open to any client that can reach the socket, changing the lights is
+//! reserved to root, the user running the daemon and the users and groups of the `access` config.
+//!
+//! Errors use the JSON-RPC codes plus the ones in [`error_code`].
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+};
+
+use crate::{HistoryRecord, KeyLightStatus, LightUpdate, MeetingStatus, PowerStats, RoomStatus};
+
+const SOCKET_DIR_NAME: &str = "elgato-keylight";
+const SOCKET_FILE_NAME: &str = "keylightd.sock";
+
+/// Version of JSON-RPC spoken on the socket
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Method of the notifications sent after `subscribe`
+pub const STATE_NOTIFICATION: &str = "state";
+
+/// Error codes of the protocol, next to the standard JSON-RPC ones
+pub mod error_code {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const DEVICE_NOT_FOUND: i64 = -32000;
+    pub const PRESET_NOT_FOUND: i64 = -32001;
+    /// The device is unreachable, the change is applied when it is back
+    pub const QUEUED: i64 = -32002;
+    /// The device failed to answer
+    pub const DEVICE_ERROR: i64 = -32003;
+    pub const ROOM_NOT_FOUND: i64 = -32004;
+    pub const SCENE_NOT_FOUND: i64 = -32005;
+    /// The scene file is invalid, the message tells why
+    pub const INVALID_SCENE: i64 = -32006;
+    /// The history is disabled or failed to load, the message tells why
+    pub const HISTORY_UNAVAILABLE: i64 = -32007;
+    /// The client is not allowed to change the lights, see [`crate::AccessConfig`]
+    pub const PERMISSION_DENIED: i64 = -32008;
+    /// Changes from the client are paused by the meeting mode, see [`crate::MeetingConfig`]
+    pub const PAUSED: i64 = -32009;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Runtime directory not found")]
+    NoRuntimeDir,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Connection closed by the daemon")]
+    Closed,
+}
+
+/// Path of the daemon socket: `$XDG_RUNTIME_DIR/elgato-keylight/keylightd.sock`
+pub fn socket_path() -> Result<PathBuf, ClientError> {
+    let dir = dirs::runtime_dir().ok_or(ClientError::NoRuntimeDir)?;
+    Ok(dir.join(SOCKET_DIR_NAME).join(SOCKET_FILE_NAME))
+}
+
+/// Request or notification, notifications have no `id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub params: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    /// `null` if the request could not be parsed
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Result(serde_json::Value),
+    Error(RpcError),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// Message received by a client: a response or a notification
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Message {
+    Response(Response),
+    Notification(Request),
+}
+
+/// Params of the methods targeting a single device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceParams {
+    pub device: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetParams {
+    pub device: String,
+    #[serde(flatten)]
+    pub update: LightUpdate,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresetParams {
+    pub device: String,
+    pub preset: String,
+}
+
+/// Params of the methods targeting a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomParams {
+    pub room: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomSetParams {
+    pub room: String,
+    #[serde(flatten)]
+    pub update: LightUpdate,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomPresetParams {
+    pub room: String,
+    pub preset: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneParams {
+    pub scene: String,
+}
+
+/// Params of `audit`, every field is optional
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditParams {
+    /// Only the changes of this device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Only the changes made through this source, e.g. `ipc` or `rest`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Number of changes, [`AuditParams::DEFAULT_LIMIT`] if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+impl AuditParams {
+    pub const DEFAULT_LIMIT: usize = 50;
+}
+
+/// Params of `meeting_start`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeetingParams {
+    /// Seconds until the meeting ends on its own, never if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+}
+
+/// Entry of the `devices` result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceState {
+    pub name: String,
+    pub status: Option<KeyLightStatus>,
+}
+
+/// Params of the `state` notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateChange {
+    pub device: String,
+    pub status: KeyLightStatus,
+    /// What changed the state, e.g. `camera` or `external`
+    pub source: String,
+}
+
+/// Connection to the daemon
+#[derive(Debug)]
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the daemon at the default [`socket_path`]
+    pub async fn connect_default() -> Result<Self, ClientError> {
+        Self::connect(&socket_path()?).await
+    }
+
+    pub async fn connect(path: &Path) -> Result<Self, ClientError> {
+        let (reader, writer) = UnixStream::connect(path).await?.into_split();
+        Ok(Client {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    pub async fn devices(&mut self) -> Result<Vec<DeviceState>, ClientError> {
+        self.call("devices", serde_json::Value::Null).await
+    }
+
+    pub async fn status(&mut self, device: &str) -> Result<KeyLightStatus, ClientError> {
+        self.call("status", device_params(device)).await
+    }
+
+    pub async fn toggle(&mut self, device: &str) -> Result<KeyLightStatus, ClientError> {
+        self.call("toggle", device_params(device)).await
+    }
+
+    pub async fn set(
+        &mut self,
+        device: &str,
+        update: &LightUpdate,
+    ) -> Result<KeyLightStatus, ClientError> {
+        let params = SetParams {
+            device: device.to_string(),
+            update: update.clone(),
+        };
+        self.call("set", serde_json::to_value(params)?).await
+    }
+
+    pub async fn apply_preset(
+        &mut self,
+        device: &str,
+        preset: &str,
+    ) -> Result<KeyLightStatus, ClientError> {
+        let params = PresetParams {
+            device: device.to_string(),
+            preset: preset.to_string(),
+        };
+        self.call("preset", serde_json::to_value(params)?).await
+    }
+
+    pub async fn rooms(&mut self) -> Result<BTreeMap<String, RoomStatus>, ClientError> {
+        self.call("rooms", serde_json::Value::Null).await
+    }
+
+    pub async fn room_status(&mut self, room: &str) -> Result<RoomStatus, ClientError> {
+        self.call("room_status", room_params(room)).await
+    }
+
+    pub async fn room_toggle(&mut self, room: &str) -> Result<RoomStatus, ClientError> {
+        self.call("room_toggle", room_params(room)).await
+    }
+
+    pub async fn room_set(
+        &mut self,
+        room: &str,
+        update: &LightUpdate,
+    ) -> Result<RoomStatus, ClientError> {
+        let params = RoomSetParams {
+            room: room.to_string(),
+            update: update.clone(),
+        };
+        self.call("room_set", serde_json::to_value(params)?).await
+    }
+
+    pub async fn room_preset(
+        &mut self,
+        room: &str,
+        preset: &str,
+    ) -> Result<RoomStatus, ClientError> {
+        let params = RoomPresetParams {
+            room: room.to_string(),
+            preset: preset.to_string(),
+        };
+        self.call("room_preset", serde_json::to_value(params)?)
+            .await
+    }
+
+    pub async fn scenes(&mut self) -> Result<Vec<String>, ClientError> {
+        self.call("scenes", serde_json::Value::Null).await
+    }
+
+    /// Play a scene, returns once it is over
+    pub async fn play_scene(&mut self, scene: &str) -> Result<(), ClientError> {
+        let params = SceneParams {
+            scene: scene.to_string(),
+        };
+        self.call("scene", serde_json::to_value(params)?).await
+    }
+
+    /// Estimated power usage of the devices
+    pub async fn stats(&mut self) -> Result<Vec<PowerStats>, ClientError> {
+        self.call("stats", serde_json::Value::Null).await
+    }
+
+    /// Last changes made through the daemon, see [`AuditParams`]
+    pub async fn audit(&mut self, params: &AuditParams) -> Result<Vec<HistoryRecord>, ClientError> {
+        self.call("audit", serde_json::to_value(params)?).await
+    }
+
+    /// Start a meeting, or change the end of the current one
+    pub async fn start_meeting(
+        &mut self,
+        duration: Option<std::time::Duration>,
+    ) -> Result<MeetingStatus, ClientError> {
+        let params = MeetingParams {
+            duration: duration.map(|duration| duration.as_secs()),
+        };
+        self.call("meeting_start", serde_json::to_value(params)?)
+            .await
+    }
+
+    /// End the meeting, restoring the lights
+    pub async fn stop_meeting(&mut self) -> Result<MeetingStatus, ClientError> {
+        self.call("meeting_stop", serde_json::Value::Null).await
+    }
+
+    pub async fn meeting_status(&mut self) -> Result<MeetingStatus, ClientError> {
+        self.call("meeting_status", serde_json::Value::Null).await
+    }
+
+    /// Subscribe to the state changes, see [`Subscription::next`]
+    pub async fn subscribe(mut self) -> Result<Subscription, ClientError> {
+        self.call::<()>("subscribe", serde_json::Value::Null)
+            .await?;
+        Ok(Subscription { client: self })
+    }
+
+    /// Call `method`, skipping the notifications received in the meantime
+    pub async fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<T, ClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: Some(id),
+            method: method.to_string(),
+            params,
+        };
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+
+        loop {
+            match self.next_message().await? {
+                Message::Response(response) if response.id == Some(id) => {
+                    return match response.outcome {
+                        Outcome::Result(value) => Ok(serde_json::from_value(value)?),
+                        Outcome::Error(RpcError { code, message }) => {
+                            Err(ClientError::Rpc { code, message })
+                        }
+                    };
+                }
+                message => log::debug!("Skipping {message:?}"),
+            }
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<Message, ClientError> {
+        let line = self.lines.next_line().await?.ok_or(ClientError::Closed)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+fn device_params(device: &str) -> serde_json::Value {
+    serde_json::json!({ "device": device })
+}
+
+fn room_params(room: &str) -> serde_json::Value {
+    serde_json::json!({ "room": room })
+}
+
+/// State changes pushed by the daemon
+#[derive(Debug)]
+pub struct Subscription {
+    client: Client,
+}
+
+impl Subscription {
+    /// Wait for the next state change
+    pub async fn next(&mut self) -> Result<StateChange, ClientError> {
+        loop {
+            match self.client.next_message().await? {
+                Message::Notification(Request { method, params, .. })
+                    if method == STATE_NOTIFICATION =>
+                {
+                    return Ok(serde_json::from_value(params)?);
+                }
+                message => log::debug!("Skipping {message:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let response: Message = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"result":{"on":1,"brightness":20,"temperature":200}}"#,
+        )
+        .unwrap();
+        let Message::Response(Response {
+            id: Some(1),
+            outcome: Outcome::Result(_),
+            ..
+        }) = response
+        else {
+            panic!("unexpected {response:?}");
+        };
+
+        let error: Message = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Parse error"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            error,
+            Message::Response(Response {
+                jsonrpc: JSONRPC_VERSION.to_string(),
+                id: None,
+                outcome: Outcome::Error(RpcError {
+                    code: error_code::PARSE_ERROR,
+                    message: "Parse error".to_string()
+                }),
+            })
+        );
+
+        let notification: Message = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","method":"state","params":{"device":"Key Light","status":{"on":0,"brightness":20,"temperature":200},"source":"external"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(notification, Message::Notification(_)));
+
+        let params: SetParams =
+            serde_json::from_value(serde_json::json!({"device": "Key Light", "brightness": 40}))
+                .unwrap();
+        assert_eq!(params.update.brightness.map(|b| b.0), Some(40));
+    }
+}
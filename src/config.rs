@@ -0,0 +1,1359 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Brightness, BrightnessDelta, Kelvin, LightUpdate, Percent, PowerStatus, TemperatureDelta,
+};
+
+const CONFIG_DIR_NAME: &str = "elgato-keylight";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Config directory not found")]
+    NoConfigDir,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("Invalid config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error(transparent)]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Invalid config file: {0}")]
+    Edit(#[from] toml_edit::TomlError),
+}
+
+/// User configuration stored at `$XDG_CONFIG_HOME/elgato-keylight/config.toml`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub gui: GuiConfig,
+    pub tray: TrayConfig,
+    /// Named light settings, e.g. `[presets.meeting]`
+    pub presets: BTreeMap<String, LightUpdate>,
+    /// Lights of each room, e.g. `Studio = ["Key Light Left", "Key Light Right"]` under `[rooms]`
+    pub rooms: BTreeMap<String, Vec<String>>,
+    /// Offsets of each light, e.g. `[calibration."Key Light Right"]`
+    pub calibration: BTreeMap<String, Calibration>,
+    pub camera: CameraConfig,
+    pub microphone: MicrophoneConfig,
+    pub obs: ObsConfig,
+    pub hotkeys: HotkeysConfig,
+    pub control: ControlConfig,
+    pub ipc: IpcConfig,
+    pub access: AccessConfig,
+    pub rate_limit: RateLimitConfig,
+    pub limits: LimitsConfig,
+    pub soft_start: SoftStartConfig,
+    pub grpc: GrpcConfig,
+    pub advertise: AdvertiseConfig,
+    pub ambient: AmbientConfig,
+    pub ambilight: AmbilightConfig,
+    pub audio: AudioConfig,
+    pub circadian: CircadianConfig,
+    pub day_curve: DayCurveConfig,
+    pub do_not_disturb: DoNotDisturbConfig,
+    pub lock: LockConfig,
+    pub meeting: MeetingConfig,
+    pub resume: ResumeConfig,
+    pub webhooks: WebhooksConfig,
+    pub scripts: ScriptsConfig,
+    pub triggers: TriggersConfig,
+    pub wm: WmConfig,
+    pub apps: AppsConfig,
+    pub presence: PresenceConfig,
+    pub chat: ChatConfig,
+    pub history: HistoryConfig,
+    pub network: NetworkConfig,
+    /// Entries of the daemon scheduler, e.g. `[[schedules]]`
+    pub schedules: Vec<Schedule>,
+    /// Automation rules of the daemon, e.g. `[[rules]]`
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiConfig {
+    /// UI scale factor. Defaults to the native scale of the display.
+    pub scale: Option<f32>,
+    /// Percentage points of a PageUp/PageDown on the brightness slider
+    pub brightness_step: Option<u8>,
+    /// Device units of a PageUp/PageDown on the temperature slider
+    pub temperature_step: Option<u16>,
+}
+
+impl GuiConfig {
+    /// Change of a PageUp on the brightness slider, the step of the CLI if not set
+    pub fn brightness_step(&self) -> BrightnessDelta {
+        self.brightness_step
+            .map_or(BrightnessDelta::STEP, |step| BrightnessDelta(step.into()))
+    }
+
+    /// Change of a PageUp on the temperature slider, the step of the CLI if not set
+    pub fn temperature_step(&self) -> TemperatureDelta {
+        self.temperature_step
+            .map_or(TemperatureDelta::STEP, |step| TemperatureDelta(step.into()))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrayConfig {
+    /// Device toggled by the `toggle` entry of the tray menu. Defaults to the last used device.
+    pub toggle_device: Option<String>,
+}
+
+/// Daemon automation turning lights on while a webcam is in use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Preset applied when the camera starts, defaults to turning the lights on
+    pub preset: Option<String>,
+    /// Seconds to wait after the camera stops before turning the lights off
+    pub off_delay: u64,
+    pub backend: CameraBackend,
+}
+
+/// How the camera usage is detected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraBackend {
+    /// PipeWire if it is running, `/dev/video*` otherwise
+    #[default]
+    Auto,
+    /// Watch the `/dev/video*` device nodes
+    Device,
+    /// Watch the video source nodes of PipeWire, for cameras accessed through portals
+    PipeWire,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            enabled: false,
+            devices: vec![],
+            preset: None,
+            off_delay: 5,
+            backend: CameraBackend::default(),
+        }
+    }
+}
+
+/// Daemon automation turning lights on while a microphone is recording, e.g. during calls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MicrophoneConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Preset applied when recording starts, defaults to turning the lights on
+    pub preset: Option<String>,
+    /// Seconds to wait after recording stops before turning the lights off
+    pub off_delay: u64,
+    /// Applications whose recording is ignored, e.g. volume meters
+    pub ignore: Vec<String>,
+}
+
+impl Default for MicrophoneConfig {
+    fn default() -> Self {
+        MicrophoneConfig {
+            enabled: false,
+            devices: vec![],
+            preset: None,
+            off_delay: 5,
+            ignore: vec!["pavucontrol".to_string()],
+        }
+    }
+}
+
+/// Daemon integration reacting to OBS events through obs-websocket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObsConfig {
+    pub enabled: bool,
+    /// obs-websocket server
+    pub url: String,
+    /// obs-websocket password, if authentication is enabled
+    pub password: Option<String>,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Preset applied when streaming or recording starts, defaults to turning the lights on
+    pub preset: Option<String>,
+    /// Preset applied when switching to a scene, by scene name
+    pub scenes: BTreeMap<String, String>,
+    /// Turn the lights off when OBS exits
+    pub off_on_exit: bool,
+}
+
+impl Default for ObsConfig {
+    fn default() -> Self {
+        ObsConfig {
+            enabled: false,
+            url: "ws://localhost:4455".to_string(),
+            password: None,
+            devices: vec![],
+            preset: None,
+            scenes: BTreeMap::new(),
+            off_on_exit: true,
+        }
+    }
+}
+
+/// Daemon system-wide hotkeys
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeysConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Action by hotkey, e.g. `"ctrl+alt+L" = "toggle"`
+    pub bindings: BTreeMap<String, String>,
+}
+
+/// Daemon line-based TCP control protocol, e.g. for Stream Deck buttons through Companion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 16622)),
+        }
+    }
+}
+
+/// Daemon JSON-RPC protocol on a Unix socket, for third-party tools, enabled by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpcConfig {
+    pub enabled: bool,
+    /// Path of the socket. Defaults to `$XDG_RUNTIME_DIR/elgato-keylight/keylightd.sock`.
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        IpcConfig {
+            enabled: true,
+            socket: None,
+        }
+    }
+}
+
+/// Who may change the lights through the daemon, reading their state stays open to everyone
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    /// Users allowed over IPC next to root and the user running the daemon, by name or uid
+    pub users: Vec<String>,
+    /// Groups whose members are allowed over IPC, by name or gid
+    pub groups: Vec<String>,
+    /// Bearer tokens required for changes by the REST API, gRPC and the control protocol, open to
+    /// everyone if empty
+    pub tokens: Vec<String>,
+}
+
+/// Limit of the changes the daemon sends to each light, bursts (e.g. from scripts) can make the
+/// firmware lock up until it is power cycled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Changes per second and device, unlimited if 0
+    pub requests_per_second: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 5,
+        }
+    }
+}
+
+/// Bounds the daemon clamps every change to, whatever its source
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Brightness in percent
+    pub min_brightness: Option<Percent>,
+    pub max_brightness: Option<Percent>,
+    /// Color temperature in kelvin, e.g. never warmer than `min_kelvin = 4000`
+    pub min_kelvin: Option<Kelvin>,
+    pub max_kelvin: Option<Kelvin>,
+    /// Limits of each light, e.g. `[limits.devices."Key Light Left"]`
+    pub devices: BTreeMap<String, DeviceLimits>,
+}
+
+/// Limits of a single light, by its name or host:port
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceLimits {
+    /// host:port of the light, so the limits of a named light also apply to the `--ip`/`--port`
+    /// commands, e.g. `address = "192.168.1.20:9123"`
+    pub address: Option<String>,
+    /// Brightness in percent, also capping the changes made without the daemon
+    pub max_brightness: Option<Percent>,
+}
+
+impl LimitsConfig {
+    /// Limits of `device`, named or given as host:port
+    pub fn device(&self, device: &str) -> Option<&DeviceLimits> {
+        self.devices.get(device).or_else(|| {
+            self.devices
+                .values()
+                .find(|limits| limits.address.as_deref() == Some(device))
+        })
+    }
+
+    /// Maximum brightness of `device`, named or given as host:port
+    pub fn device_max_brightness(&self, device: &str) -> Option<Percent> {
+        self.device(device)?.max_brightness
+    }
+
+    /// `brightness` capped to the maximum brightness of `device`
+    pub fn cap_brightness(&self, device: &str, brightness: Brightness) -> Brightness {
+        brightness.capped(self.device_max_brightness(device))
+    }
+}
+
+/// Lights turned on ramp their brightness up from the minimum instead of snapping to it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoftStartConfig {
+    pub enabled: bool,
+    /// Milliseconds of the ramp
+    pub duration: u64,
+}
+
+impl Default for SoftStartConfig {
+    fn default() -> Self {
+        SoftStartConfig {
+            enabled: false,
+            duration: 1500,
+        }
+    }
+}
+
+impl SoftStartConfig {
+    /// Duration of the ramp, `None` if disabled
+    pub fn ramp(&self) -> Option<Duration> {
+        (self.enabled && self.duration > 0).then(|| Duration::from_millis(self.duration))
+    }
+}
+
+/// Offsets added to the brightness and temperature set on a light by presets, rooms and scenes,
+/// so that lights of the same model rendering slightly differently match on camera
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Calibration {
+    /// Brightness in percentage points
+    pub brightness: i8,
+    /// Color temperature in kelvin, e.g. `150` for a light rendering warmer than its pair
+    pub kelvin: i16,
+}
+
+impl Calibration {
+    /// `update` with the offsets added to the values it sets, clamped to the range of the lights
+    pub fn apply(&self, update: &LightUpdate) -> LightUpdate {
+        let mut calibrated = update.clone();
+        if let Some(brightness) = update.brightness {
+            let value = i16::from(brightness.0) + i16::from(self.brightness);
+            calibrated.brightness = Some(Brightness::new_clamped(value.clamp(0, 100) as u8));
+        }
+        let shift = |kelvin: Kelvin| {
+            let value = i32::from(kelvin.0) + i32::from(self.kelvin);
+            Kelvin(value.clamp(1, i32::from(u16::MAX)) as u16)
+        };
+        if let Some(kelvin) = update.kelvin {
+            calibrated.kelvin = Some(shift(kelvin));
+        } else if let Some(temperature) = update.temperature {
+            calibrated.temperature = Some(shift(temperature.into()).into());
+        }
+        calibrated
+    }
+}
+
+/// Daemon gRPC service, see `proto/keylight.proto`. Requires the `grpc` feature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        GrpcConfig {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 50051)),
+        }
+    }
+}
+
+/// mDNS advertisement of the REST API of `keylightd serve`, published through Avahi
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdvertiseConfig {
+    pub enabled: bool,
+    /// DNS-SD service type, e.g. `_http._tcp` for generic browsers
+    pub service_type: String,
+    /// Instance name, defaults to `keylightd on <host>`
+    pub name: Option<String>,
+}
+
+impl Default for AdvertiseConfig {
+    fn default() -> Self {
+        AdvertiseConfig {
+            enabled: true,
+            service_type: "_keylightd._tcp".to_string(),
+            name: None,
+        }
+    }
+}
+
+/// Requests to the lights
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Proxy of the requests, e.g. `socks5h://localhost:1080` for an SSH tunnel. Defaults to
+    /// `HTTP_PROXY`/`ALL_PROXY`, hosts in `NO_PROXY` are always reached directly.
+    pub proxy: Option<String>,
+    /// Bearer tokens sent to the lights behind a base URL, e.g. an authenticating reverse proxy:
+    /// `"https://lights.example.com/" = "s3cret"` under `[network.tokens]`
+    pub tokens: BTreeMap<String, String>,
+}
+
+/// Daemon recording of the state changes, summarized by `elgato-keylight stats`. Never leaves the machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Days of history to keep
+    pub retention_days: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: true,
+            retention_days: 90,
+        }
+    }
+}
+
+/// Daemon automation adjusting the brightness to the ambient light
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmbientConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// IIO device of the ambient light sensor, e.g. `/sys/bus/iio/devices/iio:device0`.
+    /// Defaults to the first sensor found.
+    pub sensor: Option<PathBuf>,
+    /// Illuminance to reach, the lights are at `min_brightness` above it
+    pub target_lux: f64,
+    pub min_brightness: Percent,
+    pub max_brightness: Percent,
+    /// Minimum brightness change applied, avoids flicker on noisy readings
+    pub hysteresis: u8,
+    /// Seconds between two readings
+    pub interval: u64,
+    /// Brightness by time of day (`"HH:MM" = 40`), used when there is no sensor
+    pub curve: BTreeMap<String, u8>,
+}
+
+impl Default for AmbientConfig {
+    fn default() -> Self {
+        AmbientConfig {
+            enabled: false,
+            devices: vec![],
+            sensor: None,
+            target_lux: 300.0,
+            min_brightness: Percent::new_clamped(10),
+            max_brightness: Percent::MAX,
+            hysteresis: 5,
+            interval: 10,
+            curve: BTreeMap::new(),
+        }
+    }
+}
+
+/// Daemon automation giving the Light Strips the color of the screen
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmbilightConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty. Only the color lights are changed.
+    pub devices: Vec<String>,
+    /// Area of the screen sampled, `x,y widthxheight` like the output of slurp, the whole
+    /// screen if not set
+    pub region: Option<String>,
+    pub mode: AmbilightMode,
+    /// Captures per second
+    pub fps: f64,
+    /// Share of a CPU core the captures may use, the rate is lowered to stay within it
+    pub cpu_budget: Percent,
+    /// Brightness of a black screen
+    pub min_brightness: Percent,
+    /// Brightness of a white screen
+    pub max_brightness: Percent,
+}
+
+/// How the color of the screen is computed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmbilightMode {
+    /// Mean of all the pixels
+    #[default]
+    Average,
+    /// Mean of the pixels of the most common saturated hue, more vivid on mostly dark screens
+    Dominant,
+}
+
+impl Default for AmbilightConfig {
+    fn default() -> Self {
+        AmbilightConfig {
+            enabled: false,
+            devices: vec![],
+            region: None,
+            mode: AmbilightMode::default(),
+            fps: 2.0,
+            cpu_budget: Percent::new_clamped(10),
+            min_brightness: Percent::new_clamped(10),
+            max_brightness: Percent::MAX,
+        }
+    }
+}
+
+/// Daemon automation modulating the brightness with the level of the system audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// PipeWire node or PulseAudio source to capture, defaults to what the default output plays
+    pub source: Option<String>,
+    /// Brightness in silence
+    pub min_brightness: Percent,
+    /// Brightness at full scale
+    pub max_brightness: Percent,
+    /// Level in dBFS below which the lights stay at `min_brightness`
+    pub floor_db: f64,
+    /// Milliseconds for the brightness to rise with a louder level
+    pub attack: u64,
+    /// Milliseconds for the brightness to fall back
+    pub decay: u64,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            enabled: false,
+            devices: vec![],
+            source: None,
+            min_brightness: Percent::new_clamped(10),
+            max_brightness: Percent::MAX,
+            floor_db: -50.0,
+            attack: 50,
+            decay: 300,
+        }
+    }
+}
+
+/// Daemon automation shifting the color temperature with the sun
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircadianConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    pub latitude: f64,
+    /// East positive
+    pub longitude: f64,
+    /// Color temperature during the day, in kelvin
+    pub day_temperature: Kelvin,
+    /// Color temperature during the night, in kelvin
+    pub night_temperature: Kelvin,
+    /// Minutes of the transition around sunrise and sunset
+    pub transition: u64,
+    /// Hours to pause after the temperature is changed manually
+    pub pause_hours: u64,
+}
+
+impl Default for CircadianConfig {
+    fn default() -> Self {
+        CircadianConfig {
+            enabled: false,
+            devices: vec![],
+            latitude: 0.0,
+            longitude: 0.0,
+            day_temperature: Kelvin(5500),
+            night_temperature: Kelvin(3200),
+            transition: 60,
+            pause_hours: 2,
+        }
+    }
+}
+
+/// Daemon automation following fixed brightness and temperature points through the day,
+/// interpolated between them, e.g. for a studio with fixed hours
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DayCurveConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Points of the curve by time of day, e.g. `"09:00" = { brightness = 60, kelvin = 5000 }`
+    pub points: BTreeMap<String, DayCurvePoint>,
+    /// Hours to pause after the brightness or temperature is changed manually
+    pub pause_hours: u64,
+}
+
+/// State of the lights at a time of day, a value without points is left alone
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DayCurvePoint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<Brightness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kelvin: Option<Kelvin>,
+}
+
+impl Default for DayCurveConfig {
+    fn default() -> Self {
+        DayCurveConfig {
+            enabled: false,
+            devices: vec![],
+            points: BTreeMap::new(),
+            pause_hours: 2,
+        }
+    }
+}
+
+/// Meeting mode of the daemon, started with `elgato-keylight-cli meeting start`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MeetingConfig {
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Preset applied during the meeting, defaults to turning the lights on
+    pub preset: Option<String>,
+    /// Automations paused during the meeting, by source
+    pub suppress: Vec<String>,
+    /// Desktop notification when the meeting starts and ends
+    pub notify: bool,
+}
+
+impl Default for MeetingConfig {
+    fn default() -> Self {
+        let suppress = [
+            "ambient",
+            "ambilight",
+            "audio",
+            "camera",
+            "circadian",
+            "day_curve",
+            "lock",
+            "microphone",
+            "presence",
+            "rules",
+            "scheduler",
+            "wm",
+        ];
+        MeetingConfig {
+            devices: vec![],
+            preset: None,
+            suppress: suppress.map(String::from).to_vec(),
+            notify: true,
+        }
+    }
+}
+
+/// Daemon automation enabling the Do Not Disturb mode of the desktop (GNOME or KDE) while the
+/// lights are on air
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DoNotDisturbConfig {
+    pub enabled: bool,
+    /// Lights to watch, all of them if empty
+    pub devices: Vec<String>,
+    /// Presets putting the lights on air
+    pub presets: Vec<String>,
+}
+
+impl Default for DoNotDisturbConfig {
+    fn default() -> Self {
+        DoNotDisturbConfig {
+            enabled: false,
+            devices: vec![],
+            presets: vec!["streaming".to_string(), "meeting".to_string()],
+        }
+    }
+}
+
+/// Daemon automation turning the lights off while the session is locked or idle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    pub off_on_lock: bool,
+    /// Minutes of inactivity before turning off, never if unset
+    pub idle_minutes: Option<u64>,
+    /// Turn the lights back on when the session is unlocked or active again
+    pub restore: bool,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        LockConfig {
+            enabled: false,
+            devices: vec![],
+            off_on_lock: true,
+            idle_minutes: None,
+            restore: true,
+        }
+    }
+}
+
+/// Daemon automation restoring the lights after a suspend, enabled by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumeConfig {
+    pub enabled: bool,
+    /// Seconds to wait for the network after resuming, multiplied by the attempt number on each retry
+    pub delay: u64,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        ResumeConfig {
+            enabled: true,
+            delay: 5,
+        }
+    }
+}
+
+/// URLs the daemon posts a JSON event to whenever a light changes state
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    pub urls: Vec<url::Url>,
+}
+
+/// Rhai scripts run by the daemon on every event, requires the `scripting` feature
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptsConfig {
+    pub files: Vec<PathBuf>,
+}
+
+/// Daemon endpoint firing predefined actions, e.g. from a doorbell or a CI pipeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggersConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    /// Token required by the requests
+    pub token: Option<String>,
+    /// Actions by name, fired with `POST /trigger/<name>`
+    pub actions: BTreeMap<String, TriggerAction>,
+}
+
+impl Default for TriggersConfig {
+    fn default() -> Self {
+        TriggersConfig {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 8081)),
+            token: None,
+            actions: BTreeMap::new(),
+        }
+    }
+}
+
+/// Action fired by a trigger: the preset is applied, then the power set, then the lights blink
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerAction {
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    pub preset: Option<String>,
+    pub power: Option<PowerStatus>,
+    /// Number of blinks
+    pub blink: u32,
+}
+
+/// Daemon automation applying presets depending on the focused workspace or application,
+/// through the i3/sway IPC or the Hyprland event socket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WmConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// The preset of the first matching rule is applied
+    pub rules: Vec<WmRule>,
+    /// Preset applied when no rule matches
+    pub default_preset: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WmRule {
+    /// Name of the focused workspace
+    pub workspace: Option<String>,
+    /// Class or app id of the focused window
+    pub app: Option<String>,
+    pub preset: String,
+}
+
+/// Daemon automation applying a preset while an application runs, e.g. OBS or Zoom
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppsConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// Seconds between two scans of the running processes
+    pub interval: u64,
+    /// The profile of the first running application is applied
+    pub profiles: Vec<AppProfile>,
+}
+
+impl Default for AppsConfig {
+    fn default() -> Self {
+        AppsConfig {
+            enabled: false,
+            devices: vec![],
+            interval: 5,
+            profiles: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppProfile {
+    /// Process name, case insensitive
+    pub process: String,
+    pub preset: String,
+}
+
+/// Daemon automation turning the lights off while a phone is away from the network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    pub enabled: bool,
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    /// IP address of the phone, pinged on each check
+    pub host: Option<IpAddr>,
+    /// MAC address of the phone, looked up in the ARP table
+    pub mac: Option<String>,
+    /// Seconds between two checks
+    pub interval: u64,
+    /// Minutes without seeing the phone before turning the lights off
+    pub away_minutes: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            enabled: false,
+            devices: vec![],
+            host: None,
+            mac: None,
+            interval: 30,
+            away_minutes: 10,
+        }
+    }
+}
+
+/// Daemon integration accepting simple commands ("lights on", "brightness 40") from chat bots
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    /// Lights to control, all of them if empty
+    pub devices: Vec<String>,
+    pub telegram: Option<TelegramConfig>,
+    pub matrix: Option<MatrixConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TelegramConfig {
+    /// Token of the bot, from @BotFather
+    pub token: String,
+    /// Ids of the users whose messages are answered
+    pub allowed_users: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// e.g. `https://matrix.org`
+    pub homeserver: url::Url,
+    /// Access token of the bot account
+    pub access_token: String,
+    /// Room id or alias to join
+    pub room: String,
+    /// Ids of the users whose messages are answered, e.g. `@me:matrix.org`
+    pub allowed_users: Vec<String>,
+}
+
+/// Entry of the daemon scheduler, fired by a cron expression or once at a given time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub name: String,
+    /// Cron expression, e.g. `0 8 * * 1-5` for 8:00 on weekdays
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    /// One-shot time, e.g. `2024-09-01T08:00:00+02:00`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime<FixedOffset>>,
+    /// Lights to control, all of them if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    pub action: ScheduleAction,
+}
+
+/// Automation rule: `then` runs on the lights when the `when` condition becomes true,
+/// e.g. `when = "camera_on and time < 18:00"` and `then = "preset daylight"`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub when: String,
+    pub then: String,
+    /// Lights to control, all of them if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+}
+
+/// Action of a schedule, e.g. `action = { preset = "morning" }`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleAction {
+    Power(PowerStatus),
+    Preset(String),
+    /// Gradual change, e.g. `{ fade = { brightness = 10, duration = 600 } }`
+    Fade(Fade),
+    /// Scene of the scenes directory, which picks its own lights
+    Scene(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fade {
+    #[serde(flatten)]
+    pub target: LightUpdate,
+    /// Seconds
+    pub duration: u64,
+}
+
+impl Config {
+    /// Directory of the config file, next to which the scenes are stored
+    pub fn dir() -> Result<PathBuf, ConfigError> {
+        let dir = dirs::config_dir().ok_or(ConfigError::NoConfigDir)?;
+        Ok(dir.join(CONFIG_DIR_NAME))
+    }
+
+    /// Default location of the config file
+    pub fn path() -> Result<PathBuf, ConfigError> {
+        Ok(Self::dir()?.join(CONFIG_FILE_NAME))
+    }
+
+    /// Load the config from the default location, or the default config if there is none
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&Self::path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// `update` with the calibration offsets of `device`, if any
+    pub fn calibrated(&self, device: &str, update: &LightUpdate) -> LightUpdate {
+        match self.calibration.get(device) {
+            Some(calibration) => calibration.apply(update),
+            None => update.clone(),
+        }
+    }
+
+    /// Save the config to the default location
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&Self::path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Save the UI scale to the config file at the default location, leaving the rest of the file,
+    /// comments included, as it is
+    pub fn save_gui_scale(scale: f32) -> Result<(), ConfigError> {
+        Self::save_gui_scale_to(&Self::path()?, scale)
+    }
+
+    pub fn save_gui_scale_to(path: &Path, scale: f32) -> Result<(), ConfigError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        // Also makes sure `gui` is a table
+        toml::from_str::<Config>(&content)?;
+        let mut document: toml_edit::DocumentMut = content.parse()?;
+        // Through its shortest representation, 1.1 rather than 1.100000023841858
+        let scale: f64 = scale.to_string().parse().unwrap_or(f64::from(scale));
+        document["gui"]["scale"] = toml_edit::value(scale);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, document.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME);
+
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        let config = Config {
+            gui: GuiConfig {
+                scale: Some(1.5),
+                brightness_step: Some(5),
+                temperature_step: None,
+            },
+            tray: TrayConfig {
+                toggle_device: Some("Elgato Key Light 8D7C".to_string()),
+            },
+            presets: BTreeMap::from([(
+                "meeting".to_string(),
+                LightUpdate {
+                    brightness: Some(crate::Brightness::new(40).unwrap()),
+                    ..Default::default()
+                },
+            )]),
+            rooms: BTreeMap::from([(
+                "Studio".to_string(),
+                vec!["Elgato Key Light 8D7C".to_string()],
+            )]),
+            calibration: BTreeMap::from([(
+                "Elgato Key Light 8D7C".to_string(),
+                Calibration {
+                    brightness: -3,
+                    kelvin: 150,
+                },
+            )]),
+            camera: CameraConfig {
+                enabled: true,
+                backend: CameraBackend::PipeWire,
+                ..Default::default()
+            },
+            microphone: MicrophoneConfig {
+                enabled: true,
+                preset: Some("meeting".to_string()),
+                ..Default::default()
+            },
+            obs: ObsConfig {
+                scenes: BTreeMap::from([("Gaming".to_string(), "meeting".to_string())]),
+                ..Default::default()
+            },
+            hotkeys: HotkeysConfig {
+                enabled: true,
+                devices: vec![],
+                bindings: BTreeMap::from([("ctrl+alt+L".to_string(), "toggle".to_string())]),
+            },
+            control: ControlConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ipc: IpcConfig {
+                enabled: false,
+                socket: Some(PathBuf::from("/tmp/keylightd.sock")),
+            },
+            access: AccessConfig {
+                users: vec!["alice".to_string()],
+                groups: vec!["video".to_string()],
+                tokens: vec!["s3cret".to_string()],
+            },
+            rate_limit: RateLimitConfig {
+                requests_per_second: 0,
+            },
+            limits: LimitsConfig {
+                max_brightness: Some(Percent::new_clamped(80)),
+                min_kelvin: Some(Kelvin(4000)),
+                devices: BTreeMap::from([(
+                    "Elgato Key Light 8D7C".to_string(),
+                    DeviceLimits {
+                        address: Some("192.168.1.20:9123".to_string()),
+                        max_brightness: Some(Percent::new_clamped(60)),
+                    },
+                )]),
+                ..Default::default()
+            },
+            soft_start: SoftStartConfig {
+                enabled: true,
+                duration: 2000,
+            },
+            grpc: GrpcConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            advertise: AdvertiseConfig {
+                service_type: "_http._tcp".to_string(),
+                name: Some("Studio lights".to_string()),
+                ..Default::default()
+            },
+            ambient: AmbientConfig {
+                curve: BTreeMap::from([("08:00".to_string(), 20)]),
+                ..Default::default()
+            },
+            ambilight: AmbilightConfig {
+                region: Some("0,0 1920x200".to_string()),
+                mode: AmbilightMode::Dominant,
+                ..Default::default()
+            },
+            audio: AudioConfig {
+                source: Some("alsa_output.usb-mixer.monitor".to_string()),
+                decay: 500,
+                ..Default::default()
+            },
+            circadian: CircadianConfig {
+                latitude: 41.39,
+                longitude: 2.17,
+                ..Default::default()
+            },
+            day_curve: DayCurveConfig {
+                points: BTreeMap::from([(
+                    "09:00".to_string(),
+                    DayCurvePoint {
+                        brightness: Some(crate::Brightness::new(60).unwrap()),
+                        kelvin: Some(Kelvin(5000)),
+                    },
+                )]),
+                ..Default::default()
+            },
+            do_not_disturb: DoNotDisturbConfig {
+                enabled: true,
+                presets: vec!["live".to_string()],
+                ..Default::default()
+            },
+            meeting: MeetingConfig {
+                preset: Some("meeting".to_string()),
+                suppress: vec!["circadian".to_string()],
+                notify: false,
+                ..Default::default()
+            },
+            lock: LockConfig {
+                idle_minutes: Some(10),
+                ..Default::default()
+            },
+            resume: ResumeConfig {
+                enabled: false,
+                delay: 10,
+            },
+            webhooks: WebhooksConfig {
+                urls: vec!["http://localhost:1880/keylight".parse().unwrap()],
+            },
+            scripts: ScriptsConfig {
+                files: vec![PathBuf::from("on_air.rhai")],
+            },
+            triggers: TriggersConfig {
+                token: Some("secret".to_string()),
+                actions: BTreeMap::from([(
+                    "doorbell".to_string(),
+                    TriggerAction {
+                        power: Some(PowerStatus::On),
+                        blink: 2,
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            },
+            wm: WmConfig {
+                rules: vec![WmRule {
+                    workspace: Some("call".to_string()),
+                    app: None,
+                    preset: "meeting".to_string(),
+                }],
+                ..Default::default()
+            },
+            apps: AppsConfig {
+                profiles: vec![AppProfile {
+                    process: "obs".to_string(),
+                    preset: "streaming".to_string(),
+                }],
+                ..Default::default()
+            },
+            presence: PresenceConfig {
+                host: Some("192.168.1.20".parse().unwrap()),
+                mac: Some("aa:bb:cc:dd:ee:ff".to_string()),
+                ..Default::default()
+            },
+            chat: ChatConfig {
+                devices: vec![],
+                telegram: Some(TelegramConfig {
+                    token: "123:abc".to_string(),
+                    allowed_users: vec![42],
+                }),
+                matrix: None,
+            },
+            history: HistoryConfig {
+                enabled: false,
+                retention_days: 30,
+            },
+            network: NetworkConfig {
+                proxy: Some("socks5h://localhost:1080".to_string()),
+                tokens: BTreeMap::from([(
+                    "https://lights.example.com/".to_string(),
+                    "s3cret".to_string(),
+                )]),
+            },
+            schedules: vec![
+                Schedule {
+                    name: "morning".to_string(),
+                    cron: Some("0 8 * * 1-5".to_string()),
+                    at: None,
+                    devices: vec![],
+                    action: ScheduleAction::Preset("meeting".to_string()),
+                },
+                Schedule {
+                    name: "evening".to_string(),
+                    cron: None,
+                    at: Some("2024-09-01T20:00:00+02:00".parse().unwrap()),
+                    devices: vec!["Elgato Key Light 8D7C".to_string()],
+                    action: ScheduleAction::Fade(Fade {
+                        target: LightUpdate {
+                            brightness: Some(crate::Brightness::new(10).unwrap()),
+                            ..Default::default()
+                        },
+                        duration: 600,
+                    }),
+                },
+            ],
+            rules: vec![Rule {
+                when: "camera_on and time < 18:00".to_string(),
+                then: "preset daylight".to_string(),
+                devices: vec![],
+            }],
+        };
+        config.save_to(&path).unwrap();
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+
+        std::fs::write(&path, "[tray]\ntoggle_device = 3").unwrap();
+        assert!(Config::load_from(&path).is_err());
+    }
+
+    #[test]
+    fn save_gui_scale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME);
+
+        Config::save_gui_scale_to(&path, 1.5).unwrap();
+        assert_eq!(Config::load_from(&path).unwrap().gui.scale, Some(1.5));
+
+        // Only the scale is changed, the comments and the sections added meanwhile are kept
+        let content =
+            "# Lights of the office\n[rooms]\nOffice = [\"Left\"]\n\n[gui]\nscale = 2.0\n";
+        std::fs::write(&path, content).unwrap();
+        Config::save_gui_scale_to(&path, 1.1).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            content.replace("scale = 2.0", "scale = 1.1")
+        );
+
+        std::fs::write(&path, "gui = 3").unwrap();
+        assert!(Config::save_gui_scale_to(&path, 1.5).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "gui = 3");
+    }
+
+    #[test]
+    fn device_limits() {
+        let limits = LimitsConfig {
+            devices: BTreeMap::from([(
+                "Left".to_string(),
+                DeviceLimits {
+                    address: Some("192.168.1.20:9123".to_string()),
+                    max_brightness: Some(Percent::new_clamped(50)),
+                },
+            )]),
+            ..Default::default()
+        };
+        let max_brightness = Some(Percent::new_clamped(50));
+        assert_eq!(limits.device_max_brightness("Left"), max_brightness);
+        assert_eq!(
+            limits.device_max_brightness("192.168.1.20:9123"),
+            max_brightness
+        );
+        assert_eq!(limits.device_max_brightness("192.168.1.21:9123"), None);
+        assert_eq!(limits.device_max_brightness("Right"), None);
+    }
+
+    #[test]
+    fn calibration() {
+        let calibration = Calibration {
+            brightness: 5,
+            kelvin: -200,
+        };
+        let update = LightUpdate {
+            power: Some(PowerStatus::On),
+            brightness: Some(Brightness::new(98).unwrap()),
+            kelvin: Some(Kelvin(5000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            calibration.apply(&update),
+            LightUpdate {
+                brightness: Some(Brightness::new(100).unwrap()),
+                kelvin: Some(Kelvin(4800)),
+                ..update.clone()
+            }
+        );
+        // 5000 K
+        let mireds = LightUpdate {
+            temperature: crate::Temperature::new(200).ok(),
+            ..Default::default()
+        };
+        assert_eq!(
+            calibration.apply(&mireds).temperature,
+            crate::Temperature::new(208).ok()
+        );
+
+        let config = Config {
+            calibration: BTreeMap::from([("Left".to_string(), calibration)]),
+            ..Default::default()
+        };
+        assert_eq!(config.calibrated("Right", &update), update);
+        assert_eq!(
+            config.calibrated("Left", &LightUpdate::default()),
+            LightUpdate::default()
+        );
+    }
+}
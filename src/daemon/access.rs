@@ -0,0 +1,116 @@
+use crate::AccessConfig;
+
+/// Whether the `Authorization: Bearer <token>` header holds one of the configured tokens,
+/// always true when there are none
+pub fn token_allowed(config: &AccessConfig, authorization: Option<&str>) -> bool {
+    token_valid(
+        config,
+        authorization.and_then(|header| header.strip_prefix("Bearer ")),
+    )
+}
+
+/// Whether `token` is one of the configured tokens, always true when there are none
+pub fn token_valid(config: &AccessConfig, token: Option<&str>) -> bool {
+    if config.tokens.is_empty() {
+        return true;
+    }
+    let Some(token) = token else {
+        return false;
+    };
+    config
+        .tokens
+        .iter()
+        .any(|allowed| constant_time_eq(allowed.as_bytes(), token.trim().as_bytes()))
+}
+
+/// Compare without leaking the length of the common prefix through the timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Whether the user `uid`, whose primary group is `gid`, may change the lights over IPC:
+/// root, the user running the daemon and the configured users and groups
+#[cfg(unix)]
+pub fn peer_allowed(config: &AccessConfig, uid: u32, gid: u32) -> bool {
+    if uid == 0 || uid == uzers::get_effective_uid() {
+        return true;
+    }
+    let user = uzers::get_user_by_uid(uid);
+    let user_name = user
+        .as_ref()
+        .map(|user| user.name().to_string_lossy().into_owned());
+    if config
+        .users
+        .iter()
+        .any(|allowed| matches_id(allowed, uid, user_name.as_deref()))
+    {
+        return true;
+    }
+    if config.groups.is_empty() {
+        return false;
+    }
+
+    let group_name = |group: &uzers::Group| group.name().to_string_lossy().into_owned();
+    let mut groups = vec![(gid, uzers::get_group_by_gid(gid).as_ref().map(group_name))];
+    if let Some(user) = &user {
+        let supplementary = uzers::get_user_groups(user.name(), gid).unwrap_or_default();
+        groups.extend(
+            supplementary
+                .iter()
+                .map(|group| (group.gid(), Some(group_name(group)))),
+        );
+    }
+    config.groups.iter().any(|allowed| {
+        groups
+            .iter()
+            .any(|(gid, name)| matches_id(allowed, *gid, name.as_deref()))
+    })
+}
+
+/// Whether `allowed`, a name or a numeric id, designates `id` named `name`
+#[cfg(unix)]
+fn matches_id(allowed: &str, id: u32, name: Option<&str>) -> bool {
+    match allowed.parse::<u32>() {
+        Ok(allowed) => allowed == id,
+        Err(_) => name == Some(allowed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let mut config = AccessConfig::default();
+        assert!(token_allowed(&config, None));
+
+        config.tokens = vec!["s3cret".to_string()];
+        assert!(token_allowed(&config, Some("Bearer s3cret")));
+        assert!(!token_allowed(&config, Some("Bearer s3cre")));
+        assert!(!token_allowed(&config, Some("Basic s3cret")));
+        assert!(!token_allowed(&config, None));
+        assert!(token_valid(&config, Some("s3cret")));
+        assert!(!token_valid(&config, Some("Bearer s3cret")));
+        assert!(!token_valid(&config, None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn peers() {
+        let config = AccessConfig {
+            users: vec!["4242".to_string()],
+            groups: vec!["4343".to_string()],
+            ..Default::default()
+        };
+        let own = uzers::get_effective_uid();
+        assert!(peer_allowed(&config, own, 0));
+        assert!(peer_allowed(&config, 0, 0));
+        assert!(peer_allowed(&config, 4242, 4242));
+        // Primary group, unknown to the system
+        assert!(peer_allowed(&config, 4444, 4343));
+        if own != 4444 {
+            assert!(!peer_allowed(&config, 4444, 4444));
+        }
+    }
+}
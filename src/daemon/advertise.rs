@@ -0,0 +1,96 @@
+use zbus::{zvariant::OwnedObjectPath, Connection, Proxy};
+
+use crate::AdvertiseConfig;
+
+const AVAHI_DESTINATION: &str = "org.freedesktop.Avahi";
+const AVAHI_SERVER_INTERFACE: &str = "org.freedesktop.Avahi.Server";
+const AVAHI_ENTRY_GROUP_INTERFACE: &str = "org.freedesktop.Avahi.EntryGroup";
+/// Error of `AddService` when another host already uses the instance name
+const AVAHI_COLLISION_ERROR: &str = "org.freedesktop.Avahi.CollisionError";
+
+/// `AVAHI_IF_UNSPEC` and `AVAHI_PROTO_UNSPEC`: all the interfaces, IPv4 and IPv6
+const UNSPEC: i32 = -1;
+
+/// Renames tried when the instance name is taken
+const MAX_RENAMES: usize = 5;
+
+/// Published service, withdrawn by Avahi once this is dropped and its connection closed
+#[derive(Debug)]
+pub struct Advertisement {
+    _connection: Connection,
+    pub name: String,
+}
+
+/// TXT records of the service: the version of the daemon and where its API lives
+pub fn txt_records() -> Vec<Vec<u8>> {
+    [
+        concat!("version=", env!("CARGO_PKG_VERSION")),
+        "path=/",
+        "openapi=/openapi.json",
+    ]
+    .into_iter()
+    .map(|record| record.as_bytes().to_vec())
+    .collect()
+}
+
+/// Publish the REST API listening on `port` through the Avahi daemon of the system bus,
+/// the same way the lights announce themselves
+pub async fn advertise(config: &AdvertiseConfig, port: u16) -> zbus::Result<Advertisement> {
+    let connection = Connection::system().await?;
+    let server = Proxy::new(&connection, AVAHI_DESTINATION, "/", AVAHI_SERVER_INTERFACE).await?;
+    let mut name = match &config.name {
+        Some(name) => name.clone(),
+        None => {
+            let host: String = server.call("GetHostName", &()).await?;
+            format!("keylightd on {host}")
+        }
+    };
+
+    let path: OwnedObjectPath = server.call("EntryGroupNew", &()).await?;
+    let group = Proxy::new(
+        &connection,
+        AVAHI_DESTINATION,
+        path,
+        AVAHI_ENTRY_GROUP_INTERFACE,
+    )
+    .await?;
+    for attempt in 0.. {
+        let added: zbus::Result<()> = group
+            .call(
+                "AddService",
+                &(
+                    UNSPEC,
+                    UNSPEC,
+                    0u32,
+                    &name,
+                    &config.service_type,
+                    "",
+                    "",
+                    port,
+                    txt_records(),
+                ),
+            )
+            .await;
+        match added {
+            Err(zbus::Error::MethodError(error, _, _))
+                if error.as_str() == AVAHI_COLLISION_ERROR && attempt < MAX_RENAMES =>
+            {
+                name = server.call("GetAlternativeServiceName", &(&name,)).await?;
+                log::debug!("mDNS name taken, trying {name}");
+            }
+            result => {
+                result?;
+                break;
+            }
+        }
+    }
+    group.call::<_, _, ()>("Commit", &()).await?;
+    log::info!(
+        "Advertising the REST API as \"{name}\" ({}) on port {port}",
+        config.service_type
+    );
+    Ok(Advertisement {
+        _connection: connection,
+        name,
+    })
+}
@@ -0,0 +1,221 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Timelike as _;
+
+use crate::{AmbientConfig, Brightness, Percent};
+
+use super::{
+    day_curve::{interpolate, parse_minute},
+    Daemon,
+};
+
+/// Directory of the Industrial I/O devices, ambient light sensors expose `in_illuminance_*`
+const IIO_DIR: &str = "/sys/bus/iio/devices";
+
+/// Weight of the new reading in the moving average of the illuminance
+const SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmbientError {
+    #[error("No ambient light sensor found and no brightness curve configured")]
+    NoSource,
+    #[error("Invalid curve time {0}, expected HH:MM")]
+    InvalidTime(String),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Brightness keeping the exposure at `target_lux`: the darker the room, the brighter the light
+pub fn brightness_for_lux(config: &AmbientConfig, lux: f64) -> Percent {
+    let missing = ((config.target_lux - lux) / config.target_lux).clamp(0.0, 1.0);
+    let (min, max) = (
+        config.min_brightness.fraction(),
+        config.max_brightness.fraction(),
+    );
+    Percent::from_fraction(min + missing * (max - min))
+}
+
+/// Brightness at `minute` of the day, interpolated between the points of the curve
+pub fn curve_brightness(curve: &[(u32, u8)], minute: u32) -> Option<u8> {
+    interpolate(curve, minute).map(|value| value.round() as u8)
+}
+
+/// Parse the `"HH:MM" = brightness` points of the config, sorted by time
+pub fn parse_curve<'a>(
+    points: impl IntoIterator<Item = (&'a String, &'a u8)>,
+) -> Result<Vec<(u32, u8)>, AmbientError> {
+    let mut curve = points
+        .into_iter()
+        .map(|(time, brightness)| {
+            let minute =
+                parse_minute(time).ok_or_else(|| AmbientError::InvalidTime(time.clone()))?;
+            Ok((minute, *brightness))
+        })
+        .collect::<Result<Vec<_>, AmbientError>>()?;
+    curve.sort_unstable();
+    Ok(curve)
+}
+
+/// Only lets through changes larger than the threshold, avoiding flicker on noisy readings
+#[derive(Debug)]
+pub struct Hysteresis {
+    threshold: u8,
+    last: Option<u8>,
+}
+
+impl Hysteresis {
+    pub fn new(threshold: u8) -> Self {
+        Hysteresis {
+            threshold,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, value: u8) -> Option<u8> {
+        match self.last {
+            Some(last) if last.abs_diff(value) < self.threshold => None,
+            _ => {
+                self.last = Some(value);
+                Some(value)
+            }
+        }
+    }
+}
+
+/// First IIO device with an illuminance channel
+pub fn find_sensor() -> Option<PathBuf> {
+    std::fs::read_dir(IIO_DIR)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| illuminance_file(path).is_some())
+}
+
+fn illuminance_file(sensor: &Path) -> Option<PathBuf> {
+    ["in_illuminance_input", "in_illuminance_raw"]
+        .iter()
+        .map(|name| sensor.join(name))
+        .find(|path| path.exists())
+}
+
+/// Current illuminance in lux
+pub fn read_lux(sensor: &Path) -> std::io::Result<f64> {
+    let parse = |path: PathBuf| -> std::io::Result<f64> {
+        std::fs::read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(std::io::Error::other)
+    };
+    let file = illuminance_file(sensor).ok_or(std::io::ErrorKind::NotFound)?;
+    let raw = parse(file)?;
+    let scale = parse(sensor.join("in_illuminance_scale")).unwrap_or(1.0);
+    Ok(raw * scale)
+}
+
+/// Continuously adjust the brightness from the ambient light sensor, or from the
+/// brightness curve when there is no sensor
+pub async fn run(daemon: Daemon, config: AmbientConfig) -> Result<(), AmbientError> {
+    let curve = parse_curve(&config.curve)?;
+    let sensor = config.sensor.clone().or_else(find_sensor);
+    match &sensor {
+        Some(sensor) => log::info!("Adjusting brightness from {}", sensor.display()),
+        None if !curve.is_empty() => log::info!("Adjusting brightness from the curve"),
+        None => return Err(AmbientError::NoSource),
+    }
+
+    let mut hysteresis = Hysteresis::new(config.hysteresis);
+    let mut lux: Option<f64> = None;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    loop {
+        interval.tick().await;
+
+        let brightness = match &sensor {
+            Some(sensor) => match read_lux(sensor) {
+                Ok(reading) => {
+                    let smoothed = lux.map_or(reading, |lux| lux + SMOOTHING * (reading - lux));
+                    lux = Some(smoothed);
+                    brightness_for_lux(&config, smoothed).value()
+                }
+                Err(err) => {
+                    log::error!("Failed to read {}: {err}", sensor.display());
+                    continue;
+                }
+            },
+            None => {
+                let now = chrono::Local::now();
+                let Some(brightness) = curve_brightness(&curve, now.hour() * 60 + now.minute())
+                else {
+                    continue;
+                };
+                brightness
+            }
+        };
+
+        let Some(brightness) = hysteresis.update(brightness) else {
+            continue;
+        };
+        let brightness = Brightness::new_clamped(brightness);
+        log::debug!("Ambient brightness: {brightness}");
+        for device in daemon.targets(&config.devices) {
+            if let Err(err) = daemon.set_brightness(&device.name, brightness).await {
+                log::error!("Failed to set the brightness of {}: {err}", device.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn lux_to_brightness() {
+        let config = AmbientConfig {
+            target_lux: 400.0,
+            min_brightness: Percent::new(10).unwrap(),
+            max_brightness: Percent::new(90).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(brightness_for_lux(&config, 0.0).value(), 90);
+        assert_eq!(brightness_for_lux(&config, 200.0).value(), 50);
+        assert_eq!(brightness_for_lux(&config, 400.0).value(), 10);
+        assert_eq!(brightness_for_lux(&config, 10_000.0).value(), 10);
+    }
+
+    #[test]
+    fn curve() {
+        let points = BTreeMap::from([
+            ("08:00".to_string(), 20),
+            ("12:00".to_string(), 60),
+            ("20:00".to_string(), 40),
+        ]);
+        let curve = parse_curve(&points).unwrap();
+        assert_eq!(curve, vec![(480, 20), (720, 60), (1200, 40)]);
+
+        assert_eq!(curve_brightness(&curve, 480), Some(20));
+        assert_eq!(curve_brightness(&curve, 600), Some(40));
+        assert_eq!(curve_brightness(&curve, 1200), Some(40));
+        // Wraps around midnight, from 40 at 20:00 to 20 at 08:00
+        assert_eq!(curve_brightness(&curve, 0), Some(33));
+        assert_eq!(curve_brightness(&curve, 120), Some(30));
+        assert_eq!(curve_brightness(&[], 120), None);
+
+        let invalid = BTreeMap::from([("25:00".to_string(), 20)]);
+        assert!(parse_curve(&invalid).is_err());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut hysteresis = Hysteresis::new(5);
+        assert_eq!(hysteresis.update(50), Some(50));
+        assert_eq!(hysteresis.update(53), None);
+        assert_eq!(hysteresis.update(47), None);
+        assert_eq!(hysteresis.update(55), Some(55));
+        assert_eq!(hysteresis.update(51), None);
+    }
+}
@@ -0,0 +1,413 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    find_executable, AmbilightConfig, AmbilightMode, Brightness, Hue, Percent, PowerStatus,
+};
+
+use super::Daemon;
+
+/// Pixels analyzed at most per capture, the others are skipped
+const MAX_SAMPLES: usize = 4096;
+
+/// Width of the hue bins of the dominant color, in degrees
+const HUE_BIN: f64 = 10.0;
+
+/// Pixels less saturated than this don't count towards the dominant color
+const MIN_SATURATION: f64 = 0.2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AmbilightError {
+    #[error("neither grim (Wayland) nor import (X11, ImageMagick) installed")]
+    NotInstalled,
+    #[error("Invalid region {0}, expected \"x,y widthxheight\"")]
+    InvalidRegion(String),
+    #[error("Invalid screenshot: {0}")]
+    Image(&'static str),
+    #[error("{program} failed: {stderr}")]
+    Capture {
+        program: &'static str,
+        stderr: String,
+    },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Area of the screen, in the `x,y widthxheight` format of slurp and grim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for Region {
+    type Err = AmbilightError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (position, size) = s.trim().split_once(' ')?;
+            let (x, y) = position.split_once(',')?;
+            let (width, height) = size.split_once('x')?;
+            Some(Region {
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+                width: width.parse().ok().filter(|width| *width > 0)?,
+                height: height.parse().ok().filter(|height| *height > 0)?,
+            })
+        };
+        parse().ok_or_else(|| AmbilightError::InvalidRegion(s.to_string()))
+    }
+}
+
+/// Screenshot tool, writing a PPM image to its stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// `grim`, wlroots-based Wayland compositors
+    Grim,
+    /// `import` of ImageMagick, X11
+    Import,
+}
+
+impl Backend {
+    fn program(self) -> &'static str {
+        match self {
+            Backend::Grim => "grim",
+            Backend::Import => "import",
+        }
+    }
+
+    fn command(self, region: Option<Region>) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(self.program());
+        match self {
+            Backend::Grim => {
+                command.args(["-t", "ppm"]);
+                if let Some(Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                }) = region
+                {
+                    command.arg("-g").arg(format!("{x},{y} {width}x{height}"));
+                }
+                command.arg("-");
+            }
+            Backend::Import => {
+                command.args(["-silent", "-window", "root"]);
+                if let Some(Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                }) = region
+                {
+                    command
+                        .arg("-crop")
+                        .arg(format!("{width}x{height}{x:+}{y:+}"));
+                }
+                command.arg("ppm:-");
+            }
+        }
+        command
+    }
+}
+
+/// Color of the screen as shown by a Light Strip
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StripColor {
+    pub hue: Hue,
+    pub saturation: Percent,
+    pub brightness: Brightness,
+}
+
+/// RGB pixels of a binary PPM (P6) image
+pub fn parse_ppm(data: &[u8]) -> Result<Vec<[u8; 3]>, AmbilightError> {
+    // Header: magic, width, height and maximum value, separated by whitespace and comments
+    let mut fields = Vec::with_capacity(4);
+    let mut i = 0;
+    while fields.len() < 4 {
+        match data.get(i) {
+            None => return Err(AmbilightError::Image("truncated header")),
+            Some(b'#') => {
+                while data.get(i).is_some_and(|byte| *byte != b'\n') {
+                    i += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => i += 1,
+            Some(_) => {
+                let start = i;
+                while data.get(i).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                    i += 1;
+                }
+                fields.push(&data[start..i]);
+            }
+        }
+    }
+    if fields[0] != b"P6" {
+        return Err(AmbilightError::Image("not a binary PPM"));
+    }
+    let number = |field: &[u8]| -> Result<usize, AmbilightError> {
+        std::str::from_utf8(field)
+            .ok()
+            .and_then(|field| field.parse().ok())
+            .ok_or(AmbilightError::Image("invalid header"))
+    };
+    let (width, height, max) = (number(fields[1])?, number(fields[2])?, number(fields[3])?);
+    if max == 0 || max > 255 {
+        return Err(AmbilightError::Image("only 8-bit images are supported"));
+    }
+    // A single whitespace separates the header from the pixels
+    let pixels = data.get(i + 1..).unwrap_or_default();
+    let len = width * height * 3;
+    if pixels.len() < len {
+        return Err(AmbilightError::Image("truncated pixels"));
+    }
+    let scale = |value: u8| (usize::from(value) * 255 / max) as u8;
+    Ok(pixels[..len]
+        .chunks_exact(3)
+        .map(|pixel| [scale(pixel[0]), scale(pixel[1]), scale(pixel[2])])
+        .collect())
+}
+
+/// Hue in degrees, saturation and value from 0 to 1
+pub fn rgb_to_hsv([r, g, b]: [f64; 3]) -> (f64, f64, f64) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, saturation, max)
+}
+
+/// Mean color of `pixels`, as fractions from 0 to 1
+fn mean<'a>(pixels: impl Iterator<Item = &'a [u8; 3]>) -> Option<[f64; 3]> {
+    let mut sum = [0.0; 3];
+    let mut count = 0;
+    for pixel in pixels {
+        for (sum, value) in sum.iter_mut().zip(pixel) {
+            *sum += f64::from(*value);
+        }
+        count += 1;
+    }
+    (count > 0).then(|| sum.map(|sum| sum / f64::from(count) / 255.0))
+}
+
+/// Color of the screen: the mean of the pixels, or the mean of the most common saturated hue
+pub fn screen_color(pixels: &[[u8; 3]], mode: AmbilightMode) -> Option<[f64; 3]> {
+    let step = (pixels.len() / MAX_SAMPLES).max(1);
+    let samples = || pixels.iter().step_by(step);
+    if mode == AmbilightMode::Dominant {
+        let bins = (360.0 / HUE_BIN) as usize;
+        let bin = |pixel: &[u8; 3]| {
+            let (hue, saturation, value) = rgb_to_hsv(pixel.map(|value| f64::from(value) / 255.0));
+            let bin = (hue / HUE_BIN) as usize % bins;
+            (bin, saturation * value, saturation >= MIN_SATURATION)
+        };
+        let mut weights = vec![0.0; bins];
+        for (bin, weight, saturated) in samples().map(bin) {
+            if saturated {
+                weights[bin] += weight;
+            }
+        }
+        let dominant = weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(bin, _)| bin);
+        // A grayscale screen has no dominant hue
+        if let Some(dominant) = dominant {
+            return mean(samples().filter(|pixel| {
+                let (bin, _, saturated) = bin(pixel);
+                saturated && bin == dominant
+            }));
+        }
+    }
+    mean(samples())
+}
+
+/// Color sent to the strips, its value scaled within the brightness band of the config
+pub fn strip_color(config: &AmbilightConfig, rgb: [f64; 3]) -> StripColor {
+    let (hue, saturation, value) = rgb_to_hsv(rgb);
+    let (min, max) = (
+        config.min_brightness.fraction(),
+        config.max_brightness.fraction(),
+    );
+    StripColor {
+        hue: Hue::new_clamped(hue.round() as u16),
+        saturation: Percent::from_fraction(saturation),
+        brightness: Brightness::new_clamped(
+            Percent::from_fraction(min + value * (max - min)).value(),
+        ),
+    }
+}
+
+/// Time until the next capture: the frame interval, stretched when a capture took more than
+/// the CPU budget allows
+pub fn next_interval(fps: f64, cpu_budget: Percent, work: Duration) -> Duration {
+    let frame = Duration::from_secs_f64(1.0 / fps.clamp(0.1, 30.0));
+    let budget = cpu_budget.fraction();
+    let minimum = if budget > 0.0 {
+        work.div_f64(budget)
+    } else {
+        Duration::ZERO
+    };
+    frame.max(minimum).saturating_sub(work)
+}
+
+async fn capture(backend: Backend, region: Option<Region>) -> Result<Vec<[u8; 3]>, AmbilightError> {
+    let output = backend.command(region).output().await?;
+    if !output.status.success() {
+        return Err(AmbilightError::Capture {
+            program: backend.program(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    parse_ppm(&output.stdout)
+}
+
+/// Push the color of the screen, or of a region of it, to the Light Strips of the config
+pub async fn run(daemon: Daemon, config: AmbilightConfig) -> Result<(), AmbilightError> {
+    let region = config.region.as_deref().map(str::parse).transpose()?;
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let backend = if wayland && find_executable("grim").is_some() {
+        Backend::Grim
+    } else if find_executable("import").is_some() {
+        Backend::Import
+    } else {
+        return Err(AmbilightError::NotInstalled);
+    };
+    log::info!("Following the screen color ({backend:?})");
+
+    let mut last = None;
+    loop {
+        let start = Instant::now();
+        match capture(backend, region).await {
+            Ok(pixels) => {
+                if let Some(rgb) = screen_color(&pixels, config.mode) {
+                    let color = strip_color(&config, rgb);
+                    if last != Some(color) {
+                        last = Some(color);
+                        send(&daemon, &config, color).await;
+                    }
+                }
+            }
+            Err(err) => log::error!("Screen capture failed: {err}"),
+        }
+        tokio::time::sleep(next_interval(
+            config.fps,
+            config.cpu_budget,
+            start.elapsed(),
+        ))
+        .await;
+    }
+}
+
+async fn send(daemon: &Daemon, config: &AmbilightConfig, color: StripColor) {
+    log::debug!("Screen color: {color:?}");
+    let strips = daemon
+        .targets(&config.devices)
+        .into_iter()
+        .filter(|device| daemon.capabilities(&device.name).color);
+    for device in strips {
+        let result = daemon
+            .update(&device.name, |status| {
+                status.power = PowerStatus::On;
+                status.brightness = color.brightness;
+                status.temperature = None;
+                status.hue = Some(color.hue);
+                status.saturation = Some(color.saturation);
+            })
+            .await;
+        if let Err(err) = result {
+            log::error!("Failed to set the color of {}: {err}", device.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ppm(pixels: &[[u8; 3]], width: usize) -> Vec<u8> {
+        let height = pixels.len() / width;
+        let mut data = format!("P6\n# grim\n{width} {height}\n255\n").into_bytes();
+        data.extend(pixels.iter().flatten());
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let pixels = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [10, 20, 30]];
+        assert_eq!(parse_ppm(&ppm(&pixels, 2)).unwrap(), pixels);
+        assert!(parse_ppm(b"P3\n1 1\n255\n0 0 0").is_err());
+        assert!(parse_ppm(b"P6\n2 2\n255\n\x00\x00\x00").is_err());
+
+        assert_eq!(
+            "10,20 300x200".parse::<Region>().unwrap(),
+            Region {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200
+            }
+        );
+        assert!("10,20 0x200".parse::<Region>().is_err());
+        assert!("300x200".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn colors() {
+        let (hue, saturation, value) = rgb_to_hsv([0.0, 0.0, 1.0]);
+        assert_eq!((hue, saturation, value), (240.0, 1.0, 1.0));
+        assert_eq!(rgb_to_hsv([0.5, 0.5, 0.5]), (0.0, 0.0, 0.5));
+
+        // Mostly dark gray with some red: the average is a dim brown, the dominant color is red
+        let mut pixels = vec![[40, 40, 40]; 6];
+        pixels.extend([[200, 0, 0], [220, 0, 0]]);
+        let average = screen_color(&pixels, AmbilightMode::Average).unwrap();
+        assert_eq!(average, [82.5 / 255.0, 30.0 / 255.0, 30.0 / 255.0]);
+        let dominant = screen_color(&pixels, AmbilightMode::Dominant).unwrap();
+        assert_eq!(dominant, [210.0 / 255.0, 0.0, 0.0]);
+        // No saturated pixel, the average is used
+        let gray = screen_color(&[[40, 40, 40]], AmbilightMode::Dominant).unwrap();
+        assert_eq!(gray, [40.0 / 255.0; 3]);
+        assert_eq!(screen_color(&[], AmbilightMode::Average), None);
+
+        let config = AmbilightConfig {
+            min_brightness: Percent::new(20).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(
+            strip_color(&config, [0.0, 0.0, 1.0]),
+            StripColor {
+                hue: Hue::new(240).unwrap(),
+                saturation: Percent::MAX,
+                brightness: Brightness::new(100).unwrap(),
+            }
+        );
+        assert_eq!(strip_color(&config, [0.0; 3]).brightness.0, 20);
+    }
+
+    #[test]
+    fn budget() {
+        let budget = Percent::new(10).unwrap();
+        let work = Duration::from_millis(20);
+        // 2 fps leave plenty of time for a 20 ms capture
+        assert_eq!(next_interval(2.0, budget, work), Duration::from_millis(480));
+        // 10 fps would need 20% of a core, slowed down to 5 fps
+        assert_eq!(
+            next_interval(10.0, budget, work),
+            Duration::from_millis(180)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use crate::{AppProfile, AppsConfig, KeyLightStatus};
+
+use super::Daemon;
+
+/// Names of the running processes, lowercased: the `comm` of each process and the
+/// file name of its executable
+pub fn running_processes() -> HashSet<String> {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return HashSet::new();
+    };
+    let mut names = HashSet::new();
+    for process in processes.flatten() {
+        let path = process.path();
+        if let Ok(comm) = std::fs::read_to_string(path.join("comm")) {
+            names.insert(comm.trim().to_lowercase());
+        }
+        if let Ok(cmdline) = std::fs::read(path.join("cmdline")) {
+            let argv0 = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+            let argv0 = String::from_utf8_lossy(argv0);
+            if let Some(name) = argv0.rsplit('/').next().filter(|name| !name.is_empty()) {
+                names.insert(name.to_lowercase());
+            }
+        }
+    }
+    names
+}
+
+/// First profile whose application is running
+pub fn active_profile<'a>(
+    profiles: &'a [AppProfile],
+    running: &HashSet<String>,
+) -> Option<&'a AppProfile> {
+    profiles
+        .iter()
+        .find(|profile| running.contains(&profile.process.to_lowercase()))
+}
+
+/// Apply the profile of the running applications, restoring the lights when they all exit
+pub async fn run(daemon: Daemon, config: AppsConfig) {
+    log::info!("Watching {} application(s)", config.profiles.len());
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    let mut active: Option<String> = None;
+    // State of the lights before the first profile was applied
+    let mut saved: HashMap<String, KeyLightStatus> = HashMap::new();
+
+    loop {
+        interval.tick().await;
+        let running = running_processes();
+        let profile = active_profile(&config.profiles, &running);
+        if profile.map(|profile| &profile.process) == active.as_ref() {
+            continue;
+        }
+
+        match profile {
+            Some(profile) => {
+                log::info!("{} running, applying {}", profile.process, profile.preset);
+                daemon.notify(format!("{} started", profile.process));
+                if active.is_none() {
+                    saved = daemon
+                        .targets(&config.devices)
+                        .into_iter()
+                        .filter_map(|device| {
+                            let status = daemon.cached_status(&device.name)?;
+                            Some((device.name, status))
+                        })
+                        .collect();
+                }
+                daemon
+                    .turn_on_all(&config.devices, Some(&profile.preset))
+                    .await;
+                active = Some(profile.process.clone());
+            }
+            None => {
+                log::info!("No profile application running, restoring the lights");
+                if let Some(process) = active.take() {
+                    daemon.notify(format!("{process} stopped"));
+                }
+                for (name, status) in std::mem::take(&mut saved) {
+                    if let Err(err) = daemon.update(&name, move |current| *current = status).await {
+                        log::error!("Failed to restore {name}: {err}");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let profiles = vec![
+            AppProfile {
+                process: "obs".to_string(),
+                preset: "streaming".to_string(),
+            },
+            AppProfile {
+                process: "Zoom".to_string(),
+                preset: "meeting".to_string(),
+            },
+        ];
+        let running = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        assert_eq!(active_profile(&profiles, &running(&["bash"])), None);
+        assert_eq!(
+            active_profile(&profiles, &running(&["bash", "zoom"])).map(|p| &p.preset[..]),
+            Some("meeting")
+        );
+        assert_eq!(
+            active_profile(&profiles, &running(&["zoom", "obs"])).map(|p| &p.preset[..]),
+            Some("streaming")
+        );
+    }
+
+    #[test]
+    fn processes() {
+        assert!(running_processes().contains(
+            &std::fs::read_to_string("/proc/self/comm")
+                .unwrap()
+                .trim()
+                .to_lowercase()
+        ));
+    }
+}
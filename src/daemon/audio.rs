@@ -0,0 +1,252 @@
+use std::{process::Stdio, time::Duration};
+
+use tokio::{io::AsyncReadExt as _, sync::watch};
+
+use crate::{find_executable, AudioConfig, Brightness, Percent};
+
+use super::{camera::pipewire_running, Daemon};
+
+/// Sample rate of the captured audio, plenty for a level meter
+const SAMPLE_RATE: u32 = 8000;
+
+/// Audio measured at once, the brightness follows the level at this pace
+const WINDOW: Duration = Duration::from_millis(50);
+
+/// Level of the loudest signal, `i16::MAX` samples
+const FULL_SCALE: f64 = 32768.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("neither pw-record nor parec installed")]
+    NotInstalled,
+    #[error("{0} exited")]
+    Exited(&'static str),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Program capturing the system audio as raw mono 16-bit samples on its stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    PipeWire,
+    PulseAudio,
+}
+
+impl Backend {
+    fn program(self) -> &'static str {
+        match self {
+            Backend::PipeWire => "pw-record",
+            Backend::PulseAudio => "parec",
+        }
+    }
+
+    /// Capture `source`, by default what is played on the default output
+    fn command(self, source: Option<&str>) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(self.program());
+        match self {
+            Backend::PipeWire => {
+                match source {
+                    Some(source) => command.args(["--target", source]),
+                    None => command.args(["-P", "{ stream.capture.sink = true }"]),
+                };
+                command.args(["--format", "s16", "--channels", "1"]);
+                command.arg(format!("--rate={SAMPLE_RATE}")).arg("-");
+            }
+            Backend::PulseAudio => {
+                command.arg(format!(
+                    "--device={}",
+                    source.unwrap_or("@DEFAULT_MONITOR@")
+                ));
+                command.args(["--format=s16le", "--channels=1", "--raw"]);
+                command.arg(format!("--rate={SAMPLE_RATE}"));
+            }
+        }
+        command
+    }
+}
+
+/// Level of little-endian 16-bit samples in dBFS, `-inf` for silence
+pub fn level_db(samples: &[u8]) -> f64 {
+    let count = samples.len() / 2;
+    if count == 0 {
+        return f64::NEG_INFINITY;
+    }
+    let sum: f64 = samples
+        .chunks_exact(2)
+        .map(|sample| f64::from(i16::from_le_bytes([sample[0], sample[1]])) / FULL_SCALE)
+        .map(|sample| sample * sample)
+        .sum();
+    20.0 * (sum / count as f64).sqrt().log10()
+}
+
+/// Position of `db` between `floor_db` and full scale, from 0 to 1
+pub fn loudness(db: f64, floor_db: f64) -> f64 {
+    if !db.is_finite() || floor_db >= 0.0 {
+        return 0.0;
+    }
+    ((db - floor_db) / -floor_db).clamp(0.0, 1.0)
+}
+
+/// Follows the loudness, rising with the `attack` time constant and falling with the `decay`
+/// one, so that the lights pulse with the beat instead of flickering with every sample
+#[derive(Debug)]
+pub struct Envelope {
+    attack: Duration,
+    decay: Duration,
+    value: f64,
+}
+
+impl Envelope {
+    pub fn new(attack: Duration, decay: Duration) -> Self {
+        Envelope {
+            attack,
+            decay,
+            value: 0.0,
+        }
+    }
+
+    /// Move towards `target` for `elapsed`, returns the new value
+    pub fn update(&mut self, target: f64, elapsed: Duration) -> f64 {
+        let time_constant = if target > self.value {
+            self.attack
+        } else {
+            self.decay
+        };
+        if time_constant.is_zero() {
+            self.value = target;
+            return target;
+        }
+        let step = 1.0 - (-elapsed.as_secs_f64() / time_constant.as_secs_f64()).exp();
+        self.value += step * (target - self.value);
+        self.value
+    }
+}
+
+/// Brightness for an envelope `value` within the band of the config
+pub fn brightness_for(config: &AudioConfig, value: f64) -> Percent {
+    let (min, max) = (
+        config.min_brightness.fraction(),
+        config.max_brightness.fraction(),
+    );
+    Percent::from_fraction(min + value * (max - min))
+}
+
+/// Modulate the brightness of the configured lights with the level of the system audio,
+/// captured from PipeWire or PulseAudio. The changes go through the send queue, which
+/// drops the levels the lights can't keep up with.
+pub async fn run(daemon: Daemon, config: AudioConfig) -> Result<(), AudioError> {
+    let backend = if pipewire_running() && find_executable("pw-record").is_some() {
+        Backend::PipeWire
+    } else if find_executable("parec").is_some() {
+        Backend::PulseAudio
+    } else {
+        return Err(AudioError::NotInstalled);
+    };
+    log::info!("Following the audio level ({backend:?})");
+
+    let (sender, mut receiver) = watch::channel(None);
+    let capture = tokio::spawn(capture(backend, config.clone(), sender));
+    while receiver.changed().await.is_ok() {
+        let Some(brightness) = *receiver.borrow_and_update() else {
+            continue;
+        };
+        for device in daemon.targets(&config.devices) {
+            if let Err(err) = daemon.set_brightness(&device.name, brightness).await {
+                log::error!("Failed to set the brightness of {}: {err}", device.name);
+            }
+        }
+    }
+    match capture.await {
+        Ok(result) => result,
+        Err(err) => Err(std::io::Error::other(err).into()),
+    }
+}
+
+/// Read the captured audio window by window, publishing the brightness when it changes
+async fn capture(
+    backend: Backend,
+    config: AudioConfig,
+    brightness: watch::Sender<Option<Brightness>>,
+) -> Result<(), AudioError> {
+    let mut child = backend
+        .command(config.source.as_deref())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+
+    let samples = (SAMPLE_RATE as u128 * WINDOW.as_millis() / 1000) as usize;
+    let mut window = vec![0; 2 * samples];
+    let mut envelope = Envelope::new(
+        Duration::from_millis(config.attack),
+        Duration::from_millis(config.decay),
+    );
+    loop {
+        if stdout.read_exact(&mut window).await.is_err() {
+            return Err(AudioError::Exited(backend.program()));
+        }
+        let value = envelope.update(loudness(level_db(&window), config.floor_db), WINDOW);
+        let value = Brightness::new_clamped(brightness_for(&config, value).value());
+        brightness.send_if_modified(|brightness| {
+            let changed = *brightness != Some(value);
+            *brightness = Some(value);
+            changed
+        });
+        if brightness.is_closed() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(amplitude: i16, count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| {
+                let sample = if i % 2 == 0 { amplitude } else { -amplitude };
+                sample.to_le_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(level_db(&samples(0, 400)), f64::NEG_INFINITY);
+        assert!(level_db(&samples(i16::MAX, 400)).abs() < 0.01);
+        // Half the amplitude is 6 dB below
+        assert!((level_db(&samples(16384, 400)) + 6.02).abs() < 0.01);
+
+        assert_eq!(loudness(f64::NEG_INFINITY, -50.0), 0.0);
+        assert_eq!(loudness(-60.0, -50.0), 0.0);
+        assert_eq!(loudness(-25.0, -50.0), 0.5);
+        assert_eq!(loudness(0.0, -50.0), 1.0);
+
+        let config = AudioConfig {
+            min_brightness: Percent::new(20).unwrap(),
+            max_brightness: Percent::new(80).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(brightness_for(&config, 0.0).value(), 20);
+        assert_eq!(brightness_for(&config, 0.5).value(), 50);
+        assert_eq!(brightness_for(&config, 1.0).value(), 80);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut envelope = Envelope::new(Duration::from_millis(50), Duration::from_millis(500));
+        let step = Duration::from_millis(50);
+        // Rises by 1 - 1/e in one attack time constant
+        let rise = envelope.update(1.0, step);
+        assert!((rise - 0.632).abs() < 0.001);
+        // Falls much slower
+        let fall = envelope.update(0.0, step);
+        assert!(fall > 0.55 && fall < rise);
+
+        let mut instant = Envelope::new(Duration::ZERO, Duration::ZERO);
+        assert_eq!(instant.update(0.7, step), 0.7);
+        assert_eq!(instant.update(0.1, step), 0.1);
+    }
+}
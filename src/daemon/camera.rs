@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt as _;
+use inotify::{Inotify, WatchMask};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{find_executable, CameraBackend, CameraConfig};
+
+use super::Daemon;
+
+/// Directory holding the `video*` device nodes
+const DEV_DIR: &str = "/dev";
+
+/// Socket of the PipeWire daemon, relative to `$XDG_RUNTIME_DIR`
+const PIPEWIRE_SOCKET: &str = "pipewire-0";
+
+/// Changes of the PipeWire objects buffered while the camera usage is updated
+const PIPEWIRE_CHANGES_CAPACITY: usize = 16;
+
+/// Action to take on the lights after a change of the camera usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraAction {
+    TurnOn,
+    TurnOff,
+}
+
+/// Tracks the camera usage, turning off is delayed to ignore short gaps
+/// (e.g. applications probing the cameras)
+#[derive(Debug)]
+pub struct CameraState {
+    off_delay: Duration,
+    /// Lights turned on because of the camera
+    active: bool,
+    /// When the lights will be turned off if the camera stays unused
+    off_at: Option<Instant>,
+}
+
+impl CameraState {
+    pub fn new(off_delay: Duration) -> Self {
+        CameraState {
+            off_delay,
+            active: false,
+            off_at: None,
+        }
+    }
+
+    /// Deadline of the pending turn off, if any
+    pub fn off_at(&self) -> Option<Instant> {
+        self.off_at
+    }
+
+    pub fn update(&mut self, in_use: bool, now: Instant) -> Option<CameraAction> {
+        if in_use {
+            self.off_at = None;
+            if !self.active {
+                self.active = true;
+                return Some(CameraAction::TurnOn);
+            }
+            return None;
+        }
+
+        if !self.active {
+            return None;
+        }
+        match self.off_at {
+            None => {
+                self.off_at = Some(now + self.off_delay);
+                None
+            }
+            Some(off_at) if now >= off_at => {
+                self.active = false;
+                self.off_at = None;
+                Some(CameraAction::TurnOff)
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Whether any process (readable by the current user) has a `/dev/video*` device open
+pub fn camera_in_use() -> bool {
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+    processes
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.chars().all(|c| c.is_ascii_digit()))
+        })
+        .filter_map(|entry| std::fs::read_dir(entry.path().join("fd")).ok())
+        .flat_map(|fds| fds.flatten())
+        .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|target| is_video_device(&target)))
+}
+
+fn is_video_device(path: &Path) -> bool {
+    path.starts_with(DEV_DIR)
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("video"))
+}
+
+/// Turn the configured lights on while a webcam is in use
+pub async fn run(daemon: Daemon, config: CameraConfig) -> std::io::Result<()> {
+    let backend = match config.backend {
+        CameraBackend::Auto if pipewire_running() => CameraBackend::PipeWire,
+        CameraBackend::Auto => CameraBackend::Device,
+        backend => backend,
+    };
+    log::info!("Watching webcam usage ({backend:?})");
+
+    let mut state = CameraState::new(Duration::from_secs(config.off_delay));
+    match backend {
+        CameraBackend::PipeWire => watch_pipewire(&daemon, &config, &mut state).await,
+        _ => watch_devices(&daemon, &config, &mut state).await,
+    }
+}
+
+/// Apply the action resulting from the current camera usage
+async fn update(daemon: &Daemon, config: &CameraConfig, state: &mut CameraState, in_use: bool) {
+    match state.update(in_use, Instant::now()) {
+        Some(CameraAction::TurnOn) => {
+            log::info!("Camera started");
+            daemon.notify("started");
+            daemon
+                .turn_on_all(&config.devices, config.preset.as_deref())
+                .await;
+        }
+        Some(CameraAction::TurnOff) => {
+            log::info!("Camera stopped");
+            daemon.notify("stopped");
+            daemon.turn_off_all(&config.devices).await;
+        }
+        None => {}
+    }
+}
+
+/// Opens and closes of `/dev/video*` are watched with inotify, the usage itself is
+/// checked on each event by looking at the open file descriptors in `/proc`.
+async fn watch_devices(
+    daemon: &Daemon,
+    config: &CameraConfig,
+    state: &mut CameraState,
+) -> std::io::Result<()> {
+    let inotify = Inotify::init()?;
+    inotify.watches().add(
+        DEV_DIR,
+        WatchMask::OPEN | WatchMask::CLOSE_WRITE | WatchMask::CLOSE_NOWRITE,
+    )?;
+    let mut events = inotify.into_event_stream([0; 1024])?;
+
+    loop {
+        // Scans every process, kept off the runtime threads
+        let in_use = tokio::task::spawn_blocking(camera_in_use)
+            .await
+            .unwrap_or(false);
+        update(daemon, config, state, in_use).await;
+
+        let off_at = state.off_at();
+        loop {
+            tokio::select! {
+                event = events.next() => {
+                    let event = event.ok_or(std::io::ErrorKind::UnexpectedEof)??;
+                    let is_video = event
+                        .name
+                        .as_ref()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("video"));
+                    if is_video {
+                        break;
+                    }
+                }
+                _ = sleep_until(off_at) => break,
+            }
+        }
+    }
+}
+
+/// Whether the PipeWire socket of the current user exists
+pub(super) fn pipewire_running() -> bool {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .is_some_and(|dir| Path::new(&dir).join(PIPEWIRE_SOCKET).exists())
+}
+
+/// Video source nodes are monitored with `pw-dump --monitor`, a camera is in use while one of
+/// them is running
+async fn watch_pipewire(
+    daemon: &Daemon,
+    config: &CameraConfig,
+    state: &mut CameraState,
+) -> std::io::Result<()> {
+    if find_executable("pw-dump").is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "pw-dump not installed",
+        ));
+    }
+
+    let mut changes = spawn_pw_dump_monitor()?;
+    let mut nodes = VideoNodes::default();
+    loop {
+        tokio::select! {
+            objects = changes.recv() => {
+                let objects = objects.ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "pw-dump stopped")
+                })?;
+                nodes.apply(&objects);
+            }
+            _ = sleep_until(state.off_at()) => {}
+        }
+        update(daemon, config, state, nodes.running()).await;
+    }
+}
+
+/// Run `pw-dump --monitor`, whose output is a JSON array of all the objects, then one array of
+/// the changed objects per change. The arrays are parsed on a blocking thread, which stops
+/// `pw-dump` once the receiver is dropped.
+fn spawn_pw_dump_monitor() -> std::io::Result<mpsc::Receiver<Vec<Value>>> {
+    let mut child = Command::new("pw-dump")
+        .arg("--monitor")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child.stdout.take().ok_or(std::io::ErrorKind::BrokenPipe)?;
+    let (sender, receiver) = mpsc::channel(PIPEWIRE_CHANGES_CAPACITY);
+    tokio::task::spawn_blocking(move || {
+        for objects in serde_json::Deserializer::from_reader(stdout).into_iter::<Vec<Value>>() {
+            match objects {
+                Ok(objects) => {
+                    if sender.blocking_send(objects).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::error!("Failed to parse pw-dump output: {err}");
+                    break;
+                }
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+    Ok(receiver)
+}
+
+/// Media class and state of the PipeWire nodes, by object id, updated from the `pw-dump` changes
+#[derive(Debug, Default)]
+pub struct VideoNodes {
+    nodes: HashMap<u64, (Option<String>, Option<String>)>,
+}
+
+impl VideoNodes {
+    /// Apply a dump of `pw-dump`. The changed objects may only have the changed fields of their
+    /// info, the removed ones have a `null` info.
+    pub fn apply(&mut self, objects: &[Value]) {
+        for object in objects {
+            let Some(id) = object["id"].as_u64() else {
+                continue;
+            };
+            let info = &object["info"];
+            if info.is_null() {
+                self.nodes.remove(&id);
+                continue;
+            }
+            if object["type"]
+                .as_str()
+                .is_some_and(|kind| kind != "PipeWire:Interface:Node")
+            {
+                continue;
+            }
+            let (class, state) = self.nodes.entry(id).or_default();
+            if let Some(media_class) = info["props"]["media.class"].as_str() {
+                *class = Some(media_class.to_string());
+            }
+            if let Some(node_state) = info["state"].as_str() {
+                *state = Some(node_state.to_string());
+            }
+        }
+    }
+
+    /// Whether a video source node is running
+    pub fn running(&self) -> bool {
+        self.nodes.values().any(|(class, state)| {
+            class.as_deref() == Some("Video/Source") && state.as_deref() == Some("running")
+        })
+    }
+}
+
+pub(super) async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_state() {
+        let start = Instant::now();
+        let mut state = CameraState::new(Duration::from_secs(5));
+
+        assert_eq!(state.update(false, start), None);
+        assert_eq!(state.update(true, start), Some(CameraAction::TurnOn));
+        assert_eq!(state.update(true, start), None);
+
+        // Short gap, the camera is used again before the delay
+        assert_eq!(state.update(false, start), None);
+        assert_eq!(state.off_at(), Some(start + Duration::from_secs(5)));
+        assert_eq!(state.update(true, start + Duration::from_secs(1)), None);
+        assert_eq!(state.off_at(), None);
+
+        assert_eq!(state.update(false, start + Duration::from_secs(2)), None);
+        assert_eq!(state.update(false, start + Duration::from_secs(3)), None);
+        assert_eq!(
+            state.update(false, start + Duration::from_secs(7)),
+            Some(CameraAction::TurnOff)
+        );
+        assert_eq!(state.update(false, start + Duration::from_secs(8)), None);
+    }
+
+    #[test]
+    fn pipewire_monitor() {
+        let node = |id: u64, class: &str, state: &str| {
+            serde_json::json!({
+                "id": id,
+                "type": "PipeWire:Interface:Node",
+                "info": {"state": state, "props": {"media.class": class}}
+            })
+        };
+        let mut nodes = VideoNodes::default();
+        nodes.apply(&[
+            serde_json::json!({"id": 0, "type": "PipeWire:Interface:Core", "info": {}}),
+            node(42, "Video/Source", "suspended"),
+            node(43, "Audio/Sink", "running"),
+        ]);
+        assert!(!nodes.running());
+
+        // Only the changed fields
+        let started = serde_json::json!({
+            "id": 42,
+            "type": "PipeWire:Interface:Node",
+            "info": {"change-mask": ["state"], "state": "running"}
+        });
+        nodes.apply(std::slice::from_ref(&started));
+        assert!(nodes.running());
+
+        // Camera unplugged
+        nodes.apply(&[serde_json::json!({"id": 42, "info": null})]);
+        assert!(!nodes.running());
+        // Changes of an object first seen without its class
+        nodes.apply(&[started]);
+        assert!(!nodes.running());
+    }
+
+    #[test]
+    fn video_device() {
+        assert!(is_video_device(Path::new("/dev/video0")));
+        assert!(!is_video_device(Path::new("/dev/null")));
+        assert!(!is_video_device(Path::new("/tmp/video0")));
+    }
+}
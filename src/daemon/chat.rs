@@ -0,0 +1,514 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    Brightness, ChatConfig, Kelvin, KeyLightStatus, MatrixConfig, PowerStatus, TelegramConfig,
+    Temperature,
+};
+
+use super::{Daemon, DaemonError};
+
+/// Seconds a long poll waits for new messages
+const POLL_TIMEOUT: u64 = 30;
+/// Delay before retrying after a failed poll
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("Invalid response: {0}")]
+    Protocol(String),
+}
+
+/// Command sent to the bot, e.g. `lights on`, `/brightness 40` or `preset meeting`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommand {
+    Help,
+    Status,
+    Toggle,
+    Power(PowerStatus),
+    Brightness(Brightness),
+    Temperature(Temperature),
+    Preset(String),
+}
+
+impl std::str::FromStr for ChatCommand {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut words = text.split_whitespace();
+        let mut command = words.next().unwrap_or_default().to_lowercase();
+        if command == "lights" || command == "light" {
+            command = words.next().unwrap_or("status").to_lowercase();
+        }
+        // Telegram commands: `/on` or `/on@my_bot` in groups
+        let command = command.trim_start_matches('/');
+        let command = command.split('@').next().unwrap_or_default();
+        let argument = words.collect::<Vec<_>>().join(" ");
+        let value = || {
+            argument
+                .parse::<u16>()
+                .map_err(|_| format!("usage: {command} <value>"))
+        };
+
+        let command = match command {
+            "help" | "start" => ChatCommand::Help,
+            "status" => ChatCommand::Status,
+            "toggle" => ChatCommand::Toggle,
+            "on" => ChatCommand::Power(PowerStatus::On),
+            "off" => ChatCommand::Power(PowerStatus::Off),
+            "brightness" => {
+                let value = u8::try_from(value()?).map_err(|err| err.to_string())?;
+                ChatCommand::Brightness(Brightness::new(value)?)
+            }
+            // In kelvin, or the raw value of the light
+            "temperature" => match value()? {
+                kelvin @ 1000.. => ChatCommand::Temperature(Kelvin(kelvin).into()),
+                value => ChatCommand::Temperature(Temperature::new(value)?),
+            },
+            "preset" if !argument.is_empty() => ChatCommand::Preset(argument),
+            "preset" => return Err("usage: preset <name>".to_string()),
+            command => return Err(format!("unknown command: {command}, try help")),
+        };
+        Ok(command)
+    }
+}
+
+impl ChatCommand {
+    /// Run the command on the light `name`, returns its new state
+    pub async fn run(&self, daemon: &Daemon, name: &str) -> Result<KeyLightStatus, DaemonError> {
+        match self {
+            ChatCommand::Help | ChatCommand::Status => daemon.status(name).await,
+            ChatCommand::Toggle => daemon.update(name, |status| status.power.toggle()).await,
+            ChatCommand::Power(power) => daemon.update(name, |status| status.power = *power).await,
+            ChatCommand::Brightness(brightness) => {
+                daemon
+                    .update(name, |status| status.brightness = *brightness)
+                    .await
+            }
+            ChatCommand::Temperature(temperature) => {
+                daemon
+                    .update(name, |status| status.temperature = Some(*temperature))
+                    .await
+            }
+            ChatCommand::Preset(preset) => daemon.apply_preset(name, preset).await,
+        }
+    }
+}
+
+const HELP: &str = "Commands: lights on, lights off, toggle, status, brightness <0-100>, \
+                    temperature <kelvin>, preset <name>";
+
+/// Run the command `text` on the configured lights and answer with their state
+pub async fn handle_message(daemon: &Daemon, devices: &[String], text: &str) -> String {
+    let command = match text.parse::<ChatCommand>() {
+        Ok(ChatCommand::Help) => return HELP.to_string(),
+        Ok(command) => command,
+        Err(err) => return err,
+    };
+
+    let mut lines = Vec::new();
+    for device in daemon.targets(devices) {
+        let name = device.name;
+        match command.run(daemon, &name).await {
+            Ok(status) => lines.push(format!("{name}: {}", describe(&status))),
+            Err(err) => lines.push(format!("{name}: {err}")),
+        }
+    }
+    if lines.is_empty() {
+        return "No lights found".to_string();
+    }
+    lines.join("\n")
+}
+
+/// `on, 40%, 5000 K (200)`, or `on, 40%, hue 210°, saturation 80%` for a color light
+pub fn describe(status: &KeyLightStatus) -> String {
+    let mut parts = vec![status.power.to_string(), status.brightness.to_string()];
+    if let Some(temperature) = status.temperature {
+        parts.push(temperature.to_string());
+    }
+    if let (Some(hue), Some(saturation)) = (status.hue, status.saturation) {
+        parts.push(format!("hue {}°, saturation {saturation}", hue.0));
+    }
+    parts.join(", ")
+}
+
+/// Answer the messages of the allowed users sent to the Telegram bot
+pub async fn run_telegram(daemon: Daemon, devices: Vec<String>, config: TelegramConfig) {
+    let client = long_poll_client();
+    let mut offset = 0;
+    log::info!("Listening to the Telegram bot");
+    loop {
+        match telegram_updates(&client, &config, offset).await {
+            Ok(updates) => {
+                for update in updates {
+                    offset = offset.max(update.update_id + 1);
+                    let Some(message) = update.message else {
+                        continue;
+                    };
+                    let (Some(from), Some(text)) = (message.from, message.text) else {
+                        continue;
+                    };
+                    if !config.allowed_users.contains(&from.id) {
+                        log::warn!("Ignoring Telegram message from user {}", from.id);
+                        continue;
+                    }
+                    let reply = handle_message(&daemon, &devices, &text).await;
+                    if let Err(err) = telegram_send(&client, &config, message.chat.id, &reply).await
+                    {
+                        log::error!("Failed to answer on Telegram: {err}");
+                    }
+                }
+            }
+            Err(err) => {
+                log::debug!("Telegram poll failed: {err}");
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramUpdate {
+    update_id: i64,
+    message: Option<TelegramMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramMessage {
+    chat: TelegramId,
+    from: Option<TelegramId>,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TelegramId {
+    id: i64,
+}
+
+async fn telegram_updates(
+    client: &reqwest::Client,
+    config: &TelegramConfig,
+    offset: i64,
+) -> Result<Vec<TelegramUpdate>, ChatError> {
+    let response: TelegramResponse<Vec<TelegramUpdate>> = client
+        .get(format!("{TELEGRAM_API}/bot{}/getUpdates", config.token))
+        .query(&[("offset", offset), ("timeout", POLL_TIMEOUT as i64)])
+        .send()
+        .await?
+        .json()
+        .await?;
+    match response {
+        TelegramResponse {
+            ok: true,
+            result: Some(updates),
+            ..
+        } => Ok(updates),
+        response => Err(ChatError::Protocol(
+            response.description.unwrap_or_default(),
+        )),
+    }
+}
+
+async fn telegram_send(
+    client: &reqwest::Client,
+    config: &TelegramConfig,
+    chat: i64,
+    text: &str,
+) -> Result<(), ChatError> {
+    client
+        .post(format!("{TELEGRAM_API}/bot{}/sendMessage", config.token))
+        .json(&json!({ "chat_id": chat, "text": text }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Join the Matrix room and answer the messages of the allowed users
+pub async fn run_matrix(daemon: Daemon, devices: Vec<String>, config: MatrixConfig) {
+    let client = long_poll_client();
+    let (room, own_user) = loop {
+        let joined = async {
+            Ok::<_, ChatError>((
+                matrix_join(&client, &config).await?,
+                matrix_whoami(&client, &config).await?,
+            ))
+        };
+        match joined.await {
+            Ok(joined) => break joined,
+            Err(err) => {
+                log::error!("Failed to join the Matrix room {}: {err}", config.room);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    };
+    log::info!("Listening to the Matrix room {}", config.room);
+
+    // The first sync only skips the messages sent before the daemon started
+    let mut since = None;
+    loop {
+        let sync = match matrix_sync(&client, &config, &room, since.as_deref()).await {
+            Ok(sync) => sync,
+            Err(err) => {
+                log::debug!("Matrix sync failed: {err}");
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let first = since.is_none();
+        since = sync["next_batch"].as_str().map(str::to_string);
+        if first {
+            continue;
+        }
+
+        let events = sync["rooms"]["join"][&room]["timeline"]["events"].as_array();
+        for event in events.into_iter().flatten() {
+            let Some(body) = matrix_command(&config, &own_user, event) else {
+                continue;
+            };
+            let reply = handle_message(&daemon, &devices, body).await;
+            if let Err(err) = matrix_send(&client, &config, &room, &reply).await {
+                log::error!("Failed to answer on Matrix: {err}");
+            }
+        }
+    }
+}
+
+/// Body of a message of an allowed user to the bot, `None` for the other events.
+/// The notices, e.g. the answers of the bot, and the messages of the bot's own account are
+/// skipped, the bot would otherwise answer itself forever.
+fn matrix_command<'a>(
+    config: &MatrixConfig,
+    own_user: &str,
+    event: &'a serde_json::Value,
+) -> Option<&'a str> {
+    let sender = event["sender"].as_str()?;
+    let body = event["content"]["body"].as_str()?;
+    let allowed = event["type"] == "m.room.message"
+        && event["content"]["msgtype"] != "m.notice"
+        && sender != own_user
+        && config.allowed_users.iter().any(|u| u == sender);
+    allowed.then_some(body)
+}
+
+/// Client API URL of the homeserver
+fn matrix_url(config: &MatrixConfig, path: &[&str]) -> url::Url {
+    let mut url = config.homeserver.clone();
+    url.path_segments_mut()
+        .expect("homeserver is a base URL")
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3"])
+        .extend(path);
+    url
+}
+
+/// Join the configured room, returning its id
+async fn matrix_join(client: &reqwest::Client, config: &MatrixConfig) -> Result<String, ChatError> {
+    let response: serde_json::Value = client
+        .post(matrix_url(config, &["join", &config.room]))
+        .bearer_auth(&config.access_token)
+        .json(&json!({}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    response["room_id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| ChatError::Protocol(response.to_string()))
+}
+
+/// User id of the account of the access token
+async fn matrix_whoami(
+    client: &reqwest::Client,
+    config: &MatrixConfig,
+) -> Result<String, ChatError> {
+    let response: serde_json::Value = client
+        .get(matrix_url(config, &["account", "whoami"]))
+        .bearer_auth(&config.access_token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    response["user_id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| ChatError::Protocol(response.to_string()))
+}
+
+async fn matrix_sync(
+    client: &reqwest::Client,
+    config: &MatrixConfig,
+    room: &str,
+    since: Option<&str>,
+) -> Result<serde_json::Value, ChatError> {
+    let filter = json!({
+        "room": { "rooms": [room], "timeline": { "types": ["m.room.message"] } },
+        "presence": { "types": [] },
+        "account_data": { "types": [] },
+    });
+    let mut query = vec![
+        ("filter", filter.to_string()),
+        ("timeout", (POLL_TIMEOUT * 1000).to_string()),
+    ];
+    if let Some(since) = since {
+        query.push(("since", since.to_string()));
+    }
+    Ok(client
+        .get(matrix_url(config, &["sync"]))
+        .bearer_auth(&config.access_token)
+        .query(&query)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+async fn matrix_send(
+    client: &reqwest::Client,
+    config: &MatrixConfig,
+    room: &str,
+    text: &str,
+) -> Result<(), ChatError> {
+    static TRANSACTION: AtomicU64 = AtomicU64::new(0);
+    let transaction = format!(
+        "{}-{}",
+        chrono::Utc::now().timestamp_millis(),
+        TRANSACTION.fetch_add(1, Ordering::Relaxed)
+    );
+    client
+        .put(matrix_url(
+            config,
+            &["rooms", room, "send", "m.room.message", &transaction],
+        ))
+        .bearer_auth(&config.access_token)
+        .json(&json!({ "msgtype": "m.notice", "body": text }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Client whose timeout outlasts the long polls
+fn long_poll_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(POLL_TIMEOUT + 10))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Start the chat bots enabled in `config`
+pub fn spawn(daemon: &Daemon, config: &ChatConfig) {
+    if let Some(telegram) = &config.telegram {
+        tokio::spawn(super::with_source(
+            "telegram",
+            run_telegram(daemon.clone(), config.devices.clone(), telegram.clone()),
+        ));
+    }
+    if let Some(matrix) = &config.matrix {
+        tokio::spawn(super::with_source(
+            "matrix",
+            run_matrix(daemon.clone(), config.devices.clone(), matrix.clone()),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let parse = |text: &str| text.parse::<ChatCommand>();
+        assert_eq!(parse("lights on"), Ok(ChatCommand::Power(PowerStatus::On)));
+        assert_eq!(
+            parse("/off@studio_bot"),
+            Ok(ChatCommand::Power(PowerStatus::Off))
+        );
+        assert_eq!(parse("Lights"), Ok(ChatCommand::Status));
+        assert_eq!(
+            parse("brightness 40"),
+            Ok(ChatCommand::Brightness(Brightness::new(40).unwrap()))
+        );
+        assert_eq!(
+            parse("temperature 5000"),
+            Ok(ChatCommand::Temperature(Temperature::new(200).unwrap()))
+        );
+        assert_eq!(
+            parse("temperature 250"),
+            Ok(ChatCommand::Temperature(Temperature::new(250).unwrap()))
+        );
+        assert_eq!(
+            parse("/preset Late Night"),
+            Ok(ChatCommand::Preset("Late Night".to_string()))
+        );
+        assert!(parse("brightness 400").is_err());
+        assert!(parse("brightness").is_err());
+        assert!(parse("preset").is_err());
+        assert!(parse("dance").is_err());
+    }
+
+    #[test]
+    fn matrix_urls() {
+        let config = MatrixConfig {
+            homeserver: "https://matrix.example.org/".parse().unwrap(),
+            access_token: String::new(),
+            room: "#studio:example.org".to_string(),
+            allowed_users: vec![],
+        };
+        assert_eq!(
+            matrix_url(&config, &["join", &config.room]).as_str(),
+            "https://matrix.example.org/_matrix/client/v3/join/%23studio:example.org"
+        );
+    }
+
+    #[test]
+    fn matrix_commands() {
+        let config = MatrixConfig {
+            homeserver: "https://matrix.example.org/".parse().unwrap(),
+            access_token: String::new(),
+            room: "#studio:example.org".to_string(),
+            allowed_users: vec![
+                "@alice:example.org".to_string(),
+                "@bot:example.org".to_string(),
+            ],
+        };
+        let event = |sender: &str, msgtype: &str| {
+            json!({
+                "type": "m.room.message",
+                "sender": sender,
+                "content": { "msgtype": msgtype, "body": "lights on" },
+            })
+        };
+        let command = |event: &serde_json::Value| {
+            matrix_command(&config, "@bot:example.org", event).map(str::to_string)
+        };
+        assert_eq!(
+            command(&event("@alice:example.org", "m.text")),
+            Some("lights on".to_string())
+        );
+        assert_eq!(command(&event("@mallory:example.org", "m.text")), None);
+        // Answers of the bot, even from an allowed account
+        assert_eq!(command(&event("@bot:example.org", "m.notice")), None);
+        assert_eq!(command(&event("@alice:example.org", "m.notice")), None);
+        assert_eq!(command(&event("@bot:example.org", "m.text")), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{CircadianConfig, Kelvin, Temperature};
+
+use super::{Daemon, DaemonEvent};
+
+/// Interval between two temperature updates
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Julian date of 2000-01-01 12:00 UTC
+const J2000: f64 = 2_451_545.0;
+
+/// Julian date of the unix epoch
+const UNIX_EPOCH_JULIAN: f64 = 2_440_587.5;
+
+/// Sunrise and sunset of `date` at `latitude`/`longitude` (east positive), `None` during
+/// the polar day or night
+pub fn sun_times(
+    date: NaiveDate,
+    latitude: f64,
+    longitude: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    // https://en.wikipedia.org/wiki/Sunrise_equation
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)?;
+    let n = (date - epoch).num_days() as f64;
+    let mean_solar_time = n - longitude / 360.0;
+    let anomaly = (357.5291 + 0.985_600_28 * mean_solar_time).rem_euclid(360.0);
+    let m = anomaly.to_radians();
+    let center = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+    let ecliptic_longitude = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let l = ecliptic_longitude.to_radians();
+    let transit = J2000 + mean_solar_time + 0.0053 * m.sin() - 0.0069 * (2.0 * l).sin();
+    let declination = (l.sin() * 23.4397_f64.to_radians().sin()).asin();
+
+    let phi = latitude.to_radians();
+    let cos_hour_angle = ((-0.833_f64).to_radians().sin() - phi.sin() * declination.sin())
+        / (phi.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos() * 180.0 / PI;
+
+    let to_utc = |julian: f64| {
+        DateTime::from_timestamp(((julian - UNIX_EPOCH_JULIAN) * 86_400.0).round() as i64, 0)
+    };
+    Some((
+        to_utc(transit - hour_angle / 360.0)?,
+        to_utc(transit + hour_angle / 360.0)?,
+    ))
+}
+
+/// How much of the day it is, from 0 (night) to 1 (day), ramping over `transition` around
+/// sunrise and sunset
+pub fn daylight(
+    now: DateTime<Utc>,
+    sunrise: DateTime<Utc>,
+    sunset: DateTime<Utc>,
+    transition: chrono::Duration,
+) -> f64 {
+    let ramp = |edge: DateTime<Utc>| {
+        let half = transition / 2;
+        if transition.is_zero() {
+            return if now >= edge { 1.0 } else { 0.0 };
+        }
+        let elapsed = (now - (edge - half)).num_seconds() as f64;
+        (elapsed / transition.num_seconds() as f64).clamp(0.0, 1.0)
+    };
+    ramp(sunrise) - ramp(sunset)
+}
+
+/// Temperature of the light at `now`
+pub fn temperature_at(config: &CircadianConfig, now: DateTime<Utc>) -> Temperature {
+    let transition = chrono::Duration::minutes(config.transition as i64);
+    let daylight = match sun_times(now.date_naive(), config.latitude, config.longitude) {
+        Some((sunrise, sunset)) => daylight(now, sunrise, sunset, transition),
+        // Polar day or night, depending on the season of the hemisphere
+        None if is_polar_day(now.date_naive(), config.latitude) => 1.0,
+        None => 0.0,
+    };
+    let (night, day) = (
+        f64::from(config.night_temperature.0),
+        f64::from(config.day_temperature.0),
+    );
+    Kelvin((night + daylight * (day - night)).round() as u16).into()
+}
+
+fn is_polar_day(date: NaiveDate, latitude: f64) -> bool {
+    use chrono::Datelike as _;
+    let summer = (4..=9).contains(&date.month());
+    summer == (latitude > 0.0)
+}
+
+/// Shift the color temperature through the day, pausing after a manual change
+pub async fn run(daemon: Daemon, config: CircadianConfig) {
+    log::info!(
+        "Following the sun at {}, {}",
+        config.latitude,
+        config.longitude
+    );
+    let pause = Duration::from_secs(config.pause_hours * 60 * 60);
+    let mut events = daemon.subscribe();
+    let mut applied: HashMap<String, Temperature> = HashMap::new();
+    let mut paused_until: Option<Instant> = None;
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            event = events.recv() => {
+                match event {
+                    Ok(DaemonEvent::StateChanged { device, status, .. }) => {
+                        let overridden = applied
+                            .get(&device.name)
+                            .is_some_and(|temperature| Some(*temperature) != status.temperature);
+                        if overridden {
+                            log::info!("Temperature of {} changed manually, pausing", device.name);
+                            applied.clear();
+                            paused_until = Some(Instant::now() + pause);
+                        }
+                    }
+                    Ok(
+                        DaemonEvent::DevicesChanged(_)
+                        | DaemonEvent::PresetApplied { .. }
+                        | DaemonEvent::Automation { .. },
+                    )
+                    | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                }
+                continue;
+            }
+        }
+
+        if paused_until.is_some_and(|until| Instant::now() < until) {
+            continue;
+        }
+        paused_until = None;
+
+        let temperature = temperature_at(&config, Utc::now());
+        for device in daemon.targets(&config.devices) {
+            let current = daemon
+                .cached_status(&device.name)
+                .and_then(|status| status.temperature);
+            if current == Some(temperature) {
+                applied.insert(device.name, temperature);
+                continue;
+            }
+            applied.insert(device.name.clone(), temperature);
+            if let Err(err) = daemon.set_temperature(&device.name, temperature).await {
+                applied.remove(&device.name);
+                log::error!("Failed to set the temperature of {}: {err}", device.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone as _;
+
+    use super::*;
+
+    #[test]
+    fn sun_times_test() {
+        // London, summer solstice: sunrise 03:43 UTC, sunset 20:21 UTC
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (sunrise, sunset) = sun_times(date, 51.5074, -0.1278).unwrap();
+        let expected_sunrise = Utc.with_ymd_and_hms(2024, 6, 21, 3, 43, 0).unwrap();
+        let expected_sunset = Utc.with_ymd_and_hms(2024, 6, 21, 20, 21, 0).unwrap();
+        assert!((sunrise - expected_sunrise).num_minutes().abs() <= 3);
+        assert!((sunset - expected_sunset).num_minutes().abs() <= 3);
+
+        // Tromsø, midnight sun
+        assert!(sun_times(date, 69.6496, 18.956).is_none());
+    }
+
+    #[test]
+    fn daylight_test() {
+        let at = |h, m| Utc.with_ymd_and_hms(2024, 6, 21, h, m, 0).unwrap();
+        let (sunrise, sunset) = (at(6, 0), at(20, 0));
+        let transition = chrono::Duration::minutes(60);
+        assert_eq!(daylight(at(3, 0), sunrise, sunset, transition), 0.0);
+        assert_eq!(daylight(at(6, 0), sunrise, sunset, transition), 0.5);
+        assert_eq!(daylight(at(12, 0), sunrise, sunset, transition), 1.0);
+        assert_eq!(daylight(at(20, 15), sunrise, sunset, transition), 0.25);
+        assert_eq!(daylight(at(23, 0), sunrise, sunset, transition), 0.0);
+    }
+}
@@ -0,0 +1,379 @@
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::error::RecvError,
+};
+
+use crate::{Brightness, KeyLightStatus, PowerStatus, Temperature};
+
+use super::{access, with_actor, with_source, Daemon, DaemonError, DaemonEvent};
+
+/// Device name targeting all the devices
+const ALL_DEVICES: &str = "*";
+
+/// Command of the line-based control protocol, the device name is the rest of the line
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    /// `list`
+    List,
+    /// `status <device>`
+    Status(String),
+    /// `toggle <device>`
+    Toggle(String),
+    /// `on <device>` or `off <device>`
+    Power(PowerStatus, String),
+    /// `brightness <value> <device>`
+    Brightness(Brightness, String),
+    /// `temperature <value> <device>`
+    Temperature(Temperature, String),
+    /// `preset <preset> <device>`
+    Preset(String, String),
+    /// `subscribe`: push a `state` line on every change
+    Subscribe,
+    /// `auth <token>`: one of the tokens of the access config, required by the changes
+    Auth(String),
+}
+
+impl ControlCommand {
+    /// Whether the command changes the lights, rather than reading them
+    pub fn changes(&self) -> bool {
+        !matches!(
+            self,
+            ControlCommand::List
+                | ControlCommand::Status(_)
+                | ControlCommand::Subscribe
+                | ControlCommand::Auth(_)
+        )
+    }
+}
+
+impl std::str::FromStr for ControlCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let args = args.trim();
+        let device = || {
+            if args.is_empty() {
+                Err("missing device".to_string())
+            } else {
+                Ok(args.to_string())
+            }
+        };
+        let value_device = || {
+            args.split_once(' ')
+                .map(|(value, device)| (value, device.trim().to_string()))
+                .filter(|(_, device)| !device.is_empty())
+                .ok_or_else(|| format!("usage: {command} <value> <device>"))
+        };
+
+        let command = match command.to_lowercase().as_str() {
+            "list" => ControlCommand::List,
+            "subscribe" => ControlCommand::Subscribe,
+            "auth" if !args.is_empty() => ControlCommand::Auth(args.to_string()),
+            "auth" => return Err("usage: auth <token>".to_string()),
+            "status" => ControlCommand::Status(device()?),
+            "toggle" => ControlCommand::Toggle(device()?),
+            "on" => ControlCommand::Power(PowerStatus::On, device()?),
+            "off" => ControlCommand::Power(PowerStatus::Off, device()?),
+            "brightness" => {
+                let (value, device) = value_device()?;
+                ControlCommand::Brightness(value.parse()?, device)
+            }
+            "temperature" => {
+                let (value, device) = value_device()?;
+                ControlCommand::Temperature(value.parse()?, device)
+            }
+            "preset" => {
+                let (preset, device) = value_device()?;
+                ControlCommand::Preset(preset.to_string(), device)
+            }
+            command => return Err(format!("unknown command: {command}")),
+        };
+        Ok(command)
+    }
+}
+
+/// `state <on> <brightness> <temperature> <device>` line sent as feedback
+pub fn state_line(device: &str, status: &KeyLightStatus) -> String {
+    format!(
+        "state {} {} {} {device}",
+        status.power as u8,
+        status.brightness.0,
+        status.temperature.map_or(0, |temperature| temperature.0)
+    )
+}
+
+/// Serve the control protocol on `listen`, e.g. for Bitfocus Companion's generic TCP module.
+///
+/// Each line is a command, answered by `ok` or `error <message>`, `state` lines report the
+/// new state of the affected devices. The changes require `auth <token>` once tokens are configured.
+pub async fn serve(daemon: Daemon, listen: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(listen).await?;
+    log::info!("Control protocol listening on {}", listener.local_addr()?);
+    serve_on(daemon, listener).await
+}
+
+pub async fn serve_on(daemon: Daemon, listener: TcpListener) -> std::io::Result<()> {
+    // Unlike IPC, the peers can't be told apart, so any local user reaches it too
+    if daemon.config().access.tokens.is_empty() {
+        log::warn!("Control protocol reachable without tokens, anyone can change the lights");
+    }
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        log::debug!("Control client connected: {peer}");
+        let daemon = daemon.clone();
+        tokio::spawn(with_source(
+            "control",
+            with_actor(peer.to_string(), async move {
+                if let Err(err) = handle_client(&daemon, stream).await {
+                    log::debug!("Control client {peer} failed: {err}");
+                }
+            }),
+        ));
+    }
+}
+
+async fn handle_client(daemon: &Daemon, stream: TcpStream) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = None;
+    // Token given by `auth`, checked against the current config on every change
+    let mut token = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let reply = match line.parse::<ControlCommand>() {
+                    Ok(ControlCommand::Subscribe) => {
+                        events = Some(daemon.subscribe());
+                        let mut reply = String::new();
+                        for device in daemon.devices() {
+                            if let Some(status) = daemon.cached_status(&device.name) {
+                                reply.push_str(&state_line(&device.name, &status));
+                                reply.push('\n');
+                            }
+                        }
+                        reply + "ok\n"
+                    }
+                    Ok(ControlCommand::Auth(given)) => {
+                        if access::token_valid(&daemon.config().access, Some(&given)) {
+                            token = Some(given);
+                            "ok\n".to_string()
+                        } else {
+                            "error invalid token\n".to_string()
+                        }
+                    }
+                    Ok(command)
+                        if command.changes()
+                            && !access::token_valid(&daemon.config().access, token.as_deref()) =>
+                    {
+                        "error a valid token is required to change the lights, see auth\n"
+                            .to_string()
+                    }
+                    Ok(command) => execute(daemon, command).await,
+                    Err(err) => format!("error {err}\n"),
+                };
+                writer.write_all(reply.as_bytes()).await?;
+            }
+            event = recv(&mut events) => {
+                if let Some(DaemonEvent::StateChanged { device, status, .. }) = event {
+                    let line = state_line(&device.name, &status) + "\n";
+                    writer.write_all(line.as_bytes()).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Next event of the subscription, or never if the client is not subscribed
+async fn recv(
+    events: &mut Option<tokio::sync::broadcast::Receiver<DaemonEvent>>,
+) -> Option<DaemonEvent> {
+    let Some(receiver) = events else {
+        return std::future::pending().await;
+    };
+    match receiver.recv().await {
+        Ok(event) => Some(event),
+        Err(RecvError::Lagged(_)) => None,
+        Err(RecvError::Closed) => {
+            *events = None;
+            None
+        }
+    }
+}
+
+/// Run the command, returns the reply lines
+async fn execute(daemon: &Daemon, command: ControlCommand) -> String {
+    if command == ControlCommand::List {
+        let mut reply: String = daemon
+            .devices()
+            .into_iter()
+            .map(|device| format!("device {}\n", device.name))
+            .collect();
+        reply.push_str("ok\n");
+        return reply;
+    }
+
+    let device = match &command {
+        ControlCommand::Status(device)
+        | ControlCommand::Toggle(device)
+        | ControlCommand::Power(_, device)
+        | ControlCommand::Brightness(_, device)
+        | ControlCommand::Temperature(_, device)
+        | ControlCommand::Preset(_, device) => device.clone(),
+        ControlCommand::List | ControlCommand::Subscribe | ControlCommand::Auth(_) => {
+            unreachable!("handled by the caller")
+        }
+    };
+    let names = if device == ALL_DEVICES {
+        daemon
+            .devices()
+            .into_iter()
+            .map(|device| device.name)
+            .collect()
+    } else {
+        vec![device]
+    };
+
+    let mut reply = String::new();
+    for name in names {
+        let result: Result<KeyLightStatus, DaemonError> = match &command {
+            ControlCommand::Status(_) => daemon.status(&name).await,
+            ControlCommand::Toggle(_) => daemon.update(&name, |status| status.power.toggle()).await,
+            ControlCommand::Power(power, _) => {
+                daemon.update(&name, |status| status.power = *power).await
+            }
+            ControlCommand::Brightness(brightness, _) => {
+                daemon
+                    .update(&name, |status| status.brightness = *brightness)
+                    .await
+            }
+            ControlCommand::Temperature(temperature, _) => {
+                daemon
+                    .update(&name, |status| status.temperature = Some(*temperature))
+                    .await
+            }
+            ControlCommand::Preset(preset, _) => daemon.apply_preset(&name, preset).await,
+            ControlCommand::List | ControlCommand::Subscribe | ControlCommand::Auth(_) => {
+                unreachable!("handled by the caller")
+            }
+        };
+        match result {
+            Ok(status) => {
+                reply.push_str(&state_line(&name, &status));
+                reply.push('\n');
+            }
+            Err(err) => return format!("{reply}error {err}\n"),
+        }
+    }
+    reply + "ok\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{avahi::AvahiState, Config};
+
+    use super::*;
+
+    #[test]
+    fn parse_command() {
+        assert_eq!("LIST".parse(), Ok(ControlCommand::List));
+        assert_eq!(
+            "toggle Elgato Key Light 8D7C".parse(),
+            Ok(ControlCommand::Toggle("Elgato Key Light 8D7C".to_string()))
+        );
+        assert_eq!(
+            "brightness 40 *".parse(),
+            Ok(ControlCommand::Brightness(
+                Brightness::new(40).unwrap(),
+                "*".to_string()
+            ))
+        );
+        assert_eq!(
+            "preset meeting Key Light".parse(),
+            Ok(ControlCommand::Preset(
+                "meeting".to_string(),
+                "Key Light".to_string()
+            ))
+        );
+        assert!("brightness 140 *".parse::<ControlCommand>().is_err());
+        assert!("brightness 40".parse::<ControlCommand>().is_err());
+        assert!("toggle".parse::<ControlCommand>().is_err());
+        assert!("explode *".parse::<ControlCommand>().is_err());
+        assert_eq!(
+            "auth s3cret".parse(),
+            Ok(ControlCommand::Auth("s3cret".to_string()))
+        );
+        assert!("auth".parse::<ControlCommand>().is_err());
+        assert!(!ControlCommand::Status("*".to_string()).changes());
+        assert!(ControlCommand::Toggle("*".to_string()).changes());
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(daemon, listener));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(b"list\ntoggle Unknown\nsubscribe\n")
+            .await
+            .unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "error Device not found: Unknown"
+        );
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn tokens() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let mut config = Config::default();
+        config.access.tokens = vec!["s3cret".to_string()];
+        let daemon = Daemon::new(config, avahi);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(daemon, listener));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(b"list\ntoggle Unknown\nauth wrong\nauth s3cret\ntoggle Unknown\n")
+            .await
+            .unwrap();
+        // Reads stay open
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "error a valid token is required to change the lights, see auth"
+        );
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "error invalid token"
+        );
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "ok");
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            "error Device not found: Unknown"
+        );
+    }
+}
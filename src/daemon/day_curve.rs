@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::Timelike as _;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{Brightness, DayCurveConfig, Kelvin, Temperature};
+
+use super::{Daemon, DaemonEvent};
+
+/// Interval between two updates
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Minutes in a day, the curves wrap around midnight
+const DAY: u32 = 24 * 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DayCurveError {
+    #[error("Invalid curve time {0}, expected HH:MM")]
+    InvalidTime(String),
+    #[error("The curve has no brightness nor temperature point")]
+    Empty,
+}
+
+/// Minute of the day of `HH:MM`
+pub fn parse_minute(time: &str) -> Option<u32> {
+    time.split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+        .filter(|(h, m)| *h < 24 && *m < 60)
+        .map(|(h, m)| h * 60 + m)
+}
+
+/// Value at `minute` of the day, interpolated between the points of `curve` sorted by minute
+pub fn interpolate<T: Copy + Into<f64>>(curve: &[(u32, T)], minute: u32) -> Option<f64> {
+    let first = *curve.first()?;
+    let last = *curve.last()?;
+    // The curve wraps around midnight
+    let (before, after) = match curve.iter().position(|(at, _)| *at > minute) {
+        Some(0) | None => (last, (first.0 + DAY, first.1)),
+        Some(index) => (curve[index - 1], curve[index]),
+    };
+    let minute = if minute < before.0 {
+        minute + DAY
+    } else {
+        minute
+    };
+    let span = after.0.saturating_sub(before.0);
+    if span == 0 {
+        return Some(before.1.into());
+    }
+    let t = f64::from(minute - before.0) / f64::from(span);
+    Some(before.1.into() + t * (after.1.into() - before.1.into()))
+}
+
+/// Brightness and temperature points of the config, each sorted by minute
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DayCurve {
+    pub brightness: Vec<(u32, u8)>,
+    /// In kelvin
+    pub temperature: Vec<(u32, u16)>,
+}
+
+impl DayCurve {
+    pub fn new(config: &DayCurveConfig) -> Result<Self, DayCurveError> {
+        let mut curve = DayCurve::default();
+        for (time, point) in &config.points {
+            let minute =
+                parse_minute(time).ok_or_else(|| DayCurveError::InvalidTime(time.clone()))?;
+            if let Some(brightness) = point.brightness {
+                curve.brightness.push((minute, brightness.0));
+            }
+            if let Some(Kelvin(kelvin)) = point.kelvin {
+                curve.temperature.push((minute, kelvin));
+            }
+        }
+        if curve.brightness.is_empty() && curve.temperature.is_empty() {
+            return Err(DayCurveError::Empty);
+        }
+        curve.brightness.sort_unstable();
+        curve.temperature.sort_unstable();
+        Ok(curve)
+    }
+
+    /// State of the lights at `minute` of the day, `None` for a value without points
+    pub fn at(&self, minute: u32) -> (Option<Brightness>, Option<Temperature>) {
+        let brightness = interpolate(&self.brightness, minute)
+            .map(|brightness| Brightness::new_clamped(brightness.round() as u8));
+        let temperature = interpolate(&self.temperature, minute)
+            .map(|kelvin| Temperature::from(Kelvin(kelvin.round() as u16)));
+        (brightness, temperature)
+    }
+}
+
+/// Follow the curve through the day, pausing after a manual change
+pub async fn run(daemon: Daemon, config: DayCurveConfig) -> Result<(), DayCurveError> {
+    let curve = DayCurve::new(&config)?;
+    log::info!("Following the day curve");
+    let pause = Duration::from_secs(config.pause_hours * 60 * 60);
+    let mut events = daemon.subscribe();
+    let mut applied: HashMap<String, (Option<Brightness>, Option<Temperature>)> = HashMap::new();
+    let mut paused_until: Option<Instant> = None;
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            event = events.recv() => {
+                match event {
+                    Ok(DaemonEvent::StateChanged { device, status, .. }) => {
+                        let overridden = applied.get(&device.name).is_some_and(
+                            |(brightness, temperature)| {
+                                brightness.is_some_and(|brightness| brightness != status.brightness)
+                                    || temperature.is_some_and(|temperature| {
+                                        Some(temperature) != status.temperature
+                                    })
+                            },
+                        );
+                        if overridden {
+                            log::info!("{} changed manually, pausing the day curve", device.name);
+                            applied.clear();
+                            paused_until = Some(Instant::now() + pause);
+                        }
+                    }
+                    Ok(
+                        DaemonEvent::DevicesChanged(_)
+                        | DaemonEvent::PresetApplied { .. }
+                        | DaemonEvent::Automation { .. },
+                    )
+                    | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Ok(()),
+                }
+                continue;
+            }
+        }
+
+        if paused_until.is_some_and(|until| Instant::now() < until) {
+            continue;
+        }
+        paused_until = None;
+
+        let now = chrono::Local::now();
+        let (brightness, temperature) = curve.at(now.hour() * 60 + now.minute());
+        for device in daemon.targets(&config.devices) {
+            applied.insert(device.name.clone(), (brightness, temperature));
+            let current = daemon.cached_status(&device.name);
+            let up_to_date = current.is_some_and(|current| {
+                brightness.map_or(true, |brightness| brightness == current.brightness)
+                    && temperature
+                        .map_or(true, |temperature| Some(temperature) == current.temperature)
+            });
+            if up_to_date {
+                continue;
+            }
+            let result = daemon
+                .update(&device.name, |status| {
+                    if let Some(brightness) = brightness {
+                        status.brightness = brightness;
+                    }
+                    if let Some(temperature) = temperature {
+                        status.temperature = Some(temperature);
+                    }
+                })
+                .await;
+            if let Err(err) = result {
+                applied.remove(&device.name);
+                log::error!("Failed to follow the day curve on {}: {err}", device.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::DayCurvePoint;
+
+    use super::*;
+
+    #[test]
+    fn day_curve() {
+        let point = |brightness: Option<u8>, kelvin: Option<u16>| DayCurvePoint {
+            brightness: brightness.map(Brightness::new_clamped),
+            kelvin: kelvin.map(Kelvin),
+        };
+        let config = DayCurveConfig {
+            points: BTreeMap::from([
+                ("07:00".to_string(), point(Some(20), Some(3000))),
+                ("09:00".to_string(), point(Some(60), Some(5000))),
+                ("18:00".to_string(), point(None, Some(4000))),
+                ("22:00".to_string(), point(Some(10), None)),
+            ]),
+            ..Default::default()
+        };
+        let curve = DayCurve::new(&config).unwrap();
+        assert_eq!(curve.brightness, vec![(420, 20), (540, 60), (1320, 10)]);
+
+        let (brightness, temperature) = curve.at(8 * 60);
+        assert_eq!(brightness, Some(Brightness::new_clamped(40)));
+        assert_eq!(temperature, Some(Temperature::from(Kelvin(4000))));
+        // Wraps around midnight, from 10 at 22:00 to 20 at 07:00
+        assert_eq!(curve.at(0).0, Some(Brightness::new_clamped(12)));
+
+        let temperatures = DayCurveConfig {
+            points: BTreeMap::from([("12:00".to_string(), point(None, Some(4500)))]),
+            ..Default::default()
+        };
+        let curve = DayCurve::new(&temperatures).unwrap();
+        assert_eq!(curve.at(600), (None, Some(Temperature::from(Kelvin(4500)))));
+
+        assert!(matches!(
+            DayCurve::new(&DayCurveConfig::default()),
+            Err(DayCurveError::Empty)
+        ));
+        let invalid = DayCurveConfig {
+            points: BTreeMap::from([("7h".to_string(), point(Some(20), None))]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            DayCurve::new(&invalid),
+            Err(DayCurveError::InvalidTime(_))
+        ));
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(parse_minute("08:30"), Some(510));
+        assert_eq!(parse_minute("24:00"), None);
+        let curve = [(480, 20u8), (720, 60)];
+        assert_eq!(interpolate(&curve, 600), Some(40.0));
+        assert_eq!(interpolate::<u8>(&[], 600), None);
+    }
+}
@@ -0,0 +1,245 @@
+use std::{collections::HashSet, fmt::Write as _};
+
+use zbus::{fdo, interface, object_server::SignalContext, Connection};
+
+use crate::{Brightness, KeyLightStatus, Temperature};
+
+use super::{Daemon, DaemonError, DaemonEvent};
+
+/// Well-known name of the daemon on the session bus
+pub const DBUS_NAME: &str = "dev.monadplus.Keylight1";
+
+/// Path of the manager object, lights are exported under `<DBUS_PATH>/devices/`
+pub const DBUS_PATH: &str = "/dev/monadplus/Keylight1";
+
+impl From<DaemonError> for fdo::Error {
+    fn from(err: DaemonError) -> Self {
+        match err {
+            DaemonError::DeviceNotFound(_) => fdo::Error::InvalidArgs(err.to_string()),
+            _ => fdo::Error::Failed(err.to_string()),
+        }
+    }
+}
+
+/// Manager object exposing the operations on all devices
+struct Manager {
+    daemon: Daemon,
+}
+
+#[interface(name = "dev.monadplus.Keylight1")]
+impl Manager {
+    /// Names of the available devices
+    async fn list_devices(&self) -> Vec<String> {
+        self.device_names()
+    }
+
+    /// Toggle the device, returns whether it is now on
+    async fn toggle(&self, device: &str) -> fdo::Result<bool> {
+        Ok(self.daemon.toggle(device).await?.into())
+    }
+
+    async fn set_brightness(&self, device: &str, brightness: u8) -> fdo::Result<()> {
+        let brightness = Brightness::new(brightness).map_err(fdo::Error::InvalidArgs)?;
+        Ok(self.daemon.set_brightness(device, brightness).await?)
+    }
+
+    async fn set_temperature(&self, device: &str, temperature: u16) -> fdo::Result<()> {
+        let temperature = Temperature::new(temperature).map_err(fdo::Error::InvalidArgs)?;
+        Ok(self.daemon.set_temperature(device, temperature).await?)
+    }
+
+    #[zbus(property)]
+    async fn devices(&self) -> Vec<String> {
+        self.device_names()
+    }
+}
+
+impl Manager {
+    fn device_names(&self) -> Vec<String> {
+        self.daemon
+            .devices()
+            .into_iter()
+            .map(|device| device.name)
+            .collect()
+    }
+}
+
+/// Object exposing the last known state of a single light as properties
+struct Light {
+    name: String,
+    status: Option<KeyLightStatus>,
+}
+
+#[interface(name = "dev.monadplus.Keylight1.Light")]
+impl Light {
+    #[zbus(property)]
+    async fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[zbus(property)]
+    async fn on(&self) -> bool {
+        self.status
+            .as_ref()
+            .map(|status| status.power.into())
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn brightness(&self) -> u8 {
+        self.status
+            .as_ref()
+            .map(|status| status.brightness.0)
+            .unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn temperature(&self) -> u16 {
+        self.status
+            .as_ref()
+            .and_then(|status| status.temperature)
+            .map(|temperature| temperature.0)
+            .unwrap_or_default()
+    }
+}
+
+/// D-Bus object path of a light. As systemd does, the bytes of the name other than ASCII
+/// alphanumerics are escaped as `_xx` in hexadecimal, so distinct names get distinct paths.
+pub fn light_path(name: &str) -> String {
+    let mut path = format!("{DBUS_PATH}/devices/");
+    if name.is_empty() {
+        path.push('_');
+    }
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() {
+            path.push(char::from(byte));
+        } else {
+            let _ = write!(path, "_{byte:02x}");
+        }
+    }
+    path
+}
+
+/// Export the daemon on the session bus
+pub async fn serve(daemon: Daemon) -> zbus::Result<Connection> {
+    let manager = Manager {
+        daemon: daemon.clone(),
+    };
+    let connection = zbus::connection::Builder::session()?
+        .name(DBUS_NAME)?
+        .serve_at(DBUS_PATH, manager)?
+        .build()
+        .await?;
+
+    let mut exported = HashSet::new();
+    for device in daemon.devices() {
+        add_light(&connection, &daemon, &device.name).await?;
+        exported.insert(device.name);
+    }
+
+    let events = daemon.subscribe();
+    tokio::spawn(forward_events(connection.clone(), daemon, exported, events));
+
+    Ok(connection)
+}
+
+async fn add_light(connection: &Connection, daemon: &Daemon, name: &str) -> zbus::Result<()> {
+    let light = Light {
+        name: name.to_string(),
+        status: daemon.cached_status(name),
+    };
+    let path = light_path(name);
+    if !connection.object_server().at(path.as_str(), light).await? {
+        log::warn!("D-Bus object {path} of {name} is already exported");
+    }
+    Ok(())
+}
+
+/// Mirror the daemon events as D-Bus objects and `PropertiesChanged` signals
+async fn forward_events(
+    connection: Connection,
+    daemon: Daemon,
+    mut exported: HashSet<String>,
+    mut events: tokio::sync::broadcast::Receiver<DaemonEvent>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("D-Bus service missed {n} events");
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+
+        if let Err(err) = forward_event(&connection, &daemon, &mut exported, event).await {
+            log::error!("Failed to forward event to D-Bus: {err}");
+        }
+    }
+}
+
+async fn forward_event(
+    connection: &Connection,
+    daemon: &Daemon,
+    exported: &mut HashSet<String>,
+    event: DaemonEvent,
+) -> zbus::Result<()> {
+    let object_server = connection.object_server();
+    match event {
+        DaemonEvent::DevicesChanged(devices) => {
+            let names: HashSet<String> = devices.into_iter().map(|device| device.name).collect();
+            for name in names.difference(exported) {
+                add_light(connection, daemon, name).await?;
+            }
+            for name in exported.difference(&names) {
+                object_server.remove::<Light, _>(light_path(name)).await?;
+            }
+            *exported = names;
+
+            let manager = object_server.interface::<_, Manager>(DBUS_PATH).await?;
+            manager
+                .get()
+                .await
+                .devices_changed(manager.signal_context())
+                .await?;
+        }
+        DaemonEvent::StateChanged { device, status, .. } => {
+            let path = light_path(&device.name);
+            if exported.insert(device.name.clone()) {
+                add_light(connection, daemon, &device.name).await?;
+            }
+            let light = object_server.interface::<_, Light>(path.as_str()).await?;
+            let mut iface = light.get_mut().await;
+            iface.status = Some(status);
+            let ctxt: &SignalContext<'_> = light.signal_context();
+            iface.on_changed(ctxt).await?;
+            iface.brightness_changed(ctxt).await?;
+            iface.temperature_changed(ctxt).await?;
+        }
+        DaemonEvent::PresetApplied { .. } | DaemonEvent::Automation { .. } => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_path_test() {
+        assert_eq!(
+            light_path("Elgato Key Light 8D7C"),
+            "/dev/monadplus/Keylight1/devices/Elgato_20Key_20Light_208D7C"
+        );
+        assert_eq!(
+            light_path("Lümen_2"),
+            "/dev/monadplus/Keylight1/devices/L_c3_bcmen_5f2"
+        );
+        assert_ne!(light_path("Key Light"), light_path("Key-Light"));
+        assert_ne!(light_path("Key_20Light"), light_path("Key Light"));
+        assert_ne!(light_path(""), light_path("_"));
+        for name in ["Lümen-2", "", "_", "8D7C"] {
+            assert!(zbus::zvariant::ObjectPath::try_from(light_path(name)).is_ok());
+        }
+    }
+}
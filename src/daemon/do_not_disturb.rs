@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use tokio::sync::broadcast::error::RecvError;
+use zbus::{zvariant::Value, Connection, Proxy};
+
+use crate::{DoNotDisturbConfig, KeyLightStatus, LightUpdate, PowerStatus};
+
+use super::{Daemon, DaemonEvent};
+
+const NOTIFICATIONS_DESTINATION: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATIONS_INTERFACE: &str = "org.freedesktop.Notifications";
+
+const GNOME_NOTIFICATIONS_SCHEMA: &str = "org.gnome.desktop.notifications";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DoNotDisturbError {
+    #[error("Unsupported desktop {0:?}, expected GNOME or KDE")]
+    Unsupported(String),
+    #[error("gsettings failed: {0}")]
+    Gsettings(String),
+    #[error(transparent)]
+    DBus(#[from] zbus::Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
+
+/// Desktop whose Do Not Disturb mode is toggled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Desktop {
+    /// The `show-banners` setting of the notifications
+    Gnome,
+    /// The inhibitions of the notification server, released with the D-Bus connection
+    Kde,
+}
+
+impl Desktop {
+    /// Desktop given by `XDG_CURRENT_DESKTOP`, e.g. `ubuntu:GNOME`
+    pub fn from_current_desktop(current: &str) -> Option<Self> {
+        current.split(':').find_map(|desktop| match desktop {
+            "GNOME" => Some(Desktop::Gnome),
+            "KDE" => Some(Desktop::Kde),
+            _ => None,
+        })
+    }
+}
+
+/// Do Not Disturb enabled by the automation, with what is needed to restore it
+enum Enabled {
+    Gnome { show_banners: bool },
+    Kde { connection: Connection, cookie: u32 },
+}
+
+/// Whether the lights are in `preset`, on
+pub fn in_preset(status: &KeyLightStatus, preset: &LightUpdate) -> bool {
+    let mut expected = status.clone();
+    preset.apply(&mut expected);
+    status.power == PowerStatus::On && expected == *status
+}
+
+/// Enable the Do Not Disturb mode of the desktop while the lights are in an on air preset,
+/// restoring it afterwards
+pub async fn run(daemon: Daemon, config: DoNotDisturbConfig) -> Result<(), DoNotDisturbError> {
+    let current = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    let desktop =
+        Desktop::from_current_desktop(&current).ok_or(DoNotDisturbError::Unsupported(current))?;
+    let presets: Vec<&LightUpdate> = config
+        .presets
+        .iter()
+        .filter_map(|name| match daemon.preset(name) {
+            Ok(preset) => Some(preset),
+            Err(err) => {
+                log::warn!("{err}, ignored");
+                None
+            }
+        })
+        .collect();
+    log::info!("Enabling Do Not Disturb ({desktop:?}) while on air");
+
+    let mut events = daemon.subscribe();
+    let mut enabled = None;
+    loop {
+        let on_air = daemon.targets(&config.devices).iter().any(|device| {
+            daemon
+                .cached_status(&device.name)
+                .is_some_and(|status| presets.iter().any(|preset| in_preset(&status, preset)))
+        });
+        match (on_air, enabled.take()) {
+            (true, None) => {
+                log::info!("On air, enabling Do Not Disturb");
+                daemon.notify("on_air");
+                match enable(desktop).await {
+                    Ok(state) => enabled = Some(state),
+                    Err(err) => log::error!("Failed to enable Do Not Disturb: {err}"),
+                }
+            }
+            (false, Some(state)) => {
+                log::info!("Off air, restoring Do Not Disturb");
+                daemon.notify("off_air");
+                if let Err(err) = restore(state).await {
+                    log::error!("Failed to restore Do Not Disturb: {err}");
+                }
+            }
+            (_, state) => enabled = state,
+        }
+
+        match events.recv().await {
+            Ok(DaemonEvent::StateChanged { .. } | DaemonEvent::DevicesChanged(_))
+            | Err(RecvError::Lagged(_)) => {}
+            Ok(_) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn enable(desktop: Desktop) -> Result<Enabled, DoNotDisturbError> {
+    match desktop {
+        Desktop::Gnome => {
+            let show_banners = gsettings(&["get", GNOME_NOTIFICATIONS_SCHEMA, "show-banners"])
+                .await?
+                .trim()
+                != "false";
+            gsettings(&["set", GNOME_NOTIFICATIONS_SCHEMA, "show-banners", "false"]).await?;
+            Ok(Enabled::Gnome { show_banners })
+        }
+        Desktop::Kde => {
+            let connection = Connection::session().await?;
+            let cookie = notifications(&connection)
+                .await?
+                .call(
+                    "Inhibit",
+                    &("keylightd", "On air", HashMap::<&str, Value>::new()),
+                )
+                .await?;
+            Ok(Enabled::Kde { connection, cookie })
+        }
+    }
+}
+
+async fn restore(enabled: Enabled) -> Result<(), DoNotDisturbError> {
+    match enabled {
+        Enabled::Gnome { show_banners } => {
+            let value = show_banners.to_string();
+            gsettings(&["set", GNOME_NOTIFICATIONS_SCHEMA, "show-banners", &value]).await?;
+        }
+        Enabled::Kde { connection, cookie } => {
+            notifications(&connection)
+                .await?
+                .call::<_, _, ()>("UnInhibit", &(cookie,))
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn notifications(connection: &Connection) -> zbus::Result<Proxy<'_>> {
+    Proxy::new(
+        connection,
+        NOTIFICATIONS_DESTINATION,
+        NOTIFICATIONS_PATH,
+        NOTIFICATIONS_INTERFACE,
+    )
+    .await
+}
+
+async fn gsettings(args: &[&str]) -> Result<String, DoNotDisturbError> {
+    let output = tokio::process::Command::new("gsettings")
+        .args(args)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(DoNotDisturbError::Gsettings(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, Temperature};
+
+    use super::*;
+
+    #[test]
+    fn desktop() {
+        assert_eq!(
+            Desktop::from_current_desktop("ubuntu:GNOME"),
+            Some(Desktop::Gnome)
+        );
+        assert_eq!(Desktop::from_current_desktop("KDE"), Some(Desktop::Kde));
+        assert_eq!(Desktop::from_current_desktop("sway"), None);
+    }
+
+    #[test]
+    fn on_air() {
+        let preset = LightUpdate {
+            power: Some(PowerStatus::On),
+            brightness: Brightness::new(80).ok(),
+            ..Default::default()
+        };
+        let mut status = KeyLightStatus::white(
+            PowerStatus::On,
+            Brightness::new(80).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        assert!(in_preset(&status, &preset));
+        status.brightness = Brightness::new(40).unwrap();
+        assert!(!in_preset(&status, &preset));
+
+        // A preset leaving the lights as they are only counts while they are on
+        status.power = PowerStatus::Off;
+        assert!(!in_preset(&status, &LightUpdate::default()));
+    }
+}
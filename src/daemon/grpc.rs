@@ -0,0 +1,379 @@
+use std::{net::SocketAddr, pin::Pin};
+
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt as _};
+use tonic::{Request, Response, Status};
+
+use crate::{scene::SceneError, Brightness, KeyLightStatus, LightUpdate, PowerStatus, Temperature};
+
+use super::{access, with_actor, with_source, Daemon, DaemonError, DaemonEvent};
+
+include!(concat!(env!("OUT_DIR"), "/keylight.v1.KeyLight.rs"));
+
+pub use key_light_client::KeyLightClient;
+pub use key_light_server::KeyLightServer;
+
+// Messages of `proto/keylight.proto`
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LightState {
+    #[prost(bool, tag = "1")]
+    pub on: bool,
+    #[prost(uint32, tag = "2")]
+    pub brightness: u32,
+    #[prost(uint32, tag = "3")]
+    pub temperature: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Device {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub status: Option<LightState>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListDevicesResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub devices: Vec<Device>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeviceRequest {
+    #[prost(string, tag = "1")]
+    pub device: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetStatusRequest {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(bool, optional, tag = "2")]
+    pub on: Option<bool>,
+    #[prost(uint32, optional, tag = "3")]
+    pub brightness: Option<u32>,
+    #[prost(uint32, optional, tag = "4")]
+    pub temperature: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ApplyPresetRequest {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(string, tag = "2")]
+    pub preset: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WatchRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StateChange {
+    #[prost(string, tag = "1")]
+    pub device: String,
+    #[prost(message, optional, tag = "2")]
+    pub status: Option<LightState>,
+    #[prost(string, tag = "3")]
+    pub source: String,
+}
+
+impl From<KeyLightStatus> for LightState {
+    fn from(status: KeyLightStatus) -> Self {
+        LightState {
+            on: status.power == PowerStatus::On,
+            brightness: status.brightness.0.into(),
+            temperature: status
+                .temperature
+                .map_or(0, |temperature| temperature.0.into()),
+        }
+    }
+}
+
+impl TryFrom<&SetStatusRequest> for LightUpdate {
+    type Error = Status;
+
+    fn try_from(request: &SetStatusRequest) -> Result<Self, Self::Error> {
+        let brightness = request
+            .brightness
+            .map(|value| {
+                u8::try_from(value)
+                    .map_err(|err| err.to_string())
+                    .and_then(Brightness::new)
+            })
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+        let temperature = request
+            .temperature
+            .map(|value| {
+                u16::try_from(value)
+                    .map_err(|err| err.to_string())
+                    .and_then(Temperature::new)
+            })
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+        Ok(LightUpdate {
+            power: request.on.map(|on| {
+                if on {
+                    PowerStatus::On
+                } else {
+                    PowerStatus::Off
+                }
+            }),
+            brightness,
+            temperature,
+            kelvin: None,
+        })
+    }
+}
+
+impl From<DaemonError> for Status {
+    fn from(err: DaemonError) -> Self {
+        let message = err.to_string();
+        match err {
+            DaemonError::DeviceNotFound(_)
+            | DaemonError::PresetNotFound(_)
+            | DaemonError::RoomNotFound(_)
+            | DaemonError::Scene(SceneError::NotFound(_)) => Status::not_found(message),
+            // Queued changes are applied once the device is back
+            DaemonError::Queued(_)
+            | DaemonError::NoLights(_)
+            | DaemonError::Request(_)
+            | DaemonError::Scene(SceneError::Device { .. }) => Status::unavailable(message),
+            DaemonError::Scene(_) | DaemonError::Unsupported(_) => {
+                Status::invalid_argument(message)
+            }
+            DaemonError::Paused(_) => Status::failed_precondition(message),
+        }
+    }
+}
+
+/// gRPC service mirroring the REST API, see `proto/keylight.proto`
+#[derive(Debug, Clone)]
+pub struct KeyLightService {
+    daemon: Daemon,
+}
+
+impl KeyLightService {
+    pub fn new(daemon: Daemon) -> Self {
+        KeyLightService { daemon }
+    }
+
+    /// Whether the `authorization: Bearer <token>` metadata holds one of the configured tokens,
+    /// required by the changes while reads stay open
+    fn authorized<T>(&self, request: &Request<T>) -> bool {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        access::token_allowed(&self.daemon.config().access, authorization)
+    }
+}
+
+/// Report gRPC as the source of the changes made by `future`, and `peer` as their actor
+async fn from_peer<F: std::future::Future>(peer: Option<SocketAddr>, future: F) -> F::Output {
+    match peer {
+        Some(peer) => with_source("grpc", with_actor(peer.to_string(), future)).await,
+        None => with_source("grpc", future).await,
+    }
+}
+
+fn unauthenticated() -> Status {
+    Status::unauthenticated("a valid bearer token is required to change the lights")
+}
+
+type StateStream = Pin<Box<dyn Stream<Item = Result<StateChange, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl key_light_server::KeyLight for KeyLightService {
+    async fn list_devices(
+        &self,
+        _: Request<ListDevicesRequest>,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        let devices = self
+            .daemon
+            .devices()
+            .into_iter()
+            .map(|device| Device {
+                status: self.daemon.cached_status(&device.name).map(Into::into),
+                name: device.name,
+            })
+            .collect();
+        Ok(Response::new(ListDevicesResponse { devices }))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<DeviceRequest>,
+    ) -> Result<Response<LightState>, Status> {
+        let status = self.daemon.status(&request.get_ref().device).await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn set_status(
+        &self,
+        request: Request<SetStatusRequest>,
+    ) -> Result<Response<LightState>, Status> {
+        if !self.authorized(&request) {
+            return Err(unauthenticated());
+        }
+        let peer = request.remote_addr();
+        let request = request.get_ref();
+        let update = LightUpdate::try_from(request)?;
+        let status = from_peer(peer, self.daemon.apply(&request.device, &update)).await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn toggle(
+        &self,
+        request: Request<DeviceRequest>,
+    ) -> Result<Response<LightState>, Status> {
+        if !self.authorized(&request) {
+            return Err(unauthenticated());
+        }
+        let update = self
+            .daemon
+            .update(&request.get_ref().device, |status| status.power.toggle());
+        let status = from_peer(request.remote_addr(), update).await?;
+        Ok(Response::new(status.into()))
+    }
+
+    async fn apply_preset(
+        &self,
+        request: Request<ApplyPresetRequest>,
+    ) -> Result<Response<LightState>, Status> {
+        if !self.authorized(&request) {
+            return Err(unauthenticated());
+        }
+        let ApplyPresetRequest { device, preset } = request.get_ref();
+        let update = self.daemon.apply_preset(device, preset);
+        let status = from_peer(request.remote_addr(), update).await?;
+        Ok(Response::new(status.into()))
+    }
+
+    type WatchStream = StateStream;
+
+    async fn watch(&self, _: Request<WatchRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let stream = BroadcastStream::new(self.daemon.subscribe()).filter_map(|event| {
+            let Ok(DaemonEvent::StateChanged {
+                device,
+                status,
+                source,
+                ..
+            }) = event
+            else {
+                return None;
+            };
+            Some(Ok(StateChange {
+                device: device.name,
+                status: Some(status.into()),
+                source: source.to_string(),
+            }))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serve the gRPC service on `listen`
+pub async fn serve(daemon: Daemon, listen: SocketAddr) -> Result<(), tonic::transport::Error> {
+    log::info!("gRPC listening on {listen}");
+    if daemon.config().access.tokens.is_empty() {
+        log::warn!("gRPC reachable without tokens, anyone can change the lights");
+    }
+    tonic::transport::Server::builder()
+        .add_service(KeyLightServer::new(KeyLightService::new(daemon)))
+        .serve(listen)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{avahi::AvahiState, Config};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn service() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(KeyLightServer::new(KeyLightService::new(daemon)))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let mut client = KeyLightClient::connect(format!("http://{address}"))
+            .await
+            .unwrap();
+        let devices = client.list_devices(ListDevicesRequest {}).await.unwrap();
+        assert_eq!(devices.into_inner().devices, vec![]);
+
+        let status = client
+            .get_status(DeviceRequest {
+                device: "Unknown".to_string(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let status = client
+            .set_status(SetStatusRequest {
+                device: "Unknown".to_string(),
+                brightness: Some(400),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn tokens() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let mut config = Config::default();
+        config.access.tokens = vec!["s3cret".to_string()];
+        let daemon = Daemon::new(config, avahi);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(KeyLightServer::new(KeyLightService::new(daemon)))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let mut client = KeyLightClient::connect(format!("http://{address}"))
+            .await
+            .unwrap();
+        let toggle = |token: Option<&str>| {
+            let mut request = Request::new(DeviceRequest {
+                device: "Unknown".to_string(),
+            });
+            if let Some(token) = token {
+                request
+                    .metadata_mut()
+                    .insert("authorization", token.parse().unwrap());
+            }
+            request
+        };
+        // Reads stay open
+        assert!(client.list_devices(ListDevicesRequest {}).await.is_ok());
+        let status = client.toggle(toggle(None)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = client
+            .toggle(toggle(Some("Bearer wrong")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        let status = client
+            .toggle(toggle(Some("Bearer s3cret")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+}
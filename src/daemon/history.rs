@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use chrono::Utc;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    append_history, prune_history, HistoryConfig, HistoryError, HistoryEvent, HistoryRecord,
+};
+
+use super::{Daemon, DaemonEvent};
+
+/// Record the state changes and applied presets to the history file at `path`,
+/// dropping the records older than the retention first
+pub async fn run(daemon: Daemon, config: HistoryConfig, path: &Path) -> Result<(), HistoryError> {
+    let before = Utc::now() - chrono::Duration::days(config.retention_days as i64);
+    prune_history(path, before)?;
+    log::info!("Recording the history to {}", path.display());
+
+    let mut events = daemon.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("History missed {n} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let (device, event) = match event {
+            DaemonEvent::StateChanged {
+                device,
+                status,
+                source,
+                actor,
+                ..
+            } => (
+                device.name,
+                HistoryEvent::State {
+                    status,
+                    source: source.to_string(),
+                    actor,
+                },
+            ),
+            DaemonEvent::PresetApplied {
+                device,
+                preset,
+                source,
+                actor,
+            } => (
+                device,
+                HistoryEvent::Preset {
+                    preset,
+                    source: source.to_string(),
+                    actor,
+                },
+            ),
+            DaemonEvent::DevicesChanged(_) | DaemonEvent::Automation { .. } => continue,
+        };
+        let record = HistoryRecord {
+            time: Utc::now(),
+            device,
+            event,
+        };
+        if let Err(err) = append_history(path, &record) {
+            log::error!("Failed to record the history: {err}");
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::HashMap, str::FromStr};
+
+use futures_util::StreamExt as _;
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use tokio::sync::mpsc;
+use zbus::{
+    zvariant::{ObjectPath, OwnedValue, Value},
+    Connection, Proxy,
+};
+
+use crate::{
+    BrightnessDelta, HotkeysConfig, KeyLightBrightness, KeyLightStatus, PowerStatus,
+    TemperatureDelta,
+};
+
+use super::Daemon;
+
+const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_INTERFACE: &str = "org.freedesktop.portal.GlobalShortcuts";
+const REQUEST_INTERFACE: &str = "org.freedesktop.portal.Request";
+
+/// `handle_token` of the portal requests, the last path element of their request object
+const SESSION_TOKEN: &str = "keylightd_session";
+const BIND_TOKEN: &str = "keylightd_bind";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HotkeyError {
+    #[error("Invalid action: {0}")]
+    InvalidAction(String),
+    #[error("Invalid hotkey {0}: {1}")]
+    InvalidHotkey(String, String),
+    #[error(transparent)]
+    Grab(#[from] global_hotkey::Error),
+    #[error(transparent)]
+    Portal(#[from] zbus::Error),
+    #[error("Global shortcuts request denied")]
+    PortalDenied,
+}
+
+/// Action bound to a hotkey, applied to all the configured lights
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotkeyAction {
+    Toggle,
+    On,
+    Off,
+    BrightnessUp,
+    BrightnessDown,
+    Warmer,
+    Cooler,
+    /// `preset:<name>`
+    Preset(String),
+}
+
+impl FromStr for HotkeyAction {
+    type Err = HotkeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let action = match s {
+            "toggle" => HotkeyAction::Toggle,
+            "on" => HotkeyAction::On,
+            "off" => HotkeyAction::Off,
+            "brightness-up" => HotkeyAction::BrightnessUp,
+            "brightness-down" => HotkeyAction::BrightnessDown,
+            "warmer" => HotkeyAction::Warmer,
+            "cooler" => HotkeyAction::Cooler,
+            _ => match s.strip_prefix("preset:") {
+                Some(preset) if !preset.is_empty() => HotkeyAction::Preset(preset.to_string()),
+                _ => return Err(HotkeyError::InvalidAction(s.to_string())),
+            },
+        };
+        Ok(action)
+    }
+}
+
+impl HotkeyAction {
+    /// Apply the action to the state of a light, presets are resolved by the daemon
+    pub fn apply(&self, status: &mut KeyLightStatus) {
+        match self {
+            HotkeyAction::Toggle => status.power.toggle(),
+            HotkeyAction::On => status.power = PowerStatus::On,
+            HotkeyAction::Off => status.power = PowerStatus::Off,
+            HotkeyAction::BrightnessUp | HotkeyAction::BrightnessDown => {
+                let step = match self {
+                    HotkeyAction::BrightnessUp => BrightnessDelta::STEP,
+                    _ => -BrightnessDelta::STEP,
+                };
+                let mut brightness = KeyLightBrightness::new_clamped(status.brightness.0);
+                step.apply(&mut brightness);
+                status.brightness = brightness.into();
+            }
+            HotkeyAction::Warmer | HotkeyAction::Cooler => {
+                let step = match self {
+                    HotkeyAction::Warmer => TemperatureDelta::STEP,
+                    _ => -TemperatureDelta::STEP,
+                };
+                // Nothing to do for a light in color mode
+                if let Some(temperature) = &mut status.temperature {
+                    step.apply(temperature);
+                }
+            }
+            HotkeyAction::Preset(_) => {}
+        }
+    }
+}
+
+/// Register the configured hotkeys and apply their action when pressed.
+/// The XDG GlobalShortcuts portal is used on Wayland, X11 key grabs otherwise.
+pub async fn run(daemon: Daemon, config: HotkeysConfig) -> Result<(), HotkeyError> {
+    let bindings = config
+        .bindings
+        .iter()
+        .map(|(hotkey, action)| Ok((hotkey.clone(), action.parse()?)))
+        .collect::<Result<Vec<(String, HotkeyAction)>, HotkeyError>>()?;
+
+    let (sender, mut pressed) = mpsc::unbounded_channel();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        log::info!("Registering {} hotkey(s) with the portal", bindings.len());
+        tokio::spawn(watch_portal(bindings.clone(), sender));
+    } else {
+        log::info!("Grabbing {} hotkey(s)", bindings.len());
+        grab(&bindings, sender)?;
+    }
+
+    while let Some(index) = pressed.recv().await {
+        let Some((hotkey, action)) = bindings.get(index) else {
+            continue;
+        };
+        log::debug!("Hotkey {hotkey} pressed: {action:?}");
+        for device in daemon.targets(&config.devices) {
+            let result = match action {
+                HotkeyAction::Preset(preset) => daemon.apply_preset(&device.name, preset).await,
+                action => {
+                    daemon
+                        .update(&device.name, |status| action.apply(status))
+                        .await
+                }
+            };
+            if let Err(err) = result {
+                log::error!("Hotkey {hotkey} failed on {}: {err}", device.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Grab the hotkeys on X11, the index of the pressed binding is sent to `sender`
+fn grab(
+    bindings: &[(String, HotkeyAction)],
+    sender: mpsc::UnboundedSender<usize>,
+) -> Result<(), HotkeyError> {
+    let hotkeys = bindings
+        .iter()
+        .map(|(hotkey, _)| {
+            HotKey::from_str(hotkey)
+                .map_err(|err| HotkeyError::InvalidHotkey(hotkey.clone(), err.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let ids: HashMap<u32, usize> = hotkeys
+        .iter()
+        .enumerate()
+        .map(|(index, hotkey)| (hotkey.id(), index))
+        .collect();
+
+    let manager = GlobalHotKeyManager::new()?;
+    manager.register_all(&hotkeys)?;
+
+    std::thread::spawn(move || {
+        // The hotkeys are released when the manager is dropped
+        let _manager = manager;
+        for event in GlobalHotKeyEvent::receiver() {
+            if event.state() != HotKeyState::Pressed {
+                continue;
+            }
+            if let Some(index) = ids.get(&event.id()) {
+                if sender.send(*index).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn watch_portal(bindings: Vec<(String, HotkeyAction)>, sender: mpsc::UnboundedSender<usize>) {
+    if let Err(err) = portal(&bindings, &sender).await {
+        log::error!("Global shortcuts portal failed: {err}");
+    }
+}
+
+/// Bind the hotkeys through the GlobalShortcuts portal, the user may change the triggers
+async fn portal(
+    bindings: &[(String, HotkeyAction)],
+    sender: &mpsc::UnboundedSender<usize>,
+) -> Result<(), HotkeyError> {
+    let connection = Connection::session().await?;
+    let shortcuts = Proxy::new(
+        &connection,
+        PORTAL_DESTINATION,
+        PORTAL_PATH,
+        PORTAL_INTERFACE,
+    )
+    .await?;
+    let mut activated = shortcuts.receive_signal("Activated").await?;
+
+    let options = HashMap::from([
+        ("handle_token", Value::from(SESSION_TOKEN)),
+        ("session_handle_token", Value::from("keylightd")),
+    ]);
+    let results = portal_request(
+        &connection,
+        &shortcuts,
+        "CreateSession",
+        SESSION_TOKEN,
+        &(options,),
+    )
+    .await?;
+    let session = match results.get("session_handle").map(|value| &**value) {
+        Some(Value::Str(handle)) => {
+            ObjectPath::try_from(handle.to_string()).map_err(zbus::Error::from)?
+        }
+        Some(Value::ObjectPath(handle)) => handle.to_owned(),
+        _ => return Err(HotkeyError::PortalDenied),
+    };
+
+    let list: Vec<(String, HashMap<&str, Value>)> = bindings
+        .iter()
+        .enumerate()
+        .map(|(index, (hotkey, action))| {
+            let properties = HashMap::from([
+                ("description", Value::from(format!("{action:?}"))),
+                ("preferred_trigger", Value::from(hotkey.to_uppercase())),
+            ]);
+            (index.to_string(), properties)
+        })
+        .collect();
+    let options = HashMap::from([("handle_token", Value::from(BIND_TOKEN))]);
+    portal_request(
+        &connection,
+        &shortcuts,
+        "BindShortcuts",
+        BIND_TOKEN,
+        &(&session, list, "", options),
+    )
+    .await?;
+
+    while let Some(message) = activated.next().await {
+        let (_, id, _, _): (ObjectPath<'_>, String, u64, HashMap<String, OwnedValue>) =
+            message.body().deserialize()?;
+        if let Ok(index) = id.parse() {
+            if sender.send(index).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Call a portal method and wait for the `Response` of its request object
+async fn portal_request<B>(
+    connection: &Connection,
+    proxy: &Proxy<'_>,
+    method: &str,
+    token: &str,
+    body: &B,
+) -> Result<HashMap<String, OwnedValue>, HotkeyError>
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    // The request path is known in advance, listening before the call avoids missing the response
+    let sender = connection
+        .unique_name()
+        .map(|name| name.trim_start_matches(':').replace('.', "_"))
+        .unwrap_or_default();
+    let path = format!("{PORTAL_PATH}/request/{sender}/{token}");
+    let request = Proxy::new(connection, PORTAL_DESTINATION, path, REQUEST_INTERFACE).await?;
+    let mut responses = request.receive_signal("Response").await?;
+
+    proxy.call_method(method, body).await?;
+
+    let message = responses.next().await.ok_or(HotkeyError::PortalDenied)?;
+    let (code, results): (u32, HashMap<String, OwnedValue>) = message.body().deserialize()?;
+    if code != 0 {
+        return Err(HotkeyError::PortalDenied);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, Temperature};
+
+    use super::*;
+
+    #[test]
+    fn parse_action() {
+        assert_eq!(
+            "toggle".parse::<HotkeyAction>().unwrap(),
+            HotkeyAction::Toggle
+        );
+        assert_eq!(
+            "brightness-up".parse::<HotkeyAction>().unwrap(),
+            HotkeyAction::BrightnessUp
+        );
+        assert_eq!(
+            "preset:meeting".parse::<HotkeyAction>().unwrap(),
+            HotkeyAction::Preset("meeting".to_string())
+        );
+        assert!("preset:".parse::<HotkeyAction>().is_err());
+        assert!("explode".parse::<HotkeyAction>().is_err());
+    }
+
+    #[test]
+    fn apply_action() {
+        let mut status = KeyLightStatus::white(
+            PowerStatus::Off,
+            Brightness::new(95).unwrap(),
+            Temperature::new(150).unwrap(),
+        );
+        HotkeyAction::Toggle.apply(&mut status);
+        assert_eq!(status.power, PowerStatus::On);
+        HotkeyAction::BrightnessUp.apply(&mut status);
+        assert_eq!(status.brightness.0, 100);
+        HotkeyAction::BrightnessDown.apply(&mut status);
+        assert_eq!(status.brightness.0, 90);
+        HotkeyAction::Cooler.apply(&mut status);
+        assert_eq!(status.temperature.unwrap().0, 143);
+        HotkeyAction::Warmer.apply(&mut status);
+        assert_eq!(status.temperature.unwrap().0, 163);
+    }
+}
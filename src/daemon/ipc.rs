@@ -0,0 +1,435 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use serde::de::DeserializeOwned;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{unix::UCred, UnixListener, UnixStream},
+    sync::broadcast::error::RecvError,
+};
+
+use crate::{
+    client::{
+        error_code, AuditParams, DeviceParams, DeviceState, MeetingParams, Outcome, PresetParams,
+        Request, Response, RoomParams, RoomPresetParams, RoomSetParams, RpcError, SceneParams,
+        SetParams, StateChange, JSONRPC_VERSION, STATE_NOTIFICATION,
+    },
+    scene::{Scene, SceneError},
+    HistoryError,
+};
+
+use super::{access, with_actor, with_source, Daemon, DaemonError, DaemonEvent};
+
+/// Serve the JSON-RPC protocol documented in [`crate::client`] on the Unix socket at `path`
+pub async fn serve(daemon: Daemon, path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Left behind by a previous run
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    log::info!("IPC listening on {}", path.display());
+    serve_on(daemon, listener).await
+}
+
+pub async fn serve_on(daemon: Daemon, listener: UnixListener) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let daemon = daemon.clone();
+        let credentials = match stream.peer_cred() {
+            Ok(credentials) => Some(credentials),
+            Err(err) => {
+                log::debug!("Failed to get the credentials of the IPC client: {err}");
+                None
+            }
+        };
+        let actor = peer_actor(credentials.as_ref());
+        let may_change = credentials.is_some_and(|credentials| {
+            access::peer_allowed(
+                &daemon.config().access,
+                credentials.uid(),
+                credentials.gid(),
+            )
+        });
+        tokio::spawn(with_source(
+            "ipc",
+            with_actor(actor, async move {
+                if let Err(err) = handle_client(&daemon, stream, may_change).await {
+                    log::debug!("IPC client failed: {err}");
+                }
+            }),
+        ));
+    }
+}
+
+/// Process of the IPC client with `credentials` for the audit trail,
+/// e.g. `elgato-keylight-cli (pid 4242, uid 1000)`
+fn peer_actor(credentials: Option<&UCred>) -> String {
+    let Some(credentials) = credentials else {
+        return "unknown".to_string();
+    };
+    let uid = credentials.uid();
+    let Some(pid) = credentials.pid() else {
+        return format!("uid {uid}");
+    };
+    match process_name(pid) {
+        Some(name) => format!("{name} (pid {pid}, uid {uid})"),
+        None => format!("pid {pid}, uid {uid}"),
+    }
+}
+
+/// Name of the executable of the process `pid`, from its command line since `comm` is truncated
+fn process_name(pid: i32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let program = cmdline.split(|byte| *byte == 0).next()?;
+    let program = Path::new(std::str::from_utf8(program).ok()?);
+    Some(program.file_name()?.to_string_lossy().into_owned())
+}
+
+/// Methods changing the lights, only allowed to the clients passing [`access::peer_allowed`]
+const CHANGE_METHODS: &[&str] = &[
+    "toggle",
+    "set",
+    "preset",
+    "room_toggle",
+    "room_set",
+    "room_preset",
+    "scene",
+    "meeting_start",
+    "meeting_stop",
+];
+
+async fn handle_client(
+    daemon: &Daemon,
+    stream: UnixStream,
+    may_change: bool,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = None;
+
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let Some(response) = handle_line(daemon, &line, &mut events, may_change).await else {
+                    continue;
+                };
+                serde_json::to_string(&response)?
+            }
+            event = recv(&mut events) => {
+                let Some(DaemonEvent::StateChanged { device, status, source, .. }) = event else {
+                    continue;
+                };
+                let change = StateChange {
+                    device: device.name,
+                    status,
+                    source: source.to_string(),
+                };
+                serde_json::to_string(&Request {
+                    jsonrpc: JSONRPC_VERSION.to_string(),
+                    id: None,
+                    method: STATE_NOTIFICATION.to_string(),
+                    params: serde_json::to_value(change)?,
+                })?
+            }
+        };
+        writer.write_all(format!("{message}\n").as_bytes()).await?;
+    }
+}
+
+/// Next event of the subscription, or never if the client is not subscribed
+async fn recv(
+    events: &mut Option<tokio::sync::broadcast::Receiver<DaemonEvent>>,
+) -> Option<DaemonEvent> {
+    let Some(receiver) = events else {
+        return std::future::pending().await;
+    };
+    match receiver.recv().await {
+        Ok(event) => Some(event),
+        Err(RecvError::Lagged(_)) => None,
+        Err(RecvError::Closed) => {
+            *events = None;
+            None
+        }
+    }
+}
+
+/// Answer a request line, notifications get no answer
+async fn handle_line(
+    daemon: &Daemon,
+    line: &str,
+    events: &mut Option<tokio::sync::broadcast::Receiver<DaemonEvent>>,
+    may_change: bool,
+) -> Option<Response> {
+    let request = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => serde_json::from_value::<Request>(value),
+        Err(err) => {
+            return Some(error_response(
+                None,
+                error_code::PARSE_ERROR,
+                err.to_string(),
+            ));
+        }
+    };
+    let request = match request {
+        Ok(request) if request.jsonrpc == JSONRPC_VERSION => request,
+        Ok(request) => {
+            let message = format!("unsupported version: {}", request.jsonrpc);
+            return Some(error_response(
+                request.id,
+                error_code::INVALID_REQUEST,
+                message,
+            ));
+        }
+        Err(err) => {
+            return Some(error_response(
+                None,
+                error_code::INVALID_REQUEST,
+                err.to_string(),
+            ));
+        }
+    };
+
+    if request.method == "subscribe" {
+        *events = Some(daemon.subscribe());
+    }
+    let outcome = if !may_change && CHANGE_METHODS.contains(&request.method.as_str()) {
+        Outcome::Error(RpcError {
+            code: error_code::PERMISSION_DENIED,
+            message: "not allowed to change the lights, see the access config".to_string(),
+        })
+    } else {
+        match call(daemon, &request.method, request.params).await {
+            Ok(result) => Outcome::Result(result),
+            Err(error) => Outcome::Error(error),
+        }
+    };
+    let id = request.id?;
+    Some(Response {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        id: Some(id),
+        outcome,
+    })
+}
+
+fn error_response(id: Option<u64>, code: i64, message: String) -> Response {
+    Response {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        id,
+        outcome: Outcome::Error(RpcError { code, message }),
+    }
+}
+
+impl From<DaemonError> for RpcError {
+    fn from(err: DaemonError) -> Self {
+        let code = match err {
+            DaemonError::DeviceNotFound(_) => error_code::DEVICE_NOT_FOUND,
+            DaemonError::PresetNotFound(_) => error_code::PRESET_NOT_FOUND,
+            DaemonError::RoomNotFound(_) => error_code::ROOM_NOT_FOUND,
+            DaemonError::Scene(SceneError::NotFound(_)) => error_code::SCENE_NOT_FOUND,
+            DaemonError::Scene(SceneError::Device { .. }) => error_code::DEVICE_ERROR,
+            DaemonError::Scene(_) => error_code::INVALID_SCENE,
+            DaemonError::Queued(_) => error_code::QUEUED,
+            DaemonError::Paused(_) => error_code::PAUSED,
+            DaemonError::Unsupported(_) => error_code::INVALID_PARAMS,
+            DaemonError::NoLights(_) | DaemonError::Request(_) => error_code::DEVICE_ERROR,
+        };
+        RpcError {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<HistoryError> for RpcError {
+    fn from(err: HistoryError) -> Self {
+        RpcError {
+            code: error_code::HISTORY_UNAVAILABLE,
+            message: err.to_string(),
+        }
+    }
+}
+
+fn params<T: DeserializeOwned>(params: serde_json::Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError {
+        code: error_code::INVALID_PARAMS,
+        message: err.to_string(),
+    })
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Result<serde_json::Value, RpcError> {
+    serde_json::to_value(value).map_err(|err| RpcError {
+        code: error_code::DEVICE_ERROR,
+        message: err.to_string(),
+    })
+}
+
+async fn call(
+    daemon: &Daemon,
+    method: &str,
+    params_value: serde_json::Value,
+) -> Result<serde_json::Value, RpcError> {
+    match method {
+        "devices" => {
+            let devices: Vec<DeviceState> = daemon
+                .devices()
+                .into_iter()
+                .map(|device| DeviceState {
+                    status: daemon.cached_status(&device.name),
+                    name: device.name,
+                })
+                .collect();
+            to_value(devices)
+        }
+        "rooms" => {
+            let rooms: BTreeMap<_, _> = daemon
+                .config()
+                .rooms
+                .keys()
+                .filter_map(|room| Some((room.clone(), daemon.room_status(room).ok()?)))
+                .collect();
+            to_value(rooms)
+        }
+        "room_status" => {
+            let RoomParams { room } = params(params_value)?;
+            to_value(daemon.room_status(&room)?)
+        }
+        "room_toggle" => {
+            let RoomParams { room } = params(params_value)?;
+            to_value(daemon.toggle_room(&room).await?)
+        }
+        "room_set" => {
+            let RoomSetParams { room, update } = params(params_value)?;
+            to_value(daemon.apply_room(&room, &update).await?)
+        }
+        "room_preset" => {
+            let RoomPresetParams { room, preset } = params(params_value)?;
+            to_value(daemon.apply_preset_room(&room, &preset).await?)
+        }
+        "scenes" => to_value(Scene::list().map_err(DaemonError::from)?),
+        "scene" => {
+            let SceneParams { scene } = params(params_value)?;
+            daemon.play_scene(&scene).await?;
+            Ok(serde_json::Value::Null)
+        }
+        "stats" => to_value(daemon.power_stats()),
+        "meeting_start" => {
+            let MeetingParams { duration } =
+                params::<Option<MeetingParams>>(params_value)?.unwrap_or_default();
+            to_value(
+                daemon
+                    .start_meeting(duration.map(Duration::from_secs))
+                    .await?,
+            )
+        }
+        "meeting_stop" => to_value(daemon.stop_meeting().await),
+        "meeting_status" => to_value(daemon.meeting_status()),
+        "audit" => {
+            let AuditParams {
+                device,
+                source,
+                limit,
+            } = params::<Option<AuditParams>>(params_value)?.unwrap_or_default();
+            if !daemon.config().history.enabled {
+                return Err(RpcError {
+                    code: error_code::HISTORY_UNAVAILABLE,
+                    message: "history disabled in the config".to_string(),
+                });
+            }
+            let records =
+                tokio::task::spawn_blocking(|| crate::load_history(&crate::history_path()?))
+                    .await
+                    .expect("history loading panicked")?;
+            to_value(crate::audit_trail(
+                &records,
+                device.as_deref(),
+                source.as_deref(),
+                limit.unwrap_or(AuditParams::DEFAULT_LIMIT),
+            ))
+        }
+        "subscribe" => Ok(serde_json::Value::Null),
+        "status" => {
+            let DeviceParams { device } = params(params_value)?;
+            to_value(daemon.status(&device).await?)
+        }
+        "toggle" => {
+            let DeviceParams { device } = params(params_value)?;
+            to_value(
+                daemon
+                    .update(&device, |status| status.power.toggle())
+                    .await?,
+            )
+        }
+        "set" => {
+            let SetParams { device, update } = params(params_value)?;
+            to_value(daemon.apply(&device, &update).await?)
+        }
+        "preset" => {
+            let PresetParams { device, preset } = params(params_value)?;
+            to_value(daemon.apply_preset(&device, &preset).await?)
+        }
+        method => Err(RpcError {
+            code: error_code::METHOD_NOT_FOUND,
+            message: format!("unknown method: {method}"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{avahi::AvahiState, client::Client, client::ClientError, Config};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn session() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keylightd.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(serve_on(daemon, listener));
+
+        let mut client = Client::connect(&path).await.unwrap();
+        assert_eq!(client.devices().await.unwrap(), vec![]);
+        assert!(matches!(
+            client.toggle("Unknown").await,
+            Err(ClientError::Rpc {
+                code: error_code::DEVICE_NOT_FOUND,
+                ..
+            })
+        ));
+        assert!(matches!(
+            client.call::<()>("explode", serde_json::Value::Null).await,
+            Err(ClientError::Rpc {
+                code: error_code::METHOD_NOT_FOUND,
+                ..
+            })
+        ));
+        assert!(matches!(
+            client.call::<()>("status", serde_json::json!({})).await,
+            Err(ClientError::Rpc {
+                code: error_code::INVALID_PARAMS,
+                ..
+            })
+        ));
+        assert!(matches!(
+            client.room_toggle("Studio").await,
+            Err(ClientError::Rpc {
+                code: error_code::ROOM_NOT_FOUND,
+                ..
+            })
+        ));
+        client.subscribe().await.unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::StreamExt as _;
+use zbus::{Connection, Proxy};
+
+use crate::{LockConfig, PowerStatus};
+
+use super::Daemon;
+
+const LOGIND_DESTINATION: &str = "org.freedesktop.login1";
+/// Session of the daemon
+const LOGIND_SESSION_PATH: &str = "/org/freedesktop/login1/session/auto";
+const LOGIND_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+/// Interval between two checks of the idle time
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Whether the lights should be off given the session state
+pub fn should_be_off(config: &LockConfig, locked: bool, idle_for: Option<Duration>) -> bool {
+    let idle = match (config.idle_minutes, idle_for) {
+        (Some(minutes), Some(idle_for)) => idle_for >= Duration::from_secs(minutes * 60),
+        _ => false,
+    };
+    (config.off_on_lock && locked) || idle
+}
+
+/// Turn the lights off when the session is locked or idle, restoring them afterwards
+pub async fn run(daemon: Daemon, config: LockConfig) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let session = Proxy::new(
+        &connection,
+        LOGIND_DESTINATION,
+        LOGIND_SESSION_PATH,
+        LOGIND_SESSION_INTERFACE,
+    )
+    .await?;
+    let mut lock = session.receive_signal("Lock").await?;
+    let mut unlock = session.receive_signal("Unlock").await?;
+    log::info!("Watching the session lock and idle state");
+
+    let mut interval = tokio::time::interval(IDLE_POLL_INTERVAL);
+    // Lights turned off by the automation, with their state to restore
+    let mut turned_off: Option<HashMap<String, PowerStatus>> = None;
+    loop {
+        let mut locked = tokio::select! {
+            _ = interval.tick() => None,
+            Some(_) = lock.next() => Some(true),
+            Some(_) = unlock.next() => Some(false),
+        };
+        if locked.is_none() {
+            locked = session.get_property::<bool>("LockedHint").await.ok();
+        }
+        let idle_for = idle_for(&session).await;
+
+        let off = should_be_off(&config, locked.unwrap_or(false), idle_for);
+        match (off, turned_off.is_some()) {
+            (true, false) => {
+                log::info!("Session locked or idle, turning off");
+                daemon.notify("locked");
+                let powers = daemon
+                    .targets(&config.devices)
+                    .into_iter()
+                    .filter_map(|device| {
+                        let status = daemon.cached_status(&device.name)?;
+                        Some((device.name, status.power))
+                    })
+                    .collect();
+                daemon.turn_off_all(&config.devices).await;
+                turned_off = Some(powers);
+            }
+            (false, true) => {
+                let powers = turned_off.take().unwrap_or_default();
+                daemon.notify("unlocked");
+                if !config.restore {
+                    continue;
+                }
+                log::info!("Session active, restoring");
+                for (name, power) in powers {
+                    if power == PowerStatus::Off {
+                        continue;
+                    }
+                    if let Err(err) = daemon.set_power(&name, power).await {
+                        log::error!("Failed to restore {name}: {err}");
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Time since the session became idle, `None` if it is active
+async fn idle_for(session: &Proxy<'_>) -> Option<Duration> {
+    if !session.get_property::<bool>("IdleHint").await.ok()? {
+        return None;
+    }
+    let since = session.get_property::<u64>("IdleSinceHint").await.ok()?;
+    let since = UNIX_EPOCH + Duration::from_micros(since);
+    Some(SystemTime::now().duration_since(since).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn off() {
+        let config = LockConfig {
+            idle_minutes: Some(10),
+            ..Default::default()
+        };
+        assert!(!should_be_off(&config, false, None));
+        assert!(should_be_off(&config, true, None));
+        assert!(!should_be_off(
+            &config,
+            false,
+            Some(Duration::from_secs(60))
+        ));
+        assert!(should_be_off(
+            &config,
+            false,
+            Some(Duration::from_secs(600))
+        ));
+
+        let config = LockConfig {
+            off_on_lock: false,
+            idle_minutes: None,
+            ..config
+        };
+        assert!(!should_be_off(
+            &config,
+            true,
+            Some(Duration::from_secs(600))
+        ));
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::{KeyLightStatus, MeetingStatus};
+
+use super::{current_source, with_source, Daemon, DaemonError, DaemonEvent};
+
+/// Meeting in progress
+#[derive(Debug)]
+pub(super) struct Meeting {
+    /// Bumped when the meeting is extended, so that the timer of the previous end does nothing
+    generation: u64,
+    ends_at: Option<DateTime<Utc>>,
+    /// State of the lights before the meeting, restored at the end
+    previous: HashMap<String, KeyLightStatus>,
+}
+
+impl Daemon {
+    /// Apply the meeting preset and pause the automations of the meeting config, until
+    /// `duration` is over or [`Daemon::stop_meeting`]. Starting a meeting during another one
+    /// only changes its end.
+    pub async fn start_meeting(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<MeetingStatus, DaemonError> {
+        let config = &self.config().meeting;
+        let ends_at = duration
+            .and_then(|duration| chrono::Duration::from_std(duration).ok())
+            .map(|duration| Utc::now() + duration);
+
+        let extended = self.meeting().as_mut().map(|meeting| {
+            meeting.generation += 1;
+            meeting.ends_at = ends_at;
+            meeting.generation
+        });
+        let generation = match extended {
+            Some(generation) => generation,
+            None => {
+                let mut previous = HashMap::new();
+                for device in self.targets(&config.devices) {
+                    let status = match self.cached_status(&device.name) {
+                        Some(status) => status,
+                        None => match self.status(&device.name).await {
+                            Ok(status) => status,
+                            Err(err) => {
+                                log::error!(
+                                    "Failed to read {} before the meeting: {err}",
+                                    device.name
+                                );
+                                continue;
+                            }
+                        },
+                    };
+                    previous.insert(device.name, status);
+                }
+                *self.meeting() = Some(Meeting {
+                    generation: 0,
+                    ends_at,
+                    previous,
+                });
+                log::info!("Meeting started");
+                self.turn_on_all(&config.devices, config.preset.as_deref())
+                    .await;
+                self.meeting_event("started").await;
+                0
+            }
+        };
+
+        if let Some(duration) = duration {
+            let daemon = self.clone();
+            tokio::spawn(with_source("meeting", async move {
+                tokio::time::sleep(duration).await;
+                let current = daemon.meeting().as_ref().map(|meeting| meeting.generation);
+                if current == Some(generation) {
+                    daemon.stop_meeting().await;
+                }
+            }));
+        }
+        Ok(self.meeting_status())
+    }
+
+    /// End the meeting, restoring the lights and the automations. Does nothing without a meeting.
+    pub async fn stop_meeting(&self) -> MeetingStatus {
+        let Some(meeting) = self.meeting().take() else {
+            return MeetingStatus::default();
+        };
+        log::info!("Meeting over, restoring the lights");
+        for (name, previous) in meeting.previous {
+            if let Err(err) = self.update(&name, |status| *status = previous).await {
+                log::error!("Failed to restore {name} after the meeting: {err}");
+            }
+        }
+        self.meeting_event("stopped").await;
+        MeetingStatus::default()
+    }
+
+    pub fn meeting_status(&self) -> MeetingStatus {
+        match &*self.meeting() {
+            Some(meeting) => MeetingStatus {
+                active: true,
+                devices: meeting.previous.keys().cloned().collect(),
+                ends_at: meeting.ends_at,
+            },
+            None => MeetingStatus::default(),
+        }
+    }
+
+    /// Whether the changes of the current source are paused by a meeting
+    pub(super) fn suppressed(&self) -> bool {
+        let source = current_source();
+        self.meeting().is_some()
+            && self
+                .config()
+                .meeting
+                .suppress
+                .iter()
+                .any(|suppressed| suppressed == source)
+    }
+
+    fn meeting(&self) -> std::sync::MutexGuard<'_, Option<Meeting>> {
+        self.inner.meeting.lock().expect("lock poisoned")
+    }
+
+    /// Tell the scripts, webhooks and chat bots, and the user if enabled
+    async fn meeting_event(&self, event: &str) {
+        let _ = self.inner.events.send(DaemonEvent::Automation {
+            automation: "meeting",
+            event: event.to_string(),
+        });
+        #[cfg(feature = "notify")]
+        if self.config().meeting.notify {
+            let message = match event {
+                "started" => "Meeting started, automations paused",
+                _ => "Meeting over, lights restored",
+            };
+            if let Err(err) = crate::notify(message).await {
+                log::error!("Failed to notify: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{avahi::AvahiState, Config, PowerStatus};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn meeting() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let mut events = daemon.subscribe();
+
+        assert!(!daemon.suppressed());
+        let status = daemon
+            .start_meeting(Some(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        assert!(status.active);
+        assert!(status.ends_at.is_some());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            DaemonEvent::Automation { automation: "meeting", event } if event == "started"
+        ));
+
+        // The default config pauses the ambient light automation, not the IPC clients
+        assert!(with_source("ambient", async { daemon.suppressed() }).await);
+        assert!(!with_source("ipc", async { daemon.suppressed() }).await);
+        let paused = with_source("ambient", daemon.set_power("light", PowerStatus::On)).await;
+        assert!(matches!(paused, Err(DaemonError::Paused(source)) if source == "ambient"));
+
+        // Extending a meeting doesn't start another one
+        let status = daemon.start_meeting(None).await.unwrap();
+        assert_eq!(status.ends_at, None);
+        assert!(events.try_recv().is_err());
+
+        assert!(!daemon.stop_meeting().await.active);
+        assert!(!daemon.meeting_status().active);
+        assert!(!with_source("ambient", async { daemon.suppressed() }).await);
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::{find_executable, MicrophoneConfig};
+
+use super::{
+    camera::{pipewire_running, sleep_until, CameraAction, CameraState},
+    Daemon,
+};
+
+/// Interval between two polls of the capture streams
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where the capture streams are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// `pw-dump`
+    PipeWire,
+    /// `pactl -f json list source-outputs`
+    PulseAudio,
+}
+
+/// Turn the configured lights on while an application records from a microphone.
+/// Covers calls in browsers (Meet, Teams, Zoom) where no camera is used.
+pub async fn run(daemon: Daemon, config: MicrophoneConfig) -> std::io::Result<()> {
+    let backend = if pipewire_running() && is_installed("pw-dump") {
+        Backend::PipeWire
+    } else if is_installed("pactl") {
+        Backend::PulseAudio
+    } else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "neither pw-dump nor pactl installed",
+        ));
+    };
+    log::info!("Watching microphone usage ({backend:?})");
+
+    let mut state = CameraState::new(Duration::from_secs(config.off_delay));
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = sleep_until(state.off_at()) => {}
+        }
+
+        let in_use = match capture_running(backend, &config.ignore).await {
+            Ok(in_use) => in_use,
+            Err(err) => {
+                log::error!("Failed to list the capture streams: {err}");
+                continue;
+            }
+        };
+        match state.update(in_use, Instant::now()) {
+            Some(CameraAction::TurnOn) => {
+                log::info!("Microphone started");
+                daemon.notify("started");
+                daemon
+                    .turn_on_all(&config.devices, config.preset.as_deref())
+                    .await;
+            }
+            Some(CameraAction::TurnOff) => {
+                log::info!("Microphone stopped");
+                daemon.notify("stopped");
+                daemon.turn_off_all(&config.devices).await;
+            }
+            None => {}
+        }
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    find_executable(program).is_some()
+}
+
+async fn capture_running(backend: Backend, ignore: &[String]) -> std::io::Result<bool> {
+    let mut command = match backend {
+        Backend::PipeWire => tokio::process::Command::new("pw-dump"),
+        Backend::PulseAudio => {
+            let mut command = tokio::process::Command::new("pactl");
+            command.args(["-f", "json", "list", "source-outputs"]);
+            command
+        }
+    };
+    let output = command.output().await?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let result = match backend {
+        Backend::PipeWire => pipewire_capture_running(&output.stdout, ignore),
+        Backend::PulseAudio => pulseaudio_capture_running(&output.stdout, ignore),
+    };
+    result.map_err(std::io::Error::other)
+}
+
+/// Whether the `pw-dump` output contains a running audio capture stream
+pub fn pipewire_capture_running(dump: &[u8], ignore: &[String]) -> Result<bool, serde_json::Error> {
+    let objects: Vec<Value> = serde_json::from_slice(dump)?;
+    Ok(objects.iter().any(|object| {
+        let info = &object["info"];
+        object["type"] == "PipeWire:Interface:Node"
+            && info["props"]["media.class"] == "Stream/Input/Audio"
+            && info["state"] == "running"
+            && !is_ignored(&info["props"], ignore)
+    }))
+}
+
+/// Whether the `pactl -f json list source-outputs` output contains an uncorked stream
+pub fn pulseaudio_capture_running(
+    outputs: &[u8],
+    ignore: &[String],
+) -> Result<bool, serde_json::Error> {
+    let outputs: Vec<Value> = serde_json::from_slice(outputs)?;
+    Ok(outputs
+        .iter()
+        .any(|output| output["corked"] != true && !is_ignored(&output["properties"], ignore)))
+}
+
+/// Whether the stream belongs to an ignored application, matched by name or binary
+fn is_ignored(props: &Value, ignore: &[String]) -> bool {
+    ["application.name", "application.process.binary"]
+        .iter()
+        .filter_map(|key| props[key].as_str())
+        .any(|name| {
+            ignore
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn capture_streams() {
+        let ignore = vec!["pavucontrol".to_string()];
+        let dump = |state: &str, binary: &str| {
+            json!([
+                {
+                    "id": 42,
+                    "type": "PipeWire:Interface:Node",
+                    "info": {
+                        "state": state,
+                        "props": {
+                            "media.class": "Stream/Input/Audio",
+                            "application.name": "Firefox",
+                            "application.process.binary": binary
+                        }
+                    }
+                },
+                {
+                    "id": 43,
+                    "type": "PipeWire:Interface:Node",
+                    "info": {"state": "running", "props": {"media.class": "Audio/Source"}}
+                }
+            ])
+            .to_string()
+        };
+        assert!(pipewire_capture_running(dump("running", "firefox").as_bytes(), &ignore).unwrap());
+        assert!(!pipewire_capture_running(dump("idle", "firefox").as_bytes(), &ignore).unwrap());
+        assert!(
+            !pipewire_capture_running(dump("running", "pavucontrol").as_bytes(), &ignore).unwrap()
+        );
+
+        let outputs = |corked: bool| {
+            json!([{
+                "index": 7,
+                "corked": corked,
+                "properties": {"application.name": "Chromium"}
+            }])
+            .to_string()
+        };
+        assert!(pulseaudio_capture_running(outputs(false).as_bytes(), &ignore).unwrap());
+        assert!(!pulseaudio_capture_running(outputs(true).as_bytes(), &ignore).unwrap());
+        assert!(!pulseaudio_capture_running(b"[]", &ignore).unwrap());
+        assert!(pulseaudio_capture_running(b"not json", &ignore).is_err());
+    }
+}
@@ -0,0 +1,1192 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use tokio::{sync::broadcast, time::Instant};
+
+use crate::{
+    avahi::{find_elgato_devices, spawn_avahi_daemon, AvahiState, Device, DiscoverError},
+    estimated_watts, get_accessory_info_with, get_status, get_statuses,
+    scene::{self, Scene, SceneError, SceneLights},
+    with_device_name, Brightness, CapabilityError, Config, DeviceCapabilities, DeviceStatus,
+    EnergyMeter, KeyLightStatus, LightUpdate, LimitsConfig, OptimisticState, PowerStats,
+    PowerStatus, RequestOptions, RoomStatus, SendQueue, Sent, Temperature,
+};
+
+pub mod access;
+pub mod advertise;
+pub mod ambient;
+#[cfg(target_os = "linux")]
+pub mod ambilight;
+#[cfg(target_os = "linux")]
+pub mod apps;
+#[cfg(target_os = "linux")]
+pub mod audio;
+#[cfg(target_os = "linux")]
+pub mod camera;
+pub mod chat;
+pub mod circadian;
+pub mod control;
+pub mod day_curve;
+pub mod dbus;
+#[cfg(target_os = "linux")]
+pub mod do_not_disturb;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod history;
+#[cfg(target_os = "linux")]
+pub mod hotkeys;
+#[cfg(unix)]
+pub mod ipc;
+#[cfg(target_os = "linux")]
+pub mod lock;
+mod meeting;
+#[cfg(target_os = "linux")]
+pub mod microphone;
+pub mod obs;
+#[cfg(target_os = "linux")]
+pub mod presence;
+pub mod rest;
+#[cfg(target_os = "linux")]
+pub mod resume;
+pub mod rules;
+pub mod scheduler;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+pub mod triggers;
+pub mod webhooks;
+#[cfg(windows)]
+pub mod windows;
+#[cfg(unix)]
+pub mod wm;
+
+/// Interval between two polls of the state of all devices
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval between two steps of a fade
+const FADE_STEP: Duration = Duration::from_millis(250);
+
+/// Timeout of the accessory info request, identifying a device is not time critical and
+/// lights busy with other clients can be slow to answer
+const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Capacity of the event channel, slow subscribers miss older events
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DaemonError {
+    #[error("Device not found: {0}")]
+    DeviceNotFound(String),
+    #[error("Device {0} has no lights")]
+    NoLights(String),
+    #[error("Preset not found: {0}")]
+    PresetNotFound(String),
+    #[error("Room not found: {0}")]
+    RoomNotFound(String),
+    #[error(transparent)]
+    Scene(#[from] SceneError),
+    #[error(transparent)]
+    Unsupported(#[from] CapabilityError),
+    #[error("Device {0} is unreachable, the change is queued until it is back")]
+    Queued(String),
+    #[error("Changes from {0} are paused by the meeting")]
+    Paused(String),
+    #[error(transparent)]
+    Request(#[from] anyhow::Error),
+}
+
+/// Events broadcasted to the control surfaces of the daemon
+#[derive(Debug, Clone)]
+pub enum DaemonEvent {
+    /// A device appeared or disappeared
+    DevicesChanged(Vec<Device>),
+    /// The state of a device changed, either through the daemon or externally
+    StateChanged {
+        device: Device,
+        /// State before the change, unknown the first time the device is reached
+        previous: Option<KeyLightStatus>,
+        status: KeyLightStatus,
+        /// What changed the state, see [`with_source`]
+        source: &'static str,
+        /// Who made the change through the source, see [`with_actor`]
+        actor: Option<String>,
+    },
+    /// A preset was applied to a device
+    PresetApplied {
+        device: String,
+        preset: String,
+        source: &'static str,
+        actor: Option<String>,
+    },
+    /// Something happened in an automation, e.g. the camera `started`
+    Automation {
+        automation: &'static str,
+        event: String,
+    },
+}
+
+/// Source of the changes noticed by polling the devices, e.g. the buttons of a light
+pub const EXTERNAL_SOURCE: &str = "external";
+
+/// Source of the changes made outside of [`with_source`]
+const DEFAULT_SOURCE: &str = "daemon";
+
+tokio::task_local! {
+    static SOURCE: &'static str;
+}
+
+/// Run `future` reporting `source` (e.g. `"camera"`) as the origin of its changes
+pub async fn with_source<F: std::future::Future>(source: &'static str, future: F) -> F::Output {
+    SOURCE.scope(source, future).await
+}
+
+fn current_source() -> &'static str {
+    SOURCE.try_with(|source| *source).unwrap_or(DEFAULT_SOURCE)
+}
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// Run `future` reporting `actor` (e.g. the process of an IPC client or the address of a REST
+/// client) as the author of its changes, recorded in the audit trail next to the source
+pub async fn with_actor<F: std::future::Future>(actor: String, future: F) -> F::Output {
+    ACTOR.scope(actor, future).await
+}
+
+fn current_actor() -> Option<String> {
+    ACTOR.try_with(Clone::clone).ok()
+}
+
+/// Shared state of the daemon: discovered devices and their last known state
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: Config,
+    avahi: Arc<RwLock<AvahiState>>,
+    statuses: RwLock<HashMap<String, KeyLightStatus>>,
+    /// Desired state of the unreachable devices and the source of the change, latest wins
+    pending: RwLock<HashMap<String, (KeyLightStatus, &'static str)>>,
+    /// Product name of the devices, from their accessory info
+    products: RwLock<HashMap<String, String>>,
+    /// Estimated energy used by the devices of a known product
+    meters: RwLock<HashMap<String, EnergyMeter>>,
+    /// Sends the changes, rate limited and collapsed to the latest while one is in flight
+    sends: SendQueue,
+    /// Held while a change is applied to the state of the device, so the changes made meanwhile
+    /// start from it
+    turns: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// State of the devices last read or set, read again after a short while
+    states: OptimisticState,
+    events: broadcast::Sender<DaemonEvent>,
+    meeting: Mutex<Option<meeting::Meeting>>,
+}
+
+impl Daemon {
+    /// Discover the devices and start watching them
+    pub async fn start(config: Config) -> Result<Self, DiscoverError> {
+        let devices = find_elgato_devices().await?;
+        let avahi = Arc::new(RwLock::new(AvahiState { devices }));
+        let _ = spawn_avahi_daemon(Arc::clone(&avahi));
+
+        let daemon = Daemon::new(config, avahi);
+        tokio::spawn(daemon.clone().poll());
+        Ok(daemon)
+    }
+
+    /// Run a new discovery, replacing the known devices
+    pub async fn rediscover(&self) -> Result<(), DiscoverError> {
+        let devices = find_elgato_devices().await?;
+        self.inner.avahi.write().expect("lock poisoned").devices = devices;
+        Ok(())
+    }
+
+    pub fn new(config: Config, avahi: Arc<RwLock<AvahiState>>) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let interval = match config.rate_limit.requests_per_second {
+            0 => Duration::ZERO,
+            n => Duration::from_secs(1) / n,
+        };
+        Daemon {
+            inner: Arc::new(Inner {
+                config,
+                avahi,
+                statuses: RwLock::new(HashMap::new()),
+                pending: RwLock::new(HashMap::new()),
+                products: RwLock::new(HashMap::new()),
+                meters: RwLock::new(HashMap::new()),
+                sends: SendQueue::with_interval(interval),
+                turns: Mutex::new(HashMap::new()),
+                states: OptimisticState::default(),
+                events,
+                meeting: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    pub fn preset(&self, name: &str) -> Result<&LightUpdate, DaemonError> {
+        self.inner
+            .config
+            .presets
+            .get(name)
+            .ok_or_else(|| DaemonError::PresetNotFound(name.to_string()))
+    }
+
+    /// Names of the lights of `room`
+    pub fn room(&self, room: &str) -> Result<&[String], DaemonError> {
+        self.inner
+            .config
+            .rooms
+            .get(room)
+            .map(Vec::as_slice)
+            .ok_or_else(|| DaemonError::RoomNotFound(room.to_string()))
+    }
+
+    /// Aggregated last known state of the lights of `room`
+    pub fn room_status(&self, room: &str) -> Result<RoomStatus, DaemonError> {
+        let devices = self.room(room)?.to_vec();
+        let statuses: Vec<KeyLightStatus> = devices
+            .iter()
+            .filter_map(|name| self.cached_status(name))
+            .collect();
+        Ok(RoomStatus::aggregate(devices, &statuses))
+    }
+
+    /// Apply `update` to every light of `room`. All the lights are tried, the first failure
+    /// is returned, lights queued until they are back don't count as failures.
+    pub async fn update_room<F>(&self, room: &str, update: F) -> Result<RoomStatus, DaemonError>
+    where
+        F: Fn(&mut KeyLightStatus),
+    {
+        self.update_room_lights(room, |_, status| update(status))
+            .await
+    }
+
+    /// Apply `update` to the lights of `room`, with the calibration offsets of each light
+    pub async fn apply_room(
+        &self,
+        room: &str,
+        update: &LightUpdate,
+    ) -> Result<RoomStatus, DaemonError> {
+        let config = self.config();
+        self.update_room_lights(room, |name, status| {
+            config.calibrated(name, update).apply(status)
+        })
+        .await
+    }
+
+    /// Update the lights of `room` with `update`, given the name of each light
+    async fn update_room_lights<F>(&self, room: &str, update: F) -> Result<RoomStatus, DaemonError>
+    where
+        F: Fn(&str, &mut KeyLightStatus),
+    {
+        let mut failure = None;
+        for name in self.room(room)? {
+            match self.update(name, |status| update(name, status)).await {
+                Ok(_) | Err(DaemonError::Queued(_)) => {}
+                Err(err) => {
+                    log::warn!("Failed to update {name} in {room}: {err}");
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+        match failure {
+            Some(err) => Err(err),
+            None => self.room_status(room),
+        }
+    }
+
+    /// Turn the lights of `room` off if any of them is on, on otherwise
+    pub async fn toggle_room(&self, room: &str) -> Result<RoomStatus, DaemonError> {
+        let power = PowerStatus::from(self.room_status(room)?.on == 0);
+        self.update_room(room, |status| status.power = power).await
+    }
+
+    pub async fn apply_preset_room(
+        &self,
+        room: &str,
+        preset: &str,
+    ) -> Result<RoomStatus, DaemonError> {
+        let update = self.preset(preset)?.clone();
+        let status = self.apply_room(room, &update).await?;
+        for name in &status.devices {
+            self.preset_applied(name, preset);
+        }
+        Ok(status)
+    }
+
+    fn preset_applied(&self, name: &str, preset: &str) {
+        let _ = self.inner.events.send(DaemonEvent::PresetApplied {
+            device: name.to_string(),
+            preset: preset.to_string(),
+            source: current_source(),
+            actor: current_actor(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DaemonEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Broadcast an event of the current automation, see [`with_source`]
+    pub fn notify(&self, event: impl Into<String>) {
+        let _ = self.inner.events.send(DaemonEvent::Automation {
+            automation: current_source(),
+            event: event.into(),
+        });
+    }
+
+    pub fn devices(&self) -> Vec<Device> {
+        self.inner
+            .avahi
+            .read()
+            .expect("lock poisoned")
+            .devices
+            .clone()
+    }
+
+    pub fn device(&self, name: &str) -> Result<Device, DaemonError> {
+        self.devices()
+            .into_iter()
+            .find(|device| device.name == name)
+            .ok_or_else(|| DaemonError::DeviceNotFound(name.to_string()))
+    }
+
+    /// Devices named in `names`, or all the devices if `names` is empty
+    pub fn targets(&self, names: &[String]) -> Vec<Device> {
+        let devices = self.devices();
+        if names.is_empty() {
+            return devices;
+        }
+        devices
+            .into_iter()
+            .filter(|device| names.contains(&device.name))
+            .collect()
+    }
+
+    /// Apply `preset` (or turn on if there is none) on the devices named in `names`,
+    /// logging failures
+    pub async fn turn_on_all(&self, names: &[String], preset: Option<&str>) {
+        for device in self.targets(names) {
+            let result = match preset {
+                Some(preset) => self.apply_preset(&device.name, preset).await.map(|_| ()),
+                None => self.set_power(&device.name, PowerStatus::On).await,
+            };
+            if let Err(err) = result {
+                log::error!("Failed to turn on {}: {err}", device.name);
+            }
+        }
+    }
+
+    /// Turn off the devices named in `names`, logging failures
+    pub async fn turn_off_all(&self, names: &[String]) {
+        for device in self.targets(names) {
+            if let Err(err) = self.set_power(&device.name, PowerStatus::Off).await {
+                log::error!("Failed to turn off {}: {err}", device.name);
+            }
+        }
+    }
+
+    /// Last known state of the device, without contacting it
+    pub fn cached_status(&self, name: &str) -> Option<KeyLightStatus> {
+        self.inner
+            .statuses
+            .read()
+            .expect("lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// Fetch the current state of the device
+    pub async fn status(&self, name: &str) -> Result<KeyLightStatus, DaemonError> {
+        let device = self.device(name)?;
+        let status = get_status(device.url.clone()).await?;
+        self.observe(device, status)
+    }
+
+    /// Record the state read from the device
+    fn observe(&self, device: Device, status: DeviceStatus) -> Result<KeyLightStatus, DaemonError> {
+        self.inner.states.store(&device.url, status.clone());
+        let light = status
+            .lights
+            .first()
+            .cloned()
+            .ok_or_else(|| DaemonError::NoLights(device.name.clone()))?;
+        self.record(device, light.clone(), EXTERNAL_SOURCE);
+        Ok(light)
+    }
+
+    /// Apply `update` to the current state of the device.
+    ///
+    /// If a device that was reached before is unreachable, the update is applied to its
+    /// last known (or queued) state and queued until the device is back, see [`DaemonError::Queued`].
+    ///
+    /// The changes of a device are rate limited and sent one at a time through a [`SendQueue`]:
+    /// a change followed by a newer one while waiting for its turn, e.g. behind a change in
+    /// flight, is not sent, the newer change is applied on top of it.
+    pub async fn update<F>(&self, name: &str, update: F) -> Result<KeyLightStatus, DaemonError>
+    where
+        F: FnOnce(&mut KeyLightStatus),
+    {
+        if self.suppressed() {
+            log::debug!(
+                "Change of {name} from {} paused by the meeting",
+                current_source()
+            );
+            return Err(DaemonError::Paused(current_source().to_string()));
+        }
+        let limits = &self.config().limits;
+        let update = |status: &mut KeyLightStatus| {
+            update(status);
+            clamp_to_limits(limits, name, status);
+        };
+        let turn = self.turn(name).await;
+
+        let device = match self.device(name) {
+            Ok(device) => device,
+            Err(err) => return self.queue(name, update, err),
+        };
+        // Changes in quick succession start from the state last set instead of reading it again
+        let mut status = match self.inner.states.get(device.url.clone()).await {
+            Ok(status) => status,
+            Err(err) => return self.queue(name, update, with_device_name(err, name).into()),
+        };
+        let light = status
+            .lights
+            .first_mut()
+            .ok_or_else(|| DaemonError::NoLights(device.name.clone()))?;
+        let was_on = light.power == PowerStatus::On;
+        update(light);
+        self.capabilities(name).validate(light)?;
+        let soft_start = self.soft_start_target(name, was_on, light);
+        let mut light = light.clone();
+        self.inner.states.store(&device.url, status.clone());
+        // The next changes start from the state just stored, without waiting for this one to
+        // be sent
+        drop(turn);
+        let reported = match self
+            .inner
+            .sends
+            .put_status(device.url.clone(), &status)
+            .await
+        {
+            Ok(Sent::Sent(reported)) => reported,
+            // Recorded by the change replacing it
+            Ok(Sent::Superseded) => {
+                if let Some(target) = soft_start {
+                    self.spawn_soft_start(name, target);
+                }
+                return Ok(light);
+            }
+            Err(err) => {
+                self.inner.states.forget(&device.url);
+                let err = with_device_name(err, name).into();
+                // Queued at the brightness it is ramped up to, the ramp starts again once sent
+                let desired = soft_start.unwrap_or(light);
+                return self.queue(name, |status| *status = desired, err);
+            }
+        };
+        if let Some(actual) = reported.lights.first().filter(|actual| **actual != light) {
+            log::debug!("{name} reported {actual:?} instead of {light:?}");
+            light = actual.clone();
+        }
+        self.inner.states.store(&device.url, reported);
+        self.inner
+            .pending
+            .write()
+            .expect("lock poisoned")
+            .remove(name);
+        self.record(device, light.clone(), current_source());
+        if let Some(target) = soft_start {
+            self.spawn_soft_start(name, target);
+        }
+        Ok(light)
+    }
+
+    /// Wait for the turn of a change to the device `name`
+    async fn turn(&self, name: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let turn = Arc::clone(
+            self.inner
+                .turns
+                .lock()
+                .expect("lock poisoned")
+                .entry(name.to_string())
+                .or_default(),
+        );
+        turn.lock_owned().await
+    }
+
+    /// Brightness `light` is ramped up to if it is turned on by a change, lowering it to the start
+    /// of the ramp. `None` without soft start.
+    fn soft_start_target(
+        &self,
+        name: &str,
+        was_on: bool,
+        light: &mut KeyLightStatus,
+    ) -> Option<KeyLightStatus> {
+        self.config().soft_start.ramp()?;
+        let min = *self.capabilities(name).brightness.start();
+        if was_on || light.power != PowerStatus::On || light.brightness.0 <= min {
+            return None;
+        }
+        let target = light.clone();
+        light.brightness = Brightness::new_clamped(min);
+        Some(target)
+    }
+
+    /// Run [`Self::soft_start`] in the background, with the source of the change
+    fn spawn_soft_start(&self, name: &str, target: KeyLightStatus) {
+        let daemon = self.clone();
+        let name = name.to_string();
+        let source = current_source();
+        tokio::spawn(async move { with_source(source, daemon.soft_start(&name, target)).await });
+    }
+
+    /// Ramp the brightness of a light just turned on up to `target`, stopping if it is changed
+    /// meanwhile, e.g. turned off again
+    async fn soft_start(&self, name: &str, target: KeyLightStatus) {
+        let Some(duration) = self.config().soft_start.ramp() else {
+            return;
+        };
+        let from = KeyLightStatus {
+            brightness: Brightness::new_clamped(*self.capabilities(name).brightness.start()),
+            ..target.clone()
+        };
+        let mut expected = from.brightness;
+        let start = Instant::now();
+        loop {
+            tokio::time::sleep(FADE_STEP).await;
+            let step = scene::interpolate(&from, &target, start.elapsed(), duration);
+            let mut interrupted = false;
+            let result = self
+                .update(name, |status| {
+                    interrupted = status.power != PowerStatus::On || status.brightness != expected;
+                    if !interrupted {
+                        status.brightness = step.brightness;
+                    }
+                })
+                .await;
+            match result {
+                Ok(_) if interrupted => {
+                    log::debug!("Soft start of {name} interrupted");
+                    return;
+                }
+                Ok(status) if status.brightness == target.brightness => return,
+                Ok(status) => expected = status.brightness,
+                Err(err) => {
+                    log::warn!("Soft start of {name} failed: {err}");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Queue `update` for a device that failed with `err`, if its state is known
+    fn queue<F>(
+        &self,
+        name: &str,
+        update: F,
+        err: DaemonError,
+    ) -> Result<KeyLightStatus, DaemonError>
+    where
+        F: FnOnce(&mut KeyLightStatus),
+    {
+        let mut pending = self.inner.pending.write().expect("lock poisoned");
+        let Some(mut desired) = pending
+            .get(name)
+            .map(|(status, _)| status.clone())
+            .or_else(|| self.cached_status(name))
+        else {
+            return Err(err);
+        };
+        log::info!("{name} is unreachable ({err}), queueing the change");
+        update(&mut desired);
+        pending.insert(name.to_string(), (desired, current_source()));
+        Err(DaemonError::Queued(name.to_string()))
+    }
+
+    /// State queued for the device while it is unreachable
+    pub fn pending_status(&self, name: &str) -> Option<KeyLightStatus> {
+        self.inner
+            .pending
+            .read()
+            .expect("lock poisoned")
+            .get(name)
+            .map(|(status, _)| status.clone())
+    }
+
+    /// Apply the state queued for a device that is reachable again
+    async fn reconcile(&self, name: &str) {
+        let pending = self
+            .inner
+            .pending
+            .write()
+            .expect("lock poisoned")
+            .remove(name);
+        let Some((desired, source)) = pending else {
+            return;
+        };
+        log::info!("{name} is back, applying the queued change");
+        let result = with_source(source, self.update(name, |status| *status = desired)).await;
+        if let Err(err) = result {
+            log::warn!("Failed to apply the queued change to {name}: {err}");
+        }
+    }
+
+    /// Apply `update` to the device, with its calibration offsets
+    pub async fn apply(
+        &self,
+        name: &str,
+        update: &LightUpdate,
+    ) -> Result<KeyLightStatus, DaemonError> {
+        let update = self.config().calibrated(name, update);
+        self.update(name, |status| update.apply(status)).await
+    }
+
+    pub async fn apply_preset(
+        &self,
+        name: &str,
+        preset: &str,
+    ) -> Result<KeyLightStatus, DaemonError> {
+        let update = self.preset(preset)?.clone();
+        let status = self.apply(name, &update).await?;
+        self.preset_applied(name, preset);
+        Ok(status)
+    }
+
+    /// Gradually change the brightness and temperature of the device to `target`, with its
+    /// calibration offsets, over `duration`. The light is turned on before fading in and off
+    /// after fading out.
+    pub async fn fade(
+        &self,
+        name: &str,
+        target: &LightUpdate,
+        duration: Duration,
+    ) -> Result<KeyLightStatus, DaemonError> {
+        let start = self.status(name).await?;
+        let mut end = start.clone();
+        self.config().calibrated(name, target).apply(&mut end);
+
+        if end.power == PowerStatus::On {
+            self.set_power(name, PowerStatus::On).await?;
+        }
+        let steps = (duration.as_millis() / FADE_STEP.as_millis()).max(1) as u32;
+        for step in 1..=steps {
+            let t = f64::from(step) / f64::from(steps);
+            let lerp = |from: f64, to: f64| (from + t * (to - from)).round();
+            let brightness = lerp(start.brightness.0.into(), end.brightness.0.into()) as u8;
+            let temperature = start
+                .temperature
+                .zip(end.temperature)
+                .map(|(from, to)| lerp(from.0.into(), to.0.into()) as u16)
+                .and_then(|temperature| Temperature::new(temperature).ok());
+            self.update(name, |status| {
+                status.brightness = Brightness::new(brightness).unwrap_or(status.brightness);
+                status.temperature = temperature.or(status.temperature);
+            })
+            .await?;
+            if step < steps {
+                tokio::time::sleep(FADE_STEP).await;
+            }
+        }
+        self.update(name, |status| *status = end).await
+    }
+
+    pub async fn toggle(&self, name: &str) -> Result<PowerStatus, DaemonError> {
+        let status = self.update(name, |status| status.power.toggle()).await?;
+        Ok(status.power)
+    }
+
+    pub async fn set_power(&self, name: &str, power: PowerStatus) -> Result<(), DaemonError> {
+        self.update(name, |status| status.power = power).await?;
+        Ok(())
+    }
+
+    pub async fn set_brightness(
+        &self,
+        name: &str,
+        brightness: Brightness,
+    ) -> Result<(), DaemonError> {
+        self.update(name, |status| status.brightness = brightness)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_temperature(
+        &self,
+        name: &str,
+        temperature: Temperature,
+    ) -> Result<(), DaemonError> {
+        self.update(name, |status| status.temperature = Some(temperature))
+            .await?;
+        Ok(())
+    }
+
+    /// Play the scene `name` from the scenes directory, reloaded on every call
+    pub async fn play_scene(&self, name: &str) -> Result<(), DaemonError> {
+        let scene = Scene::load(name)?;
+        let devices: Vec<String> = self
+            .devices()
+            .into_iter()
+            .map(|device| device.name)
+            .collect();
+        let steps = scene.steps(self.config(), &devices)?;
+        log::info!("Playing scene {name}");
+        scene::play(&steps, self).await?;
+        Ok(())
+    }
+
+    /// Estimated power usage of the devices
+    pub fn power_stats(&self) -> Vec<PowerStats> {
+        let products = self.inner.products.read().expect("lock poisoned");
+        let meters = self.inner.meters.read().expect("lock poisoned");
+        self.devices()
+            .into_iter()
+            .map(|device| {
+                let meter = meters.get(&device.name);
+                PowerStats {
+                    product: products.get(&device.name).cloned(),
+                    watts: meter.map(EnergyMeter::watts),
+                    watt_hours: meter.map(EnergyMeter::watt_hours),
+                    since: meter.map(EnergyMeter::since),
+                    device: device.name,
+                }
+            })
+            .collect()
+    }
+
+    /// Fetch the product name of the device to estimate its power usage
+    async fn identify(&self, device: &Device) {
+        if self
+            .inner
+            .products
+            .read()
+            .expect("lock poisoned")
+            .contains_key(&device.name)
+        {
+            return;
+        }
+        let options = RequestOptions::with_timeout(IDENTIFY_TIMEOUT);
+        match get_accessory_info_with(device.url.clone(), &options).await {
+            Ok(info) => {
+                self.inner
+                    .products
+                    .write()
+                    .expect("lock poisoned")
+                    .insert(device.name.clone(), info.product_name);
+                if let Some(status) = self.cached_status(&device.name) {
+                    self.meter(&device.name, &status);
+                }
+            }
+            Err(err) => log::debug!("Accessory info of {} failed: {err}", device.name),
+        }
+    }
+
+    /// Capabilities of the device from its product, the ones of a Key Light until identified
+    pub fn capabilities(&self, name: &str) -> DeviceCapabilities {
+        self.inner
+            .products
+            .read()
+            .expect("lock poisoned")
+            .get(name)
+            .map_or_else(DeviceCapabilities::default, |product| {
+                DeviceCapabilities::from_product(product)
+            })
+            .with_max_brightness(self.config().limits.device_max_brightness(name))
+    }
+
+    /// Account for the energy used by the device until its new `status`
+    fn meter(&self, name: &str, status: &KeyLightStatus) {
+        let products = self.inner.products.read().expect("lock poisoned");
+        let Some(watts) = products
+            .get(name)
+            .and_then(|product| estimated_watts(product, status))
+        else {
+            return;
+        };
+        self.inner
+            .meters
+            .write()
+            .expect("lock poisoned")
+            .entry(name.to_string())
+            .and_modify(|meter| meter.set_watts(watts))
+            .or_insert_with(|| EnergyMeter::new(watts));
+    }
+
+    /// Store the new state of the device and notify subscribers if it changed
+    fn record(&self, device: Device, status: KeyLightStatus, source: &'static str) {
+        let previous = self
+            .inner
+            .statuses
+            .write()
+            .expect("lock poisoned")
+            .insert(device.name.clone(), status.clone());
+        if previous.as_ref() != Some(&status) {
+            self.meter(&device.name, &status);
+            let _ = self.inner.events.send(DaemonEvent::StateChanged {
+                device,
+                previous,
+                status,
+                source,
+                actor: current_actor(),
+            });
+        }
+    }
+
+    /// Periodically refresh the devices and their state to pick up external changes
+    async fn poll(self) {
+        let mut known_devices = self.devices();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let devices = self.devices();
+            if devices != known_devices {
+                log::info!("Devices changed: {} device(s) available", devices.len());
+                known_devices = devices.clone();
+                let _ = self
+                    .inner
+                    .events
+                    .send(DaemonEvent::DevicesChanged(devices.clone()));
+            }
+
+            for (device, status) in get_statuses(devices).await {
+                let status = status.map_err(DaemonError::from);
+                match status.and_then(|status| self.observe(device.clone(), status)) {
+                    Ok(_) => {
+                        self.identify(&device).await;
+                        self.reconcile(&device.name).await;
+                    }
+                    Err(err) => log::debug!("Poll {} failed: {err}", device.name),
+                }
+            }
+        }
+    }
+}
+
+/// Scenes played by the daemon go through [`Daemon::update`], queueing the changes to the
+/// unreachable lights
+impl SceneLights for Daemon {
+    async fn status(&self, device: &str) -> anyhow::Result<KeyLightStatus> {
+        Ok(Daemon::status(self, device).await?)
+    }
+
+    async fn set(&self, device: &str, status: KeyLightStatus) -> anyhow::Result<()> {
+        match self.update(device, |current| *current = status).await {
+            Ok(_) | Err(DaemonError::Queued(_)) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Clamp the brightness and temperature of `status` to the configured limits of the device
+/// `name`
+fn clamp_to_limits(limits: &LimitsConfig, name: &str, status: &mut KeyLightStatus) {
+    let mut brightness = status.brightness.0;
+    if let Some(max) = limits.max_brightness {
+        brightness = brightness.min(max.0);
+    }
+    if let Some(min) = limits.min_brightness {
+        brightness = brightness.max(min.0);
+    }
+    status.brightness = limits.cap_brightness(name, Brightness::new_clamped(brightness));
+
+    // Values are in mireds: the lowest kelvin gives the highest value
+    if let Some(temperature) = &mut status.temperature {
+        let mut value = temperature.0;
+        if let Some(min_kelvin) = limits.min_kelvin {
+            value = value.min(Temperature::from(min_kelvin).0);
+        }
+        if let Some(max_kelvin) = limits.max_kelvin {
+            value = value.max(Temperature::from(max_kelvin).0);
+        }
+        *temperature = Temperature::new_clamped(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    use super::*;
+
+    /// Light answering with the state it was last set to, the first `failures` changes failing
+    async fn fake_light(status: DeviceStatus, mut failures: usize) -> url::Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(async move {
+            let mut status = serde_json::to_string(&status).unwrap();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                let body = loop {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = head
+                        .lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                        .map_or(0, |(_, value)| value.trim().parse().unwrap());
+                    if len == 0 || body.len() >= length {
+                        break (head.to_string(), body.to_string());
+                    }
+                };
+                let response = match body.0.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+                    ["GET", "/elgato/lights"] => Some(status.clone()),
+                    ["PUT", "/elgato/lights"] if failures > 0 => {
+                        failures -= 1;
+                        None
+                    }
+                    ["PUT", "/elgato/lights"] => {
+                        status = body.1;
+                        Some(status.clone())
+                    }
+                    _ => None,
+                };
+                let response = match response {
+                    Some(json) => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{json}",
+                        json.len()
+                    ),
+                    None => "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\
+                             connection: close\r\n\r\n"
+                        .to_string(),
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn limits() {
+        let limits = LimitsConfig {
+            max_brightness: Some(crate::Percent::new_clamped(80)),
+            min_kelvin: Some(crate::Kelvin(4000)),
+            devices: std::collections::BTreeMap::from([(
+                "Left".to_string(),
+                crate::DeviceLimits {
+                    max_brightness: Some(crate::Percent::new_clamped(50)),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let mut status = KeyLightStatus::white(
+            PowerStatus::On,
+            Brightness::new(100).unwrap(),
+            Temperature::new(344).unwrap(),
+        );
+        let mut left = status.clone();
+        clamp_to_limits(&limits, "Right", &mut status);
+        assert_eq!(status.brightness, Brightness::new(80).unwrap());
+        assert_eq!(status.temperature, Temperature::new(250).ok());
+        clamp_to_limits(&limits, "Left", &mut left);
+        assert_eq!(left.brightness, Brightness::new(50).unwrap());
+
+        // Within the limits
+        let mut within = KeyLightStatus {
+            brightness: Brightness::new(10).unwrap(),
+            temperature: Temperature::new(143).ok(),
+            ..status
+        };
+        let expected = within.clone();
+        clamp_to_limits(&limits, "Left", &mut within);
+        assert_eq!(within, expected);
+    }
+
+    #[test]
+    fn soft_start_target() {
+        let config = Config {
+            soft_start: crate::SoftStartConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let daemon = Daemon::new(
+            config,
+            Arc::new(RwLock::new(AvahiState { devices: vec![] })),
+        );
+        let target = KeyLightStatus::white(
+            PowerStatus::On,
+            Brightness::new(80).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        let mut light = target.clone();
+        assert_eq!(
+            daemon.soft_start_target("light", false, &mut light),
+            Some(target.clone())
+        );
+        assert_eq!(light.brightness, Brightness::new(3).unwrap());
+
+        // Already on
+        let mut light = target.clone();
+        assert_eq!(daemon.soft_start_target("light", true, &mut light), None);
+        assert_eq!(light, target);
+    }
+
+    #[tokio::test]
+    async fn soft_start_queued() {
+        let off = KeyLightStatus::white(
+            PowerStatus::Off,
+            Brightness::new(80).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        let url = fake_light(
+            DeviceStatus {
+                number_of_lights: 1,
+                lights: vec![off.clone()],
+            },
+            1,
+        )
+        .await;
+        let config = Config {
+            soft_start: crate::SoftStartConfig {
+                enabled: true,
+                duration: 100,
+            },
+            ..Default::default()
+        };
+        let devices = vec![Device {
+            name: "light".to_string(),
+            url,
+        }];
+        let daemon = Daemon::new(config, Arc::new(RwLock::new(AvahiState { devices })));
+        daemon
+            .inner
+            .statuses
+            .write()
+            .unwrap()
+            .insert("light".to_string(), off.clone());
+
+        // The change is queued at the brightness of the end of the ramp, not of its start
+        assert!(matches!(
+            daemon.set_power("light", PowerStatus::On).await,
+            Err(DaemonError::Queued(_))
+        ));
+        let on = KeyLightStatus {
+            power: PowerStatus::On,
+            ..off
+        };
+        assert_eq!(daemon.pending_status("light"), Some(on.clone()));
+
+        daemon.reconcile("light").await;
+        assert_eq!(daemon.pending_status("light"), None);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while daemon.cached_status("light") != Some(on.clone()) {
+                tokio::time::sleep(FADE_STEP).await;
+            }
+        })
+        .await
+        .expect("ramped up to the queued brightness");
+    }
+
+    #[tokio::test]
+    async fn coalesced_changes() {
+        let off = KeyLightStatus::white(
+            PowerStatus::Off,
+            Brightness::new(80).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        let url = fake_light(
+            DeviceStatus {
+                number_of_lights: 1,
+                lights: vec![off.clone()],
+            },
+            0,
+        )
+        .await;
+        let devices = vec![Device {
+            name: "light".to_string(),
+            url,
+        }];
+        let daemon = Daemon::new(
+            Config::default(),
+            Arc::new(RwLock::new(AvahiState { devices })),
+        );
+        daemon.set_power("light", PowerStatus::On).await.unwrap();
+
+        // Waiting for the turn of the first, the two others are queued behind it
+        let (first, invalid, last) = tokio::join!(
+            daemon.set_brightness("light", Brightness::new(50).unwrap()),
+            daemon.set_brightness("light", Brightness::new(1).unwrap()),
+            daemon.set_temperature("light", Temperature::new(250).unwrap()),
+        );
+        first.unwrap();
+        assert!(matches!(invalid, Err(DaemonError::Unsupported(_))));
+        last.unwrap();
+        assert_eq!(
+            daemon.cached_status("light"),
+            Some(KeyLightStatus {
+                power: PowerStatus::On,
+                brightness: Brightness::new(50).unwrap(),
+                temperature: Temperature::new(250).ok(),
+                ..off
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_unreachable() {
+        let daemon = Daemon::new(
+            Config::default(),
+            Arc::new(RwLock::new(AvahiState { devices: vec![] })),
+        );
+        assert!(matches!(
+            daemon.set_power("light", PowerStatus::On).await,
+            Err(DaemonError::DeviceNotFound(_))
+        ));
+        assert_eq!(daemon.pending_status("light"), None);
+
+        let status = KeyLightStatus::white(
+            PowerStatus::Off,
+            Brightness::new(20).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        daemon
+            .inner
+            .statuses
+            .write()
+            .unwrap()
+            .insert("light".to_string(), status.clone());
+        assert!(matches!(
+            daemon.set_power("light", PowerStatus::On).await,
+            Err(DaemonError::Queued(_))
+        ));
+        assert!(matches!(
+            daemon
+                .set_brightness("light", Brightness::new(50).unwrap())
+                .await,
+            Err(DaemonError::Queued(_))
+        ));
+        assert_eq!(
+            daemon.pending_status("light"),
+            Some(KeyLightStatus {
+                power: PowerStatus::On,
+                brightness: Brightness::new(50).unwrap(),
+                ..status
+            })
+        );
+    }
+}
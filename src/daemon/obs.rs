@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures_util::{SinkExt as _, StreamExt as _};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::ObsConfig;
+
+use super::Daemon;
+
+/// Delay before reconnecting to OBS after the connection failed or closed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// obs-websocket RPC version implemented
+const RPC_VERSION: u64 = 1;
+
+/// Event subscriptions: General (exit) | Scenes | Outputs (stream, record)
+const EVENT_SUBSCRIPTIONS: u64 = (1 << 0) | (1 << 2) | (1 << 6);
+
+/// obs-websocket message opcodes
+const OP_HELLO: u64 = 0;
+const OP_IDENTIFY: u64 = 1;
+const OP_IDENTIFIED: u64 = 2;
+const OP_EVENT: u64 = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ObsError {
+    #[error(transparent)]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error("Invalid message: {0}")]
+    Protocol(String),
+    #[error("OBS requires a password, set `obs.password` in the config")]
+    PasswordRequired,
+    #[error("Connection closed")]
+    Closed,
+}
+
+/// Light action triggered by an OBS event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObsAction {
+    /// Streaming or recording started
+    TurnOn,
+    /// Switched to a scene with a preset
+    ApplyPreset(String),
+    /// OBS is exiting
+    TurnOff,
+}
+
+/// Map an obs-websocket event to the action configured for it
+pub fn event_action(config: &ObsConfig, event_type: &str, data: &Value) -> Option<ObsAction> {
+    match event_type {
+        "StreamStateChanged" | "RecordStateChanged" if data["outputActive"] == true => {
+            Some(ObsAction::TurnOn)
+        }
+        "CurrentProgramSceneChanged" => {
+            let scene = data["sceneName"].as_str()?;
+            config
+                .scenes
+                .get(scene)
+                .cloned()
+                .map(ObsAction::ApplyPreset)
+        }
+        "ExitStarted" if config.off_on_exit => Some(ObsAction::TurnOff),
+        _ => None,
+    }
+}
+
+/// Authentication string: `base64(sha256(base64(sha256(password + salt)) + challenge))`
+pub fn authentication(password: &str, salt: &str, challenge: &str) -> String {
+    let secret = BASE64.encode(Sha256::digest(format!("{password}{salt}")));
+    BASE64.encode(Sha256::digest(format!("{secret}{challenge}")))
+}
+
+/// Apply the configured presets on OBS events, reconnecting whenever OBS restarts
+pub async fn run(daemon: Daemon, config: ObsConfig) {
+    loop {
+        match connect(&daemon, &config).await {
+            Ok(()) | Err(ObsError::Closed) => log::info!("OBS connection closed"),
+            Err(err @ ObsError::PasswordRequired) => {
+                log::error!("{err}");
+                return;
+            }
+            Err(err) => log::debug!("OBS connection failed: {err}"),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(daemon: &Daemon, config: &ObsConfig) -> Result<(), ObsError> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(config.url.as_str()).await?;
+
+    let hello = next_message(&mut socket).await?;
+    if hello["op"] != OP_HELLO {
+        return Err(ObsError::Protocol(hello.to_string()));
+    }
+
+    let mut identify = json!({
+        "rpcVersion": RPC_VERSION,
+        "eventSubscriptions": EVENT_SUBSCRIPTIONS,
+    });
+    let auth = &hello["d"]["authentication"];
+    if auth.is_object() {
+        let password = config
+            .password
+            .as_deref()
+            .ok_or(ObsError::PasswordRequired)?;
+        let (Some(salt), Some(challenge)) = (auth["salt"].as_str(), auth["challenge"].as_str())
+        else {
+            return Err(ObsError::Protocol(hello.to_string()));
+        };
+        identify["authentication"] = authentication(password, salt, challenge).into();
+    }
+    let identify = json!({ "op": OP_IDENTIFY, "d": identify });
+    socket.send(Message::text(identify.to_string())).await?;
+
+    let identified = next_message(&mut socket).await?;
+    if identified["op"] != OP_IDENTIFIED {
+        return Err(ObsError::Protocol(identified.to_string()));
+    }
+    log::info!("Connected to OBS at {}", config.url);
+
+    loop {
+        let message = next_message(&mut socket).await?;
+        if message["op"] != OP_EVENT {
+            continue;
+        }
+        let event = &message["d"];
+        let Some(event_type) = event["eventType"].as_str() else {
+            continue;
+        };
+        log::debug!("OBS event: {event_type}");
+        daemon.notify(event_type);
+
+        match event_action(config, event_type, &event["eventData"]) {
+            Some(ObsAction::TurnOn) => {
+                daemon
+                    .turn_on_all(&config.devices, config.preset.as_deref())
+                    .await
+            }
+            Some(ObsAction::ApplyPreset(preset)) => {
+                daemon.turn_on_all(&config.devices, Some(&preset)).await
+            }
+            Some(ObsAction::TurnOff) => daemon.turn_off_all(&config.devices).await,
+            None => {}
+        }
+    }
+}
+
+/// Next JSON message, skipping the websocket control frames
+async fn next_message<S>(socket: &mut S) -> Result<Value, ObsError>
+where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        match socket.next().await.ok_or(ObsError::Closed)?? {
+            Message::Text(text) => {
+                return serde_json::from_str(&text)
+                    .map_err(|err| ObsError::Protocol(err.to_string()))
+            }
+            Message::Close(_) => return Err(ObsError::Closed),
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn authentication_test() {
+        // Example from the obs-websocket protocol documentation
+        assert_eq!(
+            authentication(
+                "supersecretpassword",
+                "lM1GncleQOaCu9lT1yeUZhFYnqhsLLP1G5lAGo3ixaI=",
+                "+IxH4CnCiqpX1rM9scsNynZzbOe4KhDeYcTNS3PDaeY="
+            ),
+            "1Ct943GAT+6YQUUX47Ia/ncufilbe6+oD6lY+5kaCu4="
+        );
+    }
+
+    #[test]
+    fn event_action_test() {
+        let config = ObsConfig {
+            scenes: BTreeMap::from([("Camera".to_string(), "bright".to_string())]),
+            ..Default::default()
+        };
+
+        let started = json!({"outputActive": true, "outputState": "OBS_WEBSOCKET_OUTPUT_STARTED"});
+        let stopped = json!({"outputActive": false, "outputState": "OBS_WEBSOCKET_OUTPUT_STOPPED"});
+        assert_eq!(
+            event_action(&config, "StreamStateChanged", &started),
+            Some(ObsAction::TurnOn)
+        );
+        assert_eq!(
+            event_action(&config, "RecordStateChanged", &started),
+            Some(ObsAction::TurnOn)
+        );
+        assert_eq!(event_action(&config, "StreamStateChanged", &stopped), None);
+
+        assert_eq!(
+            event_action(
+                &config,
+                "CurrentProgramSceneChanged",
+                &json!({"sceneName": "Camera"})
+            ),
+            Some(ObsAction::ApplyPreset("bright".to_string()))
+        );
+        assert_eq!(
+            event_action(
+                &config,
+                "CurrentProgramSceneChanged",
+                &json!({"sceneName": "Desktop"})
+            ),
+            None
+        );
+
+        assert_eq!(
+            event_action(&config, "ExitStarted", &json!({})),
+            Some(ObsAction::TurnOff)
+        );
+        let config = ObsConfig {
+            off_on_exit: false,
+            ..config
+        };
+        assert_eq!(event_action(&config, "ExitStarted", &json!({})), None);
+    }
+}
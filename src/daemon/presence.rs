@@ -0,0 +1,129 @@
+use std::{collections::HashMap, net::IpAddr, process::Stdio, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{KeyLightStatus, PresenceConfig};
+
+use super::Daemon;
+
+/// ARP table of the kernel
+const ARP_TABLE: &str = "/proc/net/arp";
+/// `ATF_COM` flag of a resolved ARP entry
+const ARP_COMPLETE: u32 = 0x2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PresenceError {
+    #[error("Neither host nor mac configured for the presence automation")]
+    NoTarget,
+}
+
+/// Whether the ARP table (`/proc/net/arp` format) has a resolved entry for `host` or `mac`
+pub fn in_arp_table(table: &str, host: Option<IpAddr>, mac: Option<&str>) -> bool {
+    table.lines().skip(1).any(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let [ip, _, flags, hw_address, ..] = columns[..] else {
+            return false;
+        };
+        let complete = u32::from_str_radix(flags.trim_start_matches("0x"), 16)
+            .is_ok_and(|flags| flags & ARP_COMPLETE != 0);
+        let ip_matches = host.is_some_and(|host| ip.parse() == Ok(host));
+        let mac_matches = mac.is_some_and(|mac| hw_address.eq_ignore_ascii_case(mac));
+        complete && (ip_matches || mac_matches)
+    })
+}
+
+/// Whether the phone is on the network. Pinging also refreshes its ARP entry,
+/// phones sleeping with Wi-Fi power saving often drop pings but still answer ARP requests.
+async fn is_present(config: &PresenceConfig) -> bool {
+    if let Some(host) = config.host {
+        let ping = tokio::process::Command::new("ping")
+            .args(["-c", "1", "-W", "1", &host.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        match ping {
+            Ok(status) if status.success() => return true,
+            Ok(_) => {}
+            Err(err) => log::debug!("Failed to ping {host}: {err}"),
+        }
+    }
+    match tokio::fs::read_to_string(ARP_TABLE).await {
+        Ok(table) => in_arp_table(&table, config.host, config.mac.as_deref()),
+        Err(err) => {
+            log::debug!("Failed to read the ARP table: {err}");
+            false
+        }
+    }
+}
+
+/// Turn the lights off when the phone has left the network for a while,
+/// restoring them when it is back
+pub async fn run(daemon: Daemon, config: PresenceConfig) -> Result<(), PresenceError> {
+    if config.host.is_none() && config.mac.is_none() {
+        return Err(PresenceError::NoTarget);
+    }
+    log::info!("Watching the presence of the phone on the network");
+    let away_after = Duration::from_secs(config.away_minutes * 60);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    let mut last_seen = Instant::now();
+    // State of the lights when they were turned off, set while away
+    let mut saved: Option<HashMap<String, KeyLightStatus>> = None;
+
+    loop {
+        interval.tick().await;
+        if is_present(&config).await {
+            last_seen = Instant::now();
+            let Some(saved) = saved.take() else {
+                continue;
+            };
+            log::info!("Phone back on the network, restoring the lights");
+            daemon.notify("arrived");
+            for (name, status) in saved {
+                if let Err(err) = daemon.update(&name, move |current| *current = status).await {
+                    log::error!("Failed to restore {name}: {err}");
+                }
+            }
+        } else if saved.is_none() && last_seen.elapsed() >= away_after {
+            log::info!("Phone away from the network, turning off");
+            daemon.notify("left");
+            saved = Some(
+                daemon
+                    .targets(&config.devices)
+                    .into_iter()
+                    .filter_map(|device| {
+                        let status = daemon.cached_status(&device.name)?;
+                        Some((device.name, status))
+                    })
+                    .collect(),
+            );
+            daemon.turn_off_all(&config.devices).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arp_table() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.20     0x1         0x2         aa:bb:cc:dd:ee:ff     *        wlan0
+192.168.1.21     0x1         0x0         00:00:00:00:00:00     *        wlan0
+";
+        let ip = |ip: &str| Some(ip.parse().unwrap());
+        assert!(in_arp_table(table, ip("192.168.1.20"), None));
+        assert!(in_arp_table(table, None, Some("AA:BB:CC:DD:EE:FF")));
+        assert!(in_arp_table(
+            table,
+            ip("192.168.1.99"),
+            Some("aa:bb:cc:dd:ee:ff")
+        ));
+        // Incomplete entry, the host did not answer
+        assert!(!in_arp_table(table, ip("192.168.1.21"), None));
+        assert!(!in_arp_table(table, None, Some("11:22:33:44:55:66")));
+        assert!(!in_arp_table("", ip("192.168.1.20"), None));
+    }
+}
@@ -0,0 +1,541 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    scene::{Scene, SceneError},
+    KeyLightStatus, LightUpdate, PowerStats, PowerStatus, RoomStatus,
+};
+
+use super::{access, with_actor, with_source, Daemon, DaemonError};
+
+/// OpenAPI document of the REST API, served at `GET /openapi.json`
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Elgato Keylight REST API"),
+    paths(
+        list_devices,
+        get_device,
+        update_device,
+        toggle_device,
+        list_presets,
+        apply_preset,
+        list_rooms,
+        get_room,
+        update_room,
+        toggle_room,
+        apply_room_preset,
+        list_scenes,
+        play_scene,
+        stats
+    ),
+    components(schemas(
+        DeviceEntry,
+        ErrorBody,
+        KeyLightStatus,
+        LightUpdate,
+        PowerStats,
+        PowerStatus,
+        RoomStatus
+    ))
+)]
+pub struct ApiDoc;
+
+/// Device as listed by `GET /devices`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceEntry {
+    pub name: String,
+    pub url: String,
+    /// Last known state, if the device has been reached already
+    pub status: Option<KeyLightStatus>,
+}
+
+/// Error body: `{"error": "..."}`
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for DaemonError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            DaemonError::DeviceNotFound(_)
+            | DaemonError::PresetNotFound(_)
+            | DaemonError::RoomNotFound(_)
+            | DaemonError::Scene(SceneError::NotFound(_)) => StatusCode::NOT_FOUND,
+            DaemonError::Queued(_) => StatusCode::ACCEPTED,
+            DaemonError::Paused(_) => StatusCode::CONFLICT,
+            DaemonError::NoLights(_)
+            | DaemonError::Request(_)
+            | DaemonError::Scene(SceneError::Device { .. }) => StatusCode::BAD_GATEWAY,
+            DaemonError::Scene(_) | DaemonError::Unsupported(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        let body = ErrorBody {
+            error: self.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// REST API proxying to the lights:
+///
+/// - `GET /devices`: list the devices
+/// - `GET /devices/:name`: current state of a device
+/// - `PUT /devices/:name`: partial update, e.g. `{"on": 1, "brightness": 40}`
+/// - `POST /devices/:name/toggle`: toggle a device
+/// - `GET /presets`: list the presets
+/// - `POST /devices/:name/presets/:preset`: apply a preset to a device
+/// - `GET /rooms`: aggregated state of every room
+/// - `GET /rooms/:room`: aggregated state of a room
+/// - `PUT /rooms/:room`: partial update of all the lights of a room
+/// - `POST /rooms/:room/toggle`: turn a room off if any light is on, on otherwise
+/// - `POST /rooms/:room/presets/:preset`: apply a preset to a room
+/// - `GET /scenes`: list the scenes
+/// - `POST /scenes/:scene`: play a scene, answered once it is over
+/// - `GET /stats`: estimated power usage of the devices
+/// - `GET /metrics`: same in the Prometheus text format
+/// - `GET /openapi.json`: OpenAPI document of this API
+pub fn router(daemon: Daemon) -> Router {
+    Router::new()
+        .route("/devices", get(list_devices))
+        .route("/devices/:name", get(get_device).put(update_device))
+        .route("/devices/:name/toggle", post(toggle_device))
+        .route("/devices/:name/presets/:preset", post(apply_preset))
+        .route("/presets", get(list_presets))
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/:room", get(get_room).put(update_room))
+        .route("/rooms/:room/toggle", post(toggle_room))
+        .route("/rooms/:room/presets/:preset", post(apply_room_preset))
+        .route("/scenes", get(list_scenes))
+        .route("/scenes/:scene", post(play_scene))
+        .route("/stats", get(stats))
+        .route("/metrics", get(metrics))
+        .route("/openapi.json", get(openapi))
+        .layer(middleware::from_fn(rest_source))
+        .layer(middleware::from_fn_with_state(
+            daemon.clone(),
+            require_token,
+        ))
+        .with_state(daemon)
+}
+
+/// Reject the changes without one of the configured bearer tokens, reads stay open
+async fn require_token(State(daemon): State<Daemon>, request: Request, next: Next) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let read = matches!(*request.method(), Method::GET | Method::HEAD);
+    if read || access::token_allowed(&daemon.config().access, authorization) {
+        return next.run(request).await;
+    }
+    let body = ErrorBody {
+        error: "a valid bearer token is required to change the lights".to_string(),
+    };
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(body),
+    )
+        .into_response()
+}
+
+/// Report the REST API as the source of the changes made by the requests,
+/// and the address of the client as their actor
+async fn rest_source(request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.to_string());
+    match peer {
+        Some(peer) => with_source("rest", with_actor(peer, next.run(request))).await,
+        None => with_source("rest", next.run(request)).await,
+    }
+}
+
+/// Serve the REST API on `listen` until the process exits
+pub async fn serve(daemon: Daemon, listen: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    serve_on(daemon, listener).await
+}
+
+/// Serve the REST API on an already bound listener, e.g. passed by socket activation
+pub async fn serve_on(daemon: Daemon, listener: tokio::net::TcpListener) -> std::io::Result<()> {
+    let address = listener.local_addr()?;
+    log::info!("REST API listening on {address}");
+    if !address.ip().is_loopback() && daemon.config().access.tokens.is_empty() {
+        log::warn!(
+            "REST API reachable from the network without tokens, anyone can change the lights"
+        );
+    }
+    axum::serve(
+        listener,
+        router(daemon).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// List the devices and their last known state
+#[utoipa::path(get, path = "/devices", responses(
+    (status = 200, description = "Available devices", body = [DeviceEntry]),
+))]
+async fn list_devices(State(daemon): State<Daemon>) -> Json<Vec<DeviceEntry>> {
+    let devices = daemon
+        .devices()
+        .into_iter()
+        .map(|device| DeviceEntry {
+            status: daemon.cached_status(&device.name),
+            url: device.url.to_string(),
+            name: device.name,
+        })
+        .collect();
+    Json(devices)
+}
+
+/// Current state of a device
+#[utoipa::path(get, path = "/devices/{name}",
+    params(("name" = String, Path, description = "Device name")),
+    responses(
+        (status = 200, description = "Current state", body = KeyLightStatus),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn get_device(
+    State(daemon): State<Daemon>,
+    Path(name): Path<String>,
+) -> Result<Json<KeyLightStatus>, DaemonError> {
+    Ok(Json(daemon.status(&name).await?))
+}
+
+/// Partially update a device, unset fields are left unchanged
+#[utoipa::path(put, path = "/devices/{name}",
+    params(("name" = String, Path, description = "Device name")),
+    request_body = LightUpdate,
+    responses(
+        (status = 200, description = "New state", body = KeyLightStatus),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn update_device(
+    State(daemon): State<Daemon>,
+    Path(name): Path<String>,
+    Json(update): Json<LightUpdate>,
+) -> Result<Json<KeyLightStatus>, DaemonError> {
+    Ok(Json(daemon.apply(&name, &update).await?))
+}
+
+/// Toggle a device
+#[utoipa::path(post, path = "/devices/{name}/toggle",
+    params(("name" = String, Path, description = "Device name")),
+    responses(
+        (status = 200, description = "New state", body = KeyLightStatus),
+        (status = 404, description = "Unknown device", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn toggle_device(
+    State(daemon): State<Daemon>,
+    Path(name): Path<String>,
+) -> Result<Json<KeyLightStatus>, DaemonError> {
+    let status = daemon.update(&name, |status| status.power.toggle()).await?;
+    Ok(Json(status))
+}
+
+/// List the presets defined in the config
+#[utoipa::path(get, path = "/presets", responses(
+    (status = 200, description = "Presets by name", body = BTreeMap<String, LightUpdate>),
+))]
+async fn list_presets(State(daemon): State<Daemon>) -> Json<BTreeMap<String, LightUpdate>> {
+    Json(daemon.config().presets.clone())
+}
+
+/// Apply a preset to a device
+#[utoipa::path(post, path = "/devices/{name}/presets/{preset}",
+    params(
+        ("name" = String, Path, description = "Device name"),
+        ("preset" = String, Path, description = "Preset name"),
+    ),
+    responses(
+        (status = 200, description = "New state", body = KeyLightStatus),
+        (status = 404, description = "Unknown device or preset", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn apply_preset(
+    State(daemon): State<Daemon>,
+    Path((name, preset)): Path<(String, String)>,
+) -> Result<Json<KeyLightStatus>, DaemonError> {
+    Ok(Json(daemon.apply_preset(&name, &preset).await?))
+}
+
+/// Aggregated last known state of every room
+#[utoipa::path(get, path = "/rooms", responses(
+    (status = 200, description = "Rooms by name", body = BTreeMap<String, RoomStatus>),
+))]
+async fn list_rooms(State(daemon): State<Daemon>) -> Json<BTreeMap<String, RoomStatus>> {
+    let rooms = daemon
+        .config()
+        .rooms
+        .keys()
+        .filter_map(|room| Some((room.clone(), daemon.room_status(room).ok()?)))
+        .collect();
+    Json(rooms)
+}
+
+/// Aggregated last known state of a room
+#[utoipa::path(get, path = "/rooms/{room}",
+    params(("room" = String, Path, description = "Room name")),
+    responses(
+        (status = 200, description = "Aggregated state", body = RoomStatus),
+        (status = 404, description = "Unknown room", body = ErrorBody),
+    )
+)]
+async fn get_room(
+    State(daemon): State<Daemon>,
+    Path(room): Path<String>,
+) -> Result<Json<RoomStatus>, DaemonError> {
+    Ok(Json(daemon.room_status(&room)?))
+}
+
+/// Partially update all the lights of a room, unset fields are left unchanged
+#[utoipa::path(put, path = "/rooms/{room}",
+    params(("room" = String, Path, description = "Room name")),
+    request_body = LightUpdate,
+    responses(
+        (status = 200, description = "New state", body = RoomStatus),
+        (status = 404, description = "Unknown room or device", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn update_room(
+    State(daemon): State<Daemon>,
+    Path(room): Path<String>,
+    Json(update): Json<LightUpdate>,
+) -> Result<Json<RoomStatus>, DaemonError> {
+    let status = daemon.apply_room(&room, &update).await?;
+    Ok(Json(status))
+}
+
+/// Turn a room off if any of its lights is on, on otherwise
+#[utoipa::path(post, path = "/rooms/{room}/toggle",
+    params(("room" = String, Path, description = "Room name")),
+    responses(
+        (status = 200, description = "New state", body = RoomStatus),
+        (status = 404, description = "Unknown room or device", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn toggle_room(
+    State(daemon): State<Daemon>,
+    Path(room): Path<String>,
+) -> Result<Json<RoomStatus>, DaemonError> {
+    Ok(Json(daemon.toggle_room(&room).await?))
+}
+
+/// Apply a preset to all the lights of a room
+#[utoipa::path(post, path = "/rooms/{room}/presets/{preset}",
+    params(
+        ("room" = String, Path, description = "Room name"),
+        ("preset" = String, Path, description = "Preset name"),
+    ),
+    responses(
+        (status = 200, description = "New state", body = RoomStatus),
+        (status = 404, description = "Unknown room, device or preset", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn apply_room_preset(
+    State(daemon): State<Daemon>,
+    Path((room, preset)): Path<(String, String)>,
+) -> Result<Json<RoomStatus>, DaemonError> {
+    Ok(Json(daemon.apply_preset_room(&room, &preset).await?))
+}
+
+/// List the scenes of the scenes directory
+#[utoipa::path(get, path = "/scenes", responses(
+    (status = 200, description = "Scene names", body = [String]),
+    (status = 422, description = "Unreadable scenes directory", body = ErrorBody),
+))]
+async fn list_scenes() -> Result<Json<Vec<String>>, DaemonError> {
+    Ok(Json(Scene::list()?))
+}
+
+/// Play a scene, answered once all its fades are over
+#[utoipa::path(post, path = "/scenes/{scene}",
+    params(("scene" = String, Path, description = "Scene name")),
+    responses(
+        (status = 204, description = "Scene played"),
+        (status = 404, description = "Unknown scene", body = ErrorBody),
+        (status = 422, description = "Invalid scene", body = ErrorBody),
+        (status = 502, description = "Device unreachable", body = ErrorBody),
+    )
+)]
+async fn play_scene(
+    State(daemon): State<Daemon>,
+    Path(scene): Path<String>,
+) -> Result<StatusCode, DaemonError> {
+    daemon.play_scene(&scene).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Estimated power usage of the devices
+#[utoipa::path(get, path = "/stats", responses(
+    (status = 200, description = "Power usage of each device", body = [PowerStats]),
+))]
+async fn stats(State(daemon): State<Daemon>) -> Json<Vec<PowerStats>> {
+    Json(daemon.power_stats())
+}
+
+/// Power usage in the Prometheus text format
+async fn metrics(State(daemon): State<Daemon>) -> impl IntoResponse {
+    (
+        [("content-type", "text/plain; version=0.0.4")],
+        prometheus_metrics(&daemon.power_stats()),
+    )
+}
+
+fn prometheus_metrics(stats: &[PowerStats]) -> String {
+    let label = |device: &str| {
+        device
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let mut metrics = String::new();
+    let mut metric = |name: &str, help: &str, kind: &str, value: fn(&PowerStats) -> Option<f64>| {
+        metrics.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+        for stat in stats {
+            if let Some(value) = value(stat) {
+                metrics.push_str(&format!(
+                    "{name}{{device=\"{}\"}} {value}\n",
+                    label(&stat.device)
+                ));
+            }
+        }
+    };
+    metric(
+        "keylight_power_watts",
+        "Estimated power draw of the light",
+        "gauge",
+        |stat| stat.watts,
+    );
+    metric(
+        "keylight_energy_watt_hours_total",
+        "Estimated energy used by the light since the daemon started",
+        "counter",
+        |stat| stat.watt_hours,
+    );
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{avahi::AvahiState, Brightness, Config};
+
+    use super::*;
+
+    #[test]
+    fn metrics_format() {
+        let stats = [PowerStats {
+            device: "Key \"Light\"".to_string(),
+            product: Some("Elgato Key Light".to_string()),
+            watts: Some(18.5),
+            watt_hours: Some(2.0),
+            since: None,
+        }];
+        assert_eq!(
+            prometheus_metrics(&stats),
+            "# HELP keylight_power_watts Estimated power draw of the light\n\
+             # TYPE keylight_power_watts gauge\n\
+             keylight_power_watts{device=\"Key \\\"Light\\\"\"} 18.5\n\
+             # HELP keylight_energy_watt_hours_total Estimated energy used by the light since the daemon started\n\
+             # TYPE keylight_energy_watt_hours_total counter\n\
+             keylight_energy_watt_hours_total{device=\"Key \\\"Light\\\"\"} 2\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let mut config = Config::default();
+        config.presets.insert(
+            "meeting".to_string(),
+            LightUpdate {
+                brightness: Some(Brightness::new(40).unwrap()),
+                ..Default::default()
+            },
+        );
+        config
+            .rooms
+            .insert("Studio".to_string(), vec!["Key Light".to_string()]);
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        config.access.tokens = vec!["s3cret".to_string()];
+        let daemon = Daemon::new(config, avahi);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(daemon)).await });
+
+        let resp = reqwest::get(format!("{base}/devices")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), "[]");
+
+        let resp = reqwest::get(format!("{base}/presets")).await.unwrap();
+        assert_eq!(
+            resp.text().await.unwrap(),
+            r#"{"meeting":{"brightness":40}}"#
+        );
+
+        let resp = reqwest::get(format!("{base}/devices/Unknown%20Light"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.text().await.unwrap(),
+            r#"{"error":"Device not found: Unknown Light"}"#
+        );
+
+        let resp = reqwest::get(format!("{base}/rooms")).await.unwrap();
+        assert_eq!(
+            resp.text().await.unwrap(),
+            r#"{"Studio":{"devices":["Key Light"],"on":0,"brightness":null,"temperature":null}}"#
+        );
+
+        let resp = reqwest::get(format!("{base}/rooms/Office")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = reqwest::get(format!("{base}/openapi.json")).await.unwrap();
+        let doc: serde_json::Value = resp.json().await.unwrap();
+        assert!(doc["paths"]["/devices/{name}/toggle"]["post"].is_object());
+        assert!(doc["components"]["schemas"]["LightUpdate"].is_object());
+
+        // Changes require a token
+        let client = reqwest::Client::new();
+        let toggle = format!("{base}/devices/Unknown%20Light/toggle");
+        let resp = client.post(&toggle).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = client
+            .post(&toggle)
+            .bearer_auth("s3cret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
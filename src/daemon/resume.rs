@@ -0,0 +1,84 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures_util::StreamExt as _;
+use zbus::{Connection, Proxy};
+
+use crate::{KeyLightStatus, ResumeConfig};
+
+use super::Daemon;
+
+const LOGIND_DESTINATION: &str = "org.freedesktop.login1";
+const LOGIND_MANAGER_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+
+/// Attempts to reach the lights after resuming, the network may take a while to come back
+const RESTORE_ATTEMPTS: u32 = 5;
+
+/// Reapply the state of the lights from before the suspend once the system resumes:
+/// the lights often come back from Wi-Fi power saving in a different state
+pub async fn run(daemon: Daemon, config: ResumeConfig) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let manager = Proxy::new(
+        &connection,
+        LOGIND_DESTINATION,
+        LOGIND_MANAGER_PATH,
+        LOGIND_MANAGER_INTERFACE,
+    )
+    .await?;
+    let mut sleep = manager.receive_signal("PrepareForSleep").await?;
+    log::info!("Watching suspend and resume");
+
+    let mut before_sleep: HashMap<String, KeyLightStatus> = HashMap::new();
+    while let Some(message) = sleep.next().await {
+        let suspending: bool = message.body().deserialize()?;
+        if suspending {
+            before_sleep = daemon
+                .devices()
+                .into_iter()
+                .filter_map(|device| {
+                    let status = daemon.cached_status(&device.name)?;
+                    Some((device.name, status))
+                })
+                .collect();
+            log::info!(
+                "Suspending, saved the state of {} light(s)",
+                before_sleep.len()
+            );
+            continue;
+        }
+
+        log::info!("Resumed, restoring the lights");
+        daemon.notify("resumed");
+        let mut pending = std::mem::take(&mut before_sleep);
+        for attempt in 1..=RESTORE_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(config.delay) * attempt).await;
+            if let Err(err) = daemon.rediscover().await {
+                log::debug!("Rediscovery failed: {err}");
+            }
+            pending = restore(&daemon, pending).await;
+            if pending.is_empty() {
+                break;
+            }
+        }
+        for name in pending.keys() {
+            log::error!("Failed to restore {name} after resume");
+        }
+    }
+    Ok(())
+}
+
+/// Reapply the saved states, returns the ones that could not be applied
+async fn restore(
+    daemon: &Daemon,
+    saved: HashMap<String, KeyLightStatus>,
+) -> HashMap<String, KeyLightStatus> {
+    let mut pending = HashMap::new();
+    for (name, status) in saved {
+        let target = status.clone();
+        if let Err(err) = daemon.update(&name, move |current| *current = target).await {
+            log::debug!("Failed to restore {name}: {err}");
+            pending.insert(name, status);
+        }
+    }
+    pending
+}
@@ -0,0 +1,390 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{Datelike as _, NaiveDateTime, NaiveTime, Timelike as _, Weekday};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{PowerStatus, Rule};
+
+use super::{chat::ChatCommand, Daemon, DaemonEvent};
+
+/// Interval of the evaluation of the time conditions
+const TICK_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum RuleError {
+    #[error("Invalid condition `{condition}`: {reason}")]
+    Condition { condition: String, reason: String },
+    #[error("Invalid action `{action}`: {reason}")]
+    Action { action: String, reason: String },
+}
+
+/// What the conditions of the rules know about the world
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Facts {
+    pub camera_on: bool,
+    pub microphone_on: bool,
+    pub locked: bool,
+    /// The phone of the presence automation left the network
+    pub away: bool,
+    /// Any light of the rule is on
+    pub lights_on: bool,
+    /// Local time
+    pub now: NaiveDateTime,
+}
+
+impl Facts {
+    /// Update the facts from an event of an automation, returns whether anything changed
+    pub fn apply(&mut self, automation: &str, event: &str) -> bool {
+        let (fact, value) = match (automation, event) {
+            ("camera", "started") => (&mut self.camera_on, true),
+            ("camera", "stopped") => (&mut self.camera_on, false),
+            ("microphone", "started") => (&mut self.microphone_on, true),
+            ("microphone", "stopped") => (&mut self.microphone_on, false),
+            ("lock", "locked") => (&mut self.locked, true),
+            ("lock", "unlocked") => (&mut self.locked, false),
+            ("presence", "left") => (&mut self.away, true),
+            ("presence", "arrived") => (&mut self.away, false),
+            _ => return false,
+        };
+        let changed = *fact != value;
+        *fact = value;
+        changed
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+/// Condition of a rule, e.g. `camera_on and not (time >= 18:00 or weekend)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    CameraOn,
+    MicrophoneOn,
+    Locked,
+    Away,
+    LightsOn,
+    /// Saturday or Sunday
+    Weekend,
+    Time(Comparison, NaiveTime),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    pub fn eval(&self, facts: &Facts) -> bool {
+        match self {
+            Condition::CameraOn => facts.camera_on,
+            Condition::MicrophoneOn => facts.microphone_on,
+            Condition::Locked => facts.locked,
+            Condition::Away => facts.away,
+            Condition::LightsOn => facts.lights_on,
+            Condition::Weekend => matches!(facts.now.weekday(), Weekday::Sat | Weekday::Sun),
+            Condition::Time(comparison, time) => {
+                // Minute precision, `time == 18:00` holds for the whole minute
+                let now = facts.now.time();
+                let now = NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).expect("valid time");
+                match comparison {
+                    Comparison::Lt => now < *time,
+                    Comparison::Le => now <= *time,
+                    Comparison::Gt => now > *time,
+                    Comparison::Ge => now >= *time,
+                    Comparison::Eq => now == *time,
+                }
+            }
+            Condition::Not(condition) => !condition.eval(facts),
+            Condition::And(a, b) => a.eval(facts) && b.eval(facts),
+            Condition::Or(a, b) => a.eval(facts) || b.eval(facts),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = RuleError;
+
+    fn from_str(condition: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| RuleError::Condition {
+            condition: condition.to_string(),
+            reason,
+        };
+        let tokens = tokenize(condition);
+        let mut parser = Parser { tokens, pos: 0 };
+        let parsed = parser.or().map_err(error)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(parsed),
+            Some(token) => Err(error(format!("unexpected `{token}`"))),
+        }
+    }
+}
+
+/// Words, parentheses and comparison operators
+fn tokenize(condition: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = condition.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' => tokens.push(c.to_string()),
+            '<' | '>' | '=' | '!' => {
+                let mut token = c.to_string();
+                if chars.next_if_eq(&'=').is_some() {
+                    token.push('=');
+                }
+                tokens.push(token);
+            }
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()<>=!".contains(*c))
+                {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    tokens
+}
+
+/// Recursive descent over `or` > `and` > `not` > atoms
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self
+            .tokens
+            .get(self.pos)
+            .is_some_and(|token| token.eq_ignore_ascii_case(word));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.eat("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.unary()?;
+        while self.eat("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    fn unary(&mut self) -> Result<Condition, String> {
+        if self.eat("not") {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let condition = self.or()?;
+            if !self.eat(")") {
+                return Err("missing `)`".to_string());
+            }
+            return Ok(condition);
+        }
+        let token = self.next().ok_or("unexpected end")?;
+        let condition = match token.to_lowercase().as_str() {
+            "camera_on" => Condition::CameraOn,
+            "microphone_on" => Condition::MicrophoneOn,
+            "locked" => Condition::Locked,
+            "away" => Condition::Away,
+            "lights_on" => Condition::LightsOn,
+            "weekend" => Condition::Weekend,
+            "time" => {
+                let comparison = match self.next().as_deref() {
+                    Some("<") => Comparison::Lt,
+                    Some("<=") => Comparison::Le,
+                    Some(">") => Comparison::Gt,
+                    Some(">=") => Comparison::Ge,
+                    Some("=" | "==") => Comparison::Eq,
+                    _ => return Err("expected a comparison after `time`".to_string()),
+                };
+                let time = self.next().ok_or("expected a time, e.g. 18:00")?;
+                let time = NaiveTime::parse_from_str(&time, "%H:%M")
+                    .map_err(|_| format!("invalid time `{time}`, expected e.g. 18:00"))?;
+                Condition::Time(comparison, time)
+            }
+            fact => return Err(format!("unknown fact `{fact}`")),
+        };
+        Ok(condition)
+    }
+}
+
+/// Parse the action of a rule: a chat command (`on`, `brightness 40`, `preset daylight`...),
+/// optionally preceded by `apply`
+pub fn parse_action(action: &str) -> Result<ChatCommand, RuleError> {
+    let error = |reason: String| RuleError::Action {
+        action: action.to_string(),
+        reason,
+    };
+    let command = action.trim();
+    let command = command.strip_prefix("apply ").unwrap_or(command);
+    match command.parse().map_err(error)? {
+        ChatCommand::Help | ChatCommand::Status => Err(error("not an action".to_string())),
+        command => Ok(command),
+    }
+}
+
+/// A rule ready to be evaluated
+#[derive(Debug)]
+struct CompiledRule {
+    rule: Rule,
+    condition: Condition,
+    action: ChatCommand,
+    /// Value of the condition at the last evaluation, the rule fires when it becomes true
+    holds: bool,
+}
+
+/// Evaluate the rules on every automation event and periodically for the time conditions,
+/// running their action when their condition becomes true
+pub async fn run(daemon: Daemon, rules: Vec<Rule>) -> Result<(), RuleError> {
+    let mut facts = Facts {
+        now: chrono::Local::now().naive_local(),
+        ..Default::default()
+    };
+    let mut compiled = rules
+        .into_iter()
+        .map(|rule| {
+            let condition: Condition = rule.when.parse()?;
+            let action = parse_action(&rule.then)?;
+            facts.lights_on = lights_on(&daemon, &rule.devices);
+            // Rules holding at startup wait for the next time their condition becomes true
+            let holds = condition.eval(&facts);
+            Ok(CompiledRule {
+                rule,
+                condition,
+                action,
+                holds,
+            })
+        })
+        .collect::<Result<Vec<_>, RuleError>>()?;
+    log::info!("Evaluating {} rule(s)", compiled.len());
+
+    let mut events = daemon.subscribe();
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            event = events.recv() => match event {
+                Ok(DaemonEvent::Automation { automation, event }) => {
+                    if !facts.apply(automation, &event) {
+                        continue;
+                    }
+                }
+                Ok(DaemonEvent::StateChanged { .. }) => {}
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => log::warn!("Rules missed {n} events"),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+        facts.now = chrono::Local::now().naive_local();
+        for rule in &mut compiled {
+            facts.lights_on = lights_on(&daemon, &rule.rule.devices);
+            let holds = rule.condition.eval(&facts);
+            let fires = holds && !rule.holds;
+            rule.holds = holds;
+            if !fires {
+                continue;
+            }
+            log::info!(
+                "Rule `{}` fired, running `{}`",
+                rule.rule.when,
+                rule.rule.then
+            );
+            for device in daemon.targets(&rule.rule.devices) {
+                if let Err(err) = rule.action.run(&daemon, &device.name).await {
+                    log::error!("Rule `{}` failed on {}: {err}", rule.rule.when, device.name);
+                }
+            }
+        }
+    }
+}
+
+fn lights_on(daemon: &Daemon, devices: &[String]) -> bool {
+    daemon.targets(devices).iter().any(|device| {
+        daemon
+            .cached_status(&device.name)
+            .is_some_and(|status| status.power == PowerStatus::On)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> Facts {
+        Facts {
+            // A Monday
+            now: format!("2024-09-02T{time}:00").parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let condition: Condition = "camera_on and time < 18:00".parse().unwrap();
+        let mut facts = at("17:59");
+        assert!(!condition.eval(&facts));
+        assert!(facts.apply("camera", "started"));
+        assert!(!facts.apply("camera", "started"));
+        assert!(condition.eval(&facts));
+        facts.now = at("18:00").now;
+        assert!(!condition.eval(&facts));
+
+        let condition: Condition = "not (weekend or locked) and (time>=08:00)".parse().unwrap();
+        assert!(condition.eval(&at("08:00")));
+        assert!(!condition.eval(&at("07:59")));
+        let mut facts = at("09:00");
+        facts.apply("lock", "locked");
+        assert!(!condition.eval(&facts));
+
+        // `and` binds tighter than `or`
+        let condition: Condition = "away or camera_on and lights_on".parse().unwrap();
+        let facts = Facts {
+            away: true,
+            ..at("09:00")
+        };
+        assert!(condition.eval(&facts));
+    }
+
+    #[test]
+    fn invalid() {
+        for condition in [
+            "",
+            "camera",
+            "time < noon",
+            "(camera_on",
+            "camera_on lights_on",
+        ] {
+            assert!(
+                condition.parse::<Condition>().is_err(),
+                "{condition} should be invalid"
+            );
+        }
+        assert_eq!(
+            parse_action("apply preset daylight").unwrap(),
+            ChatCommand::Preset("daylight".to_string())
+        );
+        assert!(parse_action("status").is_err());
+    }
+}
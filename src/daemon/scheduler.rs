@@ -0,0 +1,167 @@
+use std::{path::PathBuf, time::Duration};
+
+use chrono::{DateTime, Local};
+use croner::Cron;
+
+use crate::{Config, Schedule, ScheduleAction};
+
+use super::{with_source, Daemon};
+
+/// Interval between two checks of the schedules
+const TICK: Duration = Duration::from_secs(1);
+
+/// A schedule with its parsed cron expression
+#[derive(Debug)]
+pub struct Entry {
+    pub schedule: Schedule,
+    cron: Option<Cron>,
+}
+
+impl Entry {
+    pub fn new(schedule: Schedule) -> Result<Self, croner::errors::CronError> {
+        let cron = schedule
+            .cron
+            .as_deref()
+            .map(|cron| Cron::new(cron).parse())
+            .transpose()?;
+        Ok(Entry { schedule, cron })
+    }
+
+    /// Whether the schedule fires in `(after, until]`
+    pub fn is_due(&self, after: DateTime<Local>, until: DateTime<Local>) -> bool {
+        let cron = self.cron.as_ref().is_some_and(|cron| {
+            cron.find_next_occurrence(&after, false)
+                .is_ok_and(|next| next <= until)
+        });
+        let at = self.schedule.at.is_some_and(|at| after < at && at <= until);
+        cron || at
+    }
+}
+
+/// Parse the schedules of the config, logging the invalid ones
+pub fn entries(schedules: &[Schedule]) -> Vec<Entry> {
+    schedules
+        .iter()
+        .filter_map(|schedule| match Entry::new(schedule.clone()) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                log::error!(
+                    "Invalid cron expression in schedule {}: {err}",
+                    schedule.name
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Fire the schedules of the config file, reloading it when it changes (e.g. after
+/// `elgato-keylight-cli schedule add`)
+pub async fn run(daemon: Daemon, path: PathBuf) {
+    let modified = |path: &PathBuf| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let mut last_modified = modified(&path);
+    let mut entries = entries(&daemon.config().schedules);
+    log::info!("Scheduler started with {} schedule(s)", entries.len());
+
+    let mut last = Local::now();
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+
+        let current = modified(&path);
+        if current != last_modified {
+            last_modified = current;
+            match Config::load_from(&path) {
+                Ok(config) => {
+                    entries = self::entries(&config.schedules);
+                    log::info!("Reloaded {} schedule(s)", entries.len());
+                }
+                Err(err) => log::error!("Failed to reload the schedules: {err}"),
+            }
+        }
+
+        let now = Local::now();
+        for entry in entries.iter().filter(|entry| entry.is_due(last, now)) {
+            log::info!("Schedule {} fired", entry.schedule.name);
+            daemon.notify(format!("{} fired", entry.schedule.name));
+            if let ScheduleAction::Scene(scene) = &entry.schedule.action {
+                let (daemon, scene) = (daemon.clone(), scene.clone());
+                tokio::spawn(with_source("scheduler", async move {
+                    if let Err(err) = daemon.play_scene(&scene).await {
+                        log::error!("Schedule failed to play {scene}: {err}");
+                    }
+                }));
+                continue;
+            }
+            for device in daemon.targets(&entry.schedule.devices) {
+                let (daemon, action) = (daemon.clone(), entry.schedule.action.clone());
+                // Fades take a while, each device runs on its own
+                tokio::spawn(with_source("scheduler", async move {
+                    if let Err(err) = execute(&daemon, &device.name, &action).await {
+                        log::error!("Schedule failed on {}: {err}", device.name);
+                    }
+                }));
+            }
+        }
+        last = now;
+    }
+}
+
+async fn execute(
+    daemon: &Daemon,
+    name: &str,
+    action: &ScheduleAction,
+) -> Result<(), super::DaemonError> {
+    match action {
+        ScheduleAction::Power(power) => daemon.set_power(name, *power).await,
+        ScheduleAction::Preset(preset) => daemon.apply_preset(name, preset).await.map(|_| ()),
+        ScheduleAction::Fade(fade) => daemon
+            .fade(name, &fade.target, Duration::from_secs(fade.duration))
+            .await
+            .map(|_| ()),
+        ScheduleAction::Scene(_) => unreachable!("scenes are played by the caller"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone as _;
+
+    use super::*;
+
+    #[test]
+    fn due() {
+        let at = |h, m, s| Local.with_ymd_and_hms(2024, 9, 2, h, m, s).unwrap();
+        let entry = Entry::new(Schedule {
+            name: "morning".to_string(),
+            cron: Some("0 8 * * 1-5".to_string()),
+            at: None,
+            devices: vec![],
+            action: ScheduleAction::Preset("morning".to_string()),
+        })
+        .unwrap();
+        // 2024-09-02 is a Monday
+        assert!(entry.is_due(at(7, 59, 59), at(8, 0, 0)));
+        assert!(!entry.is_due(at(8, 0, 0), at(8, 0, 1)));
+        assert!(!entry.is_due(at(8, 1, 0), at(8, 2, 0)));
+
+        let entry = Entry::new(Schedule {
+            cron: None,
+            at: Some(at(20, 0, 0).fixed_offset()),
+            ..entry.schedule
+        })
+        .unwrap();
+        assert!(entry.is_due(at(19, 59, 59), at(20, 0, 0)));
+        assert!(!entry.is_due(at(20, 0, 0), at(20, 0, 1)));
+
+        assert!(Entry::new(Schedule {
+            cron: Some("61 * * * *".to_string()),
+            ..entry.schedule
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+use tokio::{runtime::Handle, sync::broadcast::error::RecvError};
+
+use crate::{Brightness, KeyLightStatus, LightUpdate, PowerStatus, ScriptsConfig, Temperature};
+
+use super::{with_source, Daemon, DaemonError, DaemonEvent};
+
+/// Function of the scripts called on every event
+const EVENT_HANDLER: &str = "on_event";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    #[error("Failed to read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Failed to compile {0}: {1}")]
+    Compile(PathBuf, rhai::ParseError),
+}
+
+/// A compiled script and its global variables
+pub struct Script {
+    pub path: PathBuf,
+    ast: AST,
+    scope: Scope<'static>,
+}
+
+/// Rhai engine exposing the light control API to the scripts:
+///
+/// - `devices()`: names of the devices
+/// - `status(device)`: `#{on, brightness, temperature}`
+/// - `toggle(device)`, `turn_on(device)`, `turn_off(device)`
+/// - `set_brightness(device, value)`, `set_temperature(device, value)`
+/// - `apply_preset(device, preset)`
+///
+/// The functions block on the daemon, scripts must run outside of the async tasks.
+pub fn engine(daemon: Daemon, handle: Handle) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|text| log::info!("{text}"));
+    engine.on_debug(|text, _, pos| log::debug!("{pos}: {text}"));
+
+    let api = Arc::new((daemon, handle));
+
+    let a = Arc::clone(&api);
+    engine.register_fn("devices", move || -> Array {
+        a.0.devices()
+            .into_iter()
+            .map(|device| Dynamic::from(device.name))
+            .collect()
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("status", move |device: &str| {
+        let status = block_on(&a.1, a.0.status(device)).map_err(to_rhai)?;
+        Ok::<_, Box<EvalAltResult>>(status_map(&status))
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("toggle", move |device: &str| {
+        let power = block_on(&a.1, a.0.toggle(device)).map_err(to_rhai)?;
+        Ok::<_, Box<EvalAltResult>>(bool::from(power))
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("turn_on", move |device: &str| {
+        block_on(&a.1, a.0.set_power(device, PowerStatus::On)).map_err(to_rhai)
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("turn_off", move |device: &str| {
+        block_on(&a.1, a.0.set_power(device, PowerStatus::Off)).map_err(to_rhai)
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("set_brightness", move |device: &str, value: i64| {
+        let brightness = u8::try_from(value)
+            .map_err(|err| err.to_string())
+            .and_then(Brightness::new)?;
+        block_on(&a.1, a.0.set_brightness(device, brightness)).map_err(to_rhai)
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("set_temperature", move |device: &str, value: i64| {
+        let temperature = u16::try_from(value)
+            .map_err(|err| err.to_string())
+            .and_then(Temperature::new)?;
+        block_on(&a.1, a.0.set_temperature(device, temperature)).map_err(to_rhai)
+    });
+    let a = Arc::clone(&api);
+    engine.register_fn("apply_preset", move |device: &str, preset: &str| {
+        block_on(&a.1, a.0.apply_preset(device, preset))
+            .map(|_| ())
+            .map_err(to_rhai)
+    });
+    engine.register_fn("update", move |device: &str, update: Map| {
+        let update: LightUpdate = rhai::serde::from_dynamic(&update.into())?;
+        block_on(&api.1, api.0.apply(device, &update))
+            .map(|_| ())
+            .map_err(to_rhai)
+    });
+
+    engine
+}
+
+/// Run a daemon call from a script, reporting the scripts as the source of the changes
+fn block_on<F: std::future::Future>(handle: &Handle, future: F) -> F::Output {
+    handle.block_on(with_source("scripts", future))
+}
+
+fn to_rhai(err: DaemonError) -> Box<EvalAltResult> {
+    err.to_string().into()
+}
+
+/// `temperature`, `hue` and `saturation` are only set when meaningful for the light
+fn status_map(status: &KeyLightStatus) -> Map {
+    let mut map = Map::from([
+        ("on".into(), bool::from(status.power).into()),
+        ("brightness".into(), i64::from(status.brightness.0).into()),
+    ]);
+    if let Some(temperature) = status.temperature {
+        map.insert("temperature".into(), i64::from(temperature.0).into());
+    }
+    if let Some(hue) = status.hue {
+        map.insert("hue".into(), i64::from(hue.0).into());
+    }
+    if let Some(saturation) = status.saturation {
+        map.insert("saturation".into(), i64::from(saturation.0).into());
+    }
+    map
+}
+
+/// Event passed to `on_event`, `type` is one of `device_discovered`, `state_changed`,
+/// `preset_applied` or `automation`
+pub fn event_maps(event: &DaemonEvent, known: &mut HashSet<String>) -> Vec<Map> {
+    match event {
+        DaemonEvent::DevicesChanged(devices) => {
+            let names: HashSet<String> = devices.iter().map(|device| device.name.clone()).collect();
+            let discovered = names
+                .difference(known)
+                .map(|name| {
+                    Map::from([
+                        ("type".into(), "device_discovered".into()),
+                        ("device".into(), name.clone().into()),
+                    ])
+                })
+                .collect();
+            *known = names;
+            discovered
+        }
+        DaemonEvent::StateChanged {
+            device,
+            status,
+            source,
+            ..
+        } => {
+            let mut map = status_map(status);
+            map.insert("type".into(), "state_changed".into());
+            map.insert("device".into(), device.name.clone().into());
+            map.insert("source".into(), (*source).into());
+            vec![map]
+        }
+        DaemonEvent::PresetApplied {
+            device,
+            preset,
+            source,
+            ..
+        } => vec![Map::from([
+            ("type".into(), "preset_applied".into()),
+            ("device".into(), device.clone().into()),
+            ("preset".into(), preset.clone().into()),
+            ("source".into(), (*source).into()),
+        ])],
+        DaemonEvent::Automation { automation, event } => vec![Map::from([
+            ("type".into(), "automation".into()),
+            ("automation".into(), (*automation).into()),
+            ("event".into(), event.clone().into()),
+        ])],
+    }
+}
+
+impl Script {
+    /// Compile the script and run its top-level statements
+    pub fn load(engine: &Engine, path: PathBuf, source: &str) -> Result<Self, ScriptError> {
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError::Compile(path.clone(), err))?;
+        let mut scope = Scope::new();
+        if let Err(err) = engine.run_ast_with_scope(&mut scope, &ast) {
+            log::error!("{}: {err}", path.display());
+        }
+        Ok(Script { path, ast, scope })
+    }
+
+    /// Call `on_event`, if the script defines it
+    pub fn on_event(&mut self, engine: &Engine, event: Map) -> Result<Dynamic, Box<EvalAltResult>> {
+        let defined = self
+            .ast
+            .iter_functions()
+            .any(|function| function.name == EVENT_HANDLER && function.params.len() == 1);
+        if !defined {
+            return Ok(Dynamic::UNIT);
+        }
+        engine.call_fn(&mut self.scope, &self.ast, EVENT_HANDLER, (event,))
+    }
+}
+
+/// Run the `on_event` function of the scripts on every event of the daemon
+pub async fn run(daemon: Daemon, config: ScriptsConfig) -> Result<(), ScriptError> {
+    let engine = engine(daemon.clone(), Handle::current());
+    let mut scripts = config
+        .files
+        .into_iter()
+        .map(|path| {
+            let source = std::fs::read_to_string(&path)
+                .map_err(|err| ScriptError::Read(path.clone(), err))?;
+            Script::load(&engine, path, &source)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    log::info!("Loaded {} script(s)", scripts.len());
+
+    let mut events = daemon.subscribe();
+    let mut known: HashSet<String> = HashSet::new();
+    // The scripts block on the daemon, they run on a dedicated thread
+    let _ = tokio::task::spawn_blocking(move || loop {
+        let event = match events.blocking_recv() {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("Scripts missed {n} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        for event in event_maps(&event, &mut known) {
+            for script in &mut scripts {
+                if let Err(err) = script.on_event(&engine, event.clone()) {
+                    log::error!("{}: {err}", script.path.display());
+                }
+            }
+        }
+    })
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use crate::{avahi::AvahiState, Config};
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn on_event() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let engine = engine(daemon, Handle::current());
+
+        let source = r#"
+            let prefix = "got";
+            fn on_event(event) {
+                if event.type == "automation" && event.event == "started" {
+                    turn_on("Unknown");
+                }
+                `${event.type} ${devices().len()}`
+            }
+        "#;
+        tokio::task::spawn_blocking(move || {
+            let mut script = Script::load(&engine, "test.rhai".into(), source).unwrap();
+            let event = |event: &str| {
+                Map::from([
+                    ("type".into(), "automation".into()),
+                    ("automation".into(), "camera".into()),
+                    ("event".into(), event.into()),
+                ])
+            };
+
+            let result = script.on_event(&engine, event("stopped")).unwrap();
+            assert_eq!(result.into_string().unwrap(), "automation 0");
+
+            let err = script.on_event(&engine, event("started")).unwrap_err();
+            assert!(err.to_string().contains("Device not found: Unknown"));
+
+            assert!(Script::load(&engine, "bad.rhai".into(), "fn (").is_err());
+        })
+        .await
+        .unwrap();
+    }
+}
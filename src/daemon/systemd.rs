@@ -0,0 +1,130 @@
+use std::{
+    net::TcpListener,
+    os::{
+        fd::{FromRawFd as _, RawFd},
+        linux::net::SocketAddrExt as _,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    path::{Path, PathBuf},
+};
+
+/// First file descriptor passed by systemd socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Name of the units written by `install-service`
+pub const UNIT_NAME: &str = "elgato-keylightd";
+
+/// Number of sockets passed by systemd, given the `LISTEN_PID` and `LISTEN_FDS` variables
+pub fn listen_fds_count(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> usize {
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds
+        .and_then(|listen_fds| listen_fds.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Listener passed by systemd socket activation, if any
+pub fn activated_listener() -> Option<TcpListener> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    let count = listen_fds_count(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        std::process::id(),
+    );
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if count == 0 {
+        return None;
+    }
+    if count > 1 {
+        log::warn!("{count} sockets passed by systemd, only the first one is used");
+    }
+    // SAFETY: systemd passes ownership of the sockets starting at fd 3, and the variables
+    // are removed so that the fd is only taken once
+    Some(unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) })
+}
+
+/// Notify systemd of the state of the service (`Type=notify`), e.g. `READY=1`.
+/// Does nothing when not started by systemd.
+pub fn notify(state: &str) -> std::io::Result<()> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = Path::new(&path);
+    match path.as_os_str().as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => {
+            socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?
+        }
+        None => socket.send_to(state.as_bytes(), path)?,
+    };
+    Ok(())
+}
+
+/// Directory of the systemd user units
+pub fn user_unit_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("systemd").join("user"))
+}
+
+/// User service running `exe` with `args`
+pub fn service_unit(exe: &Path, args: &[&str]) -> String {
+    let command = std::iter::once(exe.display().to_string())
+        .chain(args.iter().map(|arg| arg.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "[Unit]
+Description=Elgato Key Light daemon
+After=network-online.target
+
+[Service]
+Type=notify
+ExecStart={command}
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+"
+    )
+}
+
+/// Socket starting the service on the first connection to `listen`
+pub fn socket_unit(listen: &str) -> String {
+    format!(
+        "[Unit]
+Description=Elgato Key Light daemon REST API
+
+[Socket]
+ListenStream={listen}
+
+[Install]
+WantedBy=sockets.target
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_fds() {
+        assert_eq!(listen_fds_count(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds_count(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds_count(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds_count(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds_count(Some("42"), Some("x"), 42), 0);
+    }
+
+    #[test]
+    fn units() {
+        let exe = Path::new("/usr/bin/elgato-keylightd");
+        assert!(service_unit(exe, &[]).contains("ExecStart=/usr/bin/elgato-keylightd\n"));
+        assert!(service_unit(exe, &["serve", "--dbus"])
+            .contains("ExecStart=/usr/bin/elgato-keylightd serve --dbus\n"));
+        assert!(socket_unit("127.0.0.1:8080").contains("ListenStream=127.0.0.1:8080\n"));
+    }
+}
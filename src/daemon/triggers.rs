@@ -0,0 +1,170 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{TriggerAction, TriggersConfig};
+
+use super::{with_source, Daemon};
+
+/// Time between two toggles of a blink
+const BLINK_INTERVAL: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone)]
+struct TriggerState {
+    daemon: Daemon,
+    config: TriggersConfig,
+}
+
+/// Response of `POST /trigger/:action`
+#[derive(Debug, Serialize)]
+struct TriggerResponse {
+    /// Devices the action was applied to
+    devices: Vec<String>,
+    /// Errors by device
+    errors: HashMap<String, String>,
+}
+
+/// Incoming webhooks firing the actions defined in the config: `POST /trigger/:action`,
+/// authenticated with `Authorization: Bearer <token>` or `?token=<token>`
+pub fn router(daemon: Daemon, config: TriggersConfig) -> Router {
+    Router::new()
+        .route("/trigger/:action", post(trigger))
+        .with_state(TriggerState { daemon, config })
+}
+
+/// Serve the trigger endpoint on `config.listen` until the process exits
+pub async fn serve(daemon: Daemon, config: TriggersConfig) -> std::io::Result<()> {
+    if config.token.as_deref().unwrap_or_default().is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "triggers.token must be set",
+        ));
+    }
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    log::info!("Triggers listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(daemon, config)).await
+}
+
+async fn trigger(
+    State(state): State<TriggerState>,
+    Path(action): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Json<TriggerResponse>, StatusCode> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.get("token").map(String::as_str));
+    let authorized = match (state.config.token.as_deref(), token) {
+        (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+    if !authorized {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let action = state
+        .config
+        .actions
+        .get(&action)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    log::info!("Trigger {action:?}");
+    Ok(Json(
+        with_source("trigger", run_action(&state.daemon, action)).await,
+    ))
+}
+
+async fn run_action(daemon: &Daemon, action: &TriggerAction) -> TriggerResponse {
+    let mut response = TriggerResponse {
+        devices: vec![],
+        errors: HashMap::new(),
+    };
+    for device in daemon.targets(&action.devices) {
+        let result = async {
+            if let Some(preset) = &action.preset {
+                daemon.apply_preset(&device.name, preset).await?;
+            }
+            if let Some(power) = action.power {
+                daemon.set_power(&device.name, power).await?;
+            }
+            for _ in 0..action.blink {
+                daemon.toggle(&device.name).await?;
+                tokio::time::sleep(BLINK_INTERVAL).await;
+                daemon.toggle(&device.name).await?;
+                tokio::time::sleep(BLINK_INTERVAL).await;
+            }
+            Ok::<_, super::DaemonError>(())
+        }
+        .await;
+        match result {
+            Ok(()) => response.devices.push(device.name),
+            Err(err) => {
+                response.errors.insert(device.name, err.to_string());
+            }
+        }
+    }
+    response
+}
+
+/// Compare the tokens without leaking the length of the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, RwLock},
+    };
+
+    use crate::{avahi::AvahiState, Config};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn trigger_endpoint() {
+        let avahi = Arc::new(RwLock::new(AvahiState { devices: vec![] }));
+        let daemon = Daemon::new(Config::default(), avahi);
+        let config = TriggersConfig {
+            token: Some("secret".to_string()),
+            actions: BTreeMap::from([(
+                "doorbell".to_string(),
+                TriggerAction {
+                    blink: 2,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(daemon, config)).await });
+
+        let client = reqwest::Client::new();
+        let post = |path: &str| client.post(format!("{base}{path}"));
+
+        let resp = post("/trigger/doorbell").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = post("/trigger/doorbell?token=wrong").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = post("/trigger/unknown")
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = post("/trigger/doorbell?token=secret").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), r#"{"devices":[],"errors":{}}"#);
+    }
+}
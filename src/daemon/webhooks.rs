@@ -0,0 +1,139 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{KeyLightStatus, WebhooksConfig};
+
+use super::{Daemon, DaemonEvent};
+
+/// Timeout of a webhook request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON body posted to the webhooks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookEvent {
+    /// `state_changed`
+    pub event: &'static str,
+    pub device: String,
+    /// What changed the state: `external` for changes noticed by polling (e.g. the buttons
+    /// of the light), otherwise the surface or automation of the daemon (`rest`, `camera`...)
+    pub source: &'static str,
+    /// Who made the change through the source, e.g. the address of a REST client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    pub before: Option<KeyLightStatus>,
+    pub after: KeyLightStatus,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+impl WebhookEvent {
+    pub fn from_event(event: DaemonEvent, timestamp: u64) -> Option<Self> {
+        match event {
+            DaemonEvent::StateChanged {
+                device,
+                previous,
+                status,
+                source,
+                actor,
+            } => Some(WebhookEvent {
+                event: "state_changed",
+                device: device.name,
+                source,
+                actor,
+                before: previous,
+                after: status,
+                timestamp,
+            }),
+            DaemonEvent::DevicesChanged(_)
+            | DaemonEvent::PresetApplied { .. }
+            | DaemonEvent::Automation { .. } => None,
+        }
+    }
+}
+
+/// POST every state change to the configured URLs
+pub async fn run(daemon: Daemon, config: WebhooksConfig) {
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            log::error!("Failed to create the webhook client: {err}");
+            return;
+        }
+    };
+    log::info!("Posting state changes to {} webhook(s)", config.urls.len());
+
+    let mut events = daemon.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("Webhooks missed {n} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let Some(event) = WebhookEvent::from_event(event, timestamp) else {
+            continue;
+        };
+
+        for url in &config.urls {
+            let request = client.post(url.clone()).json(&event);
+            let url = url.clone();
+            // Slow endpoints must not delay the next events
+            tokio::spawn(async move {
+                match request
+                    .send()
+                    .await
+                    .and_then(|resp| resp.error_for_status())
+                {
+                    Ok(_) => log::debug!("Posted to {url}"),
+                    Err(err) => log::error!("Webhook {url} failed: {err}"),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{avahi::Device, Brightness, PowerStatus, Temperature};
+
+    use super::*;
+
+    #[test]
+    fn payload() {
+        let status = KeyLightStatus::white(
+            PowerStatus::On,
+            Brightness::new(40).unwrap(),
+            Temperature::new(200).unwrap(),
+        );
+        let event = DaemonEvent::StateChanged {
+            device: Device {
+                name: "Elgato Key Light 8D7C".to_string(),
+                url: "http://192.168.0.92:9123".parse().unwrap(),
+            },
+            previous: None,
+            status,
+            source: "rest",
+            actor: None,
+        };
+        let event = WebhookEvent::from_event(event, 1_700_000_000).unwrap();
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            serde_json::json!({
+                "event": "state_changed",
+                "device": "Elgato Key Light 8D7C",
+                "source": "rest",
+                "before": null,
+                "after": {"on": 1, "brightness": 40, "temperature": 200},
+                "timestamp": 1_700_000_000u64,
+            })
+        );
+    }
+}
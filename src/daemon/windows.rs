@@ -0,0 +1,179 @@
+//! Windows service of the daemon, registered by `install-service` and started at boot by the
+//! service control manager, so it outlives the user sessions. It logs to the Application event
+//! log.
+
+use std::{
+    ffi::{OsStr, OsString},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use tokio::sync::oneshot;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+/// Name of the service and of its event log source
+pub const SERVICE_NAME: &str = "elgato-keylightd";
+
+const DISPLAY_NAME: &str = "Elgato Key Light daemon";
+
+const DESCRIPTION: &str = "Keeps track of the Elgato lights and runs their automations";
+
+/// Body of the service, stopping when the receiver gets the stop request of the service manager
+pub type Body = Box<dyn FnOnce(oneshot::Receiver<()>) -> anyhow::Result<()> + Send>;
+
+/// Body handed by [`run`] to the thread the service control manager starts the service on
+static BODY: Mutex<Option<Body>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Arguments the service is started with: the hidden `run-service` command of the daemon, with
+/// the config of the user installing it since the service runs as LocalSystem
+pub fn launch_arguments(listen: Option<SocketAddr>, config: &Path) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["run-service".into(), "--config".into(), config.into()];
+    if let Some(listen) = listen {
+        args.extend(["--listen".into(), listen.to_string().into()]);
+    }
+    args
+}
+
+/// Register the service started at boot, running `exe` with `args`, and its event log source.
+/// Needs an elevated prompt.
+pub fn install(exe: PathBuf, args: Vec<OsString>) -> anyhow::Result<()> {
+    eventlog::register(SERVICE_NAME).context("Failed to register the event log source")?;
+    let manager = ServiceManager::local_computer(
+        None::<&OsStr>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: DISPLAY_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: exe,
+        launch_arguments: args,
+        dependencies: vec![],
+        // LocalSystem
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description(DESCRIPTION)?;
+    Ok(())
+}
+
+/// Stop and remove the service and its event log source
+pub fn uninstall() -> anyhow::Result<()> {
+    let manager = ServiceManager::local_computer(None::<&OsStr>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    eventlog::deregister(SERVICE_NAME).context("Failed to remove the event log source")?;
+    Ok(())
+}
+
+/// Run `body` as the service, logging to the event log, until the service manager stops it.
+/// Blocks the calling thread, which must be the main thread of the process started by the
+/// service manager.
+pub fn run(level: log::Level, body: Body) -> anyhow::Result<()> {
+    eventlog::init(SERVICE_NAME, level)?;
+    *BODY.lock().unwrap() = Some(body);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(err) = serve() {
+        log::error!("Service failed: {err:#}");
+    }
+}
+
+fn serve() -> anyhow::Result<()> {
+    let body = BODY
+        .lock()
+        .unwrap()
+        .take()
+        .context("Service already started")?;
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let mut stop_tx = Some(stop_tx);
+    let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(stop_tx) = stop_tx.take() {
+                let _ = stop_tx.send(());
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    set_state(
+        &status,
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    )?;
+    log::info!("Service started");
+    let result = body(stop_rx);
+    let exit_code = if result.is_ok() { 0 } else { 1 };
+    set_state(
+        &status,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+    result
+}
+
+fn set_state(
+    status: &ServiceStatusHandle,
+    state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+    exit_code: u32,
+) -> windows_service::Result<()> {
+    status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let config = Path::new(r"C:\Users\me\AppData\Roaming\elgato-keylight\config.toml");
+        assert_eq!(
+            launch_arguments(None, config),
+            ["run-service", "--config", config.to_str().unwrap()]
+        );
+        let listen = "0.0.0.0:8080".parse().ok();
+        assert_eq!(
+            launch_arguments(listen, config)[3..],
+            ["--listen", "0.0.0.0:8080"]
+        );
+    }
+}
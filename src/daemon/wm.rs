@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::UnixStream,
+};
+
+use crate::{WmConfig, WmRule};
+
+use super::Daemon;
+
+/// Magic string starting the i3/sway IPC messages
+const I3_MAGIC: &[u8; 6] = b"i3-ipc";
+const I3_SUBSCRIBE: u32 = 2;
+const I3_EVENT_WORKSPACE: u32 = 0x8000_0000;
+const I3_EVENT_WINDOW: u32 = 0x8000_0003;
+
+/// Change of focus reported by the window manager
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FocusChange {
+    Workspace(String),
+    /// Class or app id of the focused window
+    App(String),
+}
+
+/// Focused workspace and application
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Focus {
+    pub workspace: Option<String>,
+    pub app: Option<String>,
+}
+
+impl Focus {
+    pub fn apply(&mut self, change: FocusChange) {
+        match change {
+            FocusChange::Workspace(workspace) => self.workspace = Some(workspace),
+            FocusChange::App(app) => self.app = Some(app),
+        }
+    }
+}
+
+impl WmRule {
+    /// All the matchers of the rule match the focus, application names are case insensitive
+    pub fn matches(&self, focus: &Focus) -> bool {
+        let workspace = self.workspace.as_ref().map_or(true, |workspace| {
+            focus.workspace.as_ref() == Some(workspace)
+        });
+        let app = self.app.as_ref().map_or(true, |app| {
+            focus
+                .app
+                .as_ref()
+                .is_some_and(|focused| focused.eq_ignore_ascii_case(app))
+        });
+        workspace && app
+    }
+}
+
+/// Preset of the first rule matching the focus, or the default preset
+pub fn matching_preset<'a>(config: &'a WmConfig, focus: &Focus) -> Option<&'a str> {
+    config
+        .rules
+        .iter()
+        .find(|rule| rule.matches(focus))
+        .map(|rule| rule.preset.as_str())
+        .or(config.default_preset.as_deref())
+}
+
+/// Focus change of an i3/sway event
+pub fn i3_event(event_type: u32, payload: &Value) -> Option<FocusChange> {
+    if payload["change"] != "focus" {
+        return None;
+    }
+    match event_type {
+        I3_EVENT_WORKSPACE => Some(FocusChange::Workspace(
+            payload["current"]["name"].as_str()?.to_string(),
+        )),
+        I3_EVENT_WINDOW => {
+            let container = &payload["container"];
+            let app = container["app_id"]
+                .as_str()
+                .or(container["window_properties"]["class"].as_str())?;
+            Some(FocusChange::App(app.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Focus change of a line of the Hyprland event socket, e.g. `workspace>>2`
+pub fn hyprland_event(line: &str) -> Option<FocusChange> {
+    let (event, data) = line.split_once(">>")?;
+    match event {
+        "workspace" => Some(FocusChange::Workspace(data.to_string())),
+        "activewindow" => {
+            let (class, _title) = data.split_once(',')?;
+            Some(FocusChange::App(class.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Apply the preset of the rules matching the focused workspace and application
+pub async fn run(daemon: Daemon, config: WmConfig) -> std::io::Result<()> {
+    let mut focus = Focus::default();
+    let mut applied: Option<String> = None;
+    let mut on_change = |change: FocusChange| {
+        focus.apply(change);
+        let preset = matching_preset(&config, &focus).map(str::to_string);
+        if preset == applied {
+            return None;
+        }
+        applied.clone_from(&preset);
+        preset
+    };
+
+    if let Some(socket) = hyprland_socket() {
+        log::info!("Watching Hyprland events");
+        let stream = UnixStream::connect(socket).await?;
+        let mut lines = BufReader::new(stream).lines();
+        while let Some(line) = lines.next_line().await? {
+            if let Some(preset) = hyprland_event(&line).and_then(&mut on_change) {
+                daemon.turn_on_all(&config.devices, Some(&preset)).await;
+            }
+        }
+        return Ok(());
+    }
+
+    let socket = i3_socket().await.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no i3, sway or Hyprland socket",
+        )
+    })?;
+    log::info!("Watching i3/sway events");
+    let mut stream = UnixStream::connect(socket).await?;
+    i3_send(&mut stream, I3_SUBSCRIBE, br#"["workspace","window"]"#).await?;
+    loop {
+        let (event_type, payload) = i3_receive(&mut stream).await?;
+        let Ok(payload) = serde_json::from_slice::<Value>(&payload) else {
+            continue;
+        };
+        if let Some(preset) = i3_event(event_type, &payload).and_then(&mut on_change) {
+            daemon.turn_on_all(&config.devices, Some(&preset)).await;
+        }
+    }
+}
+
+fn hyprland_socket() -> Option<PathBuf> {
+    let signature = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    // Hyprland moved its sockets from /tmp to $XDG_RUNTIME_DIR in 0.40
+    runtime_dir
+        .into_iter()
+        .chain([PathBuf::from("/tmp")])
+        .map(|dir| dir.join("hypr").join(&signature).join(".socket2.sock"))
+        .find(|path| path.exists())
+}
+
+async fn i3_socket() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("SWAYSOCK").or_else(|| std::env::var_os("I3SOCK")) {
+        return Some(path.into());
+    }
+    let output = tokio::process::Command::new("i3")
+        .arg("--get-socketpath")
+        .output()
+        .await
+        .ok()?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    let path = path.trim();
+    (output.status.success() && !path.is_empty()).then(|| path.into())
+}
+
+async fn i3_send(
+    stream: &mut UnixStream,
+    message_type: u32,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut message = I3_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message).await
+}
+
+async fn i3_receive(stream: &mut UnixStream) -> std::io::Result<(u32, Vec<u8>)> {
+    let mut header = [0; 14];
+    stream.read_exact(&mut header).await?;
+    if &header[..6] != I3_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "invalid i3 IPC message",
+        ));
+    }
+    let len = u32::from_ne_bytes(header[6..10].try_into().expect("4 bytes"));
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().expect("4 bytes"));
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload).await?;
+    Ok((message_type, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn events() {
+        let workspace = json!({"change": "focus", "current": {"name": "call"}});
+        assert_eq!(
+            i3_event(I3_EVENT_WORKSPACE, &workspace),
+            Some(FocusChange::Workspace("call".to_string()))
+        );
+        let sway_window = json!({"change": "focus", "container": {"app_id": "firefox"}});
+        let i3_window = json!({
+            "change": "focus",
+            "container": {"app_id": null, "window_properties": {"class": "zoom"}}
+        });
+        assert_eq!(
+            i3_event(I3_EVENT_WINDOW, &sway_window),
+            Some(FocusChange::App("firefox".to_string()))
+        );
+        assert_eq!(
+            i3_event(I3_EVENT_WINDOW, &i3_window),
+            Some(FocusChange::App("zoom".to_string()))
+        );
+        assert_eq!(i3_event(I3_EVENT_WINDOW, &json!({"change": "title"})), None);
+
+        assert_eq!(
+            hyprland_event("workspace>>2"),
+            Some(FocusChange::Workspace("2".to_string()))
+        );
+        assert_eq!(
+            hyprland_event("activewindow>>zoom,Zoom Meeting"),
+            Some(FocusChange::App("zoom".to_string()))
+        );
+        assert_eq!(hyprland_event("openwindow>>..."), None);
+    }
+
+    #[test]
+    fn rules() {
+        let config = WmConfig {
+            rules: vec![
+                WmRule {
+                    workspace: Some("call".to_string()),
+                    app: None,
+                    preset: "bright".to_string(),
+                },
+                WmRule {
+                    workspace: None,
+                    app: Some("Zoom".to_string()),
+                    preset: "meeting".to_string(),
+                },
+            ],
+            default_preset: Some("normal".to_string()),
+            ..Default::default()
+        };
+        let mut focus = Focus::default();
+        assert_eq!(matching_preset(&config, &focus), Some("normal"));
+        focus.apply(FocusChange::App("zoom".to_string()));
+        assert_eq!(matching_preset(&config, &focus), Some("meeting"));
+        focus.apply(FocusChange::Workspace("call".to_string()));
+        assert_eq!(matching_preset(&config, &focus), Some("bright"));
+    }
+}
@@ -0,0 +1,30 @@
+use std::{fmt::Display, hash::Hash};
+
+use url::Url;
+
+/// Light on the network, told apart by its name
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    pub url: Url,
+}
+
+impl PartialEq for Device {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Device {}
+
+impl Hash for Device {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} => {}", self.name, self.url)
+    }
+}
@@ -0,0 +1,141 @@
+//! Errors as shown to the user by the binaries: the message, its causes and what to try next.
+
+use std::error::Error;
+
+use miette::{Diagnostic, GraphicalReportHandler, GraphicalTheme};
+
+#[cfg(feature = "discovery")]
+use crate::avahi::DiscoverError;
+use crate::{exit_code::NotFound, Config, ConfigError, UnsupportedFirmware};
+
+/// Error with its causes, and a hint to fix it when the kind of failure is known
+#[derive(Debug, thiserror::Error, Diagnostic)]
+#[error("{message}")]
+pub struct UserError {
+    message: String,
+    #[source]
+    cause: Option<Box<UserError>>,
+    #[help]
+    help: Option<String>,
+}
+
+impl UserError {
+    pub fn new(err: &(dyn Error + 'static)) -> Self {
+        let messages: Vec<String> = causes(err).map(ToString::to_string).collect();
+        let cause = messages.iter().skip(1).rev().fold(None, |cause, message| {
+            Some(Box::new(UserError {
+                message: message.clone(),
+                cause,
+                help: None,
+            }))
+        });
+        UserError {
+            message: messages[0].clone(),
+            cause,
+            help: causes(err).find_map(hint),
+        }
+    }
+
+    /// Plain text rendering, for the GUI
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let handler = GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor());
+        match handler.render_report(&mut out, self) {
+            Ok(()) => out,
+            Err(_) => self.message.clone(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for UserError {
+    fn from(err: anyhow::Error) -> Self {
+        UserError::new(err.as_ref())
+    }
+}
+
+fn causes<'a>(err: &'a (dyn Error + 'static)) -> impl Iterator<Item = &'a (dyn Error + 'static)> {
+    std::iter::successors(Some(err), |&err| err.source())
+}
+
+/// What to try for a known kind of failure
+fn hint(err: &(dyn Error + 'static)) -> Option<String> {
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        if err.is_connect() || err.is_timeout() {
+            return Some(
+                "Check that the light is plugged in and on the same network (VLAN) as this \
+                 computer, and that elgato-keylight-discover finds it"
+                    .to_string(),
+            );
+        }
+        if err.is_decode() {
+            return Some(
+                "The device answered but not like an Elgato light, check its address and port \
+                 (9123 by default)"
+                    .to_string(),
+            );
+        }
+    }
+    #[cfg(feature = "discovery")]
+    match err.downcast_ref::<DiscoverError>() {
+        Some(DiscoverError::AvahiBrowseNotInstalled) => {
+            return Some(
+                "Install avahi-browse, e.g. with the avahi-utils package, or give the address of \
+                 the light"
+                    .to_string(),
+            )
+        }
+        Some(DiscoverError::AvahiBrowseFailed { .. }) => {
+            return Some(
+                "Check that the Avahi daemon is running: systemctl status avahi-daemon".to_string(),
+            )
+        }
+        _ => {}
+    }
+    if let Some(ConfigError::Parse(_)) = err.downcast_ref::<ConfigError>() {
+        let path = Config::path().map_or("the config file".to_string(), |path| {
+            path.display().to_string()
+        });
+        return Some(format!("Fix or move away {path}"));
+    }
+    if err.is::<UnsupportedFirmware>() {
+        return Some(
+            "Update the firmware of the light with the Elgato Control Center app".to_string(),
+        );
+    }
+    match err.downcast_ref::<NotFound>() {
+        Some(NotFound { kind: "Light", .. }) => {
+            Some("elgato-keylight-discover lists the lights found on the network".to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "discovery"))]
+mod tests {
+    use anyhow::Context as _;
+
+    use super::*;
+
+    #[test]
+    fn user_error() {
+        let err = Err::<(), _>(DiscoverError::AvahiBrowseNotInstalled)
+            .context("Discovery failed")
+            .unwrap_err();
+        let err = UserError::from(err);
+        assert_eq!(err.to_string(), "Discovery failed");
+        assert_eq!(
+            err.cause.as_ref().map(ToString::to_string).as_deref(),
+            Some("avahi-browse not installed")
+        );
+        assert!(err
+            .help
+            .as_deref()
+            .unwrap()
+            .starts_with("Install avahi-browse"));
+
+        let rendered = err.render();
+        assert!(rendered.contains("Discovery failed"));
+        assert!(rendered.contains("avahi-browse not installed"));
+        assert!(rendered.contains("help: Install avahi-browse"));
+    }
+}
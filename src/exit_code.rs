@@ -0,0 +1,108 @@
+//! Exit codes of `elgato-keylight-cli`, stable across releases so that scripts can tell the
+//! failures apart without parsing the error message.
+
+use crate::{CapabilityError, InvalidLightIndex};
+
+/// Exit code of the CLI, see the README for the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any failure without a code of its own
+    Failure = 1,
+    /// Invalid arguments or values, also the code of the argument parsing errors
+    Validation = 2,
+    /// The light or the daemon can't be reached, or didn't answer in time
+    Unreachable = 3,
+    /// No light, schedule, preset, scene or file by this name
+    NotFound = 4,
+    /// A command on several lights failed on some of them only
+    PartialFailure = 5,
+}
+
+/// Something named by the user doesn't exist, exits with [`ExitCode::NotFound`]
+#[derive(Debug, thiserror::Error)]
+#[error("{kind} not found: {name}")]
+pub struct NotFound {
+    /// What was looked up, e.g. "Light"
+    pub kind: &'static str,
+    pub name: String,
+}
+
+/// Invalid user input, exits with [`ExitCode::Validation`]
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ValidationError(pub String);
+
+/// A command on several lights failed on some of them, exits with [`ExitCode::PartialFailure`]
+#[derive(Debug, thiserror::Error)]
+#[error("Failed on {failed} of {total} lights")]
+pub struct PartialFailure {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl ExitCode {
+    /// Code of a failed command, from the first error of the chain that has one
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if cause.is::<NotFound>() {
+                    Some(ExitCode::NotFound)
+                } else if cause.is::<PartialFailure>() {
+                    Some(ExitCode::PartialFailure)
+                } else if cause.is::<ValidationError>()
+                    || cause.is::<CapabilityError>()
+                    || cause.is::<InvalidLightIndex>()
+                    || cause.is::<serde_json::Error>()
+                    || cause.is::<toml::de::Error>()
+                {
+                    Some(ExitCode::Validation)
+                } else if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                    (err.is_connect() || err.is_timeout()).then_some(ExitCode::Unreachable)
+                } else if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                    (err.kind() == std::io::ErrorKind::NotFound).then_some(ExitCode::NotFound)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(ExitCode::Failure)
+    }
+}
+
+impl From<ExitCode> for std::process::ExitCode {
+    fn from(code: ExitCode) -> Self {
+        std::process::ExitCode::from(code as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn exit_codes() {
+        let not_found = anyhow::Error::new(NotFound {
+            kind: "Light",
+            name: "desk".to_string(),
+        });
+        assert_eq!(
+            ExitCode::of(&not_found.context("Backup")),
+            ExitCode::NotFound
+        );
+
+        let invalid = anyhow::Error::new(ValidationError("--ip is required".to_string()));
+        assert_eq!(ExitCode::of(&invalid), ExitCode::Validation);
+
+        // Nothing listens on the discard port
+        let unreachable = crate::get_status("http://127.0.0.1:9".parse().unwrap())
+            .await
+            .context("Status")
+            .unwrap_err();
+        assert_eq!(ExitCode::of(&unreachable), ExitCode::Unreachable);
+
+        assert_eq!(ExitCode::of(&anyhow::anyhow!("boom")), ExitCode::Failure);
+    }
+}
@@ -0,0 +1,248 @@
+//! C API for OBS plugins and other C/C++ tools, see `include/elgato_keylight.h`.
+//!
+//! Functions returning an `int32_t` return [`KEYLIGHT_OK`] on success, or a negative error code
+//! whose message is available through [`keylight_last_error`] on the same thread.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    sync::OnceLock,
+};
+
+use reqwest::Url;
+use tokio::runtime::Runtime;
+
+use crate::{
+    avahi::find_elgato_devices, get_status, set_status, Brightness, LightIndex, Temperature,
+};
+
+pub const KEYLIGHT_OK: i32 = 0;
+/// A null pointer, an invalid URL or an out of range value
+pub const KEYLIGHT_INVALID_ARGUMENT: i32 = -1;
+/// The request to the light or the discovery failed
+pub const KEYLIGHT_REQUEST_FAILED: i32 = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// State of the first light of a device
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeylightStatus {
+    /// 0 or 1
+    pub on: u8,
+    /// Percent, from 0 to 100
+    pub brightness: u8,
+    /// Device units, from 143 (7000 K) to 344 (2900 K), 0 for a light in color mode
+    pub temperature: u16,
+}
+
+impl From<&crate::KeyLightStatus> for KeylightStatus {
+    fn from(status: &crate::KeyLightStatus) -> Self {
+        KeylightStatus {
+            on: status.power as u8,
+            brightness: status.brightness.0,
+            temperature: status.temperature.map_or(0, |temperature| temperature.0),
+        }
+    }
+}
+
+impl TryFrom<&KeylightStatus> for crate::KeyLightStatus {
+    type Error = String;
+
+    fn try_from(status: &KeylightStatus) -> Result<Self, Self::Error> {
+        Ok(crate::KeyLightStatus::white(
+            (status.on != 0).into(),
+            Brightness::new(status.brightness)?,
+            Temperature::new(status.temperature)?,
+        ))
+    }
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+fn fail(code: i32, message: impl ToString) -> i32 {
+    let message = CString::new(message.to_string().replace('\0', " ")).expect("no nul byte");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+/// # Safety
+/// `url` must be null or a valid nul-terminated string
+unsafe fn parse_url(url: *const c_char) -> Result<Url, i32> {
+    if url.is_null() {
+        return Err(fail(KEYLIGHT_INVALID_ARGUMENT, "url is null"));
+    }
+    let url = CStr::from_ptr(url)
+        .to_str()
+        .map_err(|err| fail(KEYLIGHT_INVALID_ARGUMENT, err))?;
+    Url::parse(url).map_err(|err| fail(KEYLIGHT_INVALID_ARGUMENT, format!("{url}: {err}")))
+}
+
+/// Message of the last error of the calling thread, null if none. Valid until the next call
+/// failing on this thread.
+#[no_mangle]
+pub extern "C" fn keylight_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Discover the lights on the network through Avahi. On success `*devices` is set to a JSON
+/// array of `{"name": ..., "url": ...}`, to release with [`keylight_string_free`].
+///
+/// # Safety
+/// `devices` must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn keylight_discover(devices: *mut *mut c_char) -> i32 {
+    if devices.is_null() {
+        return fail(KEYLIGHT_INVALID_ARGUMENT, "devices is null");
+    }
+    let found = match runtime().block_on(find_elgato_devices()) {
+        Ok(found) => found,
+        Err(err) => return fail(KEYLIGHT_REQUEST_FAILED, err),
+    };
+    let json = serde_json::Value::Array(
+        found
+            .iter()
+            .map(|device| serde_json::json!({"name": device.name, "url": device.url}))
+            .collect(),
+    );
+    let json = CString::new(json.to_string()).expect("JSON has no nul byte");
+    *devices = json.into_raw();
+    KEYLIGHT_OK
+}
+
+/// Release a string returned by this library
+///
+/// # Safety
+/// `s` must be null or a string returned by this library, not released yet
+#[no_mangle]
+pub unsafe extern "C" fn keylight_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Read the state of the light at `url`, e.g. `http://192.168.1.100:9123`
+///
+/// # Safety
+/// `url` must be a valid nul-terminated string and `status` a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn keylight_get_status(
+    url: *const c_char,
+    status: *mut KeylightStatus,
+) -> i32 {
+    let url = match parse_url(url) {
+        Ok(url) => url,
+        Err(code) => return code,
+    };
+    if status.is_null() {
+        return fail(KEYLIGHT_INVALID_ARGUMENT, "status is null");
+    }
+    match runtime().block_on(get_status(url)) {
+        Ok(device) => match device.lights.first() {
+            Some(light) => {
+                *status = light.into();
+                KEYLIGHT_OK
+            }
+            None => fail(KEYLIGHT_REQUEST_FAILED, "the device has no lights"),
+        },
+        Err(err) => fail(KEYLIGHT_REQUEST_FAILED, err),
+    }
+}
+
+/// Set the state of the light at `url`
+///
+/// # Safety
+/// `url` must be a valid nul-terminated string and `status` a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn keylight_set_status(
+    url: *const c_char,
+    status: *const KeylightStatus,
+) -> i32 {
+    let url = match parse_url(url) {
+        Ok(url) => url,
+        Err(code) => return code,
+    };
+    let Some(status) = status.as_ref() else {
+        return fail(KEYLIGHT_INVALID_ARGUMENT, "status is null");
+    };
+    let light = match crate::KeyLightStatus::try_from(status) {
+        Ok(light) => light,
+        Err(err) => return fail(KEYLIGHT_INVALID_ARGUMENT, err),
+    };
+    let device = crate::DeviceStatus {
+        number_of_lights: 1,
+        lights: vec![light],
+    };
+    match runtime().block_on(set_status(url, device)) {
+        Ok(()) => KEYLIGHT_OK,
+        Err(err) => fail(KEYLIGHT_REQUEST_FAILED, err),
+    }
+}
+
+/// Turn the light at `url` off if it is on, on otherwise. On success `*status`, if not null,
+/// is set to the new state.
+///
+/// # Safety
+/// `url` must be a valid nul-terminated string and `status` null or a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn keylight_toggle(url: *const c_char, status: *mut KeylightStatus) -> i32 {
+    let url = match parse_url(url) {
+        Ok(url) => url,
+        Err(code) => return code,
+    };
+    let toggled = runtime().block_on(async {
+        let mut device = get_status(url.clone()).await?;
+        device.set(LightIndex::FIRST, |light| light.power.toggle())?;
+        let light = device.lights[0].clone();
+        set_status(url, device).await?;
+        anyhow::Ok(light)
+    });
+    match toggled {
+        Ok(light) => {
+            if let Some(status) = status.as_mut() {
+                *status = (&light).into();
+            }
+            KEYLIGHT_OK
+        }
+        Err(err) => fail(KEYLIGHT_REQUEST_FAILED, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors() {
+        let mut status = KeylightStatus {
+            on: 1,
+            brightness: 40,
+            temperature: 200,
+        };
+        let url = CString::new("not a url").unwrap();
+        unsafe {
+            assert_eq!(
+                keylight_get_status(url.as_ptr(), &mut status),
+                KEYLIGHT_INVALID_ARGUMENT
+            );
+            assert!(!keylight_last_error().is_null());
+            let url = CString::new("http://127.0.0.1:9").unwrap();
+            status.temperature = 100;
+            assert_eq!(
+                keylight_set_status(url.as_ptr(), &status),
+                KEYLIGHT_INVALID_ARGUMENT
+            );
+            let message = CStr::from_ptr(keylight_last_error()).to_str().unwrap();
+            assert_eq!(message, "Outside range [143, 344]");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fmt::Display, num::ParseIntError, str::FromStr};
+
+use serde::{de::Error, Deserialize, Serialize};
+
+/// Latest firmware known for each product, as reported by `productName` in the accessory info.
+///
+/// Bundled with each release of this crate, devices running an older firmware will get
+/// an update notice.
+const LATEST_FIRMWARE: &[(&str, FirmwareVersion)] = &[
+    ("Elgato Key Light", FirmwareVersion::new(1, 0, 3)),
+    ("Elgato Key Light Air", FirmwareVersion::new(1, 0, 3)),
+    ("Elgato Key Light Mini", FirmwareVersion::new(1, 0, 4)),
+    ("Elgato Light Strip", FirmwareVersion::new(1, 0, 4)),
+    ("Elgato Ring Light", FirmwareVersion::new(1, 0, 3)),
+];
+
+/// Part of the API missing from older firmware. There are no documented versions introducing
+/// them, a light lacks one if it answers 404 to its endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display)]
+pub enum FirmwareFeature {
+    /// `/elgato/lights/settings`: power-on behavior and transitions
+    #[strum(to_string = "Light settings")]
+    Settings,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{feature} are not supported by the firmware of the light")]
+pub struct UnsupportedFirmware {
+    pub feature: FirmwareFeature,
+}
+
+/// Firmware version in the `major.minor.patch` format used by the devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FirmwareVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        FirmwareVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Latest firmware known for the given product
+    pub fn latest(product_name: &str) -> Option<Self> {
+        LATEST_FIRMWARE
+            .iter()
+            .find(|(name, _)| *name == product_name)
+            .map(|(_, version)| *version)
+    }
+}
+
+impl Display for FirmwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for FirmwareVersion {
+    type Err = ParseIntError;
+
+    /// Missing components default to 0, i.e. `1.2` is `1.2.0`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '.');
+        let major = parts.next().unwrap_or_default().parse()?;
+        let minor = parts.next().map(u16::from_str).transpose()?.unwrap_or(0);
+        let patch = parts.next().map(u16::from_str).transpose()?.unwrap_or(0);
+        Ok(FirmwareVersion::new(major, minor, patch))
+    }
+}
+
+impl Serialize for FirmwareVersion {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FirmwareVersion {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        FirmwareVersion::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_compare() {
+        assert_eq!("1.0.3".parse(), Ok(FirmwareVersion::new(1, 0, 3)));
+        assert_eq!("2.1".parse(), Ok(FirmwareVersion::new(2, 1, 0)));
+        assert!("1.x.3".parse::<FirmwareVersion>().is_err());
+        assert!("".parse::<FirmwareVersion>().is_err());
+
+        assert!(FirmwareVersion::new(1, 0, 3) < FirmwareVersion::new(1, 0, 10));
+        assert!(FirmwareVersion::new(1, 1, 0) > FirmwareVersion::new(1, 0, 10));
+        assert_eq!(FirmwareVersion::new(1, 0, 3).to_string(), "1.0.3");
+    }
+
+    #[test]
+    fn unsupported_firmware() {
+        let err = UnsupportedFirmware {
+            feature: FirmwareFeature::Settings,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Light settings are not supported by the firmware of the light"
+        );
+    }
+}
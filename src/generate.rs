@@ -0,0 +1,57 @@
+//! `generate` subcommand of the binaries, writing their man pages and completion scripts from
+//! their clap definitions, for packagers
+
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Subcommand};
+use clap_complete::Shell;
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Generate {
+    /// Write the man pages of the binary and of its subcommands to a directory
+    Man { dir: PathBuf },
+    /// Print the completion script of a shell
+    Completions { shell: Shell },
+}
+
+impl Generate {
+    /// Generate the artifact of the binary `bin` parsing `A`, see `CARGO_BIN_NAME`
+    pub fn run<A: CommandFactory>(&self, bin: &'static str) -> std::io::Result<()> {
+        let mut command = A::command().name(bin).bin_name(bin);
+        match self {
+            Generate::Man { dir } => {
+                std::fs::create_dir_all(dir)?;
+                clap_mangen::generate_to(command, dir)
+            }
+            Generate::Completions { shell } => {
+                clap_complete::generate(*shell, &mut command, bin, &mut std::io::stdout());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Args {
+        #[command(subcommand)]
+        command: Generate,
+    }
+
+    #[test]
+    fn man() {
+        let dir = std::env::temp_dir().join(format!("keylight-man-{}", std::process::id()));
+        Generate::Man { dir: dir.clone() }
+            .run::<Args>("elgato-keylight-test")
+            .unwrap();
+        let page = std::fs::read_to_string(dir.join("elgato-keylight-test.1")).unwrap();
+        assert!(page.contains("elgato\\-keylight\\-test"));
+        assert!(dir.join("elgato-keylight-test-completions.1").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write as _,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{KeyLightStatus, PowerStatus};
+
+const HISTORY_DIR_NAME: &str = "elgato-keylight";
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("Data directory not found")]
+    NoDataDir,
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// Line of the history file recorded by the daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub time: DateTime<Utc>,
+    pub device: String,
+    #[serde(flatten)]
+    pub event: HistoryEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum HistoryEvent {
+    /// New state of the light
+    State {
+        status: KeyLightStatus,
+        source: String,
+        /// Who made the change through the source, e.g. the process of an IPC client
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        actor: Option<String>,
+    },
+    /// A preset was applied to the light
+    Preset {
+        preset: String,
+        source: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        actor: Option<String>,
+    },
+}
+
+impl HistoryEvent {
+    /// What made the change, `external` for the changes noticed by polling the light
+    pub fn source(&self) -> &str {
+        match self {
+            HistoryEvent::State { source, .. } | HistoryEvent::Preset { source, .. } => source,
+        }
+    }
+}
+
+/// Default location of the history file: `$XDG_DATA_HOME/elgato-keylight/history.jsonl`
+pub fn history_path() -> Result<PathBuf, HistoryError> {
+    let dir = dirs::data_dir().ok_or(HistoryError::NoDataDir)?;
+    Ok(dir.join(HISTORY_DIR_NAME).join(HISTORY_FILE_NAME))
+}
+
+/// Append `record` to the history file at `path`
+pub fn append_history(path: &Path, record: &HistoryRecord) -> Result<(), HistoryError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Records of the history file at `path`, empty if there is none. Invalid lines are skipped.
+pub fn load_history(path: &Path) -> Result<Vec<HistoryRecord>, HistoryError> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut records: Vec<HistoryRecord> = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(err) => {
+                log::warn!("Skipping invalid history line: {err}");
+                None
+            }
+        })
+        .collect();
+    records.sort_by_key(|record| record.time);
+    Ok(records)
+}
+
+/// Drop the records older than `before`, keeping the last known state of each light
+pub fn prune_history(path: &Path, before: DateTime<Utc>) -> Result<(), HistoryError> {
+    let records = load_history(path)?;
+    let mut last_states: HashMap<&str, &HistoryRecord> = HashMap::new();
+    for record in records.iter().filter(|record| record.time < before) {
+        if matches!(record.event, HistoryEvent::State { .. }) {
+            last_states.insert(&record.device, record);
+        }
+    }
+    let mut kept: Vec<&HistoryRecord> = last_states.into_values().collect();
+    kept.extend(records.iter().filter(|record| record.time >= before));
+    kept.sort_by_key(|record| record.time);
+
+    let mut content = String::new();
+    for record in kept {
+        content.push_str(&serde_json::to_string(record)?);
+        content.push('\n');
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Last `limit` changes made through the daemon, optionally only those of `device` or made
+/// through `source`. The changes noticed by polling the lights are left out.
+pub fn audit_trail(
+    records: &[HistoryRecord],
+    device: Option<&str>,
+    source: Option<&str>,
+    limit: usize,
+) -> Vec<HistoryRecord> {
+    let matching: Vec<&HistoryRecord> = records
+        .iter()
+        .filter(|record| record.event.source() != "external")
+        .filter(|record| device.map_or(true, |device| record.device == device))
+        .filter(|record| source.map_or(true, |source| record.event.source() == source))
+        .collect();
+    let skip = matching.len().saturating_sub(limit);
+    matching.into_iter().skip(skip).cloned().collect()
+}
+
+/// Usage of the lights over a period, see [`summarize`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistorySummary {
+    pub devices: BTreeMap<String, DeviceSummary>,
+    /// Number of times each preset was applied, most used first
+    pub presets: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceSummary {
+    /// Time the light was on, per local day
+    pub on_time: BTreeMap<NaiveDate, Duration>,
+    /// Average brightness while the light was on, weighted by time
+    pub average_brightness: Option<f64>,
+}
+
+/// Summarize the `records` between `since` and `now`, splitting the days in `tz`
+pub fn summarize<Tz: TimeZone>(
+    records: &[HistoryRecord],
+    since: DateTime<Utc>,
+    now: DateTime<Utc>,
+    tz: &Tz,
+) -> HistorySummary {
+    let mut summary = HistorySummary::default();
+    let mut presets: HashMap<&str, usize> = HashMap::new();
+    // Last state of each light, brightness-seconds and seconds spent on
+    let mut states: HashMap<&str, (DateTime<Utc>, &KeyLightStatus)> = HashMap::new();
+    let mut brightness: HashMap<String, (f64, f64)> = HashMap::new();
+
+    let mut account = |summary: &mut HistorySummary,
+                       device: &str,
+                       (start, status): (DateTime<Utc>, &KeyLightStatus),
+                       end: DateTime<Utc>| {
+        let start = start.max(since);
+        if status.power != PowerStatus::On || end <= start {
+            return;
+        }
+        let on_time = &mut summary
+            .devices
+            .entry(device.to_string())
+            .or_default()
+            .on_time;
+        for (day, duration) in split_days(start, end, tz) {
+            *on_time.entry(day).or_default() += duration;
+        }
+        let seconds = (end - start).num_milliseconds() as f64 / 1000.0;
+        let (weighted, total) = brightness.entry(device.to_string()).or_default();
+        *weighted += f64::from(status.brightness.0) * seconds;
+        *total += seconds;
+    };
+
+    for record in records.iter().filter(|record| record.time <= now) {
+        match &record.event {
+            HistoryEvent::State { status, .. } => {
+                if let Some(previous) = states.insert(&record.device, (record.time, status)) {
+                    account(&mut summary, &record.device, previous, record.time);
+                }
+            }
+            HistoryEvent::Preset { preset, .. } if record.time >= since => {
+                *presets.entry(preset).or_default() += 1;
+            }
+            HistoryEvent::Preset { .. } => {}
+        }
+    }
+    for (device, state) in states {
+        account(&mut summary, device, state, now);
+        summary.devices.entry(device.to_string()).or_default();
+    }
+
+    for (device, (weighted, total)) in brightness {
+        if total > 0.0 {
+            if let Some(device) = summary.devices.get_mut(&device) {
+                device.average_brightness = Some(weighted / total);
+            }
+        }
+    }
+    let mut presets: Vec<(String, usize)> = presets
+        .into_iter()
+        .map(|(preset, count)| (preset.to_string(), count))
+        .collect();
+    presets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    summary.presets = presets;
+    summary
+}
+
+/// Split `[start, end)` at the midnights of `tz`
+fn split_days<Tz: TimeZone>(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    tz: &Tz,
+) -> Vec<(NaiveDate, Duration)> {
+    let mut days = Vec::new();
+    let mut current = start;
+    while current < end {
+        let day = current.with_timezone(tz).date_naive();
+        let next_midnight = day
+            .succ_opt()
+            .and_then(|next| next.and_hms_opt(0, 0, 0))
+            .and_then(|midnight| tz.from_local_datetime(&midnight).earliest())
+            .map(|midnight| midnight.with_timezone(&Utc))
+            .filter(|midnight| *midnight > current)
+            .unwrap_or(end);
+        let until = next_midnight.min(end);
+        days.push((day, (until - current).to_std().unwrap_or_default()));
+        current = until;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, Temperature};
+
+    use super::*;
+
+    fn state(time: &str, device: &str, power: PowerStatus, brightness: u8) -> HistoryRecord {
+        HistoryRecord {
+            time: time.parse().unwrap(),
+            device: device.to_string(),
+            event: HistoryEvent::State {
+                status: KeyLightStatus::white(
+                    power,
+                    Brightness::new(brightness).unwrap(),
+                    Temperature::new(200).unwrap(),
+                ),
+                source: "external".to_string(),
+                actor: None,
+            },
+        }
+    }
+
+    fn preset(time: &str, preset: &str) -> HistoryRecord {
+        HistoryRecord {
+            time: time.parse().unwrap(),
+            device: "Left".to_string(),
+            event: HistoryEvent::Preset {
+                preset: preset.to_string(),
+                source: "rest".to_string(),
+                actor: Some("127.0.0.1:50123".to_string()),
+            },
+        }
+    }
+
+    #[test]
+    fn file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        assert_eq!(load_history(&path).unwrap(), vec![]);
+
+        let records = [
+            state("2024-09-01T08:00:00Z", "Left", PowerStatus::On, 40),
+            preset("2024-09-01T09:00:00Z", "meeting"),
+            state("2024-09-02T08:00:00Z", "Left", PowerStatus::Off, 40),
+        ];
+        for record in &records {
+            append_history(&path, record).unwrap();
+        }
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(
+            r#"{"time":"2024-09-01T08:00:00Z","device":"Left","event":"state","status":{"on":1,"#
+        ));
+        content.push_str("garbage\n");
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(load_history(&path).unwrap(), records);
+
+        prune_history(&path, "2024-09-01T12:00:00Z".parse().unwrap()).unwrap();
+        assert_eq!(
+            load_history(&path).unwrap(),
+            [records[0].clone(), records[2].clone()]
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let records = [
+            state("2024-09-01T22:00:00Z", "Left", PowerStatus::On, 40),
+            preset("2024-09-01T22:00:00Z", "meeting"),
+            state("2024-09-02T01:00:00Z", "Left", PowerStatus::On, 80),
+            preset("2024-09-02T01:00:00Z", "streaming"),
+            preset("2024-09-02T02:00:00Z", "streaming"),
+            state("2024-09-02T02:00:00Z", "Left", PowerStatus::Off, 80),
+            state("2024-09-02T03:00:00Z", "Right", PowerStatus::Off, 10),
+        ];
+        let summary = summarize(
+            &records,
+            "2024-09-01T00:00:00Z".parse().unwrap(),
+            "2024-09-03T00:00:00Z".parse().unwrap(),
+            &Utc,
+        );
+        let hours = |h: u64| Duration::from_secs(h * 3600);
+        let left = &summary.devices["Left"];
+        assert_eq!(
+            left.on_time,
+            BTreeMap::from([
+                ("2024-09-01".parse().unwrap(), hours(2)),
+                ("2024-09-02".parse().unwrap(), hours(2)),
+            ])
+        );
+        // 3 hours at 40% and 1 hour at 80%
+        assert_eq!(left.average_brightness, Some(50.0));
+        assert_eq!(summary.devices["Right"], DeviceSummary::default());
+        assert_eq!(
+            summary.presets,
+            vec![("streaming".to_string(), 2), ("meeting".to_string(), 1)]
+        );
+
+        // Only the last hour the light was on
+        let summary = summarize(
+            &records,
+            "2024-09-02T01:00:00Z".parse().unwrap(),
+            "2024-09-03T00:00:00Z".parse().unwrap(),
+            &Utc,
+        );
+        assert_eq!(summary.devices["Left"].average_brightness, Some(80.0));
+        assert_eq!(summary.presets, vec![("streaming".to_string(), 2)]);
+    }
+
+    #[test]
+    fn audit() {
+        let mut change = state("2024-09-01T23:00:00Z", "Right", PowerStatus::On, 40);
+        if let HistoryEvent::State { source, actor, .. } = &mut change.event {
+            *source = "ipc".to_string();
+            *actor = Some("elgato-keylight-gui (pid 4242)".to_string());
+        }
+        let records = [
+            state("2024-09-01T21:00:00Z", "Left", PowerStatus::On, 40),
+            preset("2024-09-01T22:00:00Z", "meeting"),
+            change.clone(),
+            preset("2024-09-02T01:00:00Z", "streaming"),
+        ];
+        // The external change is left out
+        assert_eq!(audit_trail(&records, None, None, 10), records[1..].to_vec());
+        assert_eq!(audit_trail(&records, None, None, 1), [records[3].clone()]);
+        assert_eq!(audit_trail(&records, Some("Right"), None, 10), [change]);
+        assert_eq!(
+            audit_trail(&records, None, Some("rest"), 10),
+            [records[1].clone(), records[3].clone()]
+        );
+    }
+}
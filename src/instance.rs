@@ -0,0 +1,82 @@
+//! Single instance of the GUI: launching it again asks the running instance to show its window,
+//! over a Unix socket, instead of starting a second tray icon and discovery
+
+use std::{
+    io::{self, BufRead as _, BufReader, Write as _},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+const SOCKET_DIR_NAME: &str = "elgato-keylight";
+const SOCKET_FILE_NAME: &str = "gui.sock";
+
+/// Message asking the running instance to show its window
+const SHOW_MESSAGE: &str = "show";
+
+/// Socket of the running instance, `$XDG_RUNTIME_DIR/elgato-keylight/gui.sock`, in the temporary
+/// directory without a runtime directory
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(SOCKET_DIR_NAME)
+        .join(SOCKET_FILE_NAME)
+}
+
+/// Become the running instance, listening on `path`. Returns `None` once the running instance,
+/// if any, is asked to show its window.
+pub fn acquire(path: &Path) -> io::Result<Option<UnixListener>> {
+    if let Ok(mut running) = UnixStream::connect(path) {
+        writeln!(running, "{SHOW_MESSAGE}")?;
+        return Ok(None);
+    }
+    // Left by an instance that didn't exit cleanly
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    UnixListener::bind(path).map(Some)
+}
+
+/// Call `show` on a background thread whenever another instance is launched
+pub fn listen(listener: UnixListener, show: impl Fn() + Send + 'static) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut line = String::new();
+            let read = stream.and_then(|stream| BufReader::new(stream).read_line(&mut line));
+            match read {
+                Ok(_) if line.trim() == SHOW_MESSAGE => show(),
+                Ok(_) => log::debug!("Unknown instance message {line:?}"),
+                Err(err) => log::warn!("Failed to read an instance message: {err}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn single_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_DIR_NAME).join(SOCKET_FILE_NAME);
+        let listener = acquire(&path).unwrap().unwrap();
+        let (shown_tx, shown_rx) = mpsc::channel();
+        listen(listener, move || shown_tx.send(()).unwrap());
+
+        assert!(acquire(&path).unwrap().is_none());
+        shown_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+
+        // Stale socket of an instance that crashed
+        let other = dir.path().join("other.sock");
+        drop(UnixListener::bind(&other).unwrap());
+        assert!(acquire(&other).unwrap().is_some());
+    }
+}
@@ -1,17 +1,66 @@
-use anyhow::bail;
-use serde::{Deserialize, Serialize};
-use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::str::FromStr;
 
-use crate::unsigned_int::{Brightness, Temperature};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use serde_repr::Serialize_repr;
+
+use crate::{
+    unsigned_int::{Brightness, Hue, Kelvin, Percent, Temperature},
+    FirmwareVersion,
+};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", from = "RawDeviceStatus")]
 pub struct DeviceStatus {
+    /// Always the length of `lights`
     pub number_of_lights: usize,
     pub lights: Vec<KeyLightStatus>,
 }
 
-#[derive(Clone, Copy, Serialize_repr, Deserialize_repr, PartialEq, Debug, strum::Display)]
+/// [`DeviceStatus`] as sent by the device, whose count of lights can't be trusted
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDeviceStatus {
+    #[serde(default)]
+    number_of_lights: Option<usize>,
+    #[serde(deserialize_with = "deserialize_lights")]
+    lights: Vec<KeyLightStatus>,
+}
+
+/// Some firmwares and bridges report a wrong `numberOfLights`, the lights are what counts
+impl From<RawDeviceStatus> for DeviceStatus {
+    fn from(raw: RawDeviceStatus) -> Self {
+        let count = raw.lights.len();
+        if raw.number_of_lights.is_some_and(|number| number != count) {
+            log::warn!(
+                "The device reported {} light(s) but sent {count}, using {count}",
+                raw.number_of_lights.unwrap_or_default()
+            );
+        }
+        DeviceStatus {
+            number_of_lights: count,
+            lights: raw.lights,
+        }
+    }
+}
+
+/// Prefix the errors with the index of the light, e.g. `light 1: `temperature` 360: ...`
+fn deserialize_lights<'de, D>(d: D) -> Result<Vec<KeyLightStatus>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<RawKeyLightStatus>::deserialize(d)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, raw)| {
+            KeyLightStatus::try_from(raw)
+                .map_err(|err| D::Error::custom(format!("light {index}: {err}")))
+        })
+        .collect()
+}
+
+/// Serialized as 0 or 1, also deserialized from `true`/`false` and `"on"`/`"off"`
+#[derive(Clone, Copy, Serialize_repr, PartialEq, Debug, strum::Display)]
+#[cfg_attr(feature = "daemon", derive(utoipa::ToSchema))]
 #[repr(u8)]
 pub enum PowerStatus {
     #[strum(serialize = "off")]
@@ -30,6 +79,39 @@ impl PowerStatus {
     }
 }
 
+impl FromStr for PowerStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "on" | "1" | "true" => Ok(PowerStatus::On),
+            "off" | "0" | "false" => Ok(PowerStatus::Off),
+            _ => Err(format!("expected on or off, got `{s}`")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PowerStatus {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Bool(bool),
+            Name(String),
+        }
+        match Raw::deserialize(d)? {
+            Raw::Number(0) | Raw::Bool(false) => Ok(PowerStatus::Off),
+            Raw::Number(1) | Raw::Bool(true) => Ok(PowerStatus::On),
+            Raw::Number(n) => Err(D::Error::custom(format!("expected 0 or 1, got {n}"))),
+            Raw::Name(name) => name.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
 impl From<PowerStatus> for bool {
     fn from(value: PowerStatus) -> Self {
         match value {
@@ -49,34 +131,330 @@ impl From<bool> for PowerStatus {
     }
 }
 
+/// State of a light. Key Lights have a color temperature, Light Strips a color temperature in
+/// white mode or a hue and saturation in color mode.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "daemon", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase", try_from = "RawKeyLightStatus")]
 pub struct KeyLightStatus {
     #[serde(rename = "on")]
     pub power: PowerStatus,
+    #[cfg_attr(feature = "daemon", schema(value_type = u8, minimum = 0, maximum = 100))]
     pub brightness: Brightness,
-    pub temperature: Temperature,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u16>, minimum = 143, maximum = 344))]
+    pub temperature: Option<Temperature>,
+    /// Degrees, from 0 to 360
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u16>, minimum = 0, maximum = 360))]
+    pub hue: Option<Hue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u8>, minimum = 0, maximum = 100))]
+    pub saturation: Option<Percent>,
+}
+
+impl KeyLightStatus {
+    /// State of a light set by its color temperature, such as a Key Light
+    pub fn white(power: PowerStatus, brightness: Brightness, temperature: Temperature) -> Self {
+        KeyLightStatus {
+            power,
+            brightness,
+            temperature: Some(temperature),
+            hue: None,
+            saturation: None,
+        }
+    }
+
+    /// The color temperature is meaningful: Key Lights, and Light Strips in white mode
+    pub fn has_temperature(&self) -> bool {
+        self.temperature.is_some()
+    }
+
+    /// The hue and saturation are meaningful: Light Strips in color mode
+    pub fn has_color(&self) -> bool {
+        self.hue.is_some() && self.saturation.is_some()
+    }
+}
+
+/// [`KeyLightStatus`] before the validation of its values, for errors naming the field
+#[derive(Deserialize)]
+struct RawKeyLightStatus {
+    on: PowerStatus,
+    brightness: i64,
+    temperature: Option<i64>,
+    hue: Option<f64>,
+    saturation: Option<f64>,
+}
+
+impl TryFrom<RawKeyLightStatus> for KeyLightStatus {
+    type Error = String;
+
+    fn try_from(raw: RawKeyLightStatus) -> Result<Self, Self::Error> {
+        // The Light Strips send the hue and saturation as floats
+        let round = |value: f64| value.round() as i64;
+        Ok(KeyLightStatus {
+            power: raw.on,
+            brightness: field("brightness", raw.brightness, Brightness::new)?,
+            temperature: raw
+                .temperature
+                .map(|value| field("temperature", value, Temperature::new))
+                .transpose()?,
+            hue: raw
+                .hue
+                .map(|value| field("hue", round(value), Hue::new))
+                .transpose()?,
+            saturation: raw
+                .saturation
+                .map(|value| field("saturation", round(value), Percent::new))
+                .transpose()?,
+        })
+    }
+}
+
+/// Validate the value of a field, naming it in the error
+fn field<I: TryFrom<i64>, T>(
+    name: &str,
+    value: i64,
+    new: impl FnOnce(I) -> Result<T, String>,
+) -> Result<T, String> {
+    let range_error = |err| format!("`{name}` {value}: {err}");
+    let inner = I::try_from(value).map_err(|_| range_error("out of range".to_string()))?;
+    new(inner).map_err(range_error)
+}
+
+/// Partial update of a light, unset fields are left unchanged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "daemon", derive(utoipa::ToSchema))]
+#[serde(default)]
+pub struct LightUpdate {
+    #[serde(rename = "on", skip_serializing_if = "Option::is_none")]
+    pub power: Option<PowerStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u8>, minimum = 0, maximum = 100))]
+    pub brightness: Option<Brightness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u16>, minimum = 143, maximum = 344))]
+    pub temperature: Option<Temperature>,
+    /// Color temperature in kelvin, instead of `temperature`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u16>, minimum = 2900, maximum = 7000))]
+    pub kelvin: Option<Kelvin>,
+}
+
+impl LightUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.power.is_none()
+            && self.brightness.is_none()
+            && self.temperature.is_none()
+            && self.kelvin.is_none()
+    }
+
+    pub fn apply(&self, status: &mut KeyLightStatus) {
+        if let Some(power) = self.power {
+            status.power = power;
+        }
+        if let Some(brightness) = self.brightness {
+            status.brightness = brightness;
+        }
+        if let Some(temperature) = self.temperature {
+            status.temperature = Some(temperature);
+        }
+        if let Some(kelvin) = self.kelvin {
+            status.temperature = Some(kelvin.into());
+        }
+    }
+}
+
+/// Aggregated state of the lights of a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "daemon", derive(utoipa::ToSchema))]
+pub struct RoomStatus {
+    pub devices: Vec<String>,
+    /// Number of lights turned on
+    pub on: usize,
+    /// Average brightness of the lights with a known state
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u8>, minimum = 0, maximum = 100))]
+    pub brightness: Option<Brightness>,
+    /// Average temperature of the lights with a known state and a temperature
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<u16>, minimum = 143, maximum = 344))]
+    pub temperature: Option<Temperature>,
+}
+
+impl RoomStatus {
+    /// Aggregate the known `statuses` of the lights of the room
+    pub fn aggregate(devices: Vec<String>, statuses: &[KeyLightStatus]) -> Self {
+        let average = |values: Vec<usize>| {
+            (!values.is_empty()).then(|| values.iter().sum::<usize>() / values.len())
+        };
+        let brightness = average(statuses.iter().map(|s| s.brightness.0.into()).collect());
+        let temperature = average(
+            statuses
+                .iter()
+                .filter_map(|s| s.temperature)
+                .map(|t| t.0.into())
+                .collect(),
+        );
+        RoomStatus {
+            devices,
+            on: statuses
+                .iter()
+                .filter(|status| status.power == PowerStatus::On)
+                .count(),
+            brightness: brightness.and_then(|b| Brightness::new(b as u8).ok()),
+            temperature: temperature.and_then(|t| Temperature::new(t as u16).ok()),
+        }
+    }
+}
+
+/// Meeting mode of the daemon, see `meeting_start` in the IPC protocol
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeetingStatus {
+    pub active: bool,
+    /// Lights restored at the end of the meeting
+    pub devices: Vec<String>,
+    /// When the meeting ends on its own
+    pub ends_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Position of a light in [`DeviceStatus::lights`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LightIndex(pub usize);
+
+impl LightIndex {
+    /// The light of single-light devices such as the Key Lights
+    pub const FIRST: LightIndex = LightIndex(0);
+}
+
+impl std::fmt::Display for LightIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid light index {index}, the device has {count} light(s)")]
+pub struct InvalidLightIndex {
+    pub index: LightIndex,
+    pub count: usize,
 }
 
 impl DeviceStatus {
-    pub fn set<F>(&mut self, index: usize, update: F) -> anyhow::Result<()>
+    pub fn set<F>(&mut self, index: LightIndex, update: F) -> Result<(), InvalidLightIndex>
     where
         F: FnOnce(&mut KeyLightStatus),
     {
-        if index > self.number_of_lights - 1 {
-            bail!("Invalid index");
-        }
-        update(self.lights.get_mut(index).unwrap());
+        let count = self.lights.len();
+        let light = self
+            .lights
+            .get_mut(index.0)
+            .ok_or(InvalidLightIndex { index, count })?;
+        update(light);
         Ok(())
     }
 }
 
+/// Static device information returned by `/elgato/accessory-info`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessoryInfo {
+    pub product_name: String,
+    pub hardware_board_type: u32,
+    pub firmware_build_number: u32,
+    pub firmware_version: FirmwareVersion,
+    pub serial_number: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl AccessoryInfo {
+    /// Newer firmware version available for this device, if any
+    pub fn firmware_update(&self) -> Option<FirmwareVersion> {
+        FirmwareVersion::latest(&self.product_name).filter(|latest| *latest > self.firmware_version)
+    }
+}
+
+/// Power-on behavior and transition durations returned by `/elgato/lights/settings`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightSettings {
+    /// 1 to restore the last state, 2 to use the power-on brightness and temperature
+    pub power_on_behavior: u8,
+    pub power_on_brightness: u8,
+    pub power_on_temperature: u16,
+    pub switch_on_duration_ms: u32,
+    pub switch_off_duration_ms: u32,
+    pub color_change_duration_ms: u32,
+    /// Settings of other models and firmware versions, sent back as they are
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Settings of a light saved by `settings backup`, to configure another light identically
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsBackup {
+    pub product_name: String,
+    pub display_name: String,
+    pub settings: LightSettings,
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use crate::unsigned_int::UnsignedInt;
 
     use super::*;
 
+    fn power() -> impl Strategy<Value = PowerStatus> {
+        prop_oneof![Just(PowerStatus::Off), Just(PowerStatus::On)]
+    }
+
+    fn light() -> impl Strategy<Value = KeyLightStatus> {
+        (power(), 0..=100u8, 143..=344u16).prop_map(|(power, brightness, temperature)| {
+            KeyLightStatus::white(power, UnsignedInt(brightness), UnsignedInt(temperature))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn device_status_round_trip(lights in prop::collection::vec(light(), 0..4)) {
+            let status = DeviceStatus {
+                number_of_lights: lights.len(),
+                lights,
+            };
+            let json = serde_json::to_value(&status).unwrap();
+            prop_assert_eq!(serde_json::from_value::<DeviceStatus>(json).unwrap(), status);
+        }
+
+        #[test]
+        fn light_update_round_trip(
+            power in prop::option::of(power()),
+            brightness in prop::option::of(0..=100u8),
+            temperature in prop::option::of(143..=344u16),
+            kelvin in prop::option::of(any::<u16>()),
+        ) {
+            let update = LightUpdate {
+                power,
+                brightness: brightness.map(UnsignedInt),
+                temperature: temperature.map(UnsignedInt),
+                kelvin: kelvin.map(Kelvin),
+            };
+            let json = serde_json::to_value(&update).unwrap();
+            prop_assert_eq!(serde_json::from_value::<LightUpdate>(json).unwrap(), update);
+        }
+
+        #[test]
+        fn out_of_range_light_rejected(brightness in 101..=u8::MAX, temperature: u16) {
+            let json = serde_json::json!({"on": 1, "brightness": brightness, "temperature": temperature});
+            prop_assert!(serde_json::from_value::<KeyLightStatus>(json).is_err());
+            let json = serde_json::json!({"brightness": brightness});
+            prop_assert!(serde_json::from_value::<LightUpdate>(json).is_err());
+        }
+    }
+
     #[test]
     fn serde() {
         let obj = serde_json::json!({
@@ -88,18 +466,185 @@ mod tests {
             status,
             DeviceStatus {
                 number_of_lights: 1,
-                lights: vec!(KeyLightStatus {
-                    power: PowerStatus::On,
-                    brightness: UnsignedInt::new(3).unwrap(),
-                    temperature: UnsignedInt::new(191).unwrap(),
-                }),
+                lights: vec!(KeyLightStatus::white(
+                    PowerStatus::On,
+                    UnsignedInt::new(3).unwrap(),
+                    UnsignedInt::new(191).unwrap()
+                )),
             }
         );
 
         let obj = serde_json::json!({
-            "numberOfLights":1,
-            "lights":[{"on":1,"brightness":-1,"temperature":360}]
+            "numberOfLights":2,
+            "lights":[
+                {"on":1,"brightness":3,"temperature":191},
+                {"on":1,"brightness":-1,"temperature":360}
+            ]
+        });
+        let err = serde_json::from_value::<DeviceStatus>(obj).unwrap_err();
+        assert_eq!(err.to_string(), "light 1: `brightness` -1: out of range");
+
+        let obj = serde_json::json!({
+            "numberOfLights":2,
+            "lights":[{"on":1,"brightness":3,"temperature":191}]
+        });
+        let status = serde_json::from_value::<DeviceStatus>(obj).unwrap();
+        assert_eq!(status.number_of_lights, 1);
+        let obj = serde_json::json!({"lights":[]});
+        let status = serde_json::from_value::<DeviceStatus>(obj).unwrap();
+        assert_eq!(status.number_of_lights, 0);
+
+        let obj = serde_json::json!({"on":0,"brightness":40,"temperature":360});
+        let err = serde_json::from_value::<KeyLightStatus>(obj).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`temperature` 360: Outside range [143, 344]"
+        );
+    }
+
+    #[test]
+    fn power_status() {
+        for on in [
+            serde_json::json!(1),
+            serde_json::json!(true),
+            serde_json::json!("on"),
+        ] {
+            assert_eq!(
+                serde_json::from_value::<PowerStatus>(on).unwrap(),
+                PowerStatus::On
+            );
+        }
+        assert_eq!(
+            serde_json::from_value::<PowerStatus>(serde_json::json!("OFF")).unwrap(),
+            PowerStatus::Off
+        );
+        assert!(serde_json::from_value::<PowerStatus>(serde_json::json!(2)).is_err());
+        assert!(serde_json::from_value::<PowerStatus>(serde_json::json!("dim")).is_err());
+        assert_eq!(serde_json::to_value(PowerStatus::On).unwrap(), 1);
+        assert_eq!("off".parse(), Ok(PowerStatus::Off));
+    }
+
+    #[test]
+    fn light_index() {
+        let mut status = DeviceStatus {
+            number_of_lights: 0,
+            lights: vec![],
+        };
+        assert_eq!(
+            status.set(LightIndex::FIRST, |light| light.power.toggle()),
+            Err(InvalidLightIndex {
+                index: LightIndex::FIRST,
+                count: 0
+            })
+        );
+    }
+
+    #[test]
+    fn light_strip_status() {
+        let json = r#"{"numberOfLights":1,"lights":[{"on":1,"hue":40.5,"saturation":77.0,"brightness":20}]}"#;
+        let status: DeviceStatus = serde_json::from_str(json).unwrap();
+        let light = &status.lights[0];
+        assert!(light.has_color());
+        assert!(!light.has_temperature());
+        assert_eq!(light.hue, Hue::new(41).ok());
+        assert_eq!(
+            serde_json::to_value(light).unwrap(),
+            serde_json::json!({"on": 1, "brightness": 20, "hue": 41, "saturation": 77})
+        );
+    }
+
+    #[test]
+    fn light_update() {
+        let update =
+            serde_json::from_value::<LightUpdate>(serde_json::json!({"brightness": 40})).unwrap();
+        let mut status = KeyLightStatus::white(
+            PowerStatus::On,
+            UnsignedInt::new(3).unwrap(),
+            UnsignedInt::new(191).unwrap(),
+        );
+        update.apply(&mut status);
+        assert_eq!(status.brightness, UnsignedInt::new(40).unwrap());
+        assert_eq!(status.temperature, UnsignedInt::new(191).ok());
+        assert_eq!(
+            serde_json::to_value(&update).unwrap(),
+            serde_json::json!({"brightness": 40})
+        );
+
+        let update =
+            serde_json::from_value::<LightUpdate>(serde_json::json!({"kelvin": 5000})).unwrap();
+        update.apply(&mut status);
+        assert_eq!(status.temperature, UnsignedInt::new(200).ok());
+
+        assert!(LightUpdate::default().is_empty());
+        assert!(
+            serde_json::from_value::<LightUpdate>(serde_json::json!({"temperature": 1})).is_err()
+        );
+    }
+
+    #[test]
+    fn room_status() {
+        let light = |power, brightness| {
+            KeyLightStatus::white(
+                power,
+                UnsignedInt::new(brightness).unwrap(),
+                UnsignedInt::new(200).unwrap(),
+            )
+        };
+        let devices = vec!["Left".to_string(), "Right".to_string()];
+        let status = RoomStatus::aggregate(
+            devices.clone(),
+            &[light(PowerStatus::On, 40), light(PowerStatus::Off, 21)],
+        );
+        assert_eq!(status.on, 1);
+        assert_eq!(status.brightness, Some(UnsignedInt::new(30).unwrap()));
+        assert_eq!(status.temperature, Some(UnsignedInt::new(200).unwrap()));
+
+        let status = RoomStatus::aggregate(devices, &[]);
+        assert_eq!((status.on, status.brightness), (0, None));
+    }
+
+    #[test]
+    fn accessory_info() {
+        let obj = serde_json::json!({
+            "productName":"Elgato Key Light",
+            "hardwareBoardType":53,
+            "firmwareBuildNumber":192,
+            "firmwareVersion":"1.0.2",
+            "serialNumber":"BW33J1A02272",
+            "displayName":"",
+            "features":["lights"]
+        });
+        let info = serde_json::from_value::<AccessoryInfo>(obj).unwrap();
+        assert_eq!(info.firmware_version, FirmwareVersion::new(1, 0, 2));
+        assert_eq!(info.firmware_update(), Some(FirmwareVersion::new(1, 0, 3)));
+
+        let info = AccessoryInfo {
+            firmware_version: FirmwareVersion::new(1, 0, 3),
+            ..info
+        };
+        assert_eq!(info.firmware_update(), None);
+
+        let info = AccessoryInfo {
+            product_name: "Unknown".to_string(),
+            ..info
+        };
+        assert_eq!(info.firmware_update(), None);
+    }
+
+    #[test]
+    fn light_settings() {
+        let obj = serde_json::json!({
+            "powerOnBehavior":1,
+            "powerOnBrightness":20,
+            "powerOnTemperature":213,
+            "switchOnDurationMs":100,
+            "switchOffDurationMs":300,
+            "colorChangeDurationMs":100,
+            "battery":{"energySaving":{"enable":0}}
         });
-        assert!(serde_json::from_value::<DeviceStatus>(obj).is_err());
+        let settings = serde_json::from_value::<LightSettings>(obj.clone()).unwrap();
+        assert_eq!(settings.switch_off_duration_ms, 300);
+        assert!(settings.other.contains_key("battery"));
+        assert_eq!(serde_json::to_value(&settings).unwrap(), obj);
     }
 }
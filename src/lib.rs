@@ -1,11 +1,60 @@
+#[cfg(feature = "network")]
+mod cache;
+mod capabilities;
+#[cfg(unix)]
+pub mod client;
+mod config;
+#[cfg(feature = "gui")]
+pub mod crash;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+mod device;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "network")]
+pub mod exit_code;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod firmware;
+#[cfg(feature = "logging")]
+pub mod generate;
+mod history;
+#[cfg(feature = "network")]
 mod http;
+#[cfg(all(feature = "gui", unix))]
+pub mod instance;
 mod keylight;
+#[cfg(feature = "logging")]
+pub mod logging;
+#[cfg(feature = "discovery")]
 mod mdns;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(feature = "notify")]
+mod notify;
+mod power;
+#[cfg(feature = "network")]
+mod queue;
+pub mod scene;
 mod unsigned_int;
 mod util;
 
+#[cfg(feature = "network")]
+pub use cache::*;
+pub use capabilities::*;
+pub use config::*;
+pub use device::*;
+pub use firmware::*;
+pub use history::*;
+#[cfg(feature = "network")]
 pub use http::*;
 pub use keylight::*;
+#[cfg(feature = "discovery")]
 pub use mdns::*;
+#[cfg(feature = "notify")]
+pub use notify::*;
+pub use power::*;
+#[cfg(feature = "network")]
+pub use queue::*;
 pub use unsigned_int::*;
 pub use util::*;
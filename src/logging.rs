@@ -0,0 +1,173 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{Mutex, PoisonError},
+};
+
+use clap::{ArgAction, ValueEnum};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt as _, util::SubscriberInitExt as _, EnvFilter,
+    Layer as _,
+};
+
+/// Number of log lines kept in memory for the crash reports
+const RECENT_LOGS_CAPACITY: usize = 200;
+
+/// Last log lines, oldest first, at least at the info level whatever the flags
+static RECENT_LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Logging flags shared by the binaries
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct LogArgs {
+    /// More logs: -v for info, -vv for debug, -vvv for trace
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Format of the logs, written to stderr
+    #[arg(long, value_enum, default_value_t, global = true)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogArgs {
+    /// Level of the logs without `RUST_LOG`, warnings by default
+    pub fn level(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::ERROR;
+        }
+        match self.verbose {
+            0 => LevelFilter::WARN,
+            1 => LevelFilter::INFO,
+            2 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+
+    /// Level of the `log` records for the loggers other than the subscriber, e.g. the event log
+    pub fn log_level(&self) -> log::Level {
+        if self.quiet {
+            return log::Level::Error;
+        }
+        match self.verbose {
+            0 => log::Level::Warn,
+            1 => log::Level::Info,
+            2 => log::Level::Debug,
+            _ => log::Level::Trace,
+        }
+    }
+
+    /// Install the global subscriber, also receiving the `log` records. `RUST_LOG` directives,
+    /// e.g. `elgato_keylight=debug`, take precedence over the level of the flags.
+    /// The last lines are also kept for [`recent_logs`].
+    pub fn init(&self) {
+        let filter = EnvFilter::builder()
+            .with_default_directive(self.level().into())
+            .from_env_lossy();
+        let stderr = fmt::layer().with_writer(io::stderr);
+        let stderr = match self.log_format {
+            LogFormat::Text => stderr.boxed(),
+            LogFormat::Json => stderr.json().boxed(),
+        };
+        let recent = fmt::layer()
+            .with_ansi(false)
+            .with_writer(RecentLogsWriter::default)
+            .with_filter(self.level().max(LevelFilter::INFO));
+        tracing_subscriber::registry()
+            .with(stderr.with_filter(filter))
+            .with(recent)
+            .init();
+    }
+}
+
+/// Last log lines of the process, oldest first, once [`LogArgs::init`] is called
+pub fn recent_logs() -> Vec<String> {
+    RECENT_LOGS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Writer of an event to [`RECENT_LOGS`], whose lines are added once it is written
+#[derive(Default)]
+struct RecentLogsWriter(Vec<u8>);
+
+impl Write for RecentLogsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLogsWriter {
+    fn drop(&mut self) {
+        let mut logs = RECENT_LOGS.lock().unwrap_or_else(PoisonError::into_inner);
+        for line in String::from_utf8_lossy(&self.0).lines() {
+            if logs.len() == RECENT_LOGS_CAPACITY {
+                logs.pop_front();
+            }
+            logs.push_back(line.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        log: LogArgs,
+    }
+
+    #[test]
+    fn flags() {
+        let level = |args: &[&str]| Args::try_parse_from(args).map(|args| args.log.level());
+        assert_eq!(level(&["bin"]).unwrap(), LevelFilter::WARN);
+        assert_eq!(level(&["bin", "-v"]).unwrap(), LevelFilter::INFO);
+        assert_eq!(level(&["bin", "-vvvv"]).unwrap(), LevelFilter::TRACE);
+        assert_eq!(level(&["bin", "-q"]).unwrap(), LevelFilter::ERROR);
+        let args = Args::try_parse_from(["bin", "-vv"]).unwrap();
+        assert_eq!(args.log.log_level(), log::Level::Debug);
+        assert!(level(&["bin", "-q", "-v"]).is_err());
+
+        let args = Args::try_parse_from(["bin", "--log-format", "json"]).unwrap();
+        assert_eq!(args.log.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn recent() {
+        for i in 0..=RECENT_LOGS_CAPACITY {
+            writeln!(RecentLogsWriter::default(), "line {i}").unwrap();
+        }
+        let mut writer = RecentLogsWriter::default();
+        writer.write_all(b"first ").unwrap();
+        writer.write_all(b"half\nsecond line\n").unwrap();
+        drop(writer);
+
+        let logs = recent_logs();
+        assert_eq!(logs.len(), RECENT_LOGS_CAPACITY);
+        assert_eq!(logs[0], "line 3");
+        assert_eq!(
+            logs[RECENT_LOGS_CAPACITY - 2..],
+            ["first half", "second line"]
+        );
+    }
+}
@@ -1,37 +1,106 @@
 use std::{
     convert::TryFrom,
-    fmt::Display,
-    hash::Hash,
     io::BufRead as _,
-    process::Stdio,
+    process::{ExitStatus, Stdio},
     string::FromUtf8Error,
     sync::{Arc, RwLock},
     thread::JoinHandle,
+    time::Duration,
 };
 
 use itertools::Itertools as _;
+use strum::IntoEnumIterator as _;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt as _},
+    task::JoinSet,
+};
 use url::Url;
 
-use crate::{find_executable, FindExecError, MdnsPacket, PacketParseError};
+pub use crate::Device;
+use crate::{find_executable, ping, MdnsPacket, PacketParseError};
+
+use super::{avahi_dbus, query};
 
-const ELGATO_SERVICE_ID: &str = "_elg._tcp";
+/// mDNS service type the lights announce themselves with
+pub const ELGATO_SERVICE_ID: &str = "_elg._tcp";
 
 #[derive(Debug, thiserror::Error)]
 pub enum DiscoverError {
-    #[error(transparent)]
-    FindExec(#[from] FindExecError),
     #[error("avahi-browse not installed")]
     AvahiBrowseNotInstalled,
     #[error("avahi-browse error: {0}")]
     AvahiBrowseError(std::io::Error),
+    /// avahi-browse ran but failed, e.g. the Avahi daemon or D-Bus is not running
+    #[error("avahi-browse failed ({status}): {stderr}")]
+    AvahiBrowseFailed { status: ExitStatus, stderr: String },
     #[error("Output parse error: {0}")]
     OutputParse(FromUtf8Error),
     #[error(transparent)]
     Parse(#[from] PacketParseError),
+    #[error("Avahi D-Bus error: {0}")]
+    AvahiDbus(#[from] zbus::Error),
+    #[error("mDNS query failed: {0}")]
+    Query(std::io::Error),
+}
+
+/// Way of browsing the mDNS services, tried in order by the discovery until one of them runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::Display, strum::EnumIter)]
+pub enum Backend {
+    /// The Avahi daemon of the system bus
+    #[strum(to_string = "Avahi D-Bus")]
+    AvahiDbus,
+    /// The avahi-browse command of avahi-utils
+    #[strum(to_string = "avahi-browse")]
+    AvahiBrowse,
+    /// mDNS queries of our own, e.g. in a container with host networking or without Avahi
+    #[strum(to_string = "built-in mDNS")]
+    Builtin,
+}
+
+impl Backend {
+    /// Find the instances of `service_type` through this backend
+    pub async fn browse(self, service_type: &str) -> Result<Vec<Device>, DiscoverError> {
+        match self {
+            Backend::AvahiDbus => Ok(avahi_dbus::browse(service_type).await?),
+            Backend::AvahiBrowse => Ok(exec_avahi_browse(Some(service_type))
+                .await?
+                .into_iter()
+                .filter_map(|packet| {
+                    Device::from_packet(packet).unwrap_or_else(|err| {
+                        // Light started returning `fe80::3e6a:9dff:fe21:b16` instead of `192.168.0.92`
+                        log::error!("Couldn't parse url: {err}");
+                        None
+                    })
+                })
+                .collect()),
+            Backend::Builtin => query::browse(service_type)
+                .await
+                .map_err(DiscoverError::Query),
+        }
+    }
+}
+
+/// Browse `service_type` through the first [`Backend`] that runs, returning the error of the last
+/// one if none does
+async fn browse_with_fallback(service_type: &str) -> Result<Vec<Device>, DiscoverError> {
+    let mut last_err = None;
+    for backend in Backend::iter() {
+        match backend.browse(service_type).await {
+            Ok(devices) => {
+                log::info!("Found {} devices through {backend}", devices.len());
+                return Ok(devices);
+            }
+            Err(err) => {
+                log::info!("{backend} unavailable, trying the next discovery backend: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("at least one backend"))
 }
 
 pub async fn exec_avahi_browse(filter: Option<&str>) -> Result<Vec<MdnsPacket>, DiscoverError> {
-    if find_executable("avahi-browse").await?.is_none() {
+    if find_executable("avahi-browse").is_none() {
         return Err(DiscoverError::AvahiBrowseNotInstalled);
     }
 
@@ -44,6 +113,13 @@ pub async fn exec_avahi_browse(filter: Option<&str>) -> Result<Vec<MdnsPacket>,
         .await
         .map_err(DiscoverError::AvahiBrowseError)?;
 
+    if !output.status.success() {
+        return Err(DiscoverError::AvahiBrowseFailed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
     let output = String::from_utf8(output.stdout).map_err(DiscoverError::OutputParse)?;
 
     Ok(output
@@ -52,32 +128,6 @@ pub async fn exec_avahi_browse(filter: Option<&str>) -> Result<Vec<MdnsPacket>,
         .collect::<Result<Vec<_>, _>>()?)
 }
 
-#[derive(Debug, Clone)]
-pub struct Device {
-    pub name: String,
-    pub url: Url,
-}
-
-impl PartialEq for Device {
-    fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
-    }
-}
-
-impl Eq for Device {}
-
-impl Hash for Device {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.name.hash(state)
-    }
-}
-
-impl Display for Device {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} => {}", self.name, self.url)
-    }
-}
-
 impl Device {
     pub fn from_packet(packet: MdnsPacket) -> Result<Option<Self>, url::ParseError> {
         match packet {
@@ -129,7 +179,7 @@ impl AvahiState {
 
 pub fn spawn_avahi_daemon(state: Arc<RwLock<AvahiState>>) -> JoinHandle<()> {
     std::thread::spawn(move || {
-        let child = std::process::Command::new("avahi-browse")
+        let mut child = std::process::Command::new("avahi-browse")
             .arg("--parsable")
             .arg("--resolve")
             .arg(ELGATO_SERVICE_ID)
@@ -139,6 +189,7 @@ pub fn spawn_avahi_daemon(state: Arc<RwLock<AvahiState>>) -> JoinHandle<()> {
 
         let stream = child
             .stdout
+            .take()
             .expect("Failed to get stdout of avahi-browse subprocess");
         let stream = std::io::BufReader::new(stream);
         let stream = stream.lines();
@@ -159,20 +210,231 @@ pub fn spawn_avahi_daemon(state: Arc<RwLock<AvahiState>>) -> JoinHandle<()> {
                 }
             }
         }
+
+        match child.wait() {
+            Ok(status) if !status.success() => {
+                log::error!("avahi-browse subprocess failed ({status}), see its output above")
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Failed to wait for avahi-browse subprocess: {}", err),
+        }
     })
 }
 
+/// Find the lights through Avahi D-Bus, avahi-browse or mDNS queries of our own, the first of
+/// them that runs
 pub async fn find_elgato_devices() -> Result<Vec<Device>, DiscoverError> {
-    Ok(exec_avahi_browse(ELGATO_SERVICE_ID.into())
+    Ok(browse_with_fallback(ELGATO_SERVICE_ID)
         .await?
         .into_iter()
-        .filter_map(|packet| {
-            Device::from_packet(packet).unwrap_or_else(|err| {
-                // Light started returning `fe80::3e6a:9dff:fe21:b16` instead of `192.168.0.92`
-                log::error!("Couldn't parse url: {err}");
-                None
-            })
-        })
         .unique()
         .collect::<Vec<Device>>())
 }
+
+/// Device found by [`find_elgato_devices_with`], with the result of its reachability check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub device: Device,
+    /// Round trip time of the ping, `None` if the light didn't answer
+    pub latency: Option<Duration>,
+}
+
+impl DiscoveredDevice {
+    pub fn is_reachable(&self) -> bool {
+        self.latency.is_some()
+    }
+}
+
+/// Like [`find_elgato_devices`], but pings the devices as they are found, while avahi-browse is
+/// still running, and calls `on_found` with each of them as soon as its ping is over. Returns all
+/// the devices found.
+pub async fn find_elgato_devices_with(
+    mut on_found: impl FnMut(&DiscoveredDevice),
+) -> Result<Vec<DiscoveredDevice>, DiscoverError> {
+    let mut last_err = None;
+    for backend in Backend::iter() {
+        let found = match backend {
+            Backend::AvahiBrowse => browse_and_check(&mut on_found).await,
+            backend => match backend.browse(ELGATO_SERVICE_ID).await {
+                Ok(devices) => Ok(check_all(devices.into_iter().unique(), &mut on_found).await),
+                Err(err) => Err(err),
+            },
+        };
+        match found {
+            Ok(found) => {
+                log::info!("Found {} devices through {backend}", found.len());
+                return Ok(found);
+            }
+            Err(err) => {
+                log::info!("{backend} unavailable, trying the next discovery backend: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("at least one backend"))
+}
+
+/// Run avahi-browse, pinging the devices as their lines come in
+async fn browse_and_check(
+    on_found: impl FnMut(&DiscoveredDevice),
+) -> Result<Vec<DiscoveredDevice>, DiscoverError> {
+    if find_executable("avahi-browse").is_none() {
+        return Err(DiscoverError::AvahiBrowseNotInstalled);
+    }
+
+    let mut child = tokio::process::Command::new("avahi-browse")
+        .arg(ELGATO_SERVICE_ID)
+        .arg("--parsable")
+        .arg("--resolve")
+        .arg("--terminate")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(DiscoverError::AvahiBrowseError)?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let devices = check_devices(tokio::io::BufReader::new(stdout), on_found).await?;
+
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(DiscoverError::AvahiBrowseError)?;
+    if !output.status.success() {
+        return Err(DiscoverError::AvahiBrowseFailed {
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(devices)
+}
+
+/// Ping `devices` at once, calling `on_found` as their pings are over
+async fn check_all(
+    devices: impl IntoIterator<Item = Device>,
+    mut on_found: impl FnMut(&DiscoveredDevice),
+) -> Vec<DiscoveredDevice> {
+    let mut pings: JoinSet<_> = devices.into_iter().map(check).collect();
+    let mut found = Vec::new();
+    while let Some(result) = pings.join_next().await {
+        match result {
+            Ok(discovered) => {
+                on_found(&discovered);
+                found.push(discovered);
+            }
+            Err(err) => log::error!("Ping task failed: {err}"),
+        }
+    }
+    found
+}
+
+/// Ping `device`
+async fn check(device: Device) -> DiscoveredDevice {
+    let latency = match ping(device.url.clone()).await {
+        Ok(latency) => Some(latency),
+        Err(err) => {
+            log::warn!("{} is unreachable: {err:#}", device.name);
+            None
+        }
+    };
+    DiscoveredDevice { device, latency }
+}
+
+/// Ping the devices of the avahi-browse output as their lines come in
+async fn check_devices(
+    output: impl AsyncBufRead + Unpin,
+    mut on_found: impl FnMut(&DiscoveredDevice),
+) -> Result<Vec<DiscoveredDevice>, DiscoverError> {
+    let mut lines = output.lines();
+    let mut names = std::collections::HashSet::new();
+    let mut pings = JoinSet::new();
+    let mut found = Vec::new();
+    let mut on_ping = |result: Result<DiscoveredDevice, tokio::task::JoinError>| match result {
+        Ok(discovered) => {
+            on_found(&discovered);
+            found.push(discovered);
+        }
+        Err(err) => log::error!("Ping task failed: {err}"),
+    };
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line.map_err(DiscoverError::AvahiBrowseError)? else {
+                    break;
+                };
+                let device = match MdnsPacket::try_from(line).map(Device::from_packet) {
+                    Ok(Ok(Some(device))) => device,
+                    Ok(Ok(None)) => continue,
+                    Ok(Err(err)) => {
+                        log::error!("Couldn't parse url: {err}");
+                        continue;
+                    }
+                    Err(err) => {
+                        log::error!("Failed to parse packet: {err}");
+                        continue;
+                    }
+                };
+                // Resolved once per interface and protocol
+                if names.insert(device.name.clone()) {
+                    pings.spawn(check(device));
+                }
+            }
+            Some(result) = pings.join_next() => on_ping(result),
+        }
+    }
+    while let Some(result) = pings.join_next().await {
+        on_ping(result);
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn check_devices_pings() {
+        // Answers the ping of the first light, nothing listens on the discard port for the other
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut request).await;
+            let response = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        let resolved = |name: &str, port: u16| {
+            format!("=;lo;IPv4;{name};_elg._tcp;local;{name}.local;127.0.0.1;{port};\"pv=1.0\"")
+        };
+        let output = [
+            "+;lo;IPv4;Desk;_elg._tcp;local".to_string(),
+            resolved("Desk", port),
+            resolved("Desk", port),
+            resolved("Shelf", 9),
+        ]
+        .join("\n");
+
+        let mut seen = Vec::new();
+        let found = check_devices(output.as_bytes(), |discovered| {
+            seen.push(discovered.device.name.clone())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(found.len(), 2);
+        assert_eq!(seen.len(), 2);
+        let reachable = |name: &str| {
+            found
+                .iter()
+                .find(|discovered| discovered.device.name == name)
+                .unwrap()
+                .is_reachable()
+        };
+        assert!(reachable("Desk"));
+        assert!(!reachable("Shelf"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::HashSet, time::Duration};
+
+use futures_util::StreamExt as _;
+use url::Url;
+use zbus::{zvariant::OwnedObjectPath, Connection, MatchRule, MessageStream, MessageType, Proxy};
+
+use crate::Device;
+
+const AVAHI_DESTINATION: &str = "org.freedesktop.Avahi";
+const AVAHI_SERVER_INTERFACE: &str = "org.freedesktop.Avahi.Server";
+const AVAHI_SERVICE_BROWSER_INTERFACE: &str = "org.freedesktop.Avahi.ServiceBrowser";
+
+/// `AVAHI_IF_UNSPEC` and `AVAHI_PROTO_UNSPEC`: all the interfaces, IPv4 and IPv6
+const UNSPEC: i32 = -1;
+/// `AVAHI_PROTO_INET`, the lights are reached over IPv4
+const PROTO_INET: i32 = 0;
+
+/// Time to wait for the `AllForNow` signal of the browser, after which the items found so far
+/// are used
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Instance found by the service browser: interface, protocol, name, type and domain
+type Item = (i32, i32, String, String, String);
+
+/// Reply of `ResolveService`: interface, protocol, name, type, domain, host, address protocol,
+/// address, port, TXT records and flags
+type Resolved = (
+    i32,
+    i32,
+    String,
+    String,
+    String,
+    String,
+    i32,
+    String,
+    u16,
+    Vec<Vec<u8>>,
+    u32,
+);
+
+/// Find the instances of `service_type` through the Avahi daemon of the system bus, like
+/// `avahi-browse --resolve --terminate` but without avahi-utils
+pub async fn browse(service_type: &str) -> zbus::Result<Vec<Device>> {
+    let connection = Connection::system().await?;
+    let server = Proxy::new(&connection, AVAHI_DESTINATION, "/", AVAHI_SERVER_INTERFACE).await?;
+
+    // Subscribed before creating the browser, which signals the cached items right away
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(AVAHI_SERVICE_BROWSER_INTERFACE)?
+        .build();
+    let mut signals = MessageStream::for_match_rule(rule, &connection, None).await?;
+    let browser: OwnedObjectPath = server
+        .call(
+            "ServiceBrowserNew",
+            &(UNSPEC, UNSPEC, service_type, "", 0u32),
+        )
+        .await?;
+
+    let mut items = Vec::new();
+    let browsing = async {
+        while let Some(message) = signals.next().await {
+            let message = message?;
+            let header = message.header();
+            if header.path().map(|path| path.as_str()) != Some(browser.as_str()) {
+                continue;
+            }
+            match header.member().map(|member| member.as_str()) {
+                Some("ItemNew") => {
+                    let (interface, protocol, name, kind, domain, _flags): (
+                        i32,
+                        i32,
+                        String,
+                        String,
+                        String,
+                        u32,
+                    ) = message.body().deserialize()?;
+                    items.push((interface, protocol, name, kind, domain));
+                }
+                Some("AllForNow") => break,
+                Some("Failure") => {
+                    let error: String = message.body().deserialize()?;
+                    return Err(zbus::Error::Failure(error));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+    match tokio::time::timeout(BROWSE_TIMEOUT, browsing).await {
+        Ok(browsed) => browsed?,
+        Err(_) => log::debug!(
+            "Avahi browser timed out, using the {} items found",
+            items.len()
+        ),
+    }
+
+    let mut names = HashSet::new();
+    let mut devices = Vec::new();
+    // Found once per interface and protocol
+    for item in items {
+        if !names.insert(item.2.clone()) {
+            continue;
+        }
+        match resolve(&server, item).await {
+            Ok(device) => devices.push(device),
+            Err(err) => log::warn!("Failed to resolve a service: {err}"),
+        }
+    }
+    Ok(devices)
+}
+
+async fn resolve(server: &Proxy<'_>, item: Item) -> zbus::Result<Device> {
+    let (interface, protocol, name, kind, domain) = item;
+    let resolved: Resolved = server
+        .call(
+            "ResolveService",
+            &(interface, protocol, &name, kind, domain, PROTO_INET, 0u32),
+        )
+        .await?;
+    let (address, port) = (resolved.7, resolved.8);
+    let url = Url::parse(&format!("http://{address}:{port}"))
+        .map_err(|err| zbus::Error::Failure(format!("Invalid address {address}: {err}")))?;
+    Ok(Device { name, url })
+}
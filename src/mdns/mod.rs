@@ -1,8 +1,10 @@
-use std::{convert::TryFrom, net::IpAddr, panic, str::FromStr};
-
-use regex::{Captures, Regex};
+use std::{convert::TryFrom, net::IpAddr, str::FromStr, string::FromUtf8Error};
 
 pub mod avahi;
+mod avahi_dbus;
+mod query;
+
+pub use query::parse_answer;
 
 #[derive(Debug, PartialEq, Eq, thiserror::Error)]
 pub enum PacketParseError {
@@ -10,8 +12,14 @@ pub enum PacketParseError {
     ModeParse(char),
     #[error("Failed to parse internet protocol: {0}")]
     IpTypeParse(String),
+    #[error("Invalid escape sequence: \\{0}")]
+    Escape(String),
+    #[error("Escaped name is not UTF-8: {0}")]
+    Utf8(#[from] FromUtf8Error),
     #[error("Not enough arguments")]
     NotEnoughArgs,
+    #[error("Invalid TXT data: {0}")]
+    Txt(String),
     #[error(transparent)]
     AddrParse(#[from] std::net::AddrParseError),
     #[error(transparent)]
@@ -90,15 +98,29 @@ pub struct Service {
     pub ip: IpAddr,
     /// The port the service is listening on
     pub port: u16,
-    /// All additional data
+    /// TXT records, e.g. `md=Elgato Key Light 20GAK9901`
     pub data: Vec<String>,
 }
 
+impl Service {
+    /// Value of the TXT record `key`, empty for a record without `=`
+    pub fn txt(&self, key: &str) -> Option<&str> {
+        self.data
+            .iter()
+            .find_map(|record| match record.split_once('=') {
+                Some((k, value)) if k == key => Some(value),
+                None if record == key => Some(""),
+                _ => None,
+            })
+    }
+}
+
 impl TryFrom<String> for MdnsPacket {
     type Error = PacketParseError;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        let mut iter = s.split(';');
+        // The TXT records come last and may contain `;`
+        let mut iter = s.splitn(10, ';');
 
         let mode = PacketMode::try_from(
             try_unwrap_arg(iter.next())?
@@ -111,8 +133,7 @@ impl TryFrom<String> for MdnsPacket {
 
         let internet_protocol = IpType::try_from(try_unwrap_arg(iter.next())?.to_string())?;
 
-        let mut hostname = try_unwrap_arg(iter.next())?.to_string().replace("\\.", ".");
-        hostname = parse_escaped_ascii(&hostname);
+        let hostname = parse_escaped_ascii(try_unwrap_arg(iter.next())?)?;
 
         let service_type = try_unwrap_arg(iter.next())?.to_string();
 
@@ -135,7 +156,7 @@ impl TryFrom<String> for MdnsPacket {
                     hostname: try_unwrap_arg(iter.next())?.to_string(),
                     ip: IpAddr::from_str(try_unwrap_arg(iter.next())?)?,
                     port: u16::from_str(try_unwrap_arg(iter.next())?)?,
-                    data: iter.map(|s| s.to_string()).collect(),
+                    data: iter.next().map(parse_txt).transpose()?.unwrap_or_default(),
                 },
             },
             PacketMode::Exited => Self::Exited(base),
@@ -145,15 +166,72 @@ impl TryFrom<String> for MdnsPacket {
     }
 }
 
-fn parse_escaped_ascii(s: &str) -> String {
-    let re = Regex::new(r"\\(\d{1,3})").unwrap();
-    let replacement = |caps: &Captures| -> String {
-        match caps[1].parse::<u8>() {
-            Err(_) => panic!("Couldn't parse ascii code as u8"),
-            Ok(n) => char::from_u32(n as u32).unwrap().to_string(),
+/// Decode the escapes of avahi-browse: `\DDD` for a byte, e.g. `Elgato\032Key\032Light` or
+/// `K\195\188che` for the UTF-8 of "Küche", and `\.` or `\\` for the character itself
+pub fn parse_escaped_ascii(s: &str) -> Result<String, PacketParseError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..i]);
+        let escaped = &rest[i + 1..];
+        let digits = escaped
+            .bytes()
+            .take(3)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if digits > 0 {
+            let code = &escaped[..digits];
+            let byte = code
+                .parse::<u8>()
+                .map_err(|_| PacketParseError::Escape(code.to_string()))?;
+            bytes.push(byte);
+            rest = &escaped[digits..];
+        } else {
+            let c = escaped
+                .chars()
+                .next()
+                .ok_or_else(|| PacketParseError::Escape(String::new()))?;
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            rest = &escaped[c.len_utf8()..];
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Split the TXT section of avahi-browse, space separated quoted records escaped like
+/// [`parse_escaped_ascii`], e.g. `"pv=1.0" "md=Elgato Key Light 20GAK9901"`
+pub fn parse_txt(s: &str) -> Result<Vec<String>, PacketParseError> {
+    let mut records = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let quoted = rest
+            .strip_prefix('"')
+            .ok_or_else(|| PacketParseError::Txt(rest.to_string()))?;
+        let mut escaped = false;
+        let end = quoted
+            .char_indices()
+            .find_map(|(i, c)| match c {
+                _ if escaped => {
+                    escaped = false;
+                    None
+                }
+                '\\' => {
+                    escaped = true;
+                    None
+                }
+                '"' => Some(i),
+                _ => None,
+            })
+            .ok_or_else(|| PacketParseError::Txt(rest.to_string()))?;
+        records.push(parse_escaped_ascii(&quoted[..end])?);
+        rest = &quoted[end + 1..];
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return Err(PacketParseError::Txt(rest.to_string()));
         }
-    };
-    re.replace_all(s, &replacement).to_string()
+        rest = rest.trim_start();
+    }
+    Ok(records)
 }
 
 fn try_unwrap_arg(arg: Option<&str>) -> Result<&str, PacketParseError> {
@@ -169,7 +247,25 @@ mod tests {
     #[test]
     fn parse_escaped_ascii_test() {
         let input = r#"Elgato\032Key\032Light\0328D7C"#;
-        assert_eq!(parse_escaped_ascii(input), "Elgato Key Light 8D7C");
+        assert_eq!(parse_escaped_ascii(input).unwrap(), "Elgato Key Light 8D7C");
+        assert_eq!(
+            parse_escaped_ascii(r"Key\999Light"),
+            Err(PacketParseError::Escape("999".to_string()))
+        );
+        let input = r"+;eth0;IPv4;Key\256Light;_elg._tcp;local".to_string();
+        assert!(MdnsPacket::try_from(input).is_err());
+
+        assert_eq!(parse_escaped_ascii(r"K\195\188che").unwrap(), "Küche");
+        assert_eq!(
+            parse_escaped_ascii(r"Licht\032\240\159\146\161").unwrap(),
+            "Licht 💡"
+        );
+        assert_eq!(parse_escaped_ascii(r"Key\.Light\\").unwrap(), r"Key.Light\");
+        assert!(matches!(
+            parse_escaped_ascii(r"K\195che"),
+            Err(PacketParseError::Utf8(_))
+        ));
+        assert!(parse_escaped_ascii("Key\\").is_err());
     }
 
     #[test]
@@ -187,7 +283,7 @@ mod tests {
             }))
         );
 
-        let input = r#"=;enp6s0;IPv4;Elgato\032Key\032Light\0328D7C;_elg._tcp;local;elgato-key-light-8d7c.local;192.168.0.92;9123;"pv=1.0" "md=Elgato Key Light 20GAK9901" "id=3C:6A:9D:21:B1:6E" "dt=53" "mf=Elgato"#.to_string();
+        let input = r#"=;enp6s0;IPv4;Elgato\032Key\032Light\0328D7C;_elg._tcp;local;elgato-key-light-8d7c.local;192.168.0.92;9123;"pv=1.0" "md=Elgato Key Light 20GAK9901" "id=3C:6A:9D:21:B1:6E" "dt=53" "mf=Elgato""#.to_string();
         let res = MdnsPacket::try_from(input);
         assert_eq!(
             res,
@@ -204,9 +300,51 @@ mod tests {
                     hostname: "elgato-key-light-8d7c.local".to_string(),
                     ip: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 92)),
                     port: 9123,
-                    data: vec!(r#""pv=1.0" "md=Elgato Key Light 20GAK9901" "id=3C:6A:9D:21:B1:6E" "dt=53" "mf=Elgato"#.to_string()),
+                    data: [
+                        "pv=1.0",
+                        "md=Elgato Key Light 20GAK9901",
+                        "id=3C:6A:9D:21:B1:6E",
+                        "dt=53",
+                        "mf=Elgato"
+                    ]
+                    .map(String::from)
+                    .to_vec(),
                 }
             })
         );
     }
+
+    #[test]
+    fn parse_txt_test() {
+        assert_eq!(
+            parse_txt(r#""pv=1.0" "md=Elgato Key Light 20GAK9901""#).unwrap(),
+            ["pv=1.0", "md=Elgato Key Light 20GAK9901"]
+        );
+        assert_eq!(parse_txt("").unwrap(), Vec::<String>::new());
+        // Quotes, backslashes and `;` in the values
+        assert_eq!(
+            parse_txt(r#""n=Desk \"left\"" "p=a\\b;c" "flag""#).unwrap(),
+            [r#"n=Desk "left""#, r"p=a\b;c", "flag"]
+        );
+        assert_eq!(parse_txt(r#""n=K\195\188che""#).unwrap(), ["n=Küche"]);
+        assert!(matches!(
+            parse_txt(r#""pv=1.0"#),
+            Err(PacketParseError::Txt(_))
+        ));
+        assert!(matches!(parse_txt("pv=1.0"), Err(PacketParseError::Txt(_))));
+        assert!(matches!(
+            parse_txt(r#""a""b""#),
+            Err(PacketParseError::Txt(_))
+        ));
+
+        let input = r#"=;lo;IPv4;Desk;_elg._tcp;local;desk.local;127.0.0.1;9123;"md=Elgato Light Strip" "a=1;2" "b""#;
+        let Ok(MdnsPacket::Resolved { service, .. }) = MdnsPacket::try_from(input.to_string())
+        else {
+            panic!("not resolved");
+        };
+        assert_eq!(service.txt("md"), Some("Elgato Light Strip"));
+        assert_eq!(service.txt("a"), Some("1;2"));
+        assert_eq!(service.txt("b"), Some(""));
+        assert_eq!(service.txt("id"), None);
+    }
 }
@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{net::UdpSocket, time::Instant};
+use url::Url;
+
+use crate::Device;
+
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+/// Time the answers are collected for
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Interval between two sends of the query, in case it's lost
+const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Find the instances of `service_type`, e.g. `_elg._tcp`, with mDNS queries of our own, for the
+/// machines without Avahi, e.g. a container with host networking or a minimal distro.
+/// The query is sent from an ephemeral port, to which the lights answer directly (legacy unicast),
+/// so it works next to another responder bound to port 5353.
+pub async fn browse(service_type: &str) -> std::io::Result<Vec<Device>> {
+    let service = format!("{service_type}.local");
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(255)?;
+    let query = encode_query(&service);
+
+    let mut records = Records::default();
+    let mut buf = [0; 9000];
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut resend = tokio::time::interval(RESEND_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => break,
+            _ = resend.tick() => {
+                socket.send_to(&query, MDNS_ADDR).await?;
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (len, from) = received?;
+                if records.add_message(&buf[..len]).is_none() {
+                    log::debug!("Invalid mDNS answer from {from}");
+                }
+            }
+        }
+    }
+    Ok(records.devices(&service))
+}
+
+/// Instances of `service`, e.g. `_elg._tcp.local`, announced by a single mDNS answer, `None` if
+/// it is malformed
+pub fn parse_answer(service: &str, message: &[u8]) -> Option<Vec<Device>> {
+    let mut records = Records::default();
+    records.add_message(message)?;
+    Some(records.devices(service))
+}
+
+/// Query of the instances of `service`, i.e. of its PTR records
+fn encode_query(service: &str) -> Vec<u8> {
+    // ID, flags, one question, no answer, authority nor additional record
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in service.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Records of the mDNS answers used by the discovery, by lowercase name
+#[derive(Debug, Default)]
+struct Records {
+    /// Instances of the services, from the PTR records
+    instances: HashMap<String, Vec<Vec<String>>>,
+    /// Port and host of the instances, from the SRV records
+    services: HashMap<String, (u16, String)>,
+    /// Addresses of the hosts, from the A records
+    addresses: HashMap<String, Ipv4Addr>,
+}
+
+impl Records {
+    /// Add the records of a DNS message, `None` if it is malformed
+    fn add_message(&mut self, message: &[u8]) -> Option<()> {
+        let mut reader = Reader { message, pos: 4 };
+        let questions = reader.u16()?;
+        let records = [reader.u16()?, reader.u16()?, reader.u16()?]
+            .into_iter()
+            .map(usize::from)
+            .sum::<usize>();
+        for _ in 0..questions {
+            reader.name()?;
+            reader.bytes(4)?;
+        }
+        for _ in 0..records {
+            let name = reader.name()?;
+            let kind = reader.u16()?;
+            // Class, with the cache-flush bit, and TTL
+            reader.bytes(6)?;
+            let len = usize::from(reader.u16()?);
+            let end = reader.pos + len;
+            match kind {
+                TYPE_PTR => {
+                    let instance = reader.name()?;
+                    self.instances.entry(key(&name)).or_default().push(instance);
+                }
+                TYPE_SRV => {
+                    // Priority and weight
+                    reader.bytes(4)?;
+                    let port = reader.u16()?;
+                    let host = reader.name()?;
+                    self.services.insert(key(&name), (port, key(&host)));
+                }
+                TYPE_A if len == 4 => {
+                    let address = reader.bytes(4)?;
+                    let address = Ipv4Addr::new(address[0], address[1], address[2], address[3]);
+                    self.addresses.insert(key(&name), address);
+                }
+                // TXT, AAAA and the other records
+                _ => {}
+            }
+            reader.pos = end;
+        }
+        Some(())
+    }
+
+    /// Instances of `service` whose port and address are known
+    fn devices(&self, service: &str) -> Vec<Device> {
+        let mut devices = Vec::<Device>::new();
+        for instance in self
+            .instances
+            .get(&service.to_lowercase())
+            .into_iter()
+            .flatten()
+        {
+            // A PTR record to the root name has no instance name
+            let Some(name) = instance.first() else {
+                continue;
+            };
+            let Some((port, host)) = self.services.get(&key(instance)) else {
+                continue;
+            };
+            let Some(address) = self.addresses.get(host) else {
+                continue;
+            };
+            let url = Url::parse(&format!("http://{address}:{port}")).expect("valid URL");
+            let device = Device {
+                name: name.clone(),
+                url,
+            };
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+        devices
+    }
+}
+
+/// Lookup key of a name given by its labels
+fn key(labels: &[String]) -> String {
+    labels.join(".").to_lowercase()
+}
+
+/// Cursor on a DNS message
+struct Reader<'a> {
+    message: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.message.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Labels of a name, following the compression pointers
+    fn name(&mut self) -> Option<Vec<String>> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut end = None;
+        // Bounds the pointer loops of a malicious message
+        for _ in 0..128 {
+            let len = usize::from(*self.message.get(pos)?);
+            match len {
+                0 => {
+                    self.pos = end.unwrap_or(pos + 1);
+                    return Some(labels);
+                }
+                _ if len & 0xC0 == 0xC0 => {
+                    let low = usize::from(*self.message.get(pos + 1)?);
+                    end.get_or_insert(pos + 2);
+                    pos = (len & 0x3F) << 8 | low;
+                }
+                _ => {
+                    let label = self.message.get(pos + 1..pos + 1 + len)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = encode_query(name)[12..].to_vec();
+        bytes.truncate(bytes.len() - 4);
+        bytes
+    }
+
+    fn record(name: Vec<u8>, kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name;
+        record.extend_from_slice(&kind.to_be_bytes());
+        record.extend_from_slice(&[0x80, 1, 0, 0, 0x11, 0x94]);
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    #[test]
+    fn answers() {
+        let query = encode_query("_elg._tcp.local");
+        assert_eq!(&query[12..17], b"\x04_elg");
+        assert_eq!(&query[query.len() - 4..], [0, 12, 0, 1]);
+
+        // Answer to the query with the instance, and its SRV and A records as additional records
+        let mut message = vec![0, 0, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 3];
+        message.extend_from_slice(&query[12..]);
+        // Pointer to the service name of the question
+        let mut ptr = name("Elgato Key Light 8D7C");
+        ptr.truncate(ptr.len() - 1);
+        ptr.extend_from_slice(&[0xC0, 12]);
+        message.extend(record(vec![0xC0, 12], TYPE_PTR, &ptr));
+        let mut srv = vec![0, 0, 0, 0, 0x23, 0xA3];
+        srv.extend(name("elgato-key-light-8d7c.local"));
+        message.extend(record(ptr.clone(), TYPE_SRV, &srv));
+        // TXT
+        message.extend(record(ptr, 16, b"\x06pv=1.0"));
+        message.extend(record(
+            name("Elgato-Key-Light-8D7C.local"),
+            TYPE_A,
+            &[192, 168, 0, 92],
+        ));
+
+        let mut records = Records::default();
+        assert_eq!(records.add_message(&message), Some(()));
+        assert_eq!(
+            records.devices("_elg._tcp.local"),
+            [Device {
+                name: "Elgato Key Light 8D7C".to_string(),
+                url: Url::parse("http://192.168.0.92:9123").unwrap(),
+            }]
+        );
+        assert!(records.devices("_other._tcp.local").is_empty());
+
+        assert_eq!(records.add_message(&message[..message.len() - 2]), None);
+        // Pointer to itself
+        let mut looping = vec![0; 12];
+        looping[5] = 1;
+        looping.extend_from_slice(&[0xC0, 12]);
+        assert_eq!(Records::default().add_message(&looping), None);
+    }
+
+    #[test]
+    fn root_instance() {
+        let mut message = vec![0, 0, 0x84, 0, 0, 0, 0, 3, 0, 0, 0, 0];
+        // PTR record to the root name, whose SRV and A records have the root name too
+        message.extend(record(name("_elg._tcp.local"), TYPE_PTR, &[0]));
+        message.extend(record(vec![0], TYPE_SRV, &[0, 0, 0, 0, 0x23, 0xA3, 0]));
+        message.extend(record(vec![0], TYPE_A, &[192, 168, 0, 92]));
+
+        assert_eq!(parse_answer("_elg._tcp.local", &message), Some(vec![]));
+    }
+}
@@ -0,0 +1,123 @@
+//! Node.js bindings for Stream Deck plugins and other Node tools, built with napi-rs.
+//!
+//! The functions return promises and reject with the error message on failure.
+
+use napi::{Error, Result};
+use napi_derive::napi;
+use reqwest::Url;
+
+use crate::{avahi::find_elgato_devices, Brightness, KeyLightStatus, LightIndex, Temperature};
+
+/// Light found on the network
+#[napi(object)]
+pub struct Device {
+    pub name: String,
+    /// Base URL of the API, e.g. `http://192.168.1.100:9123/`
+    pub url: String,
+}
+
+/// State of the first light of a device
+#[napi(object)]
+pub struct LightStatus {
+    pub on: bool,
+    /// Percent, from 0 to 100
+    pub brightness: u32,
+    /// Device units, from 143 (7000 K) to 344 (2900 K), unset for a light in color mode
+    pub temperature: Option<u32>,
+}
+
+impl From<&KeyLightStatus> for LightStatus {
+    fn from(status: &KeyLightStatus) -> Self {
+        LightStatus {
+            on: status.power.into(),
+            brightness: status.brightness.0.into(),
+            temperature: status.temperature.map(|temperature| temperature.0.into()),
+        }
+    }
+}
+
+impl TryFrom<LightStatus> for KeyLightStatus {
+    type Error = Error;
+
+    fn try_from(status: LightStatus) -> Result<Self> {
+        let brightness = u8::try_from(status.brightness)
+            .map_err(|err| err.to_string())
+            .and_then(Brightness::new)
+            .map_err(invalid("brightness"))?;
+        let temperature = status
+            .temperature
+            .map(|temperature| {
+                u16::try_from(temperature)
+                    .map_err(|err| err.to_string())
+                    .and_then(Temperature::new)
+            })
+            .transpose()
+            .map_err(invalid("temperature"))?;
+        Ok(KeyLightStatus {
+            power: status.on.into(),
+            brightness,
+            temperature,
+            hue: None,
+            saturation: None,
+        })
+    }
+}
+
+fn invalid(field: &'static str) -> impl Fn(String) -> Error {
+    move |err| Error::new(napi::Status::InvalidArg, format!("{field}: {err}"))
+}
+
+fn failure(err: impl std::fmt::Display) -> Error {
+    Error::from_reason(err.to_string())
+}
+
+fn parse_url(url: &str) -> Result<Url> {
+    Url::parse(url).map_err(|err| invalid("url")(err.to_string()))
+}
+
+/// Discover the lights on the network through Avahi
+#[napi]
+pub async fn discover() -> Result<Vec<Device>> {
+    let devices = find_elgato_devices().await.map_err(failure)?;
+    Ok(devices
+        .into_iter()
+        .map(|device| Device {
+            name: device.name,
+            url: device.url.to_string(),
+        })
+        .collect())
+}
+
+#[napi]
+pub async fn get_status(url: String) -> Result<LightStatus> {
+    let device = crate::get_status(parse_url(&url)?).await.map_err(failure)?;
+    device
+        .lights
+        .first()
+        .map(LightStatus::from)
+        .ok_or_else(|| failure("the device has no lights"))
+}
+
+#[napi]
+pub async fn set_status(url: String, status: LightStatus) -> Result<()> {
+    let device = crate::DeviceStatus {
+        number_of_lights: 1,
+        lights: vec![status.try_into()?],
+    };
+    crate::set_status(parse_url(&url)?, device)
+        .await
+        .map_err(failure)
+}
+
+/// Turn the light off if it is on, on otherwise, resolves to its new state
+#[napi]
+pub async fn toggle(url: String) -> Result<LightStatus> {
+    let url = parse_url(&url)?;
+    let mut device = crate::get_status(url.clone()).await.map_err(failure)?;
+    device
+        .set(LightIndex::FIRST, |light| light.power.toggle())
+        .map_err(failure)?;
+    let status = LightStatus::from(&device.lights[0]);
+    crate::set_status(url, device).await.map_err(failure)?;
+    Ok(status)
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+use zbus::{zvariant::Value, Connection, Proxy};
+
+use super::{APP_NAME, ICON};
+
+const NOTIFICATIONS_DESTINATION: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATIONS_INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// Largest side of the icon sent with the notifications, in pixels
+const ICON_SIZE: u32 = 128;
+
+/// Raw image of the `image-data` hint: width, height, rowstride, has alpha, bits per sample,
+/// channels and the pixels
+type ImageData = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+
+/// Notify through the `org.freedesktop.Notifications` service of the session bus,
+/// with the icon embedded in the message
+pub async fn notify(msg: &str) -> anyhow::Result<()> {
+    let connection = Connection::session().await?;
+    let proxy = Proxy::new(
+        &connection,
+        NOTIFICATIONS_DESTINATION,
+        NOTIFICATIONS_PATH,
+        NOTIFICATIONS_INTERFACE,
+    )
+    .await?;
+
+    let mut hints: HashMap<&str, Value> = HashMap::new();
+    match icon_image() {
+        Ok(image) => {
+            hints.insert("image-data", Value::from(image));
+        }
+        Err(err) => log::debug!("Failed to decode the icon: {err}"),
+    }
+    let actions: Vec<&str> = vec![];
+    let _id: u32 = proxy
+        .call(
+            "Notify",
+            &(
+                env!("CARGO_PKG_NAME"),
+                0u32,
+                "",
+                APP_NAME,
+                msg,
+                actions,
+                hints,
+                -1i32,
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Icon decoded and downscaled to at most [`ICON_SIZE`] pixels
+fn icon_image() -> Result<ImageData, png::DecodingError> {
+    let mut decoder = png::Decoder::new(ICON);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    let channels = info.color_type.samples() as u32;
+    if channels != 3 && channels != 4 {
+        return Err(png::DecodingError::LimitsExceeded);
+    }
+
+    // Nearest neighbour is enough for a notification icon
+    let scale = (info.width.max(info.height) as f64 / ICON_SIZE as f64).max(1.0);
+    let width = (info.width as f64 / scale) as u32;
+    let height = (info.height as f64 / scale) as u32;
+    let mut data = Vec::with_capacity((width * height * channels) as usize);
+    for y in 0..height {
+        let row = (y as f64 * scale) as usize * info.line_size;
+        for x in 0..width {
+            let start = row + (x as f64 * scale) as usize * channels as usize;
+            data.extend_from_slice(&pixels[start..start + channels as usize]);
+        }
+    }
+    Ok((
+        width as i32,
+        height as i32,
+        (width * channels) as i32,
+        channels == 4,
+        8,
+        channels as i32,
+        data,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon() {
+        let (width, height, rowstride, alpha, _, channels, data) = icon_image().unwrap();
+        assert!(width <= ICON_SIZE as i32 && height == ICON_SIZE as i32);
+        assert!(alpha);
+        assert_eq!(channels, 4);
+        assert_eq!(data.len(), (rowstride * height) as usize);
+    }
+}
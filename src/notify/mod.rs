@@ -0,0 +1,48 @@
+//! Desktop notifications with the native backend of each platform, falling back to stdout
+
+#[cfg(windows)]
+use std::{io::Write as _, path::PathBuf};
+
+use log::info;
+
+#[cfg(not(any(windows, target_os = "macos")))]
+mod freedesktop;
+#[cfg(target_os = "macos")]
+mod osascript;
+#[cfg(windows)]
+mod toast;
+
+#[cfg(not(any(windows, target_os = "macos")))]
+use freedesktop as backend;
+#[cfg(target_os = "macos")]
+use osascript as backend;
+#[cfg(windows)]
+use toast as backend;
+
+/// Title of the notifications
+const APP_NAME: &str = "Key Light Controller";
+
+#[cfg(not(target_os = "macos"))]
+const ICON: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/elgato_logo.png"
+));
+
+/// Notify the user, printing to stdout when no notification backend is available
+pub async fn notify(msg: &str) -> anyhow::Result<()> {
+    if let Err(err) = backend::notify(msg).await {
+        info!("{err}. Using stdout");
+        println!("{msg}");
+    }
+    Ok(())
+}
+
+/// Write the icon to a file, for the backends taking a path
+#[cfg(windows)]
+fn inject_icon() -> std::io::Result<PathBuf> {
+    let path = std::env::temp_dir().join("elgato_logo.png");
+    let mut file = std::fs::File::create(&path)?;
+    file.write_all(ICON)?;
+    file.flush()?;
+    Ok(path)
+}
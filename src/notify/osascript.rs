@@ -0,0 +1,29 @@
+use anyhow::Context as _;
+use tokio::process::Command;
+
+use super::APP_NAME;
+
+/// AppleScript showing a banner, the message and title are passed as arguments to avoid quoting
+const SCRIPT: [&str; 3] = [
+    "on run argv",
+    "display notification (item 1 of argv) with title (item 2 of argv)",
+    "end run",
+];
+
+/// Notify with a Notification Center banner through `osascript`
+pub async fn notify(msg: &str) -> anyhow::Result<()> {
+    let output = Command::new("osascript")
+        .args(SCRIPT.iter().flat_map(|line| ["-e", line]))
+        .arg(msg)
+        .arg(APP_NAME)
+        .output()
+        .await
+        .context("`osascript` failed")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "`osascript` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
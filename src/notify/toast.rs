@@ -0,0 +1,19 @@
+use anyhow::Context as _;
+use tauri_winrt_notification::{IconCrop, Toast};
+
+use super::{inject_icon, APP_NAME};
+
+/// Notify with a WinRT toast, shown on behalf of PowerShell since the app is not registered
+pub async fn notify(msg: &str) -> anyhow::Result<()> {
+    let msg = msg.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut toast = Toast::new(Toast::POWERSHELL_APP_ID)
+            .title(APP_NAME)
+            .text1(&msg);
+        if let Ok(icon) = inject_icon() {
+            toast = toast.icon(&icon, IconCrop::Square, APP_NAME);
+        }
+        toast.show().context("Toast notification failed")
+    })
+    .await?
+}
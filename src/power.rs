@@ -0,0 +1,153 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::{KeyLightStatus, PowerStatus};
+
+/// Estimated power draw of each product, as reported by `productName` in the accessory info,
+/// at a few brightness levels: `(brightness %, watts)`. Values in between are interpolated.
+///
+/// Rough figures from the rated power of each product, not measurements of a given unit.
+const POWER_CURVES: &[(&str, &[(u8, f64)])] = &[
+    (
+        "Elgato Key Light",
+        &[(0, 1.0), (25, 9.0), (50, 18.5), (75, 29.0), (100, 41.0)],
+    ),
+    (
+        "Elgato Key Light Air",
+        &[(0, 0.8), (25, 6.0), (50, 12.0), (75, 18.0), (100, 25.0)],
+    ),
+    (
+        "Elgato Key Light Mini",
+        &[(0, 0.5), (25, 3.0), (50, 6.0), (75, 9.0), (100, 12.0)],
+    ),
+    (
+        "Elgato Light Strip",
+        &[(0, 0.8), (25, 5.0), (50, 9.5), (75, 14.0), (100, 19.0)],
+    ),
+    (
+        "Elgato Ring Light",
+        &[(0, 1.0), (25, 8.5), (50, 17.0), (75, 26.0), (100, 36.0)],
+    ),
+];
+
+/// Draw of a light turned off but still connected to the network, in watts
+const STANDBY_WATTS: f64 = 0.4;
+
+/// Estimated power draw of a light in watts, `None` for unknown products
+pub fn estimated_watts(product_name: &str, status: &KeyLightStatus) -> Option<f64> {
+    let (_, curve) = POWER_CURVES
+        .iter()
+        .find(|(name, _)| *name == product_name)?;
+    if status.power == PowerStatus::Off {
+        return Some(STANDBY_WATTS);
+    }
+    let brightness = status.brightness.0;
+    let watts = curve
+        .windows(2)
+        .find(|points| brightness <= points[1].0)
+        .map(|points| {
+            let ((b0, w0), (b1, w1)) = (points[0], points[1]);
+            let t = f64::from(brightness - b0) / f64::from(b1 - b0);
+            w0 + t * (w1 - w0)
+        })
+        .unwrap_or_else(|| curve.last().map_or(0.0, |(_, watts)| *watts));
+    Some(watts)
+}
+
+/// Cumulative energy used by a light, integrated between its state changes
+#[derive(Debug, Clone)]
+pub struct EnergyMeter {
+    /// Current draw in watts
+    watts: f64,
+    /// Energy used until `last`
+    watt_hours: f64,
+    last: Instant,
+    since: DateTime<Utc>,
+}
+
+impl EnergyMeter {
+    pub fn new(watts: f64) -> Self {
+        EnergyMeter {
+            watts,
+            watt_hours: 0.0,
+            last: Instant::now(),
+            since: Utc::now(),
+        }
+    }
+
+    /// Account for the energy used at the previous draw and switch to `watts`
+    pub fn set_watts(&mut self, watts: f64) {
+        self.watt_hours = self.watt_hours();
+        self.last = Instant::now();
+        self.watts = watts;
+    }
+
+    pub fn watts(&self) -> f64 {
+        self.watts
+    }
+
+    /// Energy used since the meter started
+    pub fn watt_hours(&self) -> f64 {
+        self.watt_hours + self.watts * self.last.elapsed().as_secs_f64() / 3600.0
+    }
+
+    /// When the meter started
+    pub fn since(&self) -> DateTime<Utc> {
+        self.since
+    }
+}
+
+/// Estimated power usage of a light, reported by the daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "daemon", derive(utoipa::ToSchema))]
+pub struct PowerStats {
+    pub device: String,
+    /// Product name from the accessory info, once the device answered
+    pub product: Option<String>,
+    /// Current draw, unknown for unsupported products
+    pub watts: Option<f64>,
+    /// Energy used since `since`
+    pub watt_hours: Option<f64>,
+    #[cfg_attr(feature = "daemon", schema(value_type = Option<String>, format = DateTime))]
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Brightness, Temperature};
+
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let status = |power, brightness| {
+            KeyLightStatus::white(
+                power,
+                Brightness::new(brightness).unwrap(),
+                Temperature::new(200).unwrap(),
+            )
+        };
+        let watts = |status| estimated_watts("Elgato Key Light", &status).unwrap();
+        assert_eq!(watts(status(PowerStatus::On, 100)), 41.0);
+        assert_eq!(watts(status(PowerStatus::On, 50)), 18.5);
+        assert!((watts(status(PowerStatus::On, 60)) - 22.7).abs() < 1e-9);
+        assert_eq!(watts(status(PowerStatus::On, 0)), 1.0);
+        assert_eq!(watts(status(PowerStatus::Off, 100)), STANDBY_WATTS);
+        assert_eq!(
+            estimated_watts("Unknown", &status(PowerStatus::On, 50)),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn meter() {
+        let mut meter = EnergyMeter::new(40.0);
+        tokio::time::advance(std::time::Duration::from_secs(1800)).await;
+        meter.set_watts(10.0);
+        assert_eq!(meter.watt_hours(), 20.0);
+        tokio::time::advance(std::time::Duration::from_secs(3600)).await;
+        assert_eq!(meter.watt_hours(), 30.0);
+        assert_eq!(meter.watts(), 10.0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use reqwest::Url;
+use tokio::time::{sleep_until, Instant};
+
+use crate::{put_status, set_status_with, DeviceStatus, RequestOptions};
+
+/// Queue of [`crate::set_status`], shared by the whole process
+static SEND_QUEUE: OnceLock<SendQueue> = OnceLock::new();
+
+/// Sends the state of the devices one request at a time per device, latest wins.
+///
+/// A state set while the previous one is still in flight waits for it, and is not sent if a newer
+/// state is set meanwhile, so continuous changes (fades, ambient light) never stack requests.
+/// The sends of a device can also be spaced by a minimum interval, the states set while waiting
+/// for it collapse the same way.
+#[derive(Debug, Default)]
+pub struct SendQueue {
+    interval: Duration,
+    slots: Mutex<HashMap<Url, Arc<Slot>>>,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// Number of states set, the newest state has the last ticket
+    tickets: AtomicU64,
+    /// Held while a state is sent, with the time the last one was
+    turn: Arc<tokio::sync::Mutex<Option<Instant>>>,
+}
+
+/// Outcome of a send through a [`SendQueue`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sent<T> {
+    /// With the response of the device
+    Sent(T),
+    /// A later state replaced this one before it was sent
+    Superseded,
+}
+
+impl SendQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue spacing the sends of each device by at least `interval`
+    pub fn with_interval(interval: Duration) -> Self {
+        SendQueue {
+            interval,
+            ..Self::default()
+        }
+    }
+
+    /// Queue used by [`crate::set_status`]
+    pub fn global() -> &'static SendQueue {
+        SEND_QUEUE.get_or_init(SendQueue::new)
+    }
+
+    /// Set the state of the device once its turn comes, see [`set_status_with`]
+    pub async fn set_status(
+        &self,
+        url: Url,
+        status: DeviceStatus,
+        options: &RequestOptions,
+    ) -> anyhow::Result<Sent<()>> {
+        self.send(&url, || set_status_with(url.clone(), status, options))
+            .await
+    }
+
+    /// Set the state of the device once its turn comes, with the state it reports back
+    pub async fn put_status(
+        &self,
+        url: Url,
+        status: &DeviceStatus,
+    ) -> anyhow::Result<Sent<DeviceStatus>> {
+        self.send(&url, || put_status(url.clone(), status)).await
+    }
+
+    /// Run `send` once the previous send to `url` is done and the interval is over, unless a
+    /// newer send is waiting by then
+    async fn send<T, F, Fut>(&self, url: &Url, send: F) -> anyhow::Result<Sent<T>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let slot = Arc::clone(
+            self.slots
+                .lock()
+                .expect("lock poisoned")
+                .entry(url.clone())
+                .or_default(),
+        );
+        let ticket = slot.tickets.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut last_sent = slot.turn.lock().await;
+        if let Some(last_sent) = *last_sent {
+            sleep_until(last_sent + self.interval).await;
+        }
+        if slot.tickets.load(Ordering::SeqCst) > ticket {
+            return Ok(Sent::Superseded);
+        }
+        *last_sent = Some(Instant::now());
+        send().await.map(Sent::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn latest_wins() {
+        let url: Url = "http://127.0.0.1:9".parse().unwrap();
+        let queue = Arc::new(SendQueue::with_interval(Duration::from_millis(500)));
+        let start = Instant::now();
+        let send = |value| {
+            let queue = Arc::clone(&queue);
+            let url = url.clone();
+            tokio::spawn(async move {
+                queue
+                    .send(&url, || async move { Ok((value, start.elapsed())) })
+                    .await
+                    .unwrap()
+            })
+        };
+
+        let first = send(1).await.unwrap();
+        // Set while waiting for the interval after the first
+        let waiting: Vec<_> = (2..=4).map(send).collect();
+        let mut sent = vec![first];
+        for send in waiting {
+            sent.push(send.await.unwrap());
+        }
+        assert_eq!(
+            sent,
+            [
+                Sent::Sent((1, Duration::ZERO)),
+                Sent::Superseded,
+                Sent::Superseded,
+                Sent::Sent((4, Duration::from_millis(500))),
+            ]
+        );
+
+        // Other devices are not spaced
+        let other: Url = "http://127.0.0.2:9".parse().unwrap();
+        let sent = queue
+            .send(&other, || async { Ok(start.elapsed()) })
+            .await
+            .unwrap();
+        assert_eq!(sent, Sent::Sent(Duration::from_millis(500)));
+    }
+
+    #[tokio::test]
+    async fn global() {
+        // Nothing listens on the port, not shared with the other tests using the global queue
+        let url: Url = "http://127.0.0.1:1".parse().unwrap();
+        let status = DeviceStatus {
+            number_of_lights: 0,
+            lights: vec![],
+        };
+        let queue = SendQueue::global();
+        let options = RequestOptions::default();
+        // The second waits for the first, and is replaced by the third meanwhile
+        let (first, second, third) = tokio::join!(
+            queue.set_status(url.clone(), status.clone(), &options),
+            queue.set_status(url.clone(), status.clone(), &options),
+            queue.set_status(url, status, &options),
+        );
+        assert!(first.is_err());
+        assert_eq!(second.unwrap(), Sent::Superseded);
+        assert!(third.is_err());
+    }
+}
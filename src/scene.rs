@@ -0,0 +1,702 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+#[cfg(feature = "network")]
+use crate::{get_status, set_status, Device, LightIndex};
+use crate::{
+    Brightness, Config, ConfigError, Kelvin, KeyLightStatus, LightUpdate, PowerStatus, Temperature,
+};
+
+const SCENES_DIR_NAME: &str = "scenes";
+
+/// Interval between two frames of a fade
+const FRAME: Duration = Duration::from_millis(250);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SceneError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("Scene not found: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error("Invalid scene file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error(transparent)]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Invalid scene {scene}, target {target}: {message}")]
+    Invalid {
+        scene: String,
+        /// 1-based index of the target in the file
+        target: usize,
+        message: String,
+    },
+    #[error("Failed to set {device}: {source}")]
+    Device {
+        device: String,
+        source: anyhow::Error,
+    },
+}
+
+/// Scene stored at `$XDG_CONFIG_HOME/elgato-keylight/scenes/<name>.toml`: a list of targets,
+/// each one fading some lights to a state after an optional delay
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    /// File name without the extension
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// `[[targets]]` tables
+    pub targets: Vec<SceneTarget>,
+}
+
+/// Lights of a scene and their state, inline settings override the ones of the preset
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SceneTarget {
+    /// Lights to control, all of them if empty and there is no room
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    /// Room whose lights are controlled, see [`Config::rooms`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<PowerStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<Brightness>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<Temperature>,
+    /// Color temperature in kelvin, instead of `temperature`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kelvin: Option<Kelvin>,
+    /// Seconds to fade from the current state, instantaneous if 0
+    pub fade: f64,
+    /// Seconds to wait from the start of the scene
+    pub delay: f64,
+}
+
+/// Change of a single light, resolved from a [`SceneTarget`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneStep {
+    pub device: String,
+    pub update: LightUpdate,
+    pub delay: Duration,
+    pub fade: Duration,
+}
+
+/// Lights a scene is played on
+pub trait SceneLights {
+    fn status(&self, device: &str) -> impl Future<Output = anyhow::Result<KeyLightStatus>> + Send;
+
+    fn set(
+        &self,
+        device: &str,
+        status: KeyLightStatus,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Lights reached directly, without the daemon
+#[cfg(feature = "network")]
+impl SceneLights for [Device] {
+    async fn status(&self, device: &str) -> anyhow::Result<KeyLightStatus> {
+        let device = find_device(self, device)?;
+        let status = get_status(device.url.clone()).await?;
+        status
+            .lights
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Device {} has no lights", device.name))
+    }
+
+    async fn set(&self, device: &str, light: KeyLightStatus) -> anyhow::Result<()> {
+        let device = find_device(self, device)?;
+        let mut status = get_status(device.url.clone()).await?;
+        status.set(LightIndex::FIRST, |current| *current = light)?;
+        set_status(device.url.clone(), status).await
+    }
+}
+
+#[cfg(feature = "network")]
+fn find_device<'a>(devices: &'a [Device], name: &str) -> anyhow::Result<&'a Device> {
+    devices
+        .iter()
+        .find(|device| device.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Device not found: {name}"))
+}
+
+impl Scene {
+    /// Directory of the scene files
+    pub fn dir() -> Result<PathBuf, SceneError> {
+        Ok(Config::dir()?.join(SCENES_DIR_NAME))
+    }
+
+    /// Names of the scenes in [`Scene::dir`], sorted
+    pub fn list() -> Result<Vec<String>, SceneError> {
+        let entries = match std::fs::read_dir(Self::dir()?) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Scene setting `devices`, all the lights if empty, to the preset `from` at once, then fading
+    /// them to the preset `to` over `duration`. The values set by `from` only are kept, the ones
+    /// set by `to` only fade from the current state of the lights.
+    pub fn crossfade(from: &str, to: &str, devices: Vec<String>, duration: Duration) -> Self {
+        let target = |preset: &str, fade: Duration| SceneTarget {
+            devices: devices.clone(),
+            preset: Some(preset.to_string()),
+            fade: fade.as_secs_f64(),
+            ..Default::default()
+        };
+        Scene {
+            name: format!("{from} to {to}"),
+            description: None,
+            // Played in order: the lights are set to `from` before their fade starts
+            targets: vec![target(from, Duration::ZERO), target(to, duration)],
+        }
+    }
+
+    /// Load the scene `name` from [`Scene::dir`]
+    pub fn load(name: &str) -> Result<Self, SceneError> {
+        let path = Self::dir()?.join(format!("{name}.toml"));
+        if !path.exists() {
+            return Err(SceneError::NotFound(name.to_string()));
+        }
+        Self::load_from(&path)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, SceneError> {
+        let content = std::fs::read_to_string(path)?;
+        let mut scene: Scene = toml::from_str(&content).map_err(|source| SceneError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        scene.name = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(scene)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), SceneError> {
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Check the targets against the presets and rooms of `config`
+    pub fn validate(&self, config: &Config) -> Result<(), SceneError> {
+        let invalid = |target: usize, message: String| SceneError::Invalid {
+            scene: self.name.clone(),
+            target: target + 1,
+            message,
+        };
+        if self.targets.is_empty() {
+            return Err(invalid(
+                0,
+                "a scene needs at least one [[targets]]".to_string(),
+            ));
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            if target.room.is_some() && !target.devices.is_empty() {
+                return Err(invalid(i, "set either `room` or `devices`".to_string()));
+            }
+            if let Some(room) = &target.room {
+                if !config.rooms.contains_key(room) {
+                    return Err(invalid(i, format!("unknown room `{room}`")));
+                }
+            }
+            if let Some(preset) = &target.preset {
+                if !config.presets.contains_key(preset) {
+                    return Err(invalid(i, format!("unknown preset `{preset}`")));
+                }
+            }
+            if target.preset.is_none()
+                && target.on.is_none()
+                && target.brightness.is_none()
+                && target.temperature.is_none()
+                && target.kelvin.is_none()
+            {
+                return Err(invalid(
+                    i,
+                    "set a `preset` or at least one of `on`, `brightness`, `temperature` and \
+                     `kelvin`"
+                        .to_string(),
+                ));
+            }
+            for (field, value) in [("fade", target.fade), ("delay", target.delay)] {
+                if !value.is_finite() || value < 0.0 {
+                    return Err(invalid(
+                        i,
+                        format!("`{field}` must be a positive number of seconds"),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the targets into one step per light, with its calibration offsets and brightness
+    /// cap. `devices` are all the known lights
+    pub fn steps(&self, config: &Config, devices: &[String]) -> Result<Vec<SceneStep>, SceneError> {
+        self.validate(config)?;
+        let mut steps = Vec::new();
+        for target in &self.targets {
+            let mut update = target
+                .preset
+                .as_ref()
+                .and_then(|preset| config.presets.get(preset))
+                .cloned()
+                .unwrap_or_default();
+            update.power = target.on.or(update.power);
+            update.brightness = target.brightness.or(update.brightness);
+            if target.temperature.is_some() || target.kelvin.is_some() {
+                update.temperature = target.temperature;
+                update.kelvin = target.kelvin;
+            }
+
+            let names = match &target.room {
+                Some(room) => config.rooms[room].clone(),
+                None if target.devices.is_empty() => devices.to_vec(),
+                None => target.devices.clone(),
+            };
+            steps.extend(names.into_iter().map(|device| {
+                let mut update = config.calibrated(&device, &update);
+                update.brightness = update
+                    .brightness
+                    .map(|brightness| config.limits.cap_brightness(&device, brightness));
+                SceneStep {
+                    device,
+                    update,
+                    delay: Duration::from_secs_f64(target.delay),
+                    fade: Duration::from_secs_f64(target.fade),
+                }
+            }));
+        }
+        Ok(steps)
+    }
+}
+
+/// Timeline of the changes of the lights, saved as a scene whose targets are delayed by the time
+/// of the change so that [`play`] repeats them with the same timing
+#[derive(Debug, Default)]
+pub struct Recording {
+    last: HashMap<String, KeyLightStatus>,
+    targets: Vec<SceneTarget>,
+}
+
+impl Recording {
+    /// Record the state of `device` seen `elapsed` after the start, returns whether it changed
+    pub fn observe(&mut self, device: &str, status: &KeyLightStatus, elapsed: Duration) -> bool {
+        if self.last.get(device) == Some(status) {
+            return false;
+        }
+        self.last.insert(device.to_string(), status.clone());
+        self.targets.push(SceneTarget {
+            devices: vec![device.to_string()],
+            on: Some(status.power),
+            brightness: Some(status.brightness),
+            temperature: status.temperature,
+            // Milliseconds are enough for lights polled a few times per second
+            delay: elapsed.as_millis() as f64 / 1000.0,
+            ..Default::default()
+        });
+        true
+    }
+
+    /// Number of changes recorded, including the initial state of each light
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    pub fn into_scene(self, description: Option<String>) -> Scene {
+        Scene {
+            name: String::new(),
+            description,
+            targets: self.targets,
+        }
+    }
+}
+
+/// Light being faded by [`play`]
+struct Fading {
+    from: KeyLightStatus,
+    to: KeyLightStatus,
+}
+
+/// Play `steps` on `lights`, fading all the lights at the same time. A failing light doesn't
+/// stop the others, the first failure is returned.
+pub async fn play<L: SceneLights + ?Sized>(
+    steps: &[SceneStep],
+    lights: &L,
+) -> Result<(), SceneError> {
+    let start = Instant::now();
+    let mut fading: Vec<Option<Fading>> = steps.iter().map(|_| None).collect();
+    let mut done = vec![false; steps.len()];
+    let mut failure = None;
+
+    while done.iter().any(|done| !done) {
+        let elapsed = start.elapsed();
+        for (i, step) in steps.iter().enumerate() {
+            if done[i] || elapsed < step.delay {
+                continue;
+            }
+            let result = frame(step, &mut fading[i], elapsed - step.delay, lights).await;
+            match result {
+                Ok(finished) => done[i] = finished,
+                Err(err) => {
+                    log::warn!("Scene failed on {}: {err}", step.device);
+                    done[i] = true;
+                    failure.get_or_insert(SceneError::Device {
+                        device: step.device.clone(),
+                        source: err,
+                    });
+                }
+            }
+        }
+        // Wake up for the next frame, or earlier for a step starting before it
+        let elapsed = start.elapsed();
+        let next = steps
+            .iter()
+            .zip(&done)
+            .filter(|(step, done)| !**done && step.delay > elapsed)
+            .map(|(step, _)| step.delay - elapsed)
+            .min()
+            .map_or(FRAME, |next| next.min(FRAME));
+        if done.iter().any(|done| !done) {
+            tokio::time::sleep(next).await;
+        }
+    }
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Set the state of the light `elapsed` after the start of `step`, returns whether it is done
+async fn frame<L: SceneLights + ?Sized>(
+    step: &SceneStep,
+    fading: &mut Option<Fading>,
+    elapsed: Duration,
+    lights: &L,
+) -> anyhow::Result<bool> {
+    let fading = match fading {
+        Some(fading) => fading,
+        None => {
+            let from = lights.status(&step.device).await?;
+            let mut to = from.clone();
+            step.update.apply(&mut to);
+            fading.insert(Fading { from, to })
+        }
+    };
+    if elapsed >= step.fade {
+        lights.set(&step.device, fading.to.clone()).await?;
+        return Ok(true);
+    }
+    lights
+        .set(
+            &step.device,
+            interpolate(&fading.from, &fading.to, elapsed, step.fade),
+        )
+        .await?;
+    Ok(false)
+}
+
+/// State `elapsed` into a fade of `duration` from `from` to `to`. The light stays on while
+/// fading out and is turned on as soon as it fades in.
+pub fn interpolate(
+    from: &KeyLightStatus,
+    to: &KeyLightStatus,
+    elapsed: Duration,
+    duration: Duration,
+) -> KeyLightStatus {
+    let t = (elapsed.as_secs_f64() / duration.as_secs_f64()).clamp(0.0, 1.0);
+    if t >= 1.0 {
+        return to.clone();
+    }
+    let lerp = |from: f64, to: f64| (from + t * (to - from)).round();
+    let brightness = lerp(from.brightness.0.into(), to.brightness.0.into()) as u8;
+    let temperature = from
+        .temperature
+        .zip(to.temperature)
+        .map(|(from, to)| lerp(from.0.into(), to.0.into()) as u16)
+        .and_then(|temperature| Temperature::new(temperature).ok());
+    KeyLightStatus {
+        power: if to.power == PowerStatus::On {
+            PowerStatus::On
+        } else {
+            from.power
+        },
+        brightness: Brightness::new(brightness).unwrap_or(to.brightness),
+        temperature: temperature.or(to.temperature),
+        ..to.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use super::*;
+
+    fn light(power: PowerStatus, brightness: u8) -> KeyLightStatus {
+        KeyLightStatus::white(
+            power,
+            Brightness::new(brightness).unwrap(),
+            Temperature::new(200).unwrap(),
+        )
+    }
+
+    fn config() -> Config {
+        Config {
+            presets: BTreeMap::from([(
+                "meeting".to_string(),
+                LightUpdate {
+                    power: Some(PowerStatus::On),
+                    brightness: Some(Brightness::new(40).unwrap()),
+                    ..Default::default()
+                },
+            )]),
+            rooms: BTreeMap::from([("Studio".to_string(), vec!["Left".to_string()])]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("streaming.toml");
+        std::fs::write(
+            &path,
+            r#"
+            description = "Go live"
+
+            [[targets]]
+            room = "Studio"
+            preset = "meeting"
+            brightness = 60
+            fade = 1.5
+
+            [[targets]]
+            devices = ["Right"]
+            on = 0
+            delay = 2
+            "#,
+        )
+        .unwrap();
+        let scene = Scene::load_from(&path).unwrap();
+        assert_eq!(scene.name, "streaming");
+        let steps = scene
+            .steps(&config(), &["Left".to_string(), "Right".to_string()])
+            .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                SceneStep {
+                    device: "Left".to_string(),
+                    update: LightUpdate {
+                        power: Some(PowerStatus::On),
+                        brightness: Some(Brightness::new(60).unwrap()),
+                        ..Default::default()
+                    },
+                    delay: Duration::ZERO,
+                    fade: Duration::from_millis(1500),
+                },
+                SceneStep {
+                    device: "Right".to_string(),
+                    update: LightUpdate {
+                        power: Some(PowerStatus::Off),
+                        ..Default::default()
+                    },
+                    delay: Duration::from_secs(2),
+                    fade: Duration::ZERO,
+                },
+            ]
+        );
+
+        std::fs::write(&path, "[[targets]]\nbrightnes = 60").unwrap();
+        let err = Scene::load_from(&path).unwrap_err().to_string();
+        assert!(err.contains("unknown field `brightnes`"), "{err}");
+
+        let scene = Scene {
+            name: "broken".to_string(),
+            targets: vec![
+                SceneTarget {
+                    preset: Some("meeting".to_string()),
+                    ..Default::default()
+                },
+                SceneTarget {
+                    preset: Some("party".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            scene.validate(&config()).unwrap_err().to_string(),
+            "Invalid scene broken, target 2: unknown preset `party`"
+        );
+        let scene = Scene {
+            targets: vec![SceneTarget {
+                on: Some(PowerStatus::On),
+                fade: -1.0,
+                ..Default::default()
+            }],
+            ..scene
+        };
+        assert!(scene.validate(&config()).is_err());
+    }
+
+    #[test]
+    fn crossfade() {
+        let mut config = config();
+        config.presets.insert(
+            "evening".to_string(),
+            LightUpdate {
+                kelvin: Some(Kelvin(3000)),
+                ..Default::default()
+            },
+        );
+        let duration = Duration::from_secs(10);
+        let scene = Scene::crossfade("meeting", "evening", vec![], duration);
+        let steps = scene
+            .steps(&config, &["Left".to_string(), "Right".to_string()])
+            .unwrap();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].update, config.presets["meeting"]);
+        assert_eq!(steps[0].fade, Duration::ZERO);
+        assert_eq!(steps[3].device, "Right");
+        assert_eq!(steps[3].update, config.presets["evening"]);
+        assert_eq!(steps[3].fade, duration);
+
+        let scene = Scene::crossfade("meeting", "unknown", vec!["Left".to_string()], duration);
+        assert!(scene.steps(&config, &[]).is_err());
+    }
+
+    #[test]
+    fn record() {
+        let mut recording = Recording::default();
+        let second = Duration::from_secs(1);
+        assert!(recording.observe("Left", &light(PowerStatus::On, 10), Duration::ZERO));
+        assert!(recording.observe("Right", &light(PowerStatus::Off, 10), Duration::ZERO));
+        assert!(!recording.observe("Left", &light(PowerStatus::On, 10), second));
+        assert!(recording.observe("Left", &light(PowerStatus::On, 50), 2 * second));
+        assert_eq!(recording.len(), 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo.toml");
+        recording
+            .into_scene(Some("Demo".to_string()))
+            .save_to(&path)
+            .unwrap();
+        let scene = Scene::load_from(&path).unwrap();
+        let steps = scene.steps(&Config::default(), &[]).unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2].device, "Left");
+        assert_eq!(steps[2].delay, 2 * second);
+        assert_eq!(
+            steps[2].update.brightness,
+            Some(Brightness::new(50).unwrap())
+        );
+        assert_eq!(steps[2].fade, Duration::ZERO);
+    }
+
+    #[test]
+    fn interpolation() {
+        let from = light(PowerStatus::Off, 10);
+        let to = light(PowerStatus::On, 50);
+        let second = Duration::from_secs(1);
+        assert_eq!(
+            interpolate(&from, &to, Duration::from_millis(500), second),
+            light(PowerStatus::On, 30)
+        );
+        assert_eq!(interpolate(&from, &to, second, second), to);
+
+        // Fading out keeps the light on until the end
+        let from = light(PowerStatus::On, 50);
+        let to = light(PowerStatus::Off, 10);
+        assert_eq!(
+            interpolate(&from, &to, Duration::from_millis(500), second),
+            light(PowerStatus::On, 30)
+        );
+    }
+
+    /// Lights recording the states they are set to
+    struct Recorder(Mutex<Vec<(String, KeyLightStatus)>>);
+
+    impl SceneLights for Recorder {
+        async fn status(&self, _device: &str) -> anyhow::Result<KeyLightStatus> {
+            Ok(light(PowerStatus::Off, 10))
+        }
+
+        async fn set(&self, device: &str, status: KeyLightStatus) -> anyhow::Result<()> {
+            if device == "Broken" {
+                anyhow::bail!("unreachable");
+            }
+            self.0.lock().unwrap().push((device.to_string(), status));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn play_steps() {
+        let step = |device: &str, delay, fade| SceneStep {
+            device: device.to_string(),
+            update: LightUpdate {
+                power: Some(PowerStatus::On),
+                brightness: Some(Brightness::new(50).unwrap()),
+                ..Default::default()
+            },
+            delay: Duration::from_secs(delay),
+            fade: Duration::from_secs(fade),
+        };
+        let lights = Recorder(Mutex::new(vec![]));
+        let steps = [
+            step("Left", 0, 1),
+            step("Right", 1, 0),
+            step("Broken", 0, 0),
+        ];
+        let err = play(&steps, &lights).await.unwrap_err();
+        assert!(matches!(err, SceneError::Device { device, .. } if device == "Broken"));
+
+        let set = lights.0.into_inner().unwrap();
+        let left: Vec<_> = set.iter().filter(|(device, _)| device == "Left").collect();
+        assert_eq!(left.len(), 5);
+        assert_eq!(left[0].1, light(PowerStatus::On, 10));
+        assert_eq!(left[2].1, light(PowerStatus::On, 30));
+        assert_eq!(left[4].1, light(PowerStatus::On, 50));
+        assert_eq!(
+            set.iter().filter(|(device, _)| device == "Right").count(),
+            1
+        );
+    }
+}
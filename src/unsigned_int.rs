@@ -2,10 +2,19 @@ use std::{num::ParseIntError, str::FromStr};
 
 use serde::{de::Error, Deserialize, Serialize};
 
-pub type Brightness = UnsignedInt<u8, 0, 100>;
+/// Percentage from 0 to 100: brightness, progress of a transition, battery level...
+pub type Percent = UnsignedInt<u8, 0, 100>;
+
+pub type Brightness = Percent;
+
+/// Brightness the Key Lights accept, they refuse anything below 3%
+pub type KeyLightBrightness = UnsignedInt<u8, 3, 100>;
 
 pub type Temperature = UnsignedInt<u16, 143, 344>;
 
+/// Hue of the color lights in degrees
+pub type Hue = UnsignedInt<u16, 0, 360>;
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 #[serde(transparent)]
 pub struct UnsignedInt<I, const S: usize, const E: usize>(pub I);
@@ -20,6 +29,185 @@ impl<const S: usize, const E: usize, I: std::fmt::Debug + Copy + PartialEq + Int
         }
         Ok(UnsignedInt(i))
     }
+
+    /// Inner value, e.g. the percentage of a [`Brightness`]
+    pub fn value(self) -> I {
+        self.0
+    }
+}
+
+/// Bounds and clamping, for the integer types the bounds are known to fit in
+macro_rules! bounded {
+    ($($int:ty),*) => {$(
+        impl<const S: usize, const E: usize> UnsignedInt<$int, S, E> {
+            pub const MIN: Self = UnsignedInt(S as $int);
+            pub const MAX: Self = UnsignedInt(E as $int);
+
+            /// Clamp `i` to the nearest bound instead of failing like [`Self::new`]
+            pub fn new_clamped(i: $int) -> Self {
+                UnsignedInt(i.clamp(Self::MIN.0, Self::MAX.0))
+            }
+
+            /// Add a signed `delta`, clamped to the bounds
+            pub fn offset(self, delta: i32) -> Self {
+                let value = (i64::from(self.0) + i64::from(delta)).clamp(S as i64, E as i64);
+                UnsignedInt(value as $int)
+            }
+        }
+    )*};
+}
+
+bounded!(u8, u16);
+
+/// Signed change of a [`Brightness`] in percentage points, clamped to its range when applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrightnessDelta(pub i32);
+
+impl BrightnessDelta {
+    /// Step of the brightness up/down commands
+    pub const STEP: Self = BrightnessDelta(10);
+
+    /// Applied to a [`KeyLightBrightness`], never goes below the lowest brightness of the Key Lights
+    pub fn apply<const S: usize, const E: usize>(self, brightness: &mut UnsignedInt<u8, S, E>) {
+        *brightness = brightness.offset(self.0);
+    }
+}
+
+impl std::ops::Neg for BrightnessDelta {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        BrightnessDelta(-self.0)
+    }
+}
+
+/// Signed change of a [`Temperature`] in device units, positive is warmer,
+/// clamped to its range when applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TemperatureDelta(pub i32);
+
+impl TemperatureDelta {
+    /// Step of the warmer/cooler commands
+    pub const STEP: Self = TemperatureDelta(20);
+
+    pub fn apply(self, temperature: &mut Temperature) {
+        *temperature = temperature.offset(self.0);
+    }
+}
+
+impl std::ops::Neg for TemperatureDelta {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        TemperatureDelta(-self.0)
+    }
+}
+
+impl Percent {
+    /// Percentage of a fraction between 0 and 1, clamped and rounded
+    pub fn from_fraction(fraction: f64) -> Self {
+        Self::new_clamped((fraction * 100.0).round().clamp(0.0, 100.0) as u8)
+    }
+
+    /// Fraction between 0 and 1
+    pub fn fraction(self) -> f64 {
+        f64::from(self.0) / 100.0
+    }
+
+    /// The lowest of the percentage and `max`, if any
+    pub fn capped(self, max: Option<Percent>) -> Self {
+        match max {
+            Some(max) if max.0 < self.0 => max,
+            _ => self,
+        }
+    }
+}
+
+impl From<KeyLightBrightness> for Percent {
+    fn from(value: KeyLightBrightness) -> Self {
+        UnsignedInt(value.0)
+    }
+}
+
+impl TryFrom<Percent> for KeyLightBrightness {
+    type Error = String;
+
+    fn try_from(value: Percent) -> Result<Self, Self::Error> {
+        KeyLightBrightness::new(value.0)
+    }
+}
+
+/// Color temperature in kelvin, the unit of the config and preset files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Kelvin(pub u16);
+
+/// The device values are in mireds, the reciprocal of kelvin
+const MIREDS_PER_KELVIN: u32 = 1_000_000;
+
+impl From<Temperature> for Kelvin {
+    fn from(temperature: Temperature) -> Self {
+        Kelvin((MIREDS_PER_KELVIN / u32::from(temperature.0)) as u16)
+    }
+}
+
+/// Clamped to the range of the lights, about 2900 K to 7000 K
+impl From<Kelvin> for Temperature {
+    fn from(kelvin: Kelvin) -> Self {
+        let value = MIREDS_PER_KELVIN / u32::from(kelvin.0.max(1));
+        Temperature::new_clamped(u16::try_from(value).unwrap_or(u16::MAX))
+    }
+}
+
+/// `5000`, `5000K` or `5000 K`
+impl FromStr for Kelvin {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_suffix(['K', 'k']).unwrap_or(s);
+        Ok(Kelvin(s.trim_end().parse()?))
+    }
+}
+
+impl std::fmt::Display for Kelvin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} K", self.0)
+    }
+}
+
+/// `42%`
+impl std::fmt::Display for Brightness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// `5000 K (200)`: kelvin, then the device value in mireds
+impl std::fmt::Display for Temperature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", Kelvin::from(*self), self.0)
+    }
+}
+
+impl<I, const S: usize, const E: usize> std::ops::Deref for UnsignedInt<I, S, E> {
+    type Target = I;
+
+    fn deref(&self) -> &I {
+        &self.0
+    }
+}
+
+impl<const S: usize, const E: usize> From<UnsignedInt<u8, S, E>> for u8 {
+    fn from(value: UnsignedInt<u8, S, E>) -> Self {
+        value.0
+    }
+}
+
+impl<const S: usize, const E: usize> From<UnsignedInt<u16, S, E>> for u16 {
+    fn from(value: UnsignedInt<u16, S, E>) -> Self {
+        value.0
+    }
 }
 
 impl<
@@ -53,8 +241,54 @@ impl<
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
+    /// In-range values round-trip through serde and `FromStr`, out-of-range ones are rejected
+    fn check<I, const S: usize, const E: usize>(value: I) -> Result<(), TestCaseError>
+    where
+        I: std::fmt::Debug
+            + std::fmt::Display
+            + Copy
+            + PartialEq
+            + Serialize
+            + serde::de::DeserializeOwned
+            + FromStr<Err = ParseIntError>
+            + Into<usize>,
+    {
+        let json = serde_json::to_string(&value).unwrap();
+        let parsed = serde_json::from_str::<UnsignedInt<I, S, E>>(&json);
+        let from_str = value.to_string().parse::<UnsignedInt<I, S, E>>();
+        if (S..=E).contains(&value.into()) {
+            let expected = UnsignedInt::<I, S, E>::new(value).unwrap();
+            prop_assert_eq!(parsed.unwrap(), expected);
+            prop_assert_eq!(from_str.unwrap(), expected);
+            prop_assert_eq!(serde_json::to_string(&expected).unwrap(), json);
+        } else {
+            prop_assert!(parsed.is_err());
+            prop_assert!(from_str.is_err());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn percent_range(value: u8) {
+            check::<u8, 0, 100>(value)?;
+        }
+
+        #[test]
+        fn key_light_brightness_range(value: u8) {
+            check::<u8, 3, 100>(value)?;
+        }
+
+        #[test]
+        fn temperature_range(value in prop_oneof![0..1000u16, any::<u16>()]) {
+            check::<u16, 143, 344>(value)?;
+        }
+    }
+
     #[test]
     fn unsigned_int() {
         let x: Result<UnsignedInt<u8, 5, 10>, _> = UnsignedInt::new(6);
@@ -63,4 +297,71 @@ mod tests {
         let x: Result<UnsignedInt<u8, 5, 10>, _> = UnsignedInt::new(3);
         assert!(x.is_err());
     }
+
+    #[test]
+    fn brightness() {
+        assert_eq!(Brightness::new_clamped(120), Brightness::MAX);
+        assert_eq!(Brightness::new(42).unwrap().to_string(), "42%");
+
+        let brightness = Brightness::new(42).unwrap();
+        assert_eq!(brightness.value(), 42);
+        assert_eq!(*brightness, 42);
+        assert_eq!(u8::from(brightness), 42);
+
+        let mut brightness = Brightness::new(95).unwrap();
+        BrightnessDelta::STEP.apply(&mut brightness);
+        assert_eq!(brightness, Brightness::MAX);
+        let mut brightness = Brightness::new(5).unwrap();
+        (-BrightnessDelta::STEP).apply(&mut brightness);
+        assert_eq!(brightness, Brightness::MIN);
+    }
+
+    #[test]
+    fn key_light_brightness() {
+        assert!(KeyLightBrightness::new(1).is_err());
+        assert!(KeyLightBrightness::try_from(Percent::new(2).unwrap()).is_err());
+        assert_eq!(
+            Percent::from(KeyLightBrightness::new(3).unwrap()),
+            Percent::new(3).unwrap()
+        );
+
+        let mut brightness = KeyLightBrightness::new(10).unwrap();
+        (-BrightnessDelta::STEP).apply(&mut brightness);
+        assert_eq!(brightness, KeyLightBrightness::MIN);
+        assert_eq!(brightness.value(), 3);
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(Percent::from_fraction(0.424).value(), 42);
+        assert_eq!(Percent::from_fraction(1.5), Percent::MAX);
+        assert_eq!(Percent::new(25).unwrap().fraction(), 0.25);
+        assert_eq!(Percent::MAX.capped(Percent::new(60).ok()).value(), 60);
+        assert_eq!(Percent::MIN.capped(Percent::new(60).ok()), Percent::MIN);
+        assert_eq!(Percent::MAX.capped(None), Percent::MAX);
+    }
+
+    #[test]
+    fn temperature() {
+        assert_eq!(Temperature::new_clamped(100), Temperature::MIN);
+        assert_eq!(Temperature::new_clamped(200).value(), 200);
+        assert_eq!(Temperature::new(200).unwrap().to_string(), "5000 K (200)");
+
+        let temperature: u16 = Temperature::new(200).unwrap().into();
+        assert_eq!(temperature, 200);
+
+        let mut temperature = Temperature::new(150).unwrap();
+        (-TemperatureDelta::STEP).apply(&mut temperature);
+        assert_eq!(temperature, Temperature::MIN);
+    }
+
+    #[test]
+    fn kelvin() {
+        assert_eq!(Temperature::from(Kelvin(7000)).0, 143);
+        assert_eq!(Temperature::from(Kelvin(5000)).0, 200);
+        assert_eq!(Temperature::from(Kelvin(2900)).0, 344);
+        assert_eq!(Temperature::from(Kelvin(1000)).0, 344);
+        assert_eq!(Kelvin::from(Temperature::new(200).unwrap()), Kelvin(5000));
+        assert_eq!("4000 K".parse(), Ok(Kelvin(4000)));
+    }
 }
@@ -1,63 +1,83 @@
-use std::{fs::File, io::Write as _, path::PathBuf, string::FromUtf8Error};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
-use log::{error, info};
-use tokio::process::Command;
-
-#[derive(Debug, thiserror::Error)]
-pub enum FindExecError {
-    #[error(transparent)]
-    OutputParse(#[from] FromUtf8Error),
-    #[error(transparent)]
-    IO(#[from] std::io::Error),
+/// Find an executable file in the process PATH, like `which`
+pub fn find_executable(executable: &str) -> Option<PathBuf> {
+    find_in_path(executable, &std::env::var_os("PATH")?)
 }
 
-/// Find executable in process PATH
-pub async fn find_executable(executable: &str) -> Result<Option<PathBuf>, FindExecError> {
-    match Command::new("which").arg(executable).output().await {
-        Ok(output) => Ok(Some(PathBuf::from(String::from_utf8(output.stdout)?))),
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
-        Err(err) => Err(FindExecError::IO(err)),
+fn find_in_path(executable: &str, path: &OsStr) -> Option<PathBuf> {
+    // Paths such as `./script` are not looked up
+    if Path::new(executable).components().count() > 1 {
+        let path = PathBuf::from(executable);
+        return is_executable(&path).then_some(path);
     }
+    std::env::split_paths(path)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .flat_map(|dir| candidates(&dir, executable))
+        .find(|candidate| is_executable(candidate))
 }
 
-/// Notify to the user using `libnotify`
-pub async fn notify(msg: &str) -> anyhow::Result<()> {
-    if find_executable("notify-send").await?.is_none() {
-        info!("notify-send not found. Using stdout");
-        println!("{msg}");
-        return Ok(());
-    }
+/// Files that would run `executable` from `dir`
+#[cfg(not(windows))]
+fn candidates(dir: &Path, executable: &str) -> Vec<PathBuf> {
+    vec![dir.join(executable)]
+}
 
-    let Ok(icon_path) = inject_icon() else {
-        error!("Inject icon failed. Using stdout");
-        println!("{msg}");
-        return Ok(());
-    };
-
-    if let Err(err) = Command::new("notify-send")
-        .arg(format!("--icon={}", icon_path.display()))
-        .arg("Key Light Controller")
-        .arg(msg)
-        .output()
-        .await
-    {
-        error!("`notify-send` failed: {err}. Using stdout");
-        println!("{msg}");
-    }
+/// Files that would run `executable` from `dir`: as is, or with one of the `PATHEXT` extensions
+#[cfg(windows)]
+fn candidates(dir: &Path, executable: &str) -> Vec<PathBuf> {
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    std::iter::once(dir.join(executable))
+        .chain(
+            extensions
+                .split(';')
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| dir.join(format!("{executable}{extension}"))),
+        )
+        .collect()
+}
 
-    Ok(())
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
 }
 
-fn inject_icon() -> anyhow::Result<PathBuf> {
-    let dir = tempfile::tempdir()?;
-    let path = dir.path().join("elgato_logo.png");
-    let mut file = File::create(&path)?;
-
-    let bytes = include_bytes!(concat!(
-        env!("CARGO_MANIFEST_DIR"),
-        "/assets/elgato_logo.png"
-    ));
-    file.write_all(bytes)?;
-    file.flush()?;
-    Ok(path)
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    use super::*;
+
+    #[test]
+    fn path_lookup() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let write = |path: PathBuf, mode| {
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            path
+        };
+        write(first.path().join("tool"), 0o644);
+        let tool = write(second.path().join("tool"), 0o755);
+        std::fs::create_dir(first.path().join("dir")).unwrap();
+
+        let path = std::env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(find_in_path("tool", &path), Some(tool.clone()));
+        assert_eq!(find_in_path("dir", &path), None);
+        assert_eq!(find_in_path("missing", &path), None);
+        assert_eq!(
+            find_in_path(tool.to_str().unwrap(), OsStr::new("")),
+            Some(tool)
+        );
+    }
 }